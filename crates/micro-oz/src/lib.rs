@@ -1,6 +1,7 @@
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
 use chrono::Utc;
@@ -9,7 +10,8 @@ use ethers::prelude::SignerMiddleware;
 use ethers::providers::{Http, Middleware, Provider};
 use ethers::signers::{LocalWallet, Signer};
 use ethers::types::transaction::eip2718::TypedTransaction;
-use ethers::types::{Eip1559TransactionRequest, U64};
+use ethers::types::{BlockNumber, Eip1559TransactionRequest, U256, U64};
+use oz_api::data::relayer::RelayerStatus;
 use oz_api::data::transactions::{RelayerTransactionBase, SendBaseTransactionRequestOwned, Status};
 use tokio::sync::{mpsc, Mutex};
 
@@ -17,6 +19,8 @@ pub mod server;
 
 const DEFAULT_GAS_LIMIT: u32 = 1_000_000;
 
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub use self::server::{spawn, ServerHandle};
 
 type PinheadSigner = SignerMiddleware<Provider<Http>, LocalWallet>;
@@ -32,8 +36,24 @@ struct PinheadInner {
     tx_id_counter:  AtomicU64,
     txs_to_execute: mpsc::Sender<String>,
     txs:            Mutex<HashMap<String, Arc<Mutex<RelayerTransactionBase>>>>,
+    // A node error that sends are rejected with, as relayed by Defender
+    send_error:     std::sync::Mutex<Option<String>>,
+    // How many of the next listings fail
+    list_failures:  AtomicUsize,
 }
 
+/// A send rejected by the node
+#[derive(Debug)]
+pub struct NodeError(pub String);
+
+impl std::fmt::Display for NodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NodeError {}
+
 impl Drop for PinheadInner {
     fn drop(&mut self) {
         self.is_running
@@ -86,34 +106,57 @@ async fn runner_inner(inner: &Arc<PinheadInner>, tx_id: String) -> Result<(), an
 
     inner.signer.fill_transaction(&mut typed_tx, None).await?;
 
-    let pending_tx = inner.signer.send_transaction(typed_tx, None).await?;
-
     {
         let mut tx_guard = tx.lock().await;
 
+        let pending_tx = inner
+            .signer
+            .send_transaction(typed_tx.clone(), None)
+            .await?;
+
         tx_guard.status = Status::Pending;
         tx_guard.hash = Some(pending_tx.tx_hash());
+        record_fees(&mut tx_guard, &typed_tx);
     }
 
     tracing::info!("Awaiting for receipt");
 
-    let receipt = pending_tx.await?;
+    // Polled by the latest hash, so that replacements are followed
+    loop {
+        {
+            let mut tx_guard = tx.lock().await;
+            let hash = tx_guard.hash.context("Missing tx hash")?;
+
+            if let Some(receipt) = inner.signer.get_transaction_receipt(hash).await? {
+                if let Some(U64([0])) = receipt.status {
+                    tracing::error!("Receipt: {:?}", receipt);
+                } else {
+                    tracing::info!("Receipt: {:?}", receipt);
+                }
+                tx_guard.status = Status::Mined;
+
+                return Ok(());
+            }
 
-    let mut tx_guard = tx.lock().await;
+            if inner.signer.get_transaction(hash).await?.is_none() {
+                tracing::error!("Transaction dropped");
+                tx_guard.status = Status::Failed;
 
-    if let Some(receipt) = receipt {
-        if let Some(U64([0])) = receipt.status {
-            tracing::error!("Receipt: {:?}", receipt);
-        } else {
-            tracing::info!("Receipt: {:?}", receipt);
+                return Ok(());
+            }
         }
-        tx_guard.status = Status::Mined;
-    } else {
-        tracing::error!("Receipt not found");
-        tx_guard.status = Status::Failed;
+
+        tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
     }
+}
 
-    Ok(())
+fn record_fees(tx: &mut RelayerTransactionBase, sent: &TypedTransaction) {
+    tx.nonce = sent.nonce().map(U256::as_u64);
+
+    if let TypedTransaction::Eip1559(request) = sent {
+        tx.max_fee_per_gas = request.max_fee_per_gas;
+        tx.max_priority_fee_per_gas = request.max_priority_fee_per_gas;
+    }
 }
 
 impl Pinhead {
@@ -137,6 +180,8 @@ impl Pinhead {
             is_running,
             txs_to_execute: tx_sender,
             txs,
+            send_error: std::sync::Mutex::new(None),
+            list_failures: AtomicUsize::new(0),
         });
 
         tokio::spawn(runner(inner.clone(), tx_receiver));
@@ -148,24 +193,32 @@ impl Pinhead {
         &self,
         tx_request: SendBaseTransactionRequestOwned,
     ) -> anyhow::Result<RelayerTransactionBase> {
+        if let Some(error) = self.inner.send_error.lock().unwrap().clone() {
+            return Err(NodeError(error).into());
+        }
+
         let mut txs = self.inner.txs.lock().await;
 
         let tx_id = self.next_tx_id();
 
         let tx = RelayerTransactionBase {
             transaction_id: tx_id.clone(),
-            to:             tx_request.to.context("Missing to")?,
-            value:          tx_request.value,
-            gas_limit:      tx_request
+            to: tx_request.to.context("Missing to")?,
+            value: tx_request.value,
+            gas_limit: tx_request
                 .gas_limit
                 .map(|gas_limit| gas_limit.as_u32())
                 .unwrap_or(DEFAULT_GAS_LIMIT),
-            data:           tx_request.data,
-            status:         Status::Pending,
-            hash:           None,
-            valid_until:    tx_request
+            data: tx_request.data,
+            status: Status::Pending,
+            hash: None,
+            valid_until: tx_request
                 .valid_until
                 .unwrap_or(Utc::now() + chrono::Duration::hours(24)),
+            gas_price: tx_request.gas_price,
+            max_fee_per_gas: tx_request.max_fee_per_gas,
+            max_priority_fee_per_gas: tx_request.max_priority_fee_per_gas,
+            nonce: None,
         };
 
         txs.insert(tx_id.clone(), Arc::new(Mutex::new(tx.clone())));
@@ -175,11 +228,98 @@ impl Pinhead {
         Ok(tx)
     }
 
+    /// Replaces a pending transaction under the same nonce, at twice its fees
+    /// or the requested ones if they're higher
+    pub async fn replace_transaction(
+        &self,
+        tx_id: &str,
+        tx_request: SendBaseTransactionRequestOwned,
+    ) -> anyhow::Result<RelayerTransactionBase> {
+        let tx = self
+            .inner
+            .txs
+            .lock()
+            .await
+            .get(tx_id)
+            .context(format!("Transaction {} not found", tx_id))?
+            .clone();
+
+        let mut tx_guard = tx.lock().await;
+
+        anyhow::ensure!(
+            tx_guard.status == Status::Pending,
+            "Transaction {} is not pending",
+            tx_id
+        );
+        let nonce = tx_guard
+            .nonce
+            .context(format!("Transaction {} has not been sent yet", tx_id))?;
+
+        let bump = |current: Option<U256>, requested: Option<U256>| {
+            current
+                .map(|current| current * 2)
+                .max(requested)
+                .unwrap_or_default()
+        };
+        let max_priority_fee_per_gas = bump(
+            tx_guard.max_priority_fee_per_gas,
+            tx_request.max_priority_fee_per_gas,
+        );
+        let max_fee_per_gas = bump(tx_guard.max_fee_per_gas, tx_request.max_fee_per_gas)
+            .max(max_priority_fee_per_gas);
+
+        let gas_limit = tx_request
+            .gas_limit
+            .map(|gas_limit| gas_limit.as_u32())
+            .unwrap_or(DEFAULT_GAS_LIMIT);
+
+        let mut typed_tx = TypedTransaction::Eip1559(Eip1559TransactionRequest {
+            to: tx_request.to.clone(),
+            value: tx_request.value,
+            gas: Some(gas_limit.into()),
+            data: tx_request.data.clone(),
+            nonce: Some(nonce.into()),
+            max_fee_per_gas: Some(max_fee_per_gas),
+            max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
+            ..Eip1559TransactionRequest::default()
+        });
+
+        self.inner
+            .signer
+            .fill_transaction(&mut typed_tx, None)
+            .await?;
+        let pending_tx = self
+            .inner
+            .signer
+            .send_transaction(typed_tx.clone(), None)
+            .await?;
+
+        tracing::info!("Replaced tx {tx_id} with {:?}", pending_tx.tx_hash());
+
+        tx_guard.to = tx_request.to.context("Missing to")?;
+        tx_guard.value = tx_request.value;
+        tx_guard.data = tx_request.data;
+        tx_guard.gas_limit = gas_limit;
+        tx_guard.hash = Some(pending_tx.tx_hash());
+        record_fees(&mut tx_guard, &typed_tx);
+
+        Ok(tx_guard.clone())
+    }
+
     pub async fn list_transactions(
         &self,
         status: Option<Status>,
         limit: Option<usize>,
     ) -> anyhow::Result<Vec<RelayerTransactionBase>> {
+        let failing = self
+            .inner
+            .list_failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+                failures.checked_sub(1)
+            })
+            .is_ok();
+        anyhow::ensure!(!failing, "Listing failed as requested");
+
         let txs = self.inner.txs.lock().await;
 
         let mut txs_to_return = vec![];
@@ -217,6 +357,40 @@ impl Pinhead {
         Ok(tx_guard.clone())
     }
 
+    pub async fn relayer_status(&self) -> anyhow::Result<RelayerStatus> {
+        let address = self.inner.signer.address();
+        let nonce = self
+            .inner
+            .signer
+            .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+            .await?;
+
+        let pending = self
+            .list_transactions(Some(Status::Pending), None)
+            .await?
+            .len();
+
+        Ok(RelayerStatus {
+            relayer_id: "micro-oz".to_string(),
+            address,
+            nonce: nonce.as_u64(),
+            number_of_pending_transactions: pending as u64,
+            paused: false,
+            last_confirmed_transaction: None,
+        })
+    }
+
+    /// Rejects every send with the node error `error`, or stops rejecting
+    /// sends if `None`
+    pub fn reject_sends(&self, error: Option<&str>) {
+        *self.inner.send_error.lock().unwrap() = error.map(str::to_string);
+    }
+
+    /// Fails the next `count` listings of transactions
+    pub fn fail_lists(&self, count: usize) {
+        self.inner.list_failures.store(count, Ordering::SeqCst);
+    }
+
     fn next_tx_id(&self) -> String {
         let id = self
             .inner
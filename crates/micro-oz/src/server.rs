@@ -8,25 +8,28 @@ use axum::routing::{get, post};
 use axum::{Json, Router};
 use ethers::prelude::k256::ecdsa::SigningKey;
 use ethers::types::Address;
+use oz_api::data::relayer::RelayerStatus;
 use oz_api::data::transactions::{RelayerTransactionBase, SendBaseTransactionRequestOwned, Status};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Notify;
 use tokio::task::JoinHandle;
 
-use crate::Pinhead;
+use crate::{NodeError, Pinhead};
 
 async fn send_transaction(
     State(pinhead): State<Pinhead>,
     Json(request): Json<SendBaseTransactionRequestOwned>,
-) -> Result<Json<RelayerTransactionBase>, StatusCode> {
+) -> Result<Json<RelayerTransactionBase>, (StatusCode, String)> {
     let result = pinhead.send_transaction(request).await;
 
     match result {
         Ok(tx) => Ok(Json(tx)),
+        // Relayed to the client like Defender does
+        Err(err) if err.is::<NodeError>() => Err((StatusCode::BAD_REQUEST, err.to_string())),
         Err(err) => {
             tracing::error!("Pinhead send_transaction error: {:?}", err);
 
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()))
         }
     }
 }
@@ -39,6 +42,23 @@ struct ListTransactionsQuery {
     limit:  Option<usize>,
 }
 
+async fn replace_transaction(
+    State(pinhead): State<Pinhead>,
+    Path(tx_id): Path<String>,
+    Json(request): Json<SendBaseTransactionRequestOwned>,
+) -> Result<Json<RelayerTransactionBase>, StatusCode> {
+    let result = pinhead.replace_transaction(&tx_id, request).await;
+
+    match result {
+        Ok(tx) => Ok(Json(tx)),
+        Err(err) => {
+            tracing::error!("Pinhead replace_transaction error: {:?}", err);
+
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 async fn list_transactions(
     State(pinhead): State<Pinhead>,
     Query(query): Query<ListTransactionsQuery>,
@@ -71,6 +91,19 @@ async fn query_transaction(
     }
 }
 
+async fn relayer_status(State(pinhead): State<Pinhead>) -> Result<Json<RelayerStatus>, StatusCode> {
+    let status = pinhead.relayer_status().await;
+
+    match status {
+        Ok(status) => Ok(Json(status)),
+        Err(err) => {
+            tracing::error!("Pinhead relayer_status error: {:?}", err);
+
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 pub struct ServerHandle {
     pinhead:            Pinhead,
     addr:               SocketAddr,
@@ -91,6 +124,17 @@ impl ServerHandle {
         format!("http://{}", self.addr)
     }
 
+    /// Rejects every send with the node error `error`, or stops rejecting
+    /// sends if `None`
+    pub fn reject_sends(&self, error: Option<&str>) {
+        self.pinhead.reject_sends(error);
+    }
+
+    /// Fails the next `count` listings of transactions
+    pub fn fail_lists(&self, count: usize) {
+        self.pinhead.fail_lists(count);
+    }
+
     pub async fn shutdown(self) {
         self.shutdown_notify.notify_waiters();
 
@@ -105,7 +149,11 @@ pub async fn spawn(rpc_url: String, secret_key: SigningKey) -> anyhow::Result<Se
 
     let router = Router::new()
         .route("/txs", post(send_transaction).get(list_transactions))
-        .route("/txs/:tx_id", get(query_transaction))
+        .route(
+            "/txs/:tx_id",
+            get(query_transaction).put(replace_transaction),
+        )
+        .route("/relayer/status", get(relayer_status))
         .with_state(pinhead.clone());
 
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
@@ -3,6 +3,7 @@ use std::time::{Duration, Instant};
 use cognitoauth::cognito_srp_auth::{auth, CognitoAuthInput};
 use hyper::http::HeaderValue;
 use hyper::HeaderMap;
+use tracing::warn;
 
 use crate::error::Error;
 
@@ -25,7 +26,11 @@ impl ExpiringHeaders {
         }
     }
 
-    pub async fn refresh(api_key: &str, api_secret: &str) -> Result<ExpiringHeaders, Error> {
+    pub async fn refresh(
+        api_key: &str,
+        api_secret: &str,
+        timeout: Duration,
+    ) -> Result<ExpiringHeaders, Error> {
         let now = Instant::now();
 
         let input = CognitoAuthInput {
@@ -37,7 +42,13 @@ impl ExpiringHeaders {
             client_secret: None,
         };
 
-        let res = auth(input).await?.ok_or(Error::Unauthorized)?;
+        let res = match tokio::time::timeout(timeout, auth(input)).await {
+            Ok(res) => res?.ok_or(Error::Unauthorized)?,
+            Err(_) => {
+                warn!(elapsed = ?now.elapsed(), "Cognito authentication timed out");
+                return Err(Error::AuthTimeout(timeout));
+            }
+        };
 
         let access_token = res.access_token().ok_or(Error::Unauthorized)?;
 
@@ -57,7 +68,43 @@ impl ExpiringHeaders {
         })
     }
 
+    /// Whether the headers expire within `clock_skew` of `now`, or already
+    /// have
+    pub fn expires_within(&self, now: Instant, clock_skew: Duration) -> bool {
+        self.expiration_time.saturating_duration_since(now) <= clock_skew
+    }
+
     pub fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
         req.headers(self.headers.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_refreshed_within_the_clock_skew() {
+        let now = Instant::now();
+        let clock_skew = Duration::from_secs(5);
+        let headers = |expires_in| ExpiringHeaders {
+            headers:         HeaderMap::new(),
+            expiration_time: now + expires_in,
+        };
+
+        assert!(!headers(Duration::from_secs(6)).expires_within(now, clock_skew));
+        assert!(headers(Duration::from_secs(5)).expires_within(now, clock_skew));
+        assert!(headers(Duration::from_secs(1)).expires_within(now, clock_skew));
+
+        // Already expired
+        let expired = ExpiringHeaders {
+            headers:         HeaderMap::new(),
+            expiration_time: now,
+        };
+        assert!(expired.expires_within(now + Duration::from_secs(1), clock_skew));
+
+        // Without a skew, only expired headers are refreshed
+        assert!(!headers(Duration::from_secs(1)).expires_within(now, Duration::ZERO));
+        assert!(expired.expires_within(now, Duration::ZERO));
+    }
+}
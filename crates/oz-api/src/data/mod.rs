@@ -1 +1,57 @@
+pub mod relayer;
 pub mod transactions;
+
+use ethers::types::U256;
+use serde::{Deserialize, Deserializer};
+
+/// Defender returns numbers either as JSON numbers or as decimal or hex
+/// strings, depending on the endpoint.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
+}
+
+impl NumberOrString {
+    fn into_u256(self) -> Result<U256, String> {
+        match self {
+            Self::Number(number) => Ok(number.into()),
+            Self::String(string) => match string.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16),
+                None => U256::from_dec_str(&string),
+            }
+            .map_err(|error| format!("invalid number {string:?}: {error}")),
+        }
+    }
+}
+
+pub(crate) fn u256_from_number_or_string<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<NumberOrString>::deserialize(deserializer)?
+        .map(NumberOrString::into_u256)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+pub(crate) fn u64_from_number_or_string<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    u256_from_number_or_string(deserializer)?
+        .map(|number| {
+            u64::try_from(number).map_err(|_| serde::de::Error::custom("number exceeds 64 bits"))
+        })
+        .transpose()
+}
+
+/// Like [`u64_from_number_or_string`], for numbers that are always present
+pub(crate) fn required_u64_from_number_or_string<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    u64_from_number_or_string(deserializer)?
+        .ok_or_else(|| serde::de::Error::custom("missing number"))
+}
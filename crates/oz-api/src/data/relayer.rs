@@ -0,0 +1,70 @@
+//! Relayer status as defined by the OpenZeppelin Defender API.
+//!
+//! https://docs.openzeppelin.com/defender/relay-api-reference#relayer-endpoint
+
+use ethers::types::{Address, H256};
+use serde::{Deserialize, Serialize};
+
+use super::required_u64_from_number_or_string;
+
+/// OpenZeppelin Defender relayer status.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayerStatus {
+    pub relayer_id: String,
+    pub address: Address,
+    /// The nonce the relayer assigns to the next transaction
+    #[serde(deserialize_with = "required_u64_from_number_or_string")]
+    pub nonce: u64,
+    #[serde(default)]
+    pub number_of_pending_transactions: u64,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub last_confirmed_transaction: Option<ConfirmedTransaction>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmedTransaction {
+    pub hash:  H256,
+    #[serde(deserialize_with = "required_u64_from_number_or_string")]
+    pub nonce: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonces_are_decoded_from_numbers_and_strings() {
+        let status: RelayerStatus = serde_json::from_str(
+            r#"{
+                "relayerId": "5fcb8a6d-8d3e-403a-b33d-ade27ce0f85a",
+                "address": "0xbce0c7d24ba52b5540d4872f0df3d5bb8ce5b701",
+                "nonce": "0x2a",
+                "numberOfPendingTransactions": 2,
+                "lastConfirmedTransaction": {
+                    "hash": "0x7f0ef2bf3b6a3d7cd2b6aa0c0c1ef7f2f8ab5bd0b0a4d32e3f4caa1a5e0d4a21",
+                    "nonce": "39"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(status.nonce, 42);
+        assert_eq!(status.last_confirmed_transaction.unwrap().nonce, 39);
+
+        let status: RelayerStatus = serde_json::from_str(
+            r#"{
+                "relayerId": "5fcb8a6d-8d3e-403a-b33d-ade27ce0f85a",
+                "address": "0xbce0c7d24ba52b5540d4872f0df3d5bb8ce5b701",
+                "nonce": 42
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(status.nonce, 42);
+    }
+}
@@ -8,6 +8,8 @@ use chrono::{DateTime, Utc};
 use ethers::types::{Bytes, NameOrAddress, H256, U256};
 use serde::{Deserialize, Serialize};
 
+use super::{u256_from_number_or_string, u64_from_number_or_string};
+
 /// OpenZeppelin Defender transaction status.
 ///
 /// https://docs.openzeppelin.com/defender/relay-api-reference#transaction-status
@@ -44,15 +46,22 @@ impl fmt::Display for Status {
 #[serde(rename_all = "camelCase")]
 pub struct SendBaseTransactionRequest<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub to:          Option<&'a NameOrAddress>,
+    pub to: Option<&'a NameOrAddress>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub value:       Option<&'a U256>,
+    pub value: Option<&'a U256>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub data:        Option<&'a Bytes>,
+    pub data: Option<&'a Bytes>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub gas_limit:   Option<&'a U256>,
+    pub gas_limit: Option<&'a U256>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub valid_until: Option<DateTime<Utc>>,
+    /// Fees, chosen by the relayer's speed setting if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<&'a U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<&'a U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_priority_fee_per_gas: Option<&'a U256>,
 }
 
 /// OpenZeppelin Defender transaction to be sent.
@@ -63,19 +72,28 @@ pub struct SendBaseTransactionRequest<'a> {
 pub struct SendBaseTransactionRequestOwned {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub to:          Option<NameOrAddress>,
+    pub to: Option<NameOrAddress>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub value:       Option<U256>,
+    pub value: Option<U256>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub data:        Option<Bytes>,
+    pub data: Option<Bytes>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub gas_limit:   Option<U256>,
+    pub gas_limit: Option<U256>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub valid_until: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub gas_price: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub max_priority_fee_per_gas: Option<U256>,
 }
 
 /// OpenZeppelin Defender transaction that has been received by the relayer and
@@ -85,18 +103,107 @@ pub struct SendBaseTransactionRequestOwned {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RelayerTransactionBase {
+    /// Only known once the relayer has broadcast the transaction. Some
+    /// endpoints report it as `transactionHash` instead of `hash`.
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(default)]
-    pub hash:           Option<H256>,
+    #[serde(default, alias = "transactionHash")]
+    pub hash: Option<H256>,
     pub transaction_id: String,
-    pub to:             NameOrAddress,
+    pub to: NameOrAddress,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub value:          Option<U256>,
-    pub gas_limit:      u32,
+    pub value: Option<U256>,
+    pub gas_limit: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub data:           Option<Bytes>,
-    pub valid_until:    DateTime<Utc>,
-    pub status:         Status,
+    pub data: Option<Bytes>,
+    pub valid_until: DateTime<Utc>,
+    pub status: Status,
+    /// The fees of the latest broadcast, `gas_price` for legacy transactions
+    /// and the max fees for EIP-1559 ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "u256_from_number_or_string")]
+    pub gas_price: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "u256_from_number_or_string")]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "u256_from_number_or_string")]
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Only known once the relayer has assigned it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "u64_from_number_or_string")]
+    pub nonce: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As returned by `GET /txs/{id}`
+    const QUERIED_TRANSACTION: &str = r#"{
+        "chainId": 11155111,
+        "hash": "0x7f0ef2bf3b6a3d7cd2b6aa0c0c1ef7f2f8ab5bd0b0a4d32e3f4caa1a5e0d4a21",
+        "transactionId": "5fcb8a6d-8d3e-403a-b33d-ade27ce0f85a",
+        "value": "0x0",
+        "gasPrice": 1000000000,
+        "gasLimit": 21000,
+        "to": "0x179810822f56b0e79469189741a3fa5f2f9a7631",
+        "from": "0xbce0c7d24ba52b5540d4872f0df3d5bb8ce5b701",
+        "data": "0x",
+        "nonce": 14,
+        "status": "submitted",
+        "speed": "fast",
+        "validUntil": "2020-12-05T13:40:49.000Z",
+        "createdAt": "2020-12-04T13:40:48.921Z",
+        "sentAt": "2020-12-04T13:40:49.413Z",
+        "pricedAt": "2020-12-04T13:40:49.413Z",
+        "isPrivate": false
+    }"#;
+
+    // As listed by `GET /txs`, with EIP-1559 fees as strings
+    const LISTED_TRANSACTION: &str = r#"{
+        "chainId": 11155111,
+        "transactionHash": "0x7f0ef2bf3b6a3d7cd2b6aa0c0c1ef7f2f8ab5bd0b0a4d32e3f4caa1a5e0d4a21",
+        "transactionId": "0f1f5d4a-1b7a-4c5e-a3bb-9c8b8ee7ad0b",
+        "value": "0x0",
+        "maxFeePerGas": "40000000000",
+        "maxPriorityFeePerGas": "0x77359400",
+        "gasLimit": 1000000,
+        "to": "0x179810822f56b0e79469189741a3fa5f2f9a7631",
+        "from": "0xbce0c7d24ba52b5540d4872f0df3d5bb8ce5b701",
+        "data": "0x2217b211",
+        "nonce": "15",
+        "status": "pending",
+        "speed": "fast",
+        "validUntil": "2020-12-05T13:40:49.000Z",
+        "createdAt": "2020-12-04T13:40:48.921Z"
+    }"#;
+
+    #[test]
+    fn fees_and_nonce_are_decoded_from_numbers() {
+        let tx: RelayerTransactionBase = serde_json::from_str(QUERIED_TRANSACTION).unwrap();
+
+        assert_eq!(tx.status, Status::Submitted);
+        assert!(tx.hash.is_some());
+        assert_eq!(tx.gas_price, Some(U256::from(1_000_000_000u64)));
+        assert_eq!(tx.max_fee_per_gas, None);
+        assert_eq!(tx.max_priority_fee_per_gas, None);
+        assert_eq!(tx.nonce, Some(14));
+    }
+
+    #[test]
+    fn fees_and_nonce_are_decoded_from_strings() {
+        let tx: RelayerTransactionBase = serde_json::from_str(LISTED_TRANSACTION).unwrap();
+
+        assert_eq!(tx.status, Status::Pending);
+        assert!(tx.hash.is_some());
+        assert_eq!(tx.gas_price, None);
+        assert_eq!(tx.max_fee_per_gas, Some(U256::from(40_000_000_000u64)));
+        assert_eq!(
+            tx.max_priority_fee_per_gas,
+            Some(U256::from(2_000_000_000u64))
+        );
+        assert_eq!(tx.nonce, Some(15));
+    }
 }
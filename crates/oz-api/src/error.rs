@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use cognitoauth::error::CognitoSrpAuthError;
 use hyper::header::InvalidHeaderValue;
 use hyper::StatusCode;
@@ -11,6 +13,9 @@ pub enum Error {
     #[error("Unauthorized")]
     Unauthorized,
 
+    #[error("Authentication timed out after {0:?}")]
+    AuthTimeout(Duration),
+
     #[error("Request failed: {0}")]
     Reqwest(#[from] reqwest::Error),
 
@@ -23,6 +28,7 @@ pub enum Error {
     #[error("Parsing error: {0}")]
     ParseError(#[from] serde_json::Error),
 
-    #[error("Invalid response with status code: {0}")]
-    InvalidResponse(StatusCode),
+    /// The status code and the body of the response
+    #[error("Invalid response with status code {0}: {1}")]
+    InvalidResponse(StatusCode, String),
 }
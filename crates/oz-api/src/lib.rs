@@ -1,6 +1,7 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use auth::ExpiringHeaders;
+use data::relayer::RelayerStatus;
 use data::transactions::{RelayerTransactionBase, SendBaseTransactionRequest, Status};
 use reqwest::{IntoUrl, Url};
 use serde::de::DeserializeOwned;
@@ -21,11 +22,20 @@ pub struct OzApi {
     expiring_headers: Mutex<ExpiringHeaders>,
     api_key:          String,
     api_secret:       String,
+    auth_timeout:     Duration,
+    clock_skew:       Duration,
     auth_disabled:    bool,
 }
 
 impl OzApi {
-    pub async fn new<U, S>(api_url: U, api_key: S, api_secret: S) -> Result<Self>
+    /// Authenticates with the given credentials. Authentication requests that
+    /// take longer than `auth_timeout` fail with [`Error::AuthTimeout`].
+    pub async fn new<U, S>(
+        api_url: U,
+        api_key: S,
+        api_secret: S,
+        auth_timeout: Duration,
+    ) -> Result<Self>
     where
         U: IntoUrl,
         S: ToString,
@@ -33,7 +43,8 @@ impl OzApi {
         let api_key = api_key.to_string();
         let api_secret = api_secret.to_string();
 
-        let expiring_headers = ExpiringHeaders::refresh(&api_key, &api_secret).await?;
+        let expiring_headers =
+            ExpiringHeaders::refresh(&api_key, &api_secret, auth_timeout).await?;
         let expiring_headers = Mutex::new(expiring_headers);
 
         Ok(Self {
@@ -42,10 +53,19 @@ impl OzApi {
             api_url: api_url.into_url()?,
             api_key,
             api_secret,
+            auth_timeout,
+            clock_skew: Duration::ZERO,
             auth_disabled: false,
         })
     }
 
+    /// Refreshes the auth headers this long before they expire, to tolerate
+    /// our clock being behind the one of the auth server
+    pub fn with_clock_skew(mut self, clock_skew: Duration) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
     pub fn without_auth<U>(api_url: U) -> Result<Self>
     where
         U: IntoUrl,
@@ -62,6 +82,8 @@ impl OzApi {
             api_url: api_url.into_url()?,
             api_key,
             api_secret,
+            auth_timeout: Duration::ZERO,
+            clock_skew: Duration::ZERO,
             auth_disabled: true,
         })
     }
@@ -119,6 +141,33 @@ impl OzApi {
         Self::json_or_error(res).await
     }
 
+    /// Replaces a pending transaction, keeping its id and nonce, e.g. to
+    /// reprice it
+    pub async fn replace_transaction(
+        &self,
+        tx_id: &str,
+        tx: SendBaseTransactionRequest<'_>,
+    ) -> Result<RelayerTransactionBase> {
+        let url = self.txs_url()?.join("txs/")?.join(tx_id)?;
+
+        let headers = self.headers().await?;
+
+        let res = headers.apply(self.client.put(url)).json(&tx).send().await?;
+
+        Self::json_or_error(res).await
+    }
+
+    /// The relayer's nonce and pending transactions
+    pub async fn relayer_status(&self) -> Result<RelayerStatus> {
+        let url = self.api_url.join("relayer/status")?;
+
+        let headers = self.headers().await?;
+
+        let res = headers.apply(self.client.get(url)).send().await?;
+
+        Self::json_or_error(res).await
+    }
+
     fn txs_url(&self) -> Result<Url> {
         Ok(self.api_url.join("txs")?)
     }
@@ -132,8 +181,10 @@ impl OzApi {
 
         let mut expiring_headers = self.expiring_headers.lock().await;
 
-        if expiring_headers.expiration_time < now {
-            let new_headers = ExpiringHeaders::refresh(&self.api_key, &self.api_secret).await?;
+        if expiring_headers.expires_within(now, self.clock_skew) {
+            let new_headers =
+                ExpiringHeaders::refresh(&self.api_key, &self.api_secret, self.auth_timeout)
+                    .await?;
 
             *expiring_headers = new_headers;
         }
@@ -151,7 +202,7 @@ impl OzApi {
             let error_text = res.text().await?;
             info!(?error_text, "response error");
 
-            Err(Error::InvalidResponse(status_code))
+            Err(Error::InvalidResponse(status_code, error_text))
         } else {
             Ok(res.json().await?)
         }
@@ -0,0 +1,3 @@
+ALTER TABLE unprocessed_identities ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_unprocessed_identities_priority ON unprocessed_identities(priority DESC, created_at ASC);
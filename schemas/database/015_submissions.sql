@@ -0,0 +1,15 @@
+-- Tracks the submission of each batch, so that a restart in the middle of a
+-- submission can be recovered from
+CREATE TABLE submissions
+(
+    batch_next_root BYTEA        NOT NULL UNIQUE PRIMARY KEY,
+    status          VARCHAR(50)  NOT NULL,
+    transaction_id  VARCHAR(256),
+    created_at      TIMESTAMPTZ  NOT NULL,
+    updated_at      TIMESTAMPTZ  NOT NULL,
+
+    FOREIGN KEY (batch_next_root) REFERENCES batches (next_root) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_submissions_status ON submissions (status);
+CREATE INDEX idx_submissions_transaction_id ON submissions (transaction_id);
@@ -0,0 +1 @@
+ALTER TABLE submissions ADD COLUMN attempts INTEGER NOT NULL DEFAULT 1;
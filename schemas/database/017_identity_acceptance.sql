@@ -0,0 +1,3 @@
+-- When each identity was accepted by the insert endpoint, which insertion
+-- timeouts are measured from. Unknown for identities inserted before.
+ALTER TABLE identities ADD COLUMN accepted_at TIMESTAMPTZ;
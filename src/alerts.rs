@@ -0,0 +1,260 @@
+//! Notifications about conditions that need an operator's attention.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tracing::{debug, error, warn};
+
+use crate::config::{default, AlertSinkConfig, AlertsConfig};
+use crate::utils::secret::{SecretString, SecretUrl};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+const PAGER_DUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// Identifies the condition, alerts with the same name are deduplicated
+    pub name:    &'static str,
+    pub summary: String,
+    pub details: Value,
+}
+
+impl Alert {
+    pub fn new(name: &'static str, summary: impl Into<String>, details: Value) -> Self {
+        Self {
+            name,
+            summary: summary.into(),
+            details,
+        }
+    }
+}
+
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()>;
+}
+
+/// Posts the alert as JSON
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url:    SecretUrl,
+}
+
+#[async_trait]
+impl AlertSink for WebhookSink {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+        let body = json!({
+            "name": alert.name,
+            "summary": alert.summary,
+            "details": alert.details,
+        });
+
+        post(&self.client, self.url.expose(), &body).await
+    }
+}
+
+/// Posts the alert to a Slack incoming webhook
+pub struct SlackSink {
+    client:      reqwest::Client,
+    webhook_url: SecretUrl,
+}
+
+#[async_trait]
+impl AlertSink for SlackSink {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+        let body = json!({
+            "text": format!("*{}*: {}\n```{:#}```", alert.name, alert.summary, alert.details),
+        });
+
+        post(&self.client, self.webhook_url.expose(), &body).await
+    }
+}
+
+/// Triggers an incident through the PagerDuty Events API v2
+pub struct PagerDutySink {
+    client:      reqwest::Client,
+    routing_key: SecretString,
+    source:      String,
+}
+
+#[async_trait]
+impl AlertSink for PagerDutySink {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+        let body = json!({
+            "routing_key": self.routing_key.expose(),
+            "event_action": "trigger",
+            "dedup_key": format!("{}:{}", self.source, alert.name),
+            "payload": {
+                "summary": alert.summary,
+                "source": self.source,
+                "severity": "critical",
+                "custom_details": alert.details,
+            },
+        });
+
+        post(&self.client, PAGER_DUTY_EVENTS_URL, &body).await
+    }
+}
+
+async fn post(client: &reqwest::Client, url: &str, body: &Value) -> anyhow::Result<()> {
+    client
+        .post(url)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+/// Fans critical conditions out to the configured sink. Alerts are logged and
+/// sent at most once per deduplication window, so that conditions checked on
+/// every tick don't flood the log.
+pub struct Alerter {
+    sink:         Option<Box<dyn AlertSink>>,
+    dedup_window: Duration,
+    last_sent:    Mutex<HashMap<&'static str, Instant>>,
+}
+
+impl Alerter {
+    pub fn new(config: Option<&AlertsConfig>, service_name: &str) -> anyhow::Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::with_sink(None, default::alert_dedup_window()));
+        };
+
+        let client = reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?;
+
+        let sink: Box<dyn AlertSink> = match &config.sink {
+            AlertSinkConfig::Webhook { url } => Box::new(WebhookSink {
+                client,
+                url: url.clone(),
+            }),
+            AlertSinkConfig::Slack { webhook_url } => Box::new(SlackSink {
+                client,
+                webhook_url: webhook_url.clone(),
+            }),
+            AlertSinkConfig::PagerDuty { routing_key } => Box::new(PagerDutySink {
+                client,
+                routing_key: routing_key.clone(),
+                source: service_name.to_string(),
+            }),
+        };
+
+        Ok(Self::with_sink(Some(sink), config.dedup_window))
+    }
+
+    pub fn with_sink(sink: Option<Box<dyn AlertSink>>, dedup_window: Duration) -> Self {
+        Self {
+            sink,
+            dedup_window,
+            last_sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reports a critical condition. Failures to deliver the alert are logged
+    /// and otherwise ignored.
+    pub async fn critical(&self, alert: Alert) {
+        if !self.should_send(alert.name) {
+            debug!(
+                alert = alert.name,
+                "Alert was raised recently, not repeating it"
+            );
+            return;
+        }
+
+        error!(alert = alert.name, details = %alert.details, "{}", alert.summary);
+
+        let Some(sink) = &self.sink else {
+            return;
+        };
+
+        if let Err(error) = sink.send(&alert).await {
+            warn!(?error, alert = alert.name, "Failed to send alert");
+        }
+    }
+
+    fn should_send(&self, name: &'static str) -> bool {
+        let mut last_sent = self.last_sent.lock().unwrap();
+        let now = Instant::now();
+
+        if last_sent
+            .get(name)
+            .is_some_and(|sent_at| now.duration_since(*sent_at) < self.dedup_window)
+        {
+            return false;
+        }
+
+        last_sent.insert(name, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingSink(Arc<Mutex<Vec<&'static str>>>);
+
+    #[async_trait]
+    impl AlertSink for RecordingSink {
+        async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(alert.name);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_alerts_are_deduplicated() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let alerter = Alerter::with_sink(
+            Some(Box::new(RecordingSink(sent.clone()))),
+            Duration::from_secs(60),
+        );
+
+        alerter
+            .critical(Alert::new("tree_full", "", Value::Null))
+            .await;
+        alerter
+            .critical(Alert::new("tree_full", "", Value::Null))
+            .await;
+        alerter
+            .critical(Alert::new("insufficient_balance", "", Value::Null))
+            .await;
+
+        assert_eq!(*sent.lock().unwrap(), vec![
+            "tree_full",
+            "insufficient_balance"
+        ]);
+    }
+
+    #[tokio::test]
+    async fn alerts_are_sent_again_after_the_window() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let alerter =
+            Alerter::with_sink(Some(Box::new(RecordingSink(sent.clone()))), Duration::ZERO);
+
+        alerter
+            .critical(Alert::new("tree_full", "", Value::Null))
+            .await;
+        alerter
+            .critical(Alert::new("tree_full", "", Value::Null))
+            .await;
+
+        assert_eq!(*sent.lock().unwrap(), vec!["tree_full", "tree_full"]);
+    }
+
+    #[test]
+    fn logged_alerts_are_deduplicated_without_a_sink() {
+        let alerter = Alerter::new(None, "signup-sequencer").unwrap();
+
+        assert!(alerter.should_send("insufficient_balance"));
+        assert!(!alerter.should_send("insufficient_balance"));
+        assert!(alerter.should_send("tree_full"));
+    }
+}
@@ -2,37 +2,77 @@ use std::collections::HashSet;
 use std::sync::{Arc, OnceLock};
 
 use chrono::{Duration, Utc};
+use ethers::types::Address;
 use ruint::Uint;
 use semaphore::protocol::verify_proof;
+use serde_json::json;
 use sqlx::{Postgres, Transaction};
+use tokio::sync::broadcast;
 use tracing::{info, instrument, warn};
 
+use crate::alerts::{Alert, Alerter};
 use crate::config::Config;
 use crate::contracts::IdentityManager;
-use crate::database::query::DatabaseQuery as _;
+use crate::database::query::{DatabaseQuery as _, DEFAULT_PRIORITY};
+use crate::database::types::QueuedIdentity;
 use crate::database::Database;
 use crate::ethereum::Ethereum;
+use crate::health::{Health, Reconciliation, ReconciliationStatus};
+use crate::identity::dedup::InsertDedup;
+use crate::identity::filter::CommitmentFilter;
 use crate::identity::validator::IdentityValidator;
 use crate::identity_tree::initializer::TreeInitializer;
+use crate::identity_tree::proof_cache::ProofCache;
+use crate::identity_tree::recent_roots::{RecentRoot, RecentRoots};
 use crate::identity_tree::{
-    Hash, InclusionProof, ProcessedStatus, RootItem, TreeState, TreeVersionReadOps,
+    Hash, InclusionProof, LeafConflict, LeafUpdate, ProcessedStatus, RootItem, TreeState,
+    TreeVersionReadOps, UnprocessedStatus,
 };
 use crate::prover::map::initialize_prover_maps;
 use crate::prover::{ProverConfig, ProverType};
+use crate::server::acknowledgment::AcknowledgmentSigner;
 use crate::server::data::{
-    InclusionProofResponse, ListBatchSizesResponse, VerifySemaphoreProofQuery,
+    InclusionProofResponse, ListBatchSizesResponse, OptimisticProof, VerifySemaphoreProofQuery,
     VerifySemaphoreProofRequest, VerifySemaphoreProofResponse,
 };
 use crate::server::error::Error as ServerError;
+use crate::server::fair_queue::FairQueue;
 use crate::utils::retry_tx;
 
+/// How many leaf updates a subscriber can fall behind before it's dropped
+const LEAF_UPDATES_CAPACITY: usize = 4096;
+
+/// How many times an optimistic proof is projected again while a batch is being
+/// inserted into the tree, before giving up on it
+const OPTIMISTIC_PROOF_ATTEMPTS: usize = 3;
+
+const OPTIMISTIC_PROOF_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How many blocks below the head pruned nodes still serve calls at (geth's
+/// default). Reconciliations that would need to read deeper are skipped,
+/// unless `app.reconcile_confirmations` is configured deeper.
+const PRUNED_STATE_BLOCKS: u64 = 128;
+
 pub struct App {
-    pub database:         Arc<Database>,
-    pub identity_manager: Arc<IdentityManager>,
-    tree_state:           OnceLock<TreeState>,
-    pub config:           Config,
+    pub database:              Arc<Database>,
+    pub identity_manager:      Arc<IdentityManager>,
+    tree_state:                OnceLock<TreeState>,
+    pub config:                Config,
+    pub health:                Health,
+    pub alerts:                Alerter,
+    /// Every insertion as it's mined on mainnet, in insertion order
+    pub leaf_updates:          broadcast::Sender<LeafUpdate>,
+    /// Inclusion proofs of the mined tree
+    pub proof_cache:           ProofCache,
+    /// The last roots mined on mainnet
+    pub recent_roots:          RecentRoots,
+    pub acknowledgment_signer: Option<AcknowledgmentSigner>,
+    /// Bounds the insertions handled at once, if configured
+    pub insert_queue:          Option<Arc<FairQueue>>,
 
     pub identity_validator: IdentityValidator,
+    insert_dedup:           InsertDedup,
+    commitment_filter:      CommitmentFilter,
 }
 
 impl App {
@@ -72,28 +112,128 @@ impl App {
         );
 
         let identity_validator = Default::default();
+        let health = Health::new(&config.app);
+        let insert_dedup = InsertDedup::new(config.app.insert_dedup_window);
+        let commitment_filter = CommitmentFilter::new(
+            config.app.commitment_allowlist.clone(),
+            config.app.commitment_denylist.clone(),
+        )?;
+        let alerts = Alerter::new(config.service.alerts.as_ref(), &config.service.service_name)?;
+
+        log_startup_summary(
+            &config,
+            &ethereum,
+            identity_manager.abi().address(),
+            &commitment_filter,
+        );
+
+        let (leaf_updates, _) = broadcast::channel(LEAF_UPDATES_CAPACITY);
+        let proof_cache = ProofCache::new(config.tree.proof_cache_size);
+        let recent_roots = RecentRoots::new(config.tree.recent_roots);
+        let acknowledgment_signer = config
+            .server
+            .acknowledgment_signing_key
+            .as_ref()
+            .map(|signing_key| AcknowledgmentSigner::new(signing_key.expose()))
+            .transpose()?;
+        anyhow::ensure!(
+            !(config.server.optimistic_proofs && config.server.await_inclusion),
+            "server.optimistic_proofs can't be combined with server.await_inclusion"
+        );
+        let insert_queue = config.server.max_concurrent_inserts.map(|max| {
+            Arc::new(FairQueue::new(
+                max.get(),
+                config.server.max_queued_inserts_per_client,
+            ))
+        });
+
+        if let Some(paused) = identity_manager.is_contract_paused().await? {
+            health.set_contract_paused(paused);
+        }
 
         let app = Arc::new(Self {
             database,
             identity_manager,
             tree_state: OnceLock::new(),
             config,
+            health,
+            alerts,
+            leaf_updates,
+            proof_cache,
+            recent_roots,
+            acknowledgment_signer,
+            insert_queue,
             identity_validator,
+            insert_dedup,
+            commitment_filter,
         });
 
         Ok(app)
     }
 
+    /// Reloads the commitment allowlist and denylist whenever the process
+    /// receives SIGHUP. Does nothing if neither list is configured.
+    pub fn watch_commitment_filter_reloads(self: &Arc<Self>) -> anyhow::Result<()> {
+        if !self.commitment_filter.is_enabled() {
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut sighup = signal(SignalKind::hangup())?;
+            let app = self.clone();
+
+            tokio::spawn(async move {
+                while sighup.recv().await.is_some() {
+                    if let Err(error) = app.commitment_filter.reload() {
+                        tracing::error!(?error, "Failed to reload the commitment filter");
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+
     /// Initializes the tree state. This should only ever be called once.
     /// Attempts to call this method more than once will result in a panic.
     pub async fn init_tree(self: Arc<Self>) -> anyhow::Result<()> {
-        let tree_state = TreeInitializer::new(
+        let tree_state = match TreeInitializer::new(
             self.database.clone(),
             self.identity_manager.clone(),
             self.config.tree.clone(),
         )
         .run()
-        .await?;
+        .await
+        {
+            Ok(tree_state) => tree_state,
+            Err(error) => {
+                if let Some(conflict) = error.downcast_ref::<LeafConflict>() {
+                    self.alerts
+                        .critical(Alert::new(
+                            "leaf_conflict",
+                            "A replayed commitment conflicts with the tree, refusing to start",
+                            json!({
+                                "leaf_index": conflict.leaf_index,
+                                "existing": conflict.existing.to_string(),
+                                "element": conflict.element.to_string(),
+                            }),
+                        ))
+                        .await;
+                }
+
+                return Err(error);
+            }
+        };
+
+        let processed_tree = tree_state.processed_tree();
+        self.recent_roots.push(RecentRoot {
+            root:         processed_tree.get_root(),
+            block_number: None,
+            tree_size:    processed_tree.next_leaf(),
+        });
 
         self.tree_state.set(tree_state).map_err(|_| {
             anyhow::anyhow!(
@@ -101,6 +241,15 @@ impl App {
             )
         })?;
 
+        if self.proof_cache.is_enabled() {
+            let app = self.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Ok(tree_state) = app.tree_state() {
+                    app.proof_cache.fill(tree_state.mined_tree());
+                }
+            });
+        }
+
         Ok::<(), anyhow::Error>(())
     }
 
@@ -111,14 +260,187 @@ impl App {
             .ok_or(ServerError::TreeStateUninitialized)?)
     }
 
-    /// Queues an insert into the merkle tree.
+    /// Counts a failed batch submission towards the read-only mode, alerting
+    /// when it's entered.
+    pub async fn record_submission_failure(&self) {
+        if !self.health.record_submission_failure() {
+            return;
+        }
+
+        self.alerts
+            .critical(Alert::new(
+                "read_only",
+                "Insertions are rejected, batch submissions keep failing",
+                json!({ "failures": self.config.app.read_only_after_failures }),
+            ))
+            .await;
+    }
+
+    /// Compares the contract root against the local tree, alerting when the
+    /// contract root is one the local tree has never seen.
+    ///
+    /// The contract root is read `app.reconcile_confirmations` blocks deep, or
+    /// at the last block whose events are applied to the local tree if that's
+    /// deeper, so that the local tree has caught up with the block it's read
+    /// at. Roots mined in between are newer than the contract root, and known
+    /// locally.
+    ///
+    /// `None` if the local tree lags too far behind to read the contract root
+    /// at a block it has caught up with, e.g. while backfilling.
+    pub async fn reconcile_roots(&self) -> anyhow::Result<Option<Reconciliation>> {
+        let Some(applied_block) = self.health.applied_block() else {
+            info!("Skipping reconciliation, no events were applied to the local tree yet");
+            return Ok(None);
+        };
+
+        let head = self.identity_manager.chain_head().await?;
+        let Some(block) =
+            reconciliation_block(head, self.config.app.reconcile_confirmations, applied_block)
+        else {
+            info!(
+                head,
+                applied_block,
+                "Skipping reconciliation, the local tree lags too far behind the chain head"
+            );
+            return Ok(None);
+        };
+
+        let contract_root: Hash = self.identity_manager.root_at(block).await?.into();
+        let mined_root = self.tree_state()?.get_mined_tree().get_root();
+
+        let status = if contract_root == mined_root {
+            ReconciliationStatus::InSync
+        } else if self
+            .database
+            .get_root_state(&contract_root)
+            .await?
+            .is_some()
+        {
+            ReconciliationStatus::Known
+        } else {
+            ReconciliationStatus::Diverged
+        };
+
+        let reconciliation = Reconciliation {
+            block,
+            contract_root,
+            mined_root,
+            status,
+            reconciled_at: Utc::now(),
+        };
+        info!(?reconciliation, "Reconciled roots");
+
+        if self.health.record_reconciliation(reconciliation.clone()) {
+            self.alerts
+                .critical(Alert::new(
+                    "root_diverged",
+                    "The contract root is unknown to the local tree",
+                    json!({
+                        "block": block,
+                        "contract_root": contract_root.to_string(),
+                        "mined_root": mined_root.to_string(),
+                    }),
+                ))
+                .await;
+        }
+
+        Ok(Some(reconciliation))
+    }
+
+    /// Reads whether the identity manager contract is paused, which would
+    /// make every insertion revert. Always `false` if it has no pause guard.
+    pub async fn refresh_contract_paused(&self) -> anyhow::Result<bool> {
+        let Some(paused) = self.identity_manager.is_contract_paused().await? else {
+            return Ok(false);
+        };
+
+        if self.health.set_contract_paused(paused) && paused {
+            self.alerts
+                .critical(Alert::new(
+                    "contract_paused",
+                    "The identity manager contract is paused, insertions are rejected",
+                    json!({ "address": format!("{:?}", self.identity_manager.abi().address()) }),
+                ))
+                .await;
+        }
+
+        Ok(paused)
+    }
+
+    /// Tracks the chain head seen by the mainnet scanner. When it stops
+    /// advancing, alerts and switches to the next fallback provider.
+    pub async fn record_chain_head(&self, head: u64) {
+        if !self.health.set_chain_head(head) {
+            return;
+        }
+
+        self.identity_manager.abi().client().switch_provider().await;
+
+        self.alerts
+            .critical(Alert::new(
+                "stale_chain_head",
+                "The Ethereum provider stopped reporting new blocks",
+                json!({
+                    "chain_head": head,
+                    "max_chain_head_age": self
+                        .config
+                        .app
+                        .max_chain_head_age
+                        .map(|age| humantime::format_duration(age).to_string()),
+                }),
+            ))
+            .await;
+    }
+
+    /// Queues an insert into the merkle tree. Returns the status of the
+    /// commitment if it was queued within `app.insert_dedup_window` already,
+    /// in which case it isn't queued again.
     ///
     /// # Errors
     ///
     /// Will return `Err` if identity is already queued, or in the tree, or the
     /// queue malfunctions.
     #[instrument(level = "debug", skip(self))]
-    pub async fn insert_identity(&self, commitment: Hash) -> Result<(), ServerError> {
+    pub async fn insert_identity(
+        &self,
+        commitment: Hash,
+        priority: Option<i16>,
+    ) -> Result<Option<UnprocessedStatus>, ServerError> {
+        if self.health.is_backpressured() {
+            warn!(
+                ?commitment,
+                block_lag = self.health.block_lag(),
+                "Rejecting insertion, event processing is lagging behind the chain head."
+            );
+            return Err(ServerError::Backpressure);
+        }
+
+        if self.health.is_paused() {
+            warn!(?commitment, "Rejecting insertion, insertions are paused.");
+            return Err(ServerError::Paused);
+        }
+
+        if self.health.is_contract_paused() {
+            warn!(
+                ?commitment,
+                "Rejecting insertion, the identity manager contract is paused."
+            );
+            return Err(ServerError::ContractPaused);
+        }
+
+        if self.health.is_read_only() {
+            warn!(
+                ?commitment,
+                "Rejecting insertion, the sequencer is read-only."
+            );
+            return Err(ServerError::ReadOnly);
+        }
+
+        if let Err(error) = self.identity_manager.check_spend_limit() {
+            warn!(?commitment, %error, "Rejecting insertion, the gas spend limit is reached.");
+            return Err(ServerError::SpendLimitExceeded);
+        }
+
         if commitment == self.identity_manager.initial_leaf_value() {
             warn!(?commitment, "Attempt to insert initial leaf.");
             return Err(ServerError::InvalidCommitment);
@@ -141,17 +463,102 @@ impl App {
             return Err(ServerError::UnreducedCommitment);
         }
 
-        // TODO: ensure that the id is not in the tree or in unprocessed identities
-
-        if self.database.identity_exists(commitment).await? {
-            return Err(ServerError::DuplicateCommitment);
+        if !self.commitment_filter.is_allowed(&commitment) {
+            warn!(?commitment, "The provided commitment is blocked.");
+            return Err(ServerError::Blocked);
         }
 
-        self.database
-            .insert_new_identity(commitment, Utc::now())
+        let queued = self
+            .database
+            .insert_new_identity_unless_exists(
+                commitment,
+                Utc::now(),
+                priority.unwrap_or(DEFAULT_PRIORITY),
+            )
             .await?;
 
-        Ok(())
+        match queued {
+            QueuedIdentity::Queued => Ok(None),
+            QueuedIdentity::AlreadyQueued { status, queued_at }
+                if self.insert_dedup.is_retry(queued_at, Utc::now()) =>
+            {
+                info!(?commitment, "Commitment was queued recently, skipping.");
+                Ok(Some(status))
+            }
+            QueuedIdentity::AlreadyQueued { .. } | QueuedIdentity::InTree => {
+                Err(ServerError::DuplicateCommitment)
+            }
+        }
+    }
+
+    /// Projects where a queued `commitment` will be inserted, by appending the
+    /// insertions queued ahead of it to a copy of the latest tree. Nothing is
+    /// stored, so a projection that turns out wrong is never served as a
+    /// confirmed proof: `/inclusionProof` only serves proofs from the tree.
+    ///
+    /// `None` if the commitment isn't queued, e.g. because it was already
+    /// inserted, or the tree kept changing while projecting.
+    pub async fn optimistic_proof(
+        &self,
+        commitment: &Hash,
+    ) -> Result<Option<OptimisticProof>, ServerError> {
+        let latest_tree = self.tree_state()?.latest_tree();
+
+        for _ in 0..OPTIMISTIC_PROOF_ATTEMPTS {
+            // The tree is ahead of the database while a batch is being
+            // inserted, in which case its commitments are both in the tree and
+            // queued
+            let next_leaf = latest_tree.next_leaf();
+            let (db_next_leaf, queued) = self.queue_snapshot(commitment).await?;
+            if db_next_leaf != next_leaf {
+                tokio::time::sleep(OPTIMISTIC_PROOF_RETRY_DELAY).await;
+                continue;
+            }
+
+            if queued.is_empty() {
+                return Ok(None);
+            }
+
+            // Fails if a batch was inserted since, the queue might have lost
+            // some of its commitments
+            let Some((root, proof, leaf_index)) = latest_tree.project_append(next_leaf, &queued)
+            else {
+                continue;
+            };
+
+            // A batch inserted while projecting makes the projection stale
+            if latest_tree.next_leaf() != next_leaf
+                || self.queue_snapshot(commitment).await? != (next_leaf, queued)
+            {
+                continue;
+            }
+
+            return Ok(Some(OptimisticProof {
+                leaf_index,
+                projected_root: root,
+                proof,
+                confirmed: false,
+            }));
+        }
+
+        warn!(
+            ?commitment,
+            "Tree kept changing, skipping the optimistic proof."
+        );
+
+        Ok(None)
+    }
+
+    /// The next leaf index of the database and the commitments queued up to
+    /// `commitment`, read in one transaction so that they agree
+    async fn queue_snapshot(&self, commitment: &Hash) -> Result<(usize, Vec<Hash>), ServerError> {
+        retry_tx!(self.database.pool, tx, {
+            let next_leaf = tx.get_next_leaf_index().await?;
+            let queued = tx.get_queued_commitments_up_to(commitment).await?;
+
+            Ok::<_, ServerError>((next_leaf, queued))
+        })
+        .await
     }
 
     pub async fn delete_identity_tx(&self, commitment: &Hash) -> Result<(), ServerError> {
@@ -256,6 +663,11 @@ impl App {
                 return Err(ServerError::UnreducedCommitment);
             }
 
+            if !self.commitment_filter.is_allowed(new_commitment) {
+                warn!(?new_commitment, "The new identity commitment is blocked.");
+                return Err(ServerError::Blocked);
+            }
+
             if tx.identity_exists(*new_commitment).await? {
                 return Err(ServerError::DuplicateCommitment);
             }
@@ -347,6 +759,9 @@ impl App {
         Ok(ListBatchSizesResponse::from(batches))
     }
 
+    /// With `verify_on_chain` or `app.verify_proofs_on_chain`, only proofs of
+    /// identities mined on mainnet whose root the contract accepts are served.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if the provided index is out of bounds.
@@ -354,6 +769,7 @@ impl App {
     pub async fn inclusion_proof(
         &self,
         commitment: &Hash,
+        verify_on_chain: bool,
     ) -> Result<InclusionProofResponse, ServerError> {
         if commitment == &self.identity_manager.initial_leaf_value() {
             return Err(ServerError::InvalidCommitment);
@@ -378,15 +794,58 @@ impl App {
             .await?
             .ok_or(ServerError::IdentityCommitmentNotFound)?;
 
-        let (leaf, proof) = self.tree_state()?.get_proof_for(&item);
+        let tree_state = self.tree_state()?;
+        let (leaf, proof) = tree_state.get_proof_for(&item, &self.proof_cache);
 
         if leaf != *commitment {
             return Err(ServerError::InvalidCommitment);
         }
 
+        if verify_on_chain || self.config.app.verify_proofs_on_chain {
+            self.verify_proof_on_chain(item.status, leaf, &proof)
+                .await?;
+        }
+
         Ok(InclusionProofResponse(proof))
     }
 
+    /// Checks that the path of `proof` leads from `leaf` to its root, and that
+    /// the mainnet contract accepts that root. The local tree can be ahead of
+    /// the contract, so only proofs of identities mined on mainnet can pass,
+    /// whether or not their root has been bridged yet.
+    async fn verify_proof_on_chain(
+        &self,
+        status: ProcessedStatus,
+        leaf: Hash,
+        proof: &InclusionProof,
+    ) -> Result<(), ServerError> {
+        let (ProcessedStatus::Processed | ProcessedStatus::Mined, Some(root), Some(path)) =
+            (status, proof.root, proof.proof.as_ref())
+        else {
+            return Err(ServerError::InclusionProofUnavailable);
+        };
+
+        if path.root(leaf) != root {
+            warn!(?leaf, ?root, "Inclusion proof doesn't lead to its root");
+            return Err(ServerError::RootMismatch);
+        }
+
+        if !self
+            .identity_manager
+            .is_root_valid_on_chain(root.into())
+            .await?
+        {
+            warn!(
+                ?leaf,
+                ?root,
+                "Inclusion proof root isn't accepted by the contract"
+            );
+            return Err(ServerError::RootMismatch);
+        }
+
+        Ok(())
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if the provided proof is invalid.
@@ -481,3 +940,78 @@ impl App {
         }
     }
 }
+
+/// Logs the settings that decide how the sequencer behaves in one line, so that
+/// a deployment can be checked at a glance. Secrets are left out.
+fn log_startup_summary(
+    config: &Config,
+    ethereum: &Ethereum,
+    identity_manager_address: Address,
+    commitment_filter: &CommitmentFilter,
+) {
+    let secondary_chain_ids: Vec<_> = config
+        .network
+        .relayed_identity_manager_addresses
+        .0
+        .keys()
+        .collect();
+
+    info!(
+        chain_id = %ethereum.provider().chain_id,
+        identity_manager_address = ?identity_manager_address,
+        ?secondary_chain_ids,
+        relayer = config.relayer.kind(),
+        relayer_address = ?ethereum.address(),
+        tree_depth = config.tree.tree_depth,
+        confirmation_strategy = ?config.app.confirmation_strategy,
+        confirmation_depth = config.app.confirmation_depth,
+        check_root_before_submit = config.app.check_root_before_submit,
+        check_tree_capacity = config.app.check_tree_capacity,
+        insert_dedup = config.app.insert_dedup_window.is_some(),
+        commitment_filter = commitment_filter.is_enabled(),
+        submission_budget = ?config.app.submission_budget,
+        insertion_timeout = ?config.app.insertion_timeout,
+        spend_limit_gwei = ?config.app.spend_limit_gwei,
+        alerts = config.service.alerts.is_some(),
+        admin_token = config.server.admin_token.is_some(),
+        "Startup summary"
+    );
+}
+
+/// The block the contract root is read at by a reconciliation with the chain
+/// head at `head`: `min_confirmations` below it, or the last block the local
+/// tree has applied if that's earlier. `None` if that's deeper than pruned
+/// nodes serve calls at, and than `min_confirmations`.
+fn reconciliation_block(head: u64, min_confirmations: u64, applied_block: u64) -> Option<u64> {
+    let block = head.saturating_sub(min_confirmations).min(applied_block);
+
+    (head - block <= min_confirmations.max(PRUNED_STATE_BLOCKS)).then_some(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconciliations_are_pinned_to_the_applied_block() {
+        // Caught up, read at the configured depth
+        assert_eq!(reconciliation_block(1000, 5, 1000), Some(995));
+        assert_eq!(reconciliation_block(1000, 5, 997), Some(995));
+
+        // Lagging behind, e.g. because the head subscription stalled
+        assert_eq!(reconciliation_block(1000, 5, 990), Some(990));
+
+        // Near genesis
+        assert_eq!(reconciliation_block(3, 5, 3), Some(0));
+    }
+
+    #[test]
+    fn reconciliations_beyond_pruned_state_are_skipped() {
+        assert_eq!(reconciliation_block(1000, 5, 872), Some(872));
+        assert_eq!(reconciliation_block(1000, 5, 871), None);
+
+        // Unless configured that deep anyway
+        assert_eq!(reconciliation_block(1000, 200, 800), Some(800));
+        assert_eq!(reconciliation_block(1000, 200, 799), None);
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+use tracing::info;
+
+use crate::app::App;
+use crate::config::Config;
+use crate::identity_tree::{Hash, TreeVersionReadOps};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatchUpReport {
+    pub contract_root:  Hash,
+    pub processed_root: Hash,
+    /// Number of leaves in the processed tree, including deleted ones
+    pub leaves:         usize,
+    /// Number of leaves that are yet to be submitted or mined
+    pub pending_leaves: usize,
+}
+
+impl CatchUpReport {
+    /// Whether the local tree ended up at the contract's latest root
+    pub fn is_synced(&self) -> bool {
+        self.contract_root == self.processed_root
+    }
+}
+
+impl fmt::Display for CatchUpReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Contract root: {:#x}", self.contract_root)?;
+        writeln!(f, "Processed root: {:#x}", self.processed_root)?;
+        writeln!(f, "Leaves: {}", self.leaves)?;
+        write!(f, "Pending leaves: {}", self.pending_leaves)
+    }
+}
+
+/// Brings the local tree up to date with the chain and writes the tree cache
+/// file, without serving requests or submitting batches. Meant for preparing
+/// the cache file of a new deployment ahead of time.
+pub async fn catch_up(config: Config) -> anyhow::Result<CatchUpReport> {
+    info!("Waiting for pending transactions and initializing the tree");
+
+    let app = App::new(config).await?;
+    app.clone().init_tree().await?;
+
+    let tree_state = app.tree_state()?;
+    let contract_root = app.identity_manager.latest_root().await?.into();
+    let processed_tree = tree_state.processed_tree();
+
+    let leaves = processed_tree.next_leaf();
+    let pending_leaves = tree_state.latest_tree().next_leaf() - leaves;
+
+    info!(leaves, pending_leaves, "Tree initialized");
+
+    Ok(CatchUpReport {
+        contract_root,
+        processed_root: processed_tree.get_root(),
+        leaves,
+        pending_leaves,
+    })
+}
@@ -0,0 +1,3 @@
+//! Standalone operator commands that run instead of the sequencer.
+pub mod catch_up;
+pub mod verify_tree;
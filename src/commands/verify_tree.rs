@@ -0,0 +1,541 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+use ethers::abi::{AbiDecode, RawLog};
+use ethers::contract::EthEvent;
+use ethers::providers::Middleware;
+use ethers::types::{Address, Topic, ValueOrArray};
+use tracing::info;
+
+use crate::config::Config;
+use crate::contracts::abi::{RegisterIdentitiesCall, TreeChangeKind, TreeChangedFilter};
+use crate::contracts::getters;
+use crate::contracts::scanner::BlockScanner;
+use crate::database::query::DatabaseQuery as _;
+use crate::database::Database;
+use crate::ethereum::read::log_batcher::LogBatcher;
+use crate::ethereum::ReadProvider;
+use crate::identity_tree::{Hash, TreeUpdate};
+
+/// The first leaf at which the local store and the chain disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub leaf_index: usize,
+    pub local:      Option<Hash>,
+    pub chain:      Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyTreeReport {
+    /// The range of blocks that was scanned for insertions
+    pub from_block:       u64,
+    pub to_block:         u64,
+    /// Number of on-chain leaves that matched the local store
+    pub matching_leaves:  usize,
+    /// Number of leaves in the local store
+    pub local_leaves:     usize,
+    pub first_divergence: Option<Divergence>,
+}
+
+impl fmt::Display for VerifyTreeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scanned blocks {}..={}", self.from_block, self.to_block)?;
+        writeln!(f, "Local leaves: {}", self.local_leaves)?;
+        writeln!(f, "Matching on-chain leaves: {}", self.matching_leaves)?;
+
+        match &self.first_divergence {
+            None => write!(f, "No divergence found"),
+            Some(divergence) => write!(f, "{divergence}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpotCheckReport {
+    pub contract_root:    Hash,
+    /// Whether the contract root is one of the roots in the local store
+    pub root_known:       bool,
+    /// Whether the contract exposes a per-index getter for the leaves
+    pub getter_available: bool,
+    /// Number of sampled leaves that matched the contract
+    pub matching_leaves:  usize,
+    pub first_divergence: Option<Divergence>,
+}
+
+impl SpotCheckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.root_known && self.first_divergence.is_none()
+    }
+}
+
+impl fmt::Display for SpotCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Contract root {:#x} known locally: {}",
+            self.contract_root, self.root_known
+        )?;
+
+        if !self.getter_available {
+            return write!(f, "Contract has no leaf getter, only the root was checked");
+        }
+
+        writeln!(f, "Matching sampled leaves: {}", self.matching_leaves)?;
+        match &self.first_divergence {
+            None => write!(f, "No divergence found"),
+            Some(divergence) => write!(f, "{divergence}"),
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let local = self
+            .local
+            .map_or_else(|| "<missing>".to_string(), |local| format!("{local:#x}"));
+
+        write!(
+            f,
+            "First divergence at leaf {}: local {local}, chain {:#x}",
+            self.leaf_index, self.chain
+        )
+    }
+}
+
+/// Reads the contract root and a sample of `sample_size` leaves through the
+/// contract getters and compares them against the local store, without
+/// replaying any events.
+///
+/// Falls back to only checking the root if the contract doesn't expose a leaf
+/// getter. Like [`verify_tree`] this never modifies any state.
+pub async fn spot_check_tree(
+    config: &Config,
+    sample_size: usize,
+) -> anyhow::Result<SpotCheckReport> {
+    let mut database_config = config.database.clone();
+    database_config.migrate = false;
+
+    let database = Database::new(&database_config).await?;
+
+    let read_provider = ReadProvider::new(
+        config.providers.primary_network_provider.clone().into(),
+        &config.providers,
+    )
+    .await?;
+
+    let address = config
+        .network
+        .resolve_identity_manager_address(read_provider.chain_id.as_u64())?;
+    let client = Arc::new(read_provider);
+
+    let contract_root: Hash = getters::contract_root(client.clone(), address)
+        .await?
+        .into();
+    let root_known = database.get_root_state(&contract_root).await?.is_some();
+
+    let local_leaves = database.get_inserted_commitments().await?;
+
+    spot_check_leaves(
+        client,
+        address,
+        contract_root,
+        root_known,
+        &local_leaves,
+        sample_size,
+    )
+    .await
+}
+
+async fn spot_check_leaves<M>(
+    client: Arc<M>,
+    address: Address,
+    contract_root: Hash,
+    root_known: bool,
+    local_leaves: &[TreeUpdate],
+    sample_size: usize,
+) -> anyhow::Result<SpotCheckReport>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let step = (local_leaves.len() / sample_size.max(1)).max(1);
+
+    let mut report = SpotCheckReport {
+        contract_root,
+        root_known,
+        getter_available: true,
+        matching_leaves: 0,
+        first_divergence: None,
+    };
+
+    for leaf in local_leaves.iter().step_by(step).take(sample_size) {
+        let Some(chain) = getters::contract_leaf(client.clone(), address, leaf.leaf_index).await?
+        else {
+            report.getter_available = false;
+            break;
+        };
+
+        let chain: Hash = chain.into();
+
+        // Not yet inserted on chain
+        if chain == Hash::ZERO {
+            continue;
+        }
+
+        if chain != leaf.element {
+            report.first_divergence = Some(Divergence {
+                leaf_index: leaf.leaf_index,
+                local: Some(leaf.element),
+                chain,
+            });
+            break;
+        }
+
+        report.matching_leaves += 1;
+    }
+
+    Ok(report)
+}
+
+/// Compares the commitments inserted on chain against the local store and
+/// reports the first divergence.
+///
+/// This is read-only: migrations are never run and nothing is written to the
+/// database or sent to the chain.
+pub async fn verify_tree(
+    config: &Config,
+    from_block: u64,
+    window_size: u64,
+) -> anyhow::Result<VerifyTreeReport> {
+    let mut database_config = config.database.clone();
+    database_config.migrate = false;
+
+    let database = Database::new(&database_config).await?;
+
+    let read_provider = ReadProvider::new(
+        config.providers.primary_network_provider.clone().into(),
+        &config.providers,
+    )
+    .await?;
+
+    let local_leaves: BTreeMap<usize, Hash> = database
+        .get_inserted_commitments()
+        .await?
+        .into_iter()
+        .map(|update| (update.leaf_index, update.element))
+        .collect();
+
+    let to_block = read_provider.get_block_number().await?.as_u64();
+
+    let mut scanner = BlockScanner::new(read_provider.clone(), from_block, window_size);
+    if let Some(batch_size) = config.providers.log_batch_size {
+        scanner = scanner.with_batching(LogBatcher::new(
+            config.providers.primary_network_provider.clone().into(),
+            batch_size.get(),
+            config.providers.request_timeout,
+        )?);
+    }
+
+    let address = config
+        .network
+        .resolve_identity_manager_address(read_provider.chain_id.as_u64())?;
+
+    let report = compare_insertions(
+        &read_provider,
+        &mut scanner,
+        address,
+        &local_leaves,
+        to_block,
+    )
+    .await;
+    scanner.log_batching_summary();
+
+    report
+}
+
+/// Scans the insertions up to `to_block`, or the chain head as far as the
+/// scanner considers it, whichever comes first
+async fn compare_insertions<M>(
+    client: &M,
+    scanner: &mut BlockScanner<M>,
+    address: Address,
+    local_leaves: &BTreeMap<usize, Hash>,
+    to_block: u64,
+) -> anyhow::Result<VerifyTreeReport>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    let from_block = scanner.current_block();
+    let address = Some(ValueOrArray::Value(address));
+    let topics = [
+        Some(Topic::from(TreeChangedFilter::signature())),
+        None,
+        None,
+        None,
+    ];
+
+    let mut report = VerifyTreeReport {
+        from_block,
+        to_block: from_block.saturating_sub(1),
+        matching_leaves: 0,
+        local_leaves: local_leaves.len(),
+        first_divergence: None,
+    };
+
+    while scanner.current_block() <= to_block {
+        let scanned_from = scanner.current_block();
+        let logs = scanner.next(address.clone(), topics.clone()).await?;
+
+        // The scanner doesn't move past the head it's confirmed, which may lag
+        // behind `to_block`
+        if scanner.current_block() == scanned_from {
+            info!(
+                block = scanned_from,
+                to_block, "Reached the chain head before the last block"
+            );
+            break;
+        }
+        report.to_block = scanner.current_block() - 1;
+
+        for log in logs {
+            let raw_log = RawLog::from((log.topics.clone(), log.data.to_vec()));
+            let event = TreeChangedFilter::decode_any_layout(&raw_log)?;
+
+            if TreeChangeKind::from(event.kind) != TreeChangeKind::Insertion {
+                continue;
+            }
+
+            let tx_hash = log.transaction_hash.context("Missing tx hash")?;
+            let tx = client
+                .get_transaction(tx_hash)
+                .await?
+                .context("Missing tx")?;
+
+            let insertion = RegisterIdentitiesCall::decode(&tx.input)
+                .with_context(|| format!("Failed to decode insertion tx {tx_hash:?}"))?;
+
+            info!(
+                ?tx_hash,
+                start_index = insertion.start_index,
+                "Verifying insertion batch"
+            );
+
+            let start_index = insertion.start_index as usize;
+            for (offset, commitment) in insertion.identity_commitments.into_iter().enumerate() {
+                let chain: Hash = commitment.into();
+
+                // Batches are padded with zeros, which are overwritten by the
+                // next batch
+                if chain == Hash::ZERO {
+                    continue;
+                }
+
+                let leaf_index = start_index + offset;
+                let local = local_leaves.get(&leaf_index).copied();
+
+                if local != Some(chain) {
+                    report.first_divergence = Some(Divergence {
+                        leaf_index,
+                        local,
+                        chain,
+                    });
+
+                    return Ok(report);
+                }
+
+                report.matching_leaves += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::AbiEncode;
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{Bytes, Log, Transaction, H256, U256, U64};
+
+    use super::*;
+
+    fn leaves(elements: &[u64]) -> BTreeMap<usize, Hash> {
+        elements
+            .iter()
+            .enumerate()
+            .map(|(leaf_index, &element)| (leaf_index, Hash::from(element)))
+            .collect()
+    }
+
+    fn word(value: u64) -> Bytes {
+        Bytes::from(U256::from(value).encode())
+    }
+
+    fn insertion_log(tx_hash: H256) -> Log {
+        Log {
+            topics: vec![
+                TreeChangedFilter::signature(),
+                H256::from_low_u64_be(1),
+                H256::from_low_u64_be(0),
+                H256::from_low_u64_be(2),
+            ],
+            transaction_hash: Some(tx_hash),
+            ..Default::default()
+        }
+    }
+
+    fn insertion_tx(start_index: u32, commitments: &[u64]) -> Transaction {
+        let call = RegisterIdentitiesCall {
+            insertion_proof: [U256::zero(); 8],
+            pre_root: U256::from(1),
+            start_index,
+            identity_commitments: commitments.iter().copied().map(U256::from).collect(),
+            post_root: U256::from(2),
+        };
+
+        Transaction {
+            input: call.encode().into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_stops_at_the_chain_head() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = BlockScanner::new(provider.clone(), 10, 100);
+
+        // Responses are popped in reverse order. The head stays behind the last
+        // block, so the second scan makes no progress.
+        mock.push(U64::from(15)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(U64::from(15)).unwrap();
+
+        let report =
+            compare_insertions(&provider, &mut scanner, Address::zero(), &leaves(&[1]), 20)
+                .await
+                .unwrap();
+
+        assert_eq!(report.from_block, 10);
+        assert_eq!(report.to_block, 15);
+        assert_eq!(report.matching_leaves, 0);
+        assert_eq!(report.first_divergence, None);
+    }
+
+    #[tokio::test]
+    async fn first_divergent_insertion_is_reported() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = BlockScanner::new(provider.clone(), 10, 100);
+
+        let tx_hash = H256::from_low_u64_be(7);
+
+        // Responses are popped in reverse order
+        mock.push(insertion_tx(0, &[1, 5, 3, 0])).unwrap();
+        mock.push(vec![insertion_log(tx_hash)]).unwrap();
+        mock.push(U64::from(20)).unwrap();
+
+        let report = compare_insertions(
+            &provider,
+            &mut scanner,
+            Address::zero(),
+            &leaves(&[1, 2, 3]),
+            20,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.matching_leaves, 1);
+        assert_eq!(
+            report.first_divergence,
+            Some(Divergence {
+                leaf_index: 1,
+                local:      Some(Hash::from(2)),
+                chain:      Hash::from(5),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn spot_check_compares_sampled_leaves() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let provider = Arc::new(provider);
+
+        let local_leaves: Vec<_> = [1u64, 2, 3, 4]
+            .into_iter()
+            .enumerate()
+            .map(|(leaf_index, element)| TreeUpdate::new(leaf_index, Hash::from(element)))
+            .collect();
+
+        // Every other leaf is sampled. Responses are popped in reverse order.
+        mock.push(word(3)).unwrap();
+        mock.push(word(1)).unwrap();
+
+        let report = spot_check_leaves(
+            provider.clone(),
+            Address::zero(),
+            Hash::from(9),
+            true,
+            &local_leaves,
+            2,
+        )
+        .await
+        .unwrap();
+        assert!(report.getter_available);
+        assert_eq!(report.matching_leaves, 2);
+        assert_eq!(report.first_divergence, None);
+
+        // Leaves that aren't inserted on chain yet are skipped
+        mock.push(word(7)).unwrap();
+        mock.push(word(0)).unwrap();
+
+        let report = spot_check_leaves(
+            provider,
+            Address::zero(),
+            Hash::from(9),
+            true,
+            &local_leaves,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.matching_leaves, 0);
+        assert_eq!(
+            report.first_divergence,
+            Some(Divergence {
+                leaf_index: 2,
+                local:      Some(Hash::from(3)),
+                chain:      Hash::from(7),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn spot_check_without_leaf_getter_only_checks_the_root() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+
+        let local_leaves = vec![TreeUpdate::new(0, Hash::from(1))];
+
+        // Answered by a fallback function, then the code is fetched. Responses
+        // are popped in reverse order.
+        mock.push(Bytes::from(vec![0x60, 0x80])).unwrap();
+        mock.push(Bytes::default()).unwrap();
+
+        let report = spot_check_leaves(
+            Arc::new(provider),
+            Address::zero(),
+            Hash::from(9),
+            false,
+            &local_leaves,
+            10,
+        )
+        .await
+        .unwrap();
+
+        assert!(!report.getter_available);
+        assert!(!report.root_known);
+        assert_eq!(report.matching_leaves, 0);
+        assert!(!report.is_consistent());
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use ethers::types::{Address, H160};
@@ -7,7 +9,7 @@ use semaphore::Field;
 use serde::{Deserialize, Serialize};
 
 use crate::prover::ProverConfig;
-use crate::utils::secret::SecretUrl;
+use crate::utils::secret::{SecretString, SecretUrl};
 use crate::utils::serde_utils::JsonStrWrapper;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,21 +67,288 @@ pub struct AppConfig {
     #[serde(default = "default::scanning_chain_head_offset")]
     pub scanning_chain_head_offset: u64,
 
+    /// How many already scanned blocks each scan fetches again, so that
+    /// events a load balanced provider missed at a window boundary aren't
+    /// lost. Events returned before are dropped.
+    #[serde(default = "default::scanning_window_overlap")]
+    pub scanning_window_overlap: u64,
+
     /// The number of seconds to wait between fetching logs
     #[serde(with = "humantime_serde")]
     #[serde(default = "default::time_between_scans")]
     pub time_between_scans: Duration,
 
+    /// How long the new block subscription of `providers.ws_network_provider`
+    /// may stay silent while the chain advances, before scans fall back to
+    /// polling. Scans switch back as soon as it delivers blocks again.
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::subscription_stall_timeout")]
+    pub subscription_stall_timeout: Duration,
+
+    /// If set, at most this many mined tree changes are applied per scan. The
+    /// rest are applied by the following scans, which then start right away,
+    /// so that a large backfill doesn't hold up the other tasks.
+    #[serde(default)]
+    pub max_events_per_scan: Option<NonZeroUsize>,
+
     /// The number of txs in the channel that we'll be monitoring
     #[serde(default = "default::monitored_txs_capacity")]
     pub monitored_txs_capacity: usize,
+
+    /// If set, the contract's latest root is read right before submitting a
+    /// batch and the submission is aborted if it doesn't match the batch's
+    /// pre-root. Useful when multiple sequencers might be active, at the cost
+    /// of an additional RPC call per batch.
+    #[serde(default = "default::check_root_before_submit")]
+    pub check_root_before_submit: bool,
+
+    /// The maximum time an identity can wait in the queue of unprocessed
+    /// identities after becoming eligible. Older identities are marked as
+    /// failed with an `Expired` message, which clients can see through the
+    /// inclusion proof endpoint.
+    ///
+    /// Identities are never expired if this is not set.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub max_queue_age: Option<Duration>,
+
+    /// If set, new insertions are rejected with 503 while the processing of
+    /// chain events is more than this many blocks behind the chain head
+    #[serde(default)]
+    pub max_block_lag: Option<u64>,
+
+    /// If set, the sequencer becomes read-only after this many consecutive
+    /// batch submissions failed: proofs are still served, but new insertions
+    /// are rejected with 503. Queued batches keep being retried and the first
+    /// one to be mined lifts the read-only mode.
+    #[serde(default)]
+    pub read_only_after_failures: Option<u32>,
+
+    /// If set, the chain head is considered stale when the provider hasn't
+    /// reported a new block for this long, e.g. because the node is stuck
+    /// syncing. This raises a critical alert, is reported by the health
+    /// endpoint and switches to the next fallback provider, if any.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub max_chain_head_age: Option<Duration>,
+
+    /// How often the contract root is compared against the local tree. A
+    /// contract root the local tree has never seen raises a critical alert.
+    /// Each round is a single call, `0s` disables the periodic rounds but
+    /// `POST /reconcile` still runs one on demand.
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::reconcile_interval")]
+    pub reconcile_interval: Duration,
+
+    /// How many blocks below the chain head the contract root is read at for
+    /// reconciliations, so that batches mined since don't look like a
+    /// divergence. The provider has to serve `eth_call` at that block, which
+    /// pruned nodes only do for recent blocks (128 on geth). It's read no
+    /// later than the last block whose events are applied to the local tree,
+    /// up to 128 blocks deep or this many if more. Reconciliations are skipped
+    /// while the local tree lags any further.
+    #[serde(default = "default::reconcile_confirmations")]
+    pub reconcile_confirmations: u64,
+
+    /// If set, every inclusion proof is checked against the contract before
+    /// it's served, as if requested with `verifyOnChain`. Costs a call every
+    /// couple of seconds, and one per proof whose root isn't the latest.
+    #[serde(default = "default::verify_proofs_on_chain")]
+    pub verify_proofs_on_chain: bool,
+
+    /// If set, inserting a commitment that was queued less than this long ago
+    /// succeeds with its status, without queuing it again, instead of failing
+    /// with a conflict. Makes client retries idempotent.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub insert_dedup_window: Option<Duration>,
+
+    /// If set, only the commitments listed in this file, one per line, can be
+    /// inserted. Reloaded on SIGHUP.
+    #[serde(default)]
+    pub commitment_allowlist: Option<PathBuf>,
+
+    /// If set, the commitments listed in this file, one per line, can't be
+    /// inserted. Reloaded on SIGHUP.
+    #[serde(default)]
+    pub commitment_denylist: Option<PathBuf>,
+
+    /// If set, an incomplete insertion batch is submitted once this much time
+    /// has passed since its first identity was picked up, instead of waiting
+    /// for the `batch_insertion_timeout` tick.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub batch_window: Option<Duration>,
+
+    /// Caps the size of insertion batches below the largest batch size
+    /// supported by the insertion provers. Smaller batches get submitted
+    /// sooner but cost more gas per identity. Must be positive if set.
+    #[serde(default)]
+    pub batch_size: Option<NonZeroUsize>,
+
+    /// The largest calldata, in bytes, that will be submitted to the relayer.
+    /// Larger transactions are rejected before submission, since nodes won't
+    /// accept them anyway (geth rejects transactions above 128 KiB).
+    #[serde(default = "default::max_calldata_size")]
+    pub max_calldata_size: usize,
+
+    /// If set, batches are only submitted while the relayer account holds at
+    /// least this many gwei. Should cover the cost of a full batch plus a
+    /// safety margin. Submission resumes once the account is topped up.
+    #[serde(default)]
+    pub min_relayer_balance_gwei: Option<u64>,
+
+    /// When a submitted batch counts as confirmed. Applies to waiting for
+    /// transactions and to finalizing roots from chain events.
+    #[serde(default)]
+    pub confirmation_strategy: ConfirmationStrategy,
+
+    /// The number of blocks a transaction has to be below the chain head with
+    /// the `depth` confirmation strategy. 0 means as soon as it's mined.
+    #[serde(default = "default::confirmation_depth")]
+    pub confirmation_depth: u64,
+
+    /// If set, insertion batches that wouldn't fit in the tree, as deployed on
+    /// chain, fail with `TxError::TreeFull` instead of being submitted. Disable
+    /// if the contract supports resizing the tree.
+    #[serde(default = "default::check_tree_capacity")]
+    pub check_tree_capacity: bool,
+
+    /// If set, the revert reason of a transaction that was mined but reverted
+    /// is looked up by replaying it at its block, which needs a node that
+    /// still has the state of that block.
+    #[serde(default = "default::decode_revert_reasons")]
+    pub decode_revert_reasons: bool,
+
+    /// If set, a mined transaction whose receipt the provider doesn't return
+    /// is checked again for this long before it's considered dropped, since
+    /// lagging providers may not know of a transaction that was just mined.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub dropped_tx_grace: Option<Duration>,
+
+    /// If set, a batch is given up on with `TxError::RetryBudgetExhausted`
+    /// instead of being attempted again once this much time has passed since
+    /// its first submission attempt, including retries after restarts. It also
+    /// cuts short the attempt in progress, with its relayer retries and
+    /// reprices, like `insertion_timeout` does. Its insertions are marked as
+    /// failed and later batches are rolled back, their identities queued
+    /// again.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub submission_budget: Option<Duration>,
+
+    /// If set, a batch is given up on like with `submission_budget` instead
+    /// of being submitted more than this many times, counting resubmissions
+    /// of transactions that failed in the relayer.
+    #[serde(default)]
+    pub submission_budget_attempts: Option<u32>,
+
+    /// If set, a batch that's not mined within this much time of the
+    /// acceptance of its earliest identity is given up on with
+    /// `TxError::InsertionTimeout`, cutting short its proving, sending, polling
+    /// and resubmissions. If its transaction was sent and it's the latest
+    /// batch sent, the transaction is cancelled by replacing it under the same
+    /// nonce and the batch is rolled back like with `submission_budget`. An
+    /// earlier batch isn't cancelled, since that would revert the batches
+    /// sent after it, it's only no longer waited for.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub insertion_timeout: Option<Duration>,
+
+    /// If set, batches are only submitted while less than this many gwei have
+    /// been spent on gas within `spend_limit_window`, counting reverted
+    /// transactions. Submission resumes as older spends leave the window.
+    /// Spends are tracked in memory, so a restart resets the window.
+    #[serde(default)]
+    pub spend_limit_gwei: Option<u64>,
+
+    /// The rolling window of `spend_limit_gwei`
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::spend_limit_window")]
+    pub spend_limit_window: Duration,
+
+    /// How the fees of submitted transactions are set. If not set, they are
+    /// left to the relayer. Only supported by the `oz_defender` relayer, the
+    /// others price their transactions themselves. Defender doesn't reprice
+    /// transactions that come with fees, see `gas_reprice_after`.
+    #[serde(default)]
+    pub gas_strategy: Option<GasStrategy>,
+
+    /// If set, a transaction priced by `gas_strategy` that's still pending
+    /// after this long is priced again and replaced, with fees at least 10%
+    /// higher. Defender only reprices transactions it priced itself, so
+    /// without this, a transaction priced below a rising base fee stays stuck
+    /// until it's given up on. Repricing stops at `gas_reprice_max_fee_gwei`,
+    /// or once the replacement wouldn't fit within `spend_limit_gwei`.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub gas_reprice_after: Option<Duration>,
+
+    /// If set, a pending transaction isn't repriced past this max fee (or gas
+    /// price on chains without EIP-1559) in gwei. Once a reprice would exceed
+    /// it, the transaction is left at its current fees.
+    #[serde(default)]
+    pub gas_reprice_max_fee_gwei: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+pub enum GasStrategy {
+    /// Trusts the node: the priority fee is its `eth_maxPriorityFeePerGas`,
+    /// the max fee twice the next base fee on top of that. On chains without
+    /// EIP-1559, its `eth_gasPrice`.
+    Node,
+    /// Estimates the priority fee locally, as the median of the
+    /// `reward_percentile` of tips paid over the last `blocks` blocks. The
+    /// max fee is twice the next base fee on top of that, which keeps the
+    /// transaction valid through several blocks of rising base fees. Needs
+    /// EIP-1559.
+    FeeHistory {
+        #[serde(default = "default::fee_history_blocks")]
+        blocks:            u64,
+        #[serde(default = "default::fee_history_reward_percentile")]
+        reward_percentile: f64,
+    },
+    /// Fetches the fees before every submission from `url`, which has to
+    /// respond with `{"maxFeePerGas": ..., "maxPriorityFeePerGas": ...}` in
+    /// wei, as hex or decimal strings. On chains without EIP-1559, the max
+    /// fee is used as the gas price.
+    Oracle { url: SecretUrl },
+    /// Fixed fees, e.g. for chains with a stable gas price. On chains without
+    /// EIP-1559, the max fee is used as the gas price.
+    Static {
+        max_fee_per_gas_gwei:          u64,
+        max_priority_fee_per_gas_gwei: u64,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmationStrategy {
+    /// Confirmed once the transaction's block is `confirmation_depth` blocks
+    /// below the chain head. The lowest latency, but a reorg deeper than the
+    /// depth can revert a confirmed batch.
+    #[default]
+    Depth,
+    /// Confirmed once the transaction's block is finalized. Safe against
+    /// reorgs, at the cost of waiting for finality (around 13 minutes on
+    /// mainnet).
+    Finalized,
+    /// Confirmed once the relayer reports the transaction as confirmed
+    /// (Defender's `confirmed` and tx-sitter's `finalized` statuses), which
+    /// delegates the tradeoff to the relayer's settings. The forwarder relay
+    /// has no such status and treats transactions as confirmed once mined.
+    Relayer,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TreeConfig {
     /// The depth of the tree that the contract is working with. This needs to
     /// agree with the verifier in the deployed contract, and also with
-    /// `semaphore-mtb`
+    /// `semaphore-mtb`. The sequencer refuses to start if the contract reports
+    /// a different depth.
     #[serde(default = "default::tree_depth")]
     pub tree_depth: usize,
 
@@ -104,12 +373,56 @@ pub struct TreeConfig {
     /// used in the identity manager contract.
     #[serde(default = "default::initial_leaf_value")]
     pub initial_leaf_value: Field,
+
+    /// The number of threads the dense prefix of the tree is hashed on when
+    /// it's built on startup. Defaults to the number of CPUs. Restoring the
+    /// cache file and applying the leaves past the dense prefix stay single
+    /// threaded.
+    #[serde(default)]
+    pub loading_threads: Option<usize>,
+
+    /// What to do when a commitment replayed on startup doesn't match the one
+    /// already at its leaf index, e.g. after a reorg.
+    #[serde(default)]
+    pub leaf_conflict_policy: LeafConflictPolicy,
+
+    /// If set, inclusion proofs of up to this many mined leaves are cached,
+    /// starting with the proofs of the first leaves right after startup. Each
+    /// proof takes around `40 * tree_depth` bytes.
+    #[serde(default)]
+    pub proof_cache_size: Option<usize>,
+
+    /// The number of roots mined on mainnet that `GET /roots/recent` returns
+    #[serde(default = "default::recent_roots")]
+    pub recent_roots: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeafConflictPolicy {
+    /// Refuses to start and raises an alert, leaving recovery to the operator.
+    #[default]
+    Halt,
+    /// Replaces the leaf with the replayed commitment, trusting the database
+    /// (which mirrors the chain) over the tree.
+    Overwrite,
+    /// Discards the cached tree and rebuilds it from the mined commitments in
+    /// the database. Halts if the rebuilt tree still conflicts.
+    RollbackAndResync,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
-    /// The address of the identity manager contract.
-    pub identity_manager_address: Address,
+    /// The address of the identity manager contract. Either this or
+    /// `identity_manager_addresses` must be set.
+    #[serde(default)]
+    pub identity_manager_address: Option<Address>,
+
+    /// The addresses of the identity manager contract mapped by chain id, for
+    /// configs shared between chains. The address is picked by the chain id of
+    /// the primary provider, and startup fails if it's not in the map.
+    #[serde(default)]
+    pub identity_manager_addresses: JsonStrWrapper<HashMap<u64, Address>>,
 
     /// The addresses of world id contracts on secondary chains
     /// mapped by chain id
@@ -117,6 +430,26 @@ pub struct NetworkConfig {
     pub relayed_identity_manager_addresses: JsonStrWrapper<HashMap<u64, Address>>,
 }
 
+impl NetworkConfig {
+    /// The identity manager address for the chain with id `chain_id`
+    pub fn resolve_identity_manager_address(&self, chain_id: u64) -> anyhow::Result<Address> {
+        let addresses = &self.identity_manager_addresses.0;
+
+        match self.identity_manager_address {
+            Some(_) if !addresses.is_empty() => anyhow::bail!(
+                "Only one of identity_manager_address and identity_manager_addresses can be set"
+            ),
+            Some(address) => Ok(address),
+            None if addresses.is_empty() => anyhow::bail!(
+                "One of identity_manager_address and identity_manager_addresses must be set"
+            ),
+            None => addresses.get(&chain_id).copied().ok_or_else(|| {
+                anyhow::anyhow!("No identity manager address configured for chain id {chain_id}")
+            }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProvidersConfig {
     /// Provider url for the primary chain
@@ -125,6 +458,59 @@ pub struct ProvidersConfig {
     /// Provider urls for the secondary chains
     #[serde(default)]
     pub relayed_network_providers: JsonStrWrapper<Vec<SecretUrl>>,
+
+    /// Provider urls to fail over to, in order, when the primary chain
+    /// provider stops responding. Requests that fail due to a transport error
+    /// are retried on the next provider.
+    #[serde(default)]
+    pub fallback_network_providers: JsonStrWrapper<Vec<SecretUrl>>,
+
+    /// The maximum number of idle connections kept open to each RPC node.
+    ///
+    /// Higher values allow more requests to run in parallel (e.g. during burst
+    /// inserts and log fetching) without paying for new connections, at the
+    /// cost of keeping more sockets open on both ends. Should be kept below
+    /// the connection limit of the node.
+    #[serde(default = "default::http_pool_max_idle_per_host")]
+    pub http_pool_max_idle_per_host: usize,
+
+    /// How long an idle connection to an RPC node is kept open before being
+    /// closed.
+    ///
+    /// Should be shorter than the node's (or load balancer's) keep-alive
+    /// timeout, otherwise requests may be sent on already closed connections.
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::http_pool_idle_timeout")]
+    pub http_pool_idle_timeout: Duration,
+
+    /// Bounds every request to an RPC node, including reads like block
+    /// numbers, logs and receipts, so that a stalled node can't hang the
+    /// sequencer. A timed out request counts as a transport error and fails
+    /// over to the next provider. Unrelated to the relayer timeouts, which
+    /// bound the submission of transactions.
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::provider_request_timeout")]
+    pub request_timeout: Duration,
+
+    /// If set, chain events are scanned for whenever this WebSocket endpoint
+    /// of the primary chain announces a new block, instead of every
+    /// `app.time_between_scans`. Polling takes over as a standby while the
+    /// subscription stalls, see `app.subscription_stall_timeout`.
+    #[serde(default)]
+    pub ws_network_provider: Option<SecretUrl>,
+
+    /// The maximum number of contract view calls and gas estimations in
+    /// flight at once, per chain. Other requests aren't limited. Unlimited by
+    /// default, and must be positive if set.
+    #[serde(default)]
+    pub max_concurrent_view_calls: Option<NonZeroUsize>,
+
+    /// If set, commands scanning events from an old block (e.g.
+    /// `verify-tree`) send this many `eth_getLogs` windows per JSON-RPC
+    /// batch. Falls back to individual requests if the provider rejects
+    /// batches. Must be positive if set.
+    #[serde(default)]
+    pub log_batch_size: Option<NonZeroUsize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -133,6 +519,7 @@ pub struct ProvidersConfig {
 pub enum RelayerConfig {
     OzDefender(OzDefenderConfig),
     TxSitter(TxSitterConfig),
+    Forwarder(ForwarderConfig),
 }
 
 impl RelayerConfig {
@@ -141,6 +528,16 @@ impl RelayerConfig {
         match self {
             RelayerConfig::OzDefender(config) => config.oz_address,
             RelayerConfig::TxSitter(config) => config.tx_sitter_address,
+            RelayerConfig::Forwarder(config) => config.forwarder_signer_address,
+        }
+    }
+
+    /// The `kind` the relayer is configured with
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            RelayerConfig::OzDefender(_) => "oz_defender",
+            RelayerConfig::TxSitter(_) => "tx_sitter",
+            RelayerConfig::Forwarder(_) => "forwarder",
         }
     }
 }
@@ -152,10 +549,10 @@ pub struct OzDefenderConfig {
     pub oz_api_url: String,
 
     /// OpenZeppelin Defender API Key
-    pub oz_api_key: String,
+    pub oz_api_key: SecretString,
 
     /// OpenZeppelin Defender API Secret
-    pub oz_api_secret: String,
+    pub oz_api_secret: SecretString,
 
     /// Address of OZ Relayer
     pub oz_address: H160,
@@ -166,6 +563,9 @@ pub struct OzDefenderConfig {
     #[serde(default = "default::oz_transaction_validity")]
     pub oz_transaction_validity: Duration,
 
+    /// Timeout for submitting a transaction. Also bounds each authentication
+    /// request to Defender. Requests to RPC nodes are bounded by
+    /// `providers.request_timeout` instead.
     #[serde(with = "humantime_serde")]
     #[serde(default = "default::oz_send_timeout")]
     pub oz_send_timeout: Duration,
@@ -174,18 +574,175 @@ pub struct OzDefenderConfig {
     #[serde(default = "default::oz_mine_timeout")]
     pub oz_mine_timeout: Duration,
 
+    /// How long before they expire Defender credentials are refreshed, so
+    /// that a host clock running behind doesn't make us use expired ones
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::oz_clock_skew")]
+    pub oz_clock_skew: Duration,
+
     pub oz_gas_limit: Option<u64>,
+
+    /// How many times listing the recent transactions of a relayer is retried
+    /// before resubmitting a batch, with exponential backoff starting at one
+    /// second. If it keeps failing, the batch is submitted anew, at the risk
+    /// of a duplicate transaction that reverts.
+    #[serde(default = "default::oz_list_transactions_retries")]
+    pub oz_list_transactions_retries: u32,
+
+    /// The maximum number of transaction status polls in flight at once,
+    /// across all relayers. Bounds the load of polling many in-flight
+    /// transactions on Defender's read quota. This caps concurrency rather
+    /// than the request rate, which still depends on how fast Defender
+    /// answers. Unlimited by default, and must be positive if set.
+    #[serde(default)]
+    pub oz_max_concurrent_polls: Option<NonZeroUsize>,
+
+    /// If set, the statuses of in-flight transactions are polled by listing
+    /// the recent transactions of a relayer once per interval and sharing the
+    /// result, instead of querying every transaction. Transactions missing
+    /// from the listing, and the only transaction in flight, are still
+    /// queried by id.
+    #[serde(default = "default::oz_batch_status_polling")]
+    pub oz_batch_status_polling: bool,
+
+    /// If set, the JSON body of every transaction sent to Defender is logged
+    /// at the trace level. Only meant for debugging rejected submissions.
+    #[serde(default = "default::oz_log_payloads")]
+    pub oz_log_payloads: bool,
+
+    /// Credentials of additional relayers to spread transactions across, e.g.
+    /// `[{"api_key": "...", "api_secret": "..."}]`. Every relayer must be
+    /// allowed to call the identity manager.
+    #[serde(default)]
+    pub oz_additional_relayers: JsonStrWrapper<Vec<OzRelayerCredentials>>,
+
+    /// How transactions are distributed across the relayers. Batches build on
+    /// each other's roots, so while any transaction is in flight the next ones
+    /// go through the same relayer, and a relayer is only picked again once
+    /// they're all settled.
+    #[serde(default)]
+    pub oz_relayer_selection: OzRelayerSelection,
+
+    /// What happens when Defender reports a transaction as failed
+    #[serde(default)]
+    pub oz_failed_policy: OzFailedPolicy,
+
+    /// Function selectors the relayer may call, e.g. `["0x2217b211"]`, and
+    /// `"transfer"` for plain value transfers. Everything is allowed if empty.
+    #[serde(default)]
+    pub oz_allowed_selectors: JsonStrWrapper<Vec<String>>,
+
+    /// If set, a transaction the node rejects as already known is looked up
+    /// among the recent pending and mined transactions of the relayers, and
+    /// the earlier submission is followed instead of failing the batch
+    #[serde(default = "default::oz_follow_known_transactions")]
+    pub oz_follow_known_transactions: bool,
+
+    /// If set, an EIP-1559 transaction that's still pending after this long
+    /// is replaced with a legacy one, for nodes that accept EIP-1559
+    /// transactions but never mine them. Its gas price is the pending max fee
+    /// raised by `oz_legacy_fallback_bump_percent`, or the current gas price
+    /// if that's higher. Transactions only fall back once.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub oz_legacy_fallback_after: Option<Duration>,
+
+    /// How much higher the gas price of a legacy fallback is than the max fee
+    /// of the transaction it replaces, in percent. At least 10, for nodes to
+    /// accept the replacement.
+    #[serde(default = "default::oz_legacy_fallback_bump_percent")]
+    pub oz_legacy_fallback_bump_percent: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OzRelayerCredentials {
+    pub api_key:    SecretString,
+    pub api_secret: SecretString,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OzRelayerSelection {
+    /// Moves on to the next relayer in turn
+    #[default]
+    RoundRobin,
+    /// Picks the unpaused relayer with the fewest pending transactions
+    /// according to Defender
+    LeastBusy,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+pub enum OzFailedPolicy {
+    /// The batch fails
+    #[default]
+    Error,
+    /// The transaction is sent again, up to `max_resubmissions` times, with
+    /// the same calldata. It's priced anew, like any other transaction, and
+    /// gets the relayer's next nonce. Waits `backoff` before the first
+    /// resubmission and twice as long before every next one. A failed batch
+    /// is only resubmitted while no later batch has been sent, which would
+    /// otherwise be mined first and revert, since its pre-root isn't the
+    /// current root yet.
+    Resubmit {
+        #[serde(default = "default::oz_max_resubmissions")]
+        max_resubmissions: u32,
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "default::oz_resubmission_backoff")]
+        backoff:           Duration,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TxSitterConfig {
-    pub tx_sitter_url: String,
+    /// Carries the API key in its path
+    pub tx_sitter_url: SecretUrl,
 
     pub tx_sitter_address: H160,
 
     pub tx_sitter_gas_limit: Option<u64>,
 }
 
+/// Submits transactions as EIP-712 signed meta-transactions through a relay
+/// that forwards them to an ERC-2771 forwarder contract.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwarderConfig {
+    /// Url of the relay accepting signed forward requests
+    pub forwarder_relay_url: SecretUrl,
+
+    /// Address of the forwarder contract, used as the EIP-712 verifying
+    /// contract
+    pub forwarder_address: Address,
+
+    /// Address of the forward request signer, must be the identity operator
+    pub forwarder_signer_address: H160,
+
+    /// Private key used to sign the forward requests
+    pub forwarder_signing_key: SecretString,
+
+    /// EIP-712 domain name of the forwarder contract
+    #[serde(default = "default::forwarder_domain_name")]
+    pub forwarder_domain_name: String,
+
+    /// EIP-712 domain version of the forwarder contract
+    #[serde(default = "default::forwarder_domain_version")]
+    pub forwarder_domain_version: String,
+
+    pub forwarder_gas_limit: Option<u64>,
+
+    /// How long gas estimates are reused for batches of the same kind and
+    /// size. Disabled by default.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub forwarder_gas_estimate_ttl: Option<Duration>,
+
+    /// Multiplies reused gas estimates, since a later batch of the same size
+    /// can cost more, e.g. by writing to fresh storage slots. At least 1.
+    #[serde(default = "default::forwarder_gas_estimate_headroom")]
+    pub forwarder_gas_estimate_headroom: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     pub database: SecretUrl,
@@ -204,14 +761,159 @@ pub struct ServerConfig {
     #[serde(with = "humantime_serde")]
     #[serde(default = "default::serve_timeout")]
     pub serve_timeout: Duration,
+
+    /// If set, `POST /insertIdentity` responds with 202 and a ticket once an
+    /// insertion takes longer than this, and finishes it in the background.
+    /// Should be below `serve_timeout`.
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub insert_timeout: Option<Duration>,
+
+    /// Admin endpoints (e.g. `GET /config`) require this token in the
+    /// `X-Admin-Token` header, and are disabled unless it's set. The
+    /// `Authorization` header can't be used, since it's stripped from all
+    /// requests.
+    #[serde(default)]
+    pub admin_token: Option<SecretString>,
+
+    /// If set, `/leafUpdates` events carry a cursor signed with this secret,
+    /// from which a reconnecting client can resume. Changing the secret
+    /// invalidates the cursors handed out so far.
+    #[serde(default)]
+    pub cursor_secret: Option<SecretString>,
+
+    /// If set, successful insertions are acknowledged with a receipt signed by
+    /// this private key, whose address is served at `/acknowledgmentSigner`.
+    /// Use a dedicated key, not one holding funds or operating the contract.
+    #[serde(default)]
+    pub acknowledgment_signing_key: Option<SecretString>,
+
+    /// Byte order of the commitments passed to `POST /insertIdentity`,
+    /// `/inclusionProof`, `/deleteIdentity` and `/recoverIdentity`. They are
+    /// converted to big endian, the order used on chain, before being
+    /// validated and looked up.
+    #[serde(default)]
+    pub commitment_byte_order: CommitmentByteOrder,
+
+    /// If set, `POST /insertIdentity` only succeeds once the batch including
+    /// the identity was mined and its `TreeChanged` event applied to the
+    /// tree, rather than once the identity is queued. Fails once the insertion
+    /// does, and gives up after `serve_timeout`. Combine with
+    /// `insert_timeout`, since that takes at least a batch timeout.
+    #[serde(default = "default::await_inclusion")]
+    pub await_inclusion: bool,
+
+    /// If set, at most this many `POST /insertIdentity` requests are handled
+    /// at once. Once they all are, the next ones wait in one queue per client
+    /// and are admitted round-robin across clients, in arrival order within a
+    /// client. With `await_inclusion`, a request holds its slot until the
+    /// identity is mined. Clients are told apart by their address. Must be
+    /// positive if set.
+    #[serde(default)]
+    pub max_concurrent_inserts: Option<NonZeroUsize>,
+
+    /// How many insertions of one client may wait for a slot. Further ones
+    /// are rejected with 429.
+    #[serde(default = "default::max_queued_inserts_per_client")]
+    pub max_queued_inserts_per_client: usize,
+
+    /// If set, clients are told apart by this header instead of their address,
+    /// and requests without it by their address still. Only set it if a
+    /// trusted gateway sets the header, overwriting what clients sent, as they
+    /// could otherwise pick a new id for every request.
+    #[serde(default)]
+    pub client_id_header: Option<String>,
+
+    /// If set, `POST /insertIdentity` returns an unconfirmed proof of the
+    /// commitment against the root the tree is projected to have once the
+    /// insertions queued ahead of it are applied. The projection can turn out
+    /// wrong, e.g. if higher priority insertions arrive, an insertion ahead
+    /// fails or the batch is reorged out, so clients must not treat it as
+    /// inclusion and should fetch the confirmed proof from `/inclusionProof`.
+    /// Can't be combined with `await_inclusion`.
+    #[serde(default = "default::optimistic_proofs")]
+    pub optimistic_proofs: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitmentByteOrder {
+    /// Ethereum tooling, e.g. ethers, viem, snarkjs and the Semaphore
+    /// libraries, as well as gnark.
+    #[default]
+    BigEndian,
+    /// Field elements serialized with arkworks' `CanonicalSerialize` or the
+    /// `ff` crate's `PrimeField::to_repr`, e.g. by clients built on
+    /// bellman or halo2. The commitment must be all 32 bytes, since leading
+    /// zeros of a little endian number are significant.
+    LittleEndian,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ServiceConfig {
     // Service name - used for logging, metrics and tracing
     #[serde(default = "default::service_name")]
-    pub service_name: String,
-    pub datadog:      Option<DatadogConfig>,
+    pub service_name:   String,
+    pub datadog:        Option<DatadogConfig>,
+    /// Where to send alerts about critical conditions. Alerts are only logged
+    /// if not set.
+    pub alerts:         Option<AlertsConfig>,
+    /// Where to push metrics to, for deployments too short-lived to be
+    /// scraped through `/metrics`, like `--catch-up-only` jobs
+    pub pushgateway:    Option<PushgatewayConfig>,
+    /// Prepended to the names of all metrics, separated by an underscore
+    pub metrics_prefix: Option<String>,
+    /// Constant labels added to all metrics, e.g. the chain id or the group
+    /// the sequencer is responsible for
+    #[serde(default)]
+    pub metrics_labels: JsonStrWrapper<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertsConfig {
+    #[serde(flatten)]
+    pub sink: AlertSinkConfig,
+
+    /// Repeated alerts about the same condition within this window are only
+    /// sent once
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::alert_dedup_window")]
+    pub dedup_window: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSinkConfig {
+    /// Posts the alerts as JSON to an arbitrary url
+    Webhook {
+        url: SecretUrl,
+    },
+    Slack {
+        webhook_url: SecretUrl,
+    },
+    PagerDuty {
+        routing_key: SecretString,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushgatewayConfig {
+    /// The Pushgateway, e.g. `http://pushgateway:9091`
+    pub url: SecretUrl,
+
+    /// The `job` label of the pushed metrics, defaults to the service name
+    #[serde(default)]
+    pub job: Option<String>,
+
+    /// The `instance` label of the pushed metrics, if any
+    #[serde(default)]
+    pub instance: Option<String>,
+
+    /// How often metrics are pushed, in addition to once on exit
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::push_interval")]
+    pub push_interval: Duration,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -226,6 +928,14 @@ pub mod default {
         "signup_sequencer".to_string()
     }
 
+    pub fn alert_dedup_window() -> Duration {
+        Duration::from_secs(15 * 60)
+    }
+
+    pub fn push_interval() -> Duration {
+        Duration::from_secs(15)
+    }
+
     pub fn oz_api_url() -> String {
         "https://api.defender.openzeppelin.com".to_string()
     }
@@ -242,6 +952,58 @@ pub mod default {
         Duration::from_secs(60)
     }
 
+    pub fn fee_history_blocks() -> u64 {
+        20
+    }
+
+    pub fn fee_history_reward_percentile() -> f64 {
+        50.0
+    }
+
+    pub fn oz_clock_skew() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    pub fn oz_list_transactions_retries() -> u32 {
+        3
+    }
+
+    pub fn oz_max_resubmissions() -> u32 {
+        3
+    }
+
+    pub fn oz_resubmission_backoff() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    pub fn oz_batch_status_polling() -> bool {
+        false
+    }
+
+    pub fn oz_log_payloads() -> bool {
+        false
+    }
+
+    pub fn oz_follow_known_transactions() -> bool {
+        false
+    }
+
+    pub fn oz_legacy_fallback_bump_percent() -> u64 {
+        20
+    }
+
+    pub fn forwarder_domain_name() -> String {
+        "MinimalForwarder".to_string()
+    }
+
+    pub fn forwarder_gas_estimate_headroom() -> f64 {
+        1.2
+    }
+
+    pub fn forwarder_domain_version() -> String {
+        "0.0.1".to_string()
+    }
+
     pub fn batch_insertion_timeout() -> Duration {
         Duration::from_secs(180)
     }
@@ -266,18 +1028,90 @@ pub mod default {
         0
     }
 
+    pub fn scanning_window_overlap() -> u64 {
+        2
+    }
+
     pub fn time_between_scans() -> Duration {
         Duration::from_secs(30)
     }
 
+    pub fn reconcile_interval() -> Duration {
+        Duration::from_secs(5 * 60)
+    }
+
+    pub fn reconcile_confirmations() -> u64 {
+        5
+    }
+
+    pub fn subscription_stall_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
     pub fn monitored_txs_capacity() -> usize {
         100
     }
 
+    pub fn check_root_before_submit() -> bool {
+        false
+    }
+
+    pub fn verify_proofs_on_chain() -> bool {
+        false
+    }
+
+    pub fn max_calldata_size() -> usize {
+        120 * 1024
+    }
+
+    pub fn confirmation_depth() -> u64 {
+        0
+    }
+
+    pub fn check_tree_capacity() -> bool {
+        true
+    }
+
+    pub fn recent_roots() -> usize {
+        16
+    }
+
+    pub fn decode_revert_reasons() -> bool {
+        false
+    }
+
+    pub fn spend_limit_window() -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    pub fn http_pool_max_idle_per_host() -> usize {
+        64
+    }
+
+    pub fn http_pool_idle_timeout() -> Duration {
+        Duration::from_secs(90)
+    }
+
+    pub fn provider_request_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
     pub fn serve_timeout() -> Duration {
         Duration::from_secs(30)
     }
 
+    pub fn await_inclusion() -> bool {
+        false
+    }
+
+    pub fn max_queued_inserts_per_client() -> usize {
+        64
+    }
+
+    pub fn optimistic_proofs() -> bool {
+        false
+    }
+
     pub fn migrate() -> bool {
         true
     }
@@ -355,8 +1189,20 @@ mod tests {
         max_epoch_duration = "0s"
         scanning_window_size = 100
         scanning_chain_head_offset = 0
+        scanning_window_overlap = 2
         time_between_scans = "30s"
+        subscription_stall_timeout = "1m"
         monitored_txs_capacity = 100
+        check_root_before_submit = false
+        reconcile_interval = "5m"
+        reconcile_confirmations = 5
+        verify_proofs_on_chain = false
+        max_calldata_size = 122880
+        confirmation_strategy = "depth"
+        confirmation_depth = 0
+        check_tree_capacity = true
+        decode_revert_reasons = false
+        spend_limit_window = "1h"
 
         [tree]
         tree_depth = 30
@@ -365,18 +1211,25 @@ mod tests {
         cache_file = "/data/cache_file"
         force_cache_purge = false
         initial_leaf_value = "0x1"
+        leaf_conflict_policy = "halt"
+        recent_roots = 16
 
         [network]
         identity_manager_address = "0x0000000000000000000000000000000000000000"
+        identity_manager_addresses = "{}"
         relayed_identity_manager_addresses = "{}"
 
         [providers]
         primary_network_provider = "http://localhost:8545/"
         relayed_network_providers = "[]"
+        fallback_network_providers = "[]"
+        http_pool_max_idle_per_host = 64
+        http_pool_idle_timeout = "1m 30s"
+        request_timeout = "1m"
 
         [relayer]
         kind = "tx_sitter"
-        tx_sitter_url = "http://localhost:3000"
+        tx_sitter_url = "http://localhost:3000/"
         tx_sitter_address = "0x0000000000000000000000000000000000000000"
         tx_sitter_gas_limit = 100000
 
@@ -388,14 +1241,125 @@ mod tests {
         [server]
         address = "0.0.0.0:3001"
         serve_timeout = "30s"
+        commitment_byte_order = "big_endian"
+        await_inclusion = false
+        max_queued_inserts_per_client = 64
+        optimistic_proofs = false
 
         [service]
         service_name = "signup-sequencer"
+        metrics_labels = "{}"
 
         [service.datadog]
         traces_endpoint = "http://localhost:8126"
     "#};
 
+    #[test]
+    fn identity_manager_address_is_picked_by_chain_id() {
+        let address = Address::repeat_byte(1);
+        let single = NetworkConfig {
+            identity_manager_address:           Some(address),
+            identity_manager_addresses:         JsonStrWrapper(HashMap::new()),
+            relayed_identity_manager_addresses: JsonStrWrapper(HashMap::new()),
+        };
+        assert_eq!(single.resolve_identity_manager_address(5).unwrap(), address);
+
+        let per_chain = NetworkConfig {
+            identity_manager_address: None,
+            identity_manager_addresses: JsonStrWrapper(HashMap::from([(1, address)])),
+            ..single.clone()
+        };
+        assert_eq!(
+            per_chain.resolve_identity_manager_address(1).unwrap(),
+            address
+        );
+        assert!(per_chain.resolve_identity_manager_address(5).is_err());
+
+        let both = NetworkConfig {
+            identity_manager_address: Some(address),
+            ..per_chain
+        };
+        assert!(both.resolve_identity_manager_address(1).is_err());
+    }
+
+    #[test]
+    fn concurrency_limits_must_be_positive() {
+        let providers = |max_concurrent_view_calls: usize| {
+            toml::from_str::<ProvidersConfig>(&format!(
+                r#"
+                primary_network_provider = "http://localhost:8545/"
+                max_concurrent_view_calls = {max_concurrent_view_calls}
+                "#
+            ))
+        };
+
+        assert!(providers(0).is_err());
+        assert_eq!(
+            providers(2).unwrap().max_concurrent_view_calls,
+            NonZeroUsize::new(2)
+        );
+
+        let log_batch_size = |log_batch_size: usize| {
+            toml::from_str::<ProvidersConfig>(&format!(
+                r#"
+                primary_network_provider = "http://localhost:8545/"
+                log_batch_size = {log_batch_size}
+                "#
+            ))
+        };
+
+        assert!(log_batch_size(0).is_err());
+        assert_eq!(
+            log_batch_size(10).unwrap().log_batch_size,
+            NonZeroUsize::new(10)
+        );
+    }
+
+    #[test]
+    fn batch_and_queue_sizes_must_be_positive() {
+        let app = |batch_size: usize| {
+            toml::from_str::<AppConfig>(&format!(
+                r#"
+                provers_urls = "[]"
+                batch_size = {batch_size}
+                "#
+            ))
+        };
+
+        assert!(app(0).is_err());
+        assert_eq!(app(4).unwrap().batch_size, NonZeroUsize::new(4));
+
+        let scan = |max_events_per_scan: usize| {
+            toml::from_str::<AppConfig>(&format!(
+                r#"
+                provers_urls = "[]"
+                max_events_per_scan = {max_events_per_scan}
+                "#
+            ))
+        };
+
+        assert!(scan(0).is_err());
+        assert_eq!(
+            scan(100).unwrap().max_events_per_scan,
+            NonZeroUsize::new(100)
+        );
+
+        let server = |max_concurrent_inserts: usize| {
+            toml::from_str::<ServerConfig>(&format!(
+                r#"
+                address = "0.0.0.0:3001"
+                max_concurrent_inserts = {max_concurrent_inserts}
+                "#
+            ))
+        };
+
+        assert!(server(0).is_err());
+        assert_eq!(
+            server(8).unwrap().max_concurrent_inserts,
+            NonZeroUsize::new(8)
+        );
+    }
+
     #[test]
     fn full_toml_round_trip() {
         let config: Config = toml::from_str(FULL_TOML).unwrap();
@@ -1,6 +1,12 @@
 #![allow(clippy::extra_unused_lifetimes)]
 
+use anyhow::Context;
+use ethers::abi::{ParamType, RawLog};
+use ethers::contract::EthEvent;
 use ethers::prelude::abigen;
+use ethers::types::U256;
+
+use crate::identity::validator::MODULUS;
 
 /// The `TreeChanged` event is emitted by the `IdentityManager` contract.
 /// Maps to the following enum in the contract code:
@@ -42,9 +48,67 @@ abigen!(
         function identityOperator() public view virtual returns (address)
         function queryRoot(uint256 root) public view virtual returns (RootInfo memory)
         function getRootHistoryExpiry() external view returns (uint256)
+        function getTreeDepth() public view virtual returns (uint8)
     ]"#,
 );
 
+impl TreeChangedFilter {
+    /// Decodes a `TreeChanged` log of either layout. The upstream contract
+    /// indexes all the fields, some forks index none of them, which moves
+    /// them from the topics to the data. Both layouts share the signature, so
+    /// the layout is told apart by the number of topics.
+    ///
+    /// Unlike `decode_log`, fails on out of range kinds and roots instead of
+    /// truncating them.
+    pub fn decode_any_layout(log: &RawLog) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            log.topics.first() == Some(&Self::signature()),
+            "Not a TreeChanged log"
+        );
+
+        // Static fields are encoded the same way in the topics and the data
+        let encoded: Vec<u8> = match log.topics.len() {
+            4 => log.topics[1..]
+                .iter()
+                .flat_map(|topic| topic.to_fixed_bytes())
+                .collect(),
+            1 => log.data.clone(),
+            topics => anyhow::bail!("Unsupported TreeChanged layout with {topics} topics"),
+        };
+
+        let fields = [
+            ParamType::Uint(256),
+            ParamType::Uint(8),
+            ParamType::Uint(256),
+        ];
+        let mut tokens = ethers::abi::decode(&fields, &encoded)
+            .context("Failed to decode TreeChanged log")?
+            .into_iter()
+            .map(|token| token.into_uint().context("Expected a uint"));
+
+        let (Some(pre_root), Some(kind), Some(post_root)) =
+            (tokens.next(), tokens.next(), tokens.next())
+        else {
+            anyhow::bail!("Missing TreeChanged field");
+        };
+        let (pre_root, kind, post_root) = (pre_root?, kind?, post_root?);
+
+        anyhow::ensure!(kind <= U256::from(2), "Invalid tree change kind {kind}");
+
+        let modulus = U256::from_big_endian(&MODULUS.to_be_bytes::<32>());
+        anyhow::ensure!(
+            pre_root < modulus && post_root < modulus,
+            "Roots of TreeChanged log are not reduced"
+        );
+
+        Ok(Self {
+            pre_root,
+            kind: kind.as_u32() as u8,
+            post_root,
+        })
+    }
+}
+
 abigen!(
     BridgedWorldId,
     r#"[
@@ -53,3 +117,91 @@ abigen!(
         function latestRoot() public view returns (uint256 root)
     ]"#
 );
+
+// Not part of the identity manager interface, only some deployments expose a
+// per-index getter for the tree leaves and the number of inserted leaves.
+abigen!(
+    IdentityLeaves,
+    r#"[
+        function leaves(uint256 index) public view returns (uint256)
+        function leafCount() public view returns (uint256)
+    ]"#
+);
+
+// Not part of the identity manager interface, only deployments with a pause
+// guard expose it.
+abigen!(
+    Pausable,
+    r#"[
+        function paused() public view returns (bool)
+    ]"#
+);
+
+abigen!(
+    MinimalForwarder,
+    r#"[
+        function getNonce(address from) public view returns (uint256)
+    ]"#
+);
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::Token;
+    use ethers::types::H256;
+
+    use super::*;
+
+    fn topic(value: u64) -> H256 {
+        H256::from_low_u64_be(value)
+    }
+
+    #[test]
+    fn both_layouts_decode_the_same() {
+        let expected = TreeChangedFilter {
+            pre_root:  U256::from(1),
+            kind:      1,
+            post_root: U256::from(2),
+        };
+
+        let indexed = RawLog {
+            topics: vec![TreeChangedFilter::signature(), topic(1), topic(1), topic(2)],
+            data:   vec![],
+        };
+        let not_indexed = RawLog {
+            topics: vec![TreeChangedFilter::signature()],
+            data:   ethers::abi::encode(&[
+                Token::Uint(U256::from(1)),
+                Token::Uint(U256::from(1)),
+                Token::Uint(U256::from(2)),
+            ]),
+        };
+
+        assert_eq!(
+            TreeChangedFilter::decode_any_layout(&indexed).unwrap(),
+            expected
+        );
+        assert_eq!(
+            TreeChangedFilter::decode_any_layout(&not_indexed).unwrap(),
+            expected
+        );
+        assert_eq!(TreeChangedFilter::decode_log(&indexed).unwrap(), expected);
+    }
+
+    #[test]
+    fn unexpected_values_are_rejected() {
+        let log = |topics: Vec<H256>| RawLog {
+            topics,
+            data: vec![],
+        };
+        let signature = TreeChangedFilter::signature();
+
+        let invalid_kind = log(vec![signature, topic(1), topic(3), topic(2)]);
+        let unreduced_root = log(vec![signature, H256::repeat_byte(0xff), topic(0), topic(2)]);
+        let partially_indexed = log(vec![signature, topic(1), topic(0)]);
+        let other_event = log(vec![H256::zero(), topic(1), topic(0), topic(2)]);
+
+        for log in [invalid_kind, unreduced_root, partially_indexed, other_event] {
+            assert!(TreeChangedFilter::decode_any_layout(&log).is_err());
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Getters that only some deployments of the identity manager expose.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use ethers::contract::ContractError;
+use ethers::providers::{JsonRpcError, Middleware, MiddlewareError};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, U256};
+
+use super::abi::{IdentityLeaves, Pausable, WorldId};
+
+/// Fetches the latest root of the identity manager contract at `address`.
+pub async fn contract_root<M>(client: Arc<M>, address: Address) -> anyhow::Result<U256>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let contract = WorldId::new(address, client);
+
+    Ok(contract.latest_root().call().await?)
+}
+
+/// Fetches the depth of the tree of the identity manager contract at
+/// `address`.
+///
+/// Returns `None` if the call reverts, which older deployments without the
+/// getter do. Any other failure is an error.
+pub async fn contract_tree_depth<M>(client: Arc<M>, address: Address) -> anyhow::Result<Option<u8>>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let contract = WorldId::new(address, client);
+
+    match contract.get_tree_depth().call().await {
+        Ok(depth) => Ok(Some(depth)),
+        Err(error) if is_revert(&error) => Ok(None),
+        Err(error) => Err(anyhow!(error)),
+    }
+}
+
+/// Fetches the leaf at `index` from the identity manager contract at
+/// `address`.
+///
+/// Returns `None` if the contract doesn't expose a per-index getter.
+pub async fn contract_leaf<M>(
+    client: Arc<M>,
+    address: Address,
+    index: usize,
+) -> anyhow::Result<Option<U256>>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let contract = IdentityLeaves::new(address, client.clone());
+    let call = contract.leaves(index.into());
+
+    let output = optional_call(&client, address, &call.tx).await?;
+
+    Ok(output.map(|output| U256::from_big_endian(&output)))
+}
+
+/// Fetches the number of leaves inserted into the identity manager contract
+/// at `address`.
+///
+/// Returns `None` if the contract doesn't expose the count.
+pub async fn contract_leaf_count<M>(
+    client: Arc<M>,
+    address: Address,
+) -> anyhow::Result<Option<U256>>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let contract = IdentityLeaves::new(address, client.clone());
+    let call = contract.leaf_count();
+
+    let output = optional_call(&client, address, &call.tx).await?;
+
+    Ok(output.map(|output| U256::from_big_endian(&output)))
+}
+
+/// Fetches whether the contract at `address` is paused.
+///
+/// Returns `None` if the contract has no pause guard.
+pub async fn contract_paused<M>(client: Arc<M>, address: Address) -> anyhow::Result<Option<bool>>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let contract = Pausable::new(address, client.clone());
+    let call = contract.paused();
+
+    let output = optional_call(&client, address, &call.tx).await?;
+
+    Ok(output.map(|output| !U256::from_big_endian(&output).is_zero()))
+}
+
+/// Calls a getter returning a single word, `None` if the contract at `address`
+/// has no function with its selector. That's the case if the call reverts
+/// without any revert data, which is what the Solidity dispatcher does, or if
+/// a fallback function answers it with anything but a word. Reverts with data
+/// are failures of the getter itself, and are returned as errors along with
+/// transport errors and calls to addresses without code.
+async fn optional_call<M>(
+    client: &M,
+    address: Address,
+    tx: &TypedTransaction,
+) -> anyhow::Result<Option<Bytes>>
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    let output = match client.call(tx, None).await {
+        Ok(output) => output,
+        Err(error) if error.as_error_response().is_some_and(is_missing_function) => {
+            return Ok(None);
+        }
+        Err(error) => return Err(anyhow!(error)),
+    };
+
+    if output.len() == 32 {
+        return Ok(Some(output));
+    }
+
+    let code = client
+        .get_code(address, None)
+        .await
+        .with_context(|| format!("Failed to fetch the code at {address:?}"))?;
+    anyhow::ensure!(!code.is_empty(), "There is no contract at {address:?}");
+
+    Ok(None)
+}
+
+fn is_revert<M: Middleware>(error: &ContractError<M>) -> bool {
+    error.is_revert()
+        || error
+            .as_middleware_error()
+            .and_then(MiddlewareError::as_error_response)
+            .is_some_and(|response| response.message.contains("revert"))
+}
+
+fn is_missing_function(response: &JsonRpcError) -> bool {
+    response.message.contains("revert")
+        && response
+            .as_revert_data()
+            .map_or(true, |revert_data| revert_data.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{MockProvider, MockResponse, Provider};
+
+    use super::*;
+
+    fn revert(data: Option<&str>) -> MockResponse {
+        MockResponse::Error(JsonRpcError {
+            code:    3,
+            message: "execution reverted".to_string(),
+            data:    data.map(|data| serde_json::Value::String(data.to_string())),
+        })
+    }
+
+    #[tokio::test]
+    async fn getters_are_read_if_present() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+
+        let mut word = [0; 32];
+        U256::from(7).to_big_endian(&mut word);
+        mock.push(Bytes::from(word.to_vec())).unwrap();
+
+        let leaf = contract_leaf(Arc::new(provider), Address::zero(), 3)
+            .await
+            .unwrap();
+
+        assert_eq!(leaf, Some(U256::from(7)));
+    }
+
+    #[tokio::test]
+    async fn missing_selectors_mean_no_getter() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let provider = Arc::new(provider);
+
+        mock.push_response(revert(None));
+        assert_eq!(
+            contract_paused(provider.clone(), Address::zero())
+                .await
+                .unwrap(),
+            None
+        );
+
+        // Answered by a fallback function. Responses are popped in reverse
+        // order.
+        mock.push(Bytes::from(vec![0x60, 0x80])).unwrap();
+        mock.push(Bytes::default()).unwrap();
+        assert_eq!(
+            contract_leaf_count(provider, Address::zero())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn only_reverts_leave_the_tree_depth_unknown() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let provider = Arc::new(provider);
+
+        let mut word = [0; 32];
+        U256::from(30).to_big_endian(&mut word);
+        mock.push(Bytes::from(word.to_vec())).unwrap();
+        assert_eq!(
+            contract_tree_depth(provider.clone(), Address::zero())
+                .await
+                .unwrap(),
+            Some(30)
+        );
+
+        mock.push_response(revert(None));
+        assert_eq!(
+            contract_tree_depth(provider.clone(), Address::zero())
+                .await
+                .unwrap(),
+            None
+        );
+
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code:    -32602,
+            message: "invalid argument".to_string(),
+            data:    None,
+        }));
+        assert!(contract_tree_depth(provider.clone(), Address::zero())
+            .await
+            .is_err());
+
+        // Nothing deployed at the address
+        mock.push(Bytes::default()).unwrap();
+        assert!(contract_tree_depth(provider, Address::zero())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn other_failures_are_errors() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let provider = Arc::new(provider);
+
+        // The getter exists, but reverted with a reason
+        mock.push_response(revert(Some("0x08c379a0")));
+        assert!(contract_paused(provider.clone(), Address::zero())
+            .await
+            .is_err());
+
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code:    -32602,
+            message: "invalid argument".to_string(),
+            data:    None,
+        }));
+        assert!(contract_paused(provider.clone(), Address::zero())
+            .await
+            .is_err());
+
+        // Nothing deployed at the address
+        mock.push(Bytes::default()).unwrap();
+        mock.push(Bytes::default()).unwrap();
+        assert!(contract_leaf(provider, Address::zero(), 0).await.is_err());
+    }
+}
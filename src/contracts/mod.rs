@@ -1,34 +1,58 @@
 //! Functionality for interacting with smart contracts deployed on chain.
 pub mod abi;
+pub mod getters;
 pub mod scanner;
 
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, Context};
 use ethers::providers::Middleware;
 use ethers::types::{H256, U256};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_gauge_with_registry, IntGauge};
 use semaphore::Field;
 use tokio::sync::{RwLock, RwLockReadGuard};
 use tracing::{error, info, instrument, warn};
 
-use self::abi::{BridgedWorldId, DeleteIdentitiesCall, WorldId};
+use self::abi::{BridgedWorldId, DeleteIdentitiesCall, RegisterIdentitiesCall, WorldId};
 use crate::config::Config;
-use crate::ethereum::write::TransactionId;
-use crate::ethereum::{Ethereum, ReadProvider};
+use crate::ethereum::write::{TransactionId, TxError};
+use crate::ethereum::{Ethereum, ReadProvider, RelayerNonces, TransactionStatus, WindowSpend};
+use crate::metrics;
 use crate::prover::identity::Identity;
 use crate::prover::{Proof, Prover, ProverConfig, ProverMap, ProverType};
 use crate::server::error::Error as ServerError;
 use crate::utils::index_packing::unpack_indices;
 
+/// How long the contract's latest root is reused when checking proofs on chain
+const LATEST_ROOT_CACHE_TTL: Duration = Duration::from_secs(2);
+
+static TREE_FULL: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "identity_tree_full",
+        "Whether the tree on chain has no room for another insertion batch.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
 /// A structure representing the interface to the batch-based identity manager
 /// contract.
 #[derive(Debug)]
 pub struct IdentityManager {
-    ethereum:             Ethereum,
+    ethereum: Ethereum,
     insertion_prover_map: RwLock<ProverMap>,
-    deletion_prover_map:  RwLock<ProverMap>,
-    abi:                  WorldId<ReadProvider>,
-    secondary_abis:       Vec<BridgedWorldId<ReadProvider>>,
-    initial_leaf_value:   Field,
-    tree_depth:           usize,
+    deletion_prover_map: RwLock<ProverMap>,
+    abi: WorldId<ReadProvider>,
+    secondary_abis: Vec<BridgedWorldId<ReadProvider>>,
+    initial_leaf_value: Field,
+    tree_depth: usize,
+    tree_capacity: Option<usize>,
+    check_root_before_submit: bool,
+    pausable: bool,
+    // When the latest root was read and its value
+    latest_root_cache: Mutex<Option<(Instant, U256)>>,
 }
 
 impl IdentityManager {
@@ -51,8 +75,11 @@ impl IdentityManager {
     where
         Self: Sized,
     {
+        let address = config
+            .network
+            .resolve_identity_manager_address(ethereum.provider().chain_id.as_u64())?;
+
         // Check that there is code deployed at the target address.
-        let address = config.network.identity_manager_address;
         let code = ethereum.provider().get_code(address, None).await?;
         if code.as_ref().is_empty() {
             error!(
@@ -62,10 +89,7 @@ impl IdentityManager {
         }
 
         // Connect to the running batching contract.
-        let abi = WorldId::new(
-            config.network.identity_manager_address,
-            ethereum.provider().clone(),
-        );
+        let abi = WorldId::new(address, ethereum.provider().clone());
 
         let operator = abi.identity_operator().call().await?;
         if operator != ethereum.address() {
@@ -93,6 +117,46 @@ impl IdentityManager {
 
         let initial_leaf_value = config.tree.initial_leaf_value;
         let tree_depth = config.tree.tree_depth;
+        let check_root_before_submit = config.app.check_root_before_submit;
+
+        // Proofs are sized by the configured depth, so they would be rejected
+        // by a verifier for a different depth
+        let contract_depth =
+            match getters::contract_tree_depth(Arc::new(ethereum.provider().clone()), address)
+                .await
+                .context("Failed to read the tree depth from the contract")?
+            {
+                Some(depth) => usize::from(depth),
+                None => {
+                    warn!(
+                        tree_depth,
+                        "The contract doesn't expose its tree depth, using the configured depth"
+                    );
+                    tree_depth
+                }
+            };
+
+        if contract_depth != tree_depth {
+            return Err(anyhow!(
+                "Configured tree depth {tree_depth} doesn't match the contract's tree depth \
+                 {contract_depth}"
+            ));
+        }
+
+        // Checked whether the capacity is enforced or not, since leaves can't be
+        // indexed in a deeper tree either
+        let capacity = tree_capacity(contract_depth)
+            .with_context(|| format!("Unsupported contract tree depth {contract_depth}"))?;
+        let tree_capacity = config.app.check_tree_capacity.then_some(capacity);
+
+        let pausable =
+            match getters::contract_paused(Arc::new(ethereum.provider().clone()), address).await? {
+                Some(paused) => {
+                    info!(paused, "The identity manager contract has a pause guard");
+                    true
+                }
+                None => false,
+            };
 
         let insertion_prover_map = RwLock::new(insertion_prover_map);
         let deletion_prover_map = RwLock::new(deletion_prover_map);
@@ -105,6 +169,10 @@ impl IdentityManager {
             secondary_abis,
             initial_leaf_value,
             tree_depth,
+            tree_capacity,
+            check_root_before_submit,
+            pausable,
+            latest_root_cache: Mutex::new(None),
         };
 
         Ok(identity_manager)
@@ -238,6 +306,9 @@ impl IdentityManager {
     ) -> anyhow::Result<TransactionId> {
         let actual_start_index: u32 = start_index.try_into()?;
 
+        self.ensure_capacity(start_index, identity_commitments.len())?;
+        self.ensure_root_unchanged(pre_root).await?;
+
         let proof_points_array: [U256; 8] = proof_data.into();
         let identities = identity_commitments
             .iter()
@@ -273,6 +344,8 @@ impl IdentityManager {
         pre_root: U256,
         post_root: U256,
     ) -> anyhow::Result<TransactionId> {
+        self.ensure_root_unchanged(pre_root).await?;
+
         let proof_points_array: [U256; 8] = deletion_proof.into();
 
         let delete_identities_transaction = self
@@ -291,6 +364,49 @@ impl IdentityManager {
             .map_err(|tx_err| anyhow!("{}", tx_err.to_string()))
     }
 
+    /// Aborts with [`TxError::TreeFull`] if a batch doesn't fit in the tree on
+    /// chain, which would make the transaction revert. A no-op unless
+    /// `check_tree_capacity` is enabled.
+    fn ensure_capacity(&self, start_index: usize, batch_size: usize) -> anyhow::Result<()> {
+        let Some(capacity) = self.tree_capacity else {
+            return Ok(());
+        };
+
+        if start_index + batch_size > capacity {
+            TREE_FULL.set(1);
+            warn!(
+                start_index,
+                batch_size, capacity, "Tree is full, aborting submission"
+            );
+            return Err(TxError::TreeFull { capacity }.into());
+        }
+
+        TREE_FULL.set(0);
+
+        Ok(())
+    }
+
+    /// Aborts with [`TxError::RootMoved`] if the contract's latest root no
+    /// longer matches the root a batch was built on. A no-op unless
+    /// `check_root_before_submit` is enabled.
+    async fn ensure_root_unchanged(&self, expected: U256) -> anyhow::Result<()> {
+        if !self.check_root_before_submit {
+            return Ok(());
+        }
+
+        let actual = self.latest_root().await?;
+        if actual != expected {
+            warn!(
+                ?expected,
+                ?actual,
+                "Contract root moved, aborting submission"
+            );
+            return Err(TxError::RootMoved { expected, actual }.into());
+        }
+
+        Ok(())
+    }
+
     #[instrument(level = "debug", skip(self))]
     pub async fn mine_transaction(&self, transaction_id: TransactionId) -> anyhow::Result<bool> {
         let result = self.ethereum.mine_transaction(transaction_id).await?;
@@ -298,6 +414,58 @@ impl IdentityManager {
         Ok(result)
     }
 
+    /// How long to wait before resubmitting a failed transaction again, `None`
+    /// if the failed policy doesn't resubmit it anymore
+    pub fn resubmission_backoff(&self, resubmissions: u32) -> Option<Duration> {
+        self.ethereum.resubmission_backoff(resubmissions)
+    }
+
+    /// Sends a failed transaction again, returning the id of the resubmission
+    #[instrument(level = "debug", skip(self))]
+    pub async fn resubmit_transaction(
+        &self,
+        transaction_id: TransactionId,
+    ) -> anyhow::Result<TransactionId> {
+        Ok(self.ethereum.resubmit_transaction(transaction_id).await?)
+    }
+
+    /// Cancels a pending transaction by replacing it with an empty one under
+    /// the same nonce. Whichever of the two is mined settles the nonce.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn cancel_transaction(&self, transaction_id: TransactionId) -> anyhow::Result<()> {
+        Ok(self.ethereum.cancel_transaction(transaction_id).await?)
+    }
+
+    /// The nonces of the relayers, to diagnose transactions stuck behind a
+    /// lower nonce
+    #[instrument(level = "debug", skip(self))]
+    pub async fn relayer_nonces(&self) -> Vec<RelayerNonces> {
+        self.ethereum.relayer_nonces().await
+    }
+
+    /// The relayer transaction of the batch leading to `next_root`, if the
+    /// relayer still lists it and it hasn't failed. The proof differs between
+    /// attempts, so batches are recognized by the root in the calldata.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn find_batch_submission(
+        &self,
+        next_root: U256,
+    ) -> anyhow::Result<Option<TransactionId>> {
+        let matches = |calldata: &[u8]| batch_post_root(calldata) == Some(next_root);
+
+        Ok(self.ethereum.find_transaction(&matches).await?)
+    }
+
+    /// The status of a sent transaction along with its fees and nonce, to
+    /// diagnose why it's stuck. `None` if the relayer doesn't report it.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn transaction_status(
+        &self,
+        transaction_id: TransactionId,
+    ) -> anyhow::Result<Option<TransactionStatus>> {
+        Ok(self.ethereum.transaction_status(transaction_id).await?)
+    }
+
     #[instrument(level = "debug", skip_all)]
     pub async fn fetch_pending_identities(&self) -> anyhow::Result<Vec<TransactionId>> {
         let pending_identities = self.ethereum.fetch_pending_transactions().await?;
@@ -320,6 +488,52 @@ impl IdentityManager {
         Ok(())
     }
 
+    /// The number of leaves inserted on chain, if the contract exposes it
+    #[instrument(level = "debug", skip_all)]
+    pub async fn leaf_count(&self) -> anyhow::Result<Option<usize>> {
+        let leaf_count = getters::contract_leaf_count(
+            Arc::new(self.ethereum.provider().clone()),
+            self.abi.address(),
+        )
+        .await?;
+
+        leaf_count
+            .map(|leaf_count| usize::try_from(leaf_count).map_err(|err| anyhow!(err)))
+            .transpose()
+    }
+
+    /// Whether the contract is paused, `None` if it has no pause guard
+    #[instrument(level = "debug", skip_all)]
+    pub async fn is_contract_paused(&self) -> anyhow::Result<Option<bool>> {
+        if !self.pausable {
+            return Ok(None);
+        }
+
+        getters::contract_paused(
+            Arc::new(self.ethereum.provider().clone()),
+            self.abi.address(),
+        )
+        .await
+    }
+
+    /// Fetches the balance of the account submitting transactions.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn relayer_balance(&self) -> anyhow::Result<U256> {
+        let balance = self
+            .ethereum
+            .provider()
+            .get_balance(self.ethereum.address(), None)
+            .await?;
+
+        Ok(balance)
+    }
+
+    /// The gas spend within the current spend limit window, `None` if there's
+    /// no limit. Fails with `TxError::SpendLimitExceeded` once it's reached.
+    pub fn check_spend_limit(&self) -> Result<Option<WindowSpend>, TxError> {
+        self.ethereum.check_spend_limit()
+    }
+
     #[instrument(level = "debug", skip_all)]
     pub async fn latest_root(&self) -> anyhow::Result<U256> {
         let latest_root = self.abi.latest_root().call().await?;
@@ -367,6 +581,27 @@ impl IdentityManager {
         Ok(true)
     }
 
+    /// The latest root as of `confirmations` blocks below the chain head, along
+    /// with that block. Needs a provider serving calls at past blocks.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn chain_head(&self) -> anyhow::Result<u64> {
+        Ok(self.ethereum.provider().get_block_number().await?.as_u64())
+    }
+
+    /// The latest root of the contract as of `block`. The provider has to
+    /// serve calls at that block.
+    pub async fn root_at(&self, block: u64) -> anyhow::Result<U256> {
+        Ok(self.abi.latest_root().block(block).call().await?)
+    }
+
+    /// Whether the contract accepts proofs against `root`, i.e. it's the latest
+    /// root or a previous one that hasn't expired yet. The latest root is
+    /// cached for [`LATEST_ROOT_CACHE_TTL`], other roots cost a call each.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn is_root_valid_on_chain(&self, root: U256) -> anyhow::Result<bool> {
+        is_root_valid(&self.abi, &self.latest_root_cache, root).await
+    }
+
     #[instrument(level = "debug", skip_all)]
     pub async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool> {
         let (root_on_mainnet, ..) = self.abi.query_root(root).call().await?;
@@ -468,3 +703,160 @@ impl IdentityManager {
         self.deletion_prover_map.read().await.len() > 0
     }
 }
+
+/// The root after the batch that `calldata` submits, `None` if it isn't a batch
+fn batch_post_root(calldata: &[u8]) -> Option<U256> {
+    use ethers::abi::AbiDecode;
+
+    if let Ok(insertion) = RegisterIdentitiesCall::decode(calldata) {
+        return Some(insertion.post_root);
+    }
+
+    DeleteIdentitiesCall::decode(calldata)
+        .ok()
+        .map(|deletion| deletion.post_root)
+}
+
+/// The number of leaves of a tree of `depth`
+fn tree_capacity(depth: usize) -> anyhow::Result<usize> {
+    u32::try_from(depth)
+        .ok()
+        .and_then(|depth| 1usize.checked_shl(depth))
+        .ok_or_else(|| anyhow!("The capacity of a tree of depth {depth} overflows"))
+}
+
+async fn is_root_valid<M>(
+    contract: &WorldId<M>,
+    latest_root_cache: &Mutex<Option<(Instant, U256)>>,
+    root: U256,
+) -> anyhow::Result<bool>
+where
+    M: Middleware + 'static,
+{
+    let cached = *latest_root_cache.lock().unwrap();
+    let latest_root = match cached {
+        Some((read_at, latest_root)) if read_at.elapsed() < LATEST_ROOT_CACHE_TTL => latest_root,
+        _ => {
+            let latest_root = contract.latest_root().call().await?;
+            *latest_root_cache.lock().unwrap() = Some((Instant::now(), latest_root));
+            latest_root
+        }
+    };
+
+    if root == latest_root {
+        return Ok(true);
+    }
+
+    let (root_on_mainnet, _, is_valid) = contract.query_root(root).call().await?;
+
+    Ok(!root_on_mainnet.is_zero() && is_valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::{encode, Token};
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{Address, Bytes};
+
+    use super::*;
+
+    fn mocked_contract() -> (WorldId<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+
+        (WorldId::new(Address::zero(), Arc::new(provider)), mock)
+    }
+
+    fn root_info(root: u64, is_valid: bool) -> Bytes {
+        encode(&[
+            Token::Uint(root.into()),
+            Token::Uint(U256::zero()),
+            Token::Bool(is_valid),
+        ])
+        .into()
+    }
+
+    #[tokio::test]
+    async fn cached_latest_roots_are_valid_without_a_call() {
+        // No responses are queued, so any call fails
+        let (contract, _mock) = mocked_contract();
+        let cache = Mutex::new(Some((Instant::now(), U256::from(7))));
+
+        assert!(is_root_valid(&contract, &cache, U256::from(7))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_latest_roots_are_read_again() {
+        let (contract, mock) = mocked_contract();
+        let expired_at = Instant::now().checked_sub(LATEST_ROOT_CACHE_TTL).unwrap();
+        let cache = Mutex::new(Some((expired_at, U256::from(7))));
+
+        mock.push(Bytes::from(encode(&[Token::Uint(U256::from(9))])))
+            .unwrap();
+
+        assert!(is_root_valid(&contract, &cache, U256::from(9))
+            .await
+            .unwrap());
+
+        let (read_at, latest_root) = cache.lock().unwrap().unwrap();
+        assert!(read_at > expired_at);
+        assert_eq!(latest_root, U256::from(9));
+    }
+
+    #[tokio::test]
+    async fn roots_other_than_the_latest_are_queried() {
+        let (contract, mock) = mocked_contract();
+        let cache = Mutex::new(Some((Instant::now(), U256::from(7))));
+
+        mock.push(root_info(5, true)).unwrap();
+        assert!(is_root_valid(&contract, &cache, U256::from(5))
+            .await
+            .unwrap());
+
+        // Known but expired
+        mock.push(root_info(4, false)).unwrap();
+        assert!(!is_root_valid(&contract, &cache, U256::from(4))
+            .await
+            .unwrap());
+
+        // Never a root of the tree
+        mock.push(root_info(0, true)).unwrap();
+        assert!(!is_root_valid(&contract, &cache, U256::from(3))
+            .await
+            .unwrap());
+
+        // The cached latest root was used throughout
+        assert_eq!(cache.lock().unwrap().unwrap().1, U256::from(7));
+    }
+
+    #[test]
+    fn batches_are_recognized_by_their_post_root() {
+        use ethers::abi::AbiEncode;
+
+        let insertion = RegisterIdentitiesCall {
+            insertion_proof:      [U256::zero(); 8],
+            pre_root:             U256::from(1),
+            start_index:          0,
+            identity_commitments: vec![U256::from(3)],
+            post_root:            U256::from(2),
+        };
+        let deletion = DeleteIdentitiesCall {
+            deletion_proof:          [U256::zero(); 8],
+            packed_deletion_indices: vec![0, 0, 0, 1].into(),
+            pre_root:                U256::from(2),
+            post_root:               U256::from(4),
+        };
+
+        assert_eq!(batch_post_root(&insertion.encode()), Some(U256::from(2)));
+        assert_eq!(batch_post_root(&deletion.encode()), Some(U256::from(4)));
+        assert_eq!(batch_post_root(&[0x12, 0x34, 0x56, 0x78]), None);
+    }
+
+    #[test]
+    fn tree_capacity_overflow_is_an_error() {
+        assert_eq!(tree_capacity(30).unwrap(), 1 << 30);
+        assert!(tree_capacity(usize::BITS as usize).is_err());
+        assert!(tree_capacity(usize::MAX).is_err());
+    }
+}
@@ -1,15 +1,78 @@
-use ethers::providers::Middleware;
-use ethers::types::{Address, BlockNumber, Filter, FilterBlockOption, Log, Topic, ValueOrArray};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use anyhow::Context;
+use ethers::providers::{Middleware, MiddlewareError};
+use ethers::types::{
+    Address, BlockNumber, Filter, FilterBlockOption, Log, Topic, ValueOrArray, H256, U256,
+};
+use tracing::{debug, warn};
+
+use crate::ethereum::confirmation::Confirmations;
+use crate::ethereum::read::log_batcher::LogBatcher;
+
+/// How many of the last scanned ranges are checked for reorgs when pinning by
+/// hash
+const MAX_PINNED_RANGES: usize = 16;
+
+/// A scanned range whose last block was queried by hash
+struct PinnedRange {
+    from_block:    u64,
+    to_block:      u64,
+    to_block_hash: H256,
+    // The blocks that the returned logs came from, by hash
+    log_blocks:    HashMap<H256, u64>,
+}
+
+impl PinnedRange {
+    fn new(from_block: u64, to_block: u64, to_block_hash: H256, logs: &[Log]) -> Self {
+        Self {
+            from_block,
+            to_block,
+            to_block_hash,
+            log_blocks: logs
+                .iter()
+                .filter_map(|log| Some((log.block_hash?, log.block_number?.as_u64())))
+                .collect(),
+        }
+    }
+}
 
 pub struct BlockScanner<T> {
     read_provider: T,
+    // The first block scanned, overlaps don't reach below it
+    start_block:   u64,
     current_block: u64,
     window_size:   u64,
+    // The latest block scanned to as of the last call to `next`
+    chain_head:    u64,
 
     // How many blocks from the chain head to scan to
     // e.g. if latest block is 20 and offset is set to 3
     // then the scanner will scan until block 17
     chain_head_offset: u64,
+
+    // Decides what the chain head is, e.g. the latest or the finalized block
+    confirmations: Confirmations,
+
+    // Whether the last block of each range is queried by hash
+    pin_by_hash:         bool,
+    // Cleared once the provider rejects a query by block hash
+    supports_block_hash: bool,
+    // The last ranges scanned, oldest first, when pinning by hash
+    pinned_ranges:       VecDeque<PinnedRange>,
+    // The first block whose returned logs were reorged out since the last call
+    // to `take_reorged_from`
+    reorged_from:        Option<u64>,
+
+    // Fetches several windows per call. Dropped once the provider rejects a
+    // batch.
+    batcher: Option<LogBatcher>,
+
+    // How many blocks before the current block each scan starts
+    overlap:   u64,
+    // The logs returned from blocks that the next scan overlaps, by
+    // transaction hash and log index, with their block number
+    seen_logs: HashMap<(H256, U256), u64>,
 }
 
 impl<T> BlockScanner<T>
@@ -17,14 +80,43 @@ where
     T: Middleware,
     <T as Middleware>::Error: 'static,
 {
+    pub fn new(read_provider: T, current_block: u64, window_size: u64) -> Self {
+        Self {
+            read_provider,
+            start_block: current_block,
+            current_block,
+            window_size,
+            chain_head: current_block,
+            chain_head_offset: 0,
+            confirmations: Confirmations::default(),
+            pin_by_hash: false,
+            supports_block_hash: true,
+            pinned_ranges: VecDeque::new(),
+            reorged_from: None,
+            batcher: None,
+            overlap: 0,
+            seen_logs: HashMap::new(),
+        }
+    }
+
     pub async fn new_latest(read_provider: T, window_size: u64) -> anyhow::Result<Self> {
         let latest_block = read_provider.get_block_number().await?;
 
         Ok(Self {
             read_provider,
+            start_block: latest_block.as_u64(),
             current_block: latest_block.as_u64(),
             window_size,
+            chain_head: latest_block.as_u64(),
             chain_head_offset: 0,
+            confirmations: Confirmations::default(),
+            pin_by_hash: false,
+            supports_block_hash: true,
+            pinned_ranges: VecDeque::new(),
+            reorged_from: None,
+            batcher: None,
+            overlap: 0,
+            seen_logs: HashMap::new(),
         })
     }
 
@@ -33,40 +125,614 @@ where
         self
     }
 
+    /// Only scans blocks that are confirmed according to `confirmations`. The
+    /// chain head offset is applied on top.
+    pub fn with_confirmations(mut self, confirmations: Confirmations) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Queries the last block of each range by hash, so that its events can't
+    /// come from a block that's been reorged out. If the last blocks of recent
+    /// ranges turn out to have been replaced by the time of the next scan,
+    /// the ranges are scanned again and the events of the replacing blocks
+    /// are returned as well.
+    pub fn with_block_hash_pinning(mut self) -> Self {
+        self.pin_by_hash = true;
+        self
+    }
+
+    /// Starts every scan `overlap` blocks before the current block, so that
+    /// events missing from a window because a load balanced provider served
+    /// it from a node lagging behind are picked up by the next scan. Events
+    /// that were already returned are dropped.
+    pub fn with_overlap(mut self, overlap: u64) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Fetches up to the batcher's batch size of windows in one round trip.
+    /// Ignored when pinning by hash.
+    pub fn with_batching(mut self, batcher: LogBatcher) -> Self {
+        self.batcher = Some(batcher);
+        self
+    }
+
+    /// Logs how many round trips batching saved, if enabled
+    pub fn log_batching_summary(&self) {
+        if let Some(batcher) = &self.batcher {
+            batcher.log_summary();
+        }
+    }
+
+    /// The first block whose logs, as returned by an earlier scan, were reorged
+    /// out since the last call, if any. Every log returned from that block on
+    /// is gone, since reorgs replace all blocks past some block.
+    pub fn take_reorged_from(&mut self) -> Option<u64> {
+        self.reorged_from.take()
+    }
+
+    /// The next block that will be scanned
+    pub const fn current_block(&self) -> u64 {
+        self.current_block
+    }
+
+    /// The number of blocks the scanner is behind the chain head, as of the
+    /// last call to `next`
+    pub const fn block_lag(&self) -> u64 {
+        self.chain_head.saturating_sub(self.current_block)
+    }
+
+    /// The latest block considered by the last call to `next`
+    pub const fn chain_head(&self) -> u64 {
+        self.chain_head
+    }
+
     pub async fn next(
         &mut self,
         address: Option<ValueOrArray<Address>>,
         topics: [Option<Topic>; 4],
     ) -> anyhow::Result<Vec<Log>> {
-        let latest_block = self.read_provider.get_block_number().await?.as_u64();
+        let latest_block = self
+            .confirmations
+            .confirmed_head(&self.read_provider)
+            .await?;
         let latest_block = latest_block.saturating_sub(self.chain_head_offset);
+        self.chain_head = latest_block;
 
         if self.current_block >= latest_block {
             return Ok(Vec::new());
         }
 
         let from_block = self.current_block;
-        let to_block = latest_block.min(from_block + self.window_size);
+        let mut to_block = latest_block.min(from_block + self.window_size);
+        let query_from = from_block
+            .saturating_sub(self.overlap)
+            .max(self.start_block);
 
-        let next_current_block = to_block + 1;
+        let logs = if self.pin_by_hash {
+            let mut logs = self.repair_pinned_ranges(&address, &topics).await?;
 
-        let from_block = Some(BlockNumber::Number(from_block.into()));
-        let to_block = Some(BlockNumber::Number(to_block.into()));
+            let to_block_hash = self.block_hash(to_block).await?;
+            let range_logs = self
+                .fetch_pinned_range(query_from, to_block, to_block_hash, &address, &topics)
+                .await?;
 
-        let logs = self
-            .read_provider
-            .get_logs(&Filter {
-                block_option: FilterBlockOption::Range {
-                    from_block,
-                    to_block,
-                },
-                address,
-                topics,
+            self.pin(PinnedRange::new(
+                from_block,
+                to_block,
+                to_block_hash,
+                &range_logs,
+            ));
+            logs.extend(range_logs);
+
+            logs
+        } else if let Some(logs) = self
+            .fetch_batched(query_from, latest_block, &address, &topics)
+            .await?
+        {
+            let (logs, batch_to_block) = logs;
+            to_block = batch_to_block;
+
+            logs
+        } else {
+            self.fetch_range(query_from, to_block, &address, &topics)
+                .await?
+        };
+        let logs = self.deduplicate(logs, to_block);
+
+        if logs.is_empty() {
+            debug!(from_block, to_block, "No new events in range");
+        }
+
+        self.current_block = to_block + 1;
+
+        Ok(logs)
+    }
+
+    /// Fetches as many windows as fit in a batch, returning their logs and the
+    /// last block fetched. `None` if batching is disabled or unsupported.
+    async fn fetch_batched(
+        &mut self,
+        from_block: u64,
+        latest_block: u64,
+        address: &Option<ValueOrArray<Address>>,
+        topics: &[Option<Topic>; 4],
+    ) -> anyhow::Result<Option<(Vec<Log>, u64)>> {
+        let Some(batcher) = &mut self.batcher else {
+            return Ok(None);
+        };
+
+        let windows = windows(
+            from_block,
+            latest_block,
+            self.window_size,
+            batcher.batch_size(),
+        );
+        let filters: Vec<_> = windows
+            .iter()
+            .map(|&(from_block, to_block)| range_filter(from_block, to_block, address, topics))
+            .collect();
+
+        let Some(logs) = batcher.get_logs(&filters).await? else {
+            self.batcher = None;
+            return Ok(None);
+        };
+
+        let to_block = windows.last().map_or(from_block, |&(_, to_block)| to_block);
+
+        Ok(Some((logs, to_block)))
+    }
+
+    /// Drops the logs already returned by the previous scan, which the
+    /// overlap fetches again
+    fn deduplicate(&mut self, logs: Vec<Log>, to_block: u64) -> Vec<Log> {
+        if self.overlap == 0 {
+            return logs;
+        }
+
+        let seen_logs = &mut self.seen_logs;
+        let logs = logs
+            .into_iter()
+            .filter(|log| {
+                let (Some(tx_hash), Some(log_index), Some(block_number)) =
+                    (log.transaction_hash, log.log_index, log.block_number)
+                else {
+                    return true;
+                };
+
+                seen_logs
+                    .insert((tx_hash, log_index), block_number.as_u64())
+                    .is_none()
             })
+            .collect();
+
+        // Only logs within the overlap of the next scan can be fetched again
+        let next_query_from = (to_block + 1).saturating_sub(self.overlap);
+        seen_logs.retain(|_, block_number| *block_number >= next_query_from);
+
+        logs
+    }
+
+    // An error here must be propagated rather than treated as an empty range,
+    // otherwise the scanner would advance past blocks it never saw.
+    async fn fetch_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        address: &Option<ValueOrArray<Address>>,
+        topics: &[Option<Topic>; 4],
+    ) -> anyhow::Result<Vec<Log>> {
+        self.read_provider
+            .get_logs(&range_filter(from_block, to_block, address, topics))
+            .await
+            .with_context(|| format!("Failed to fetch logs in range [{from_block}, {to_block}]"))
+    }
+
+    /// Fetches the events of exactly the block with the given hash. Falls back
+    /// to querying by number, keeping only the events from that block, if the
+    /// provider doesn't support querying by hash.
+    pub async fn fetch_events_by_hash(
+        &mut self,
+        block_number: u64,
+        block_hash: H256,
+        address: &Option<ValueOrArray<Address>>,
+        topics: &[Option<Topic>; 4],
+    ) -> anyhow::Result<Vec<Log>> {
+        if self.supports_block_hash {
+            let result = self
+                .read_provider
+                .get_logs(&Filter {
+                    block_option: FilterBlockOption::AtBlockHash(block_hash),
+                    address:      address.clone(),
+                    topics:       topics.clone(),
+                })
+                .await;
+
+            match result {
+                Ok(logs) => return Ok(logs),
+                // Only a rejected request means the query isn't supported
+                Err(error) if error.as_error_response().is_some() => {
+                    warn!(
+                        ?error,
+                        "Provider doesn't support fetching logs by block hash, falling back to \
+                         block numbers"
+                    );
+                    self.supports_block_hash = false;
+                }
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("Failed to fetch logs of block {block_hash:?}"))
+                }
+            }
+        }
+
+        let logs = self
+            .fetch_range(block_number, block_number, address, topics)
             .await?;
 
-        self.current_block = next_current_block;
+        Ok(logs
+            .into_iter()
+            .filter(|log| log.block_hash == Some(block_hash))
+            .collect())
+    }
+
+    /// Fetches the events of `[from_block, to_block]`, querying the last block
+    /// by hash
+    async fn fetch_pinned_range(
+        &mut self,
+        from_block: u64,
+        to_block: u64,
+        to_block_hash: H256,
+        address: &Option<ValueOrArray<Address>>,
+        topics: &[Option<Topic>; 4],
+    ) -> anyhow::Result<Vec<Log>> {
+        let mut logs = if from_block < to_block {
+            self.fetch_range(from_block, to_block - 1, address, topics)
+                .await?
+        } else {
+            Vec::new()
+        };
+        logs.extend(
+            self.fetch_events_by_hash(to_block, to_block_hash, address, topics)
+                .await?,
+        );
 
         Ok(logs)
     }
+
+    fn pin(&mut self, range: PinnedRange) {
+        self.pinned_ranges.push_back(range);
+        while self.pinned_ranges.len() > MAX_PINNED_RANGES {
+            self.pinned_ranges.pop_front();
+        }
+    }
+
+    /// Scans the pinned ranges reorged out since they were scanned again,
+    /// returning the events of the replacing blocks. A range is intact if its
+    /// last block is, so the reorged ranges are the ones after the latest
+    /// intact range.
+    async fn repair_pinned_ranges(
+        &mut self,
+        address: &Option<ValueOrArray<Address>>,
+        topics: &[Option<Topic>; 4],
+    ) -> anyhow::Result<Vec<Log>> {
+        let mut reorged = 0;
+        let mut to_block_hash = None;
+        for range in self.pinned_ranges.iter().rev() {
+            let block_hash = self.block_hash(range.to_block).await?;
+            if block_hash == range.to_block_hash {
+                break;
+            }
+
+            to_block_hash.get_or_insert(block_hash);
+            reorged += 1;
+        }
+
+        let Some(to_block_hash) = to_block_hash else {
+            return Ok(Vec::new());
+        };
+
+        let first_reorged = self.pinned_ranges.len() - reorged;
+        let from_block = self.pinned_ranges[first_reorged].from_block;
+        let to_block = self.pinned_ranges[self.pinned_ranges.len() - 1].to_block;
+
+        // Blocks before the tracked ranges may have been reorged out as well
+        // if none of them is intact
+        warn!(
+            from_block,
+            to_block,
+            reaches_past_tracked = first_reorged == 0,
+            "Scanned blocks were reorged out, rescanning them"
+        );
+
+        let logs = self
+            .fetch_pinned_range(from_block, to_block, to_block_hash, address, topics)
+            .await?;
+
+        // The events of blocks that weren't replaced were returned already
+        let seen_blocks: HashMap<H256, u64> = self
+            .pinned_ranges
+            .drain(first_reorged..)
+            .flat_map(|range| range.log_blocks)
+            .collect();
+        self.pin(PinnedRange::new(from_block, to_block, to_block_hash, &logs));
+
+        let kept_blocks: HashSet<H256> = logs.iter().filter_map(|log| log.block_hash).collect();
+        let gone_from = seen_blocks
+            .iter()
+            .filter(|(block_hash, _)| !kept_blocks.contains(block_hash))
+            .map(|(_, &block_number)| block_number)
+            .min();
+        if let Some(gone_from) = gone_from {
+            self.reorged_from = Some(
+                self.reorged_from
+                    .map_or(gone_from, |reorged_from| reorged_from.min(gone_from)),
+            );
+        }
+
+        Ok(logs
+            .into_iter()
+            .filter(|log| {
+                log.block_hash
+                    .map_or(true, |block_hash| !seen_blocks.contains_key(&block_hash))
+            })
+            .collect())
+    }
+
+    async fn block_hash(&self, block_number: u64) -> anyhow::Result<H256> {
+        self.read_provider
+            .get_block(block_number)
+            .await
+            .with_context(|| format!("Failed to fetch block {block_number}"))?
+            .and_then(|block| block.hash)
+            .with_context(|| format!("Block {block_number} has no hash"))
+    }
+}
+
+fn range_filter(
+    from_block: u64,
+    to_block: u64,
+    address: &Option<ValueOrArray<Address>>,
+    topics: &[Option<Topic>; 4],
+) -> Filter {
+    Filter {
+        block_option: FilterBlockOption::Range {
+            from_block: Some(BlockNumber::Number(from_block.into())),
+            to_block:   Some(BlockNumber::Number(to_block.into())),
+        },
+        address:      address.clone(),
+        topics:       topics.clone(),
+    }
+}
+
+/// Splits `[from_block, latest_block]` into up to `count` consecutive windows,
+/// the same way successive scans would
+fn windows(from_block: u64, latest_block: u64, window_size: u64, count: usize) -> Vec<(u64, u64)> {
+    let mut windows = Vec::with_capacity(count);
+    let mut start = from_block;
+
+    while windows.len() < count && start <= latest_block {
+        let end = latest_block.min(start + window_size);
+        windows.push((start, end));
+        start = end + 1;
+    }
+
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+    use ethers::types::{Block, U64};
+
+    use super::*;
+
+    fn scanner(provider: Provider<MockProvider>) -> BlockScanner<Provider<MockProvider>> {
+        BlockScanner::new(provider, 10, 100)
+    }
+
+    #[test]
+    fn batched_windows_match_successive_scans() {
+        assert_eq!(windows(10, 35, 10, 5), vec![(10, 20), (21, 31), (32, 35)]);
+        assert_eq!(windows(10, 100, 10, 2), vec![(10, 20), (21, 31)]);
+        assert!(windows(10, 9, 10, 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn empty_range_advances_the_scanner() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = scanner(provider);
+
+        // Responses are popped in reverse order
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(U64::from(20)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+
+        assert!(logs.is_empty());
+        assert_eq!(scanner.current_block, 21);
+    }
+
+    #[tokio::test]
+    async fn overlapping_scans_drop_seen_logs() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = scanner(provider).with_overlap(2);
+
+        let log = |block_number: u64, log_index: u64| Log {
+            block_number: Some(block_number.into()),
+            transaction_hash: Some(H256::from_low_u64_be(block_number)),
+            log_index: Some(log_index.into()),
+            ..Default::default()
+        };
+
+        // Responses are popped in reverse order
+        mock.push::<Vec<Log>, _>(vec![log(20, 0), log(25, 1)])
+            .unwrap();
+        mock.push(U64::from(30)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(20, 0)]).unwrap();
+        mock.push(U64::from(20)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+        assert_eq!(logs, vec![log(20, 0)]);
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+        assert_eq!(logs, vec![log(25, 1)]);
+        assert_eq!(scanner.seen_logs.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn provider_errors_are_not_masked() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = scanner(provider);
+
+        // Only the block number is answered, the logs request fails
+        mock.push(U64::from(20)).unwrap();
+
+        let result = scanner.next(None, Default::default()).await;
+
+        assert!(result.is_err());
+        assert_eq!(scanner.current_block, 10);
+    }
+
+    #[tokio::test]
+    async fn pinned_block_falls_back_to_block_numbers() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = scanner(provider).with_block_hash_pinning();
+
+        let block_hash = H256::repeat_byte(1);
+        let log = |hash| Log {
+            block_hash: Some(hash),
+            ..Default::default()
+        };
+
+        mock.push::<Vec<Log>, _>(vec![log(block_hash), log(H256::repeat_byte(2))])
+            .unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code:    -32602,
+            message: "blockHash is not supported".to_string(),
+            data:    None,
+        }));
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(Block::<H256> {
+            hash: Some(block_hash),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(U64::from(20)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_hash, Some(block_hash));
+        assert!(!scanner.supports_block_hash);
+        let pinned = scanner.pinned_ranges.back().unwrap();
+        assert_eq!(
+            (pinned.from_block, pinned.to_block, pinned.to_block_hash),
+            (10, 20, block_hash)
+        );
+    }
+
+    #[tokio::test]
+    async fn reorged_ranges_are_scanned_again() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = scanner(provider).with_block_hash_pinning();
+
+        let log = |block_number: u64, block_hash: u8| Log {
+            block_number: Some(block_number.into()),
+            block_hash: Some(H256::repeat_byte(block_hash)),
+            ..Default::default()
+        };
+        let block = |block_hash: u8| Block::<H256> {
+            hash: Some(H256::repeat_byte(block_hash)),
+            ..Default::default()
+        };
+
+        // Responses are popped in reverse order
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(15, 1)]).unwrap();
+        mock.push(block(2)).unwrap();
+        mock.push(U64::from(20)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+        assert_eq!(logs, vec![log(15, 1)]);
+
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(25, 3)]).unwrap();
+        mock.push(block(4)).unwrap();
+        mock.push(block(2)).unwrap();
+        mock.push(U64::from(30)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+        assert_eq!(logs, vec![log(25, 3)]);
+
+        // Blocks 25 to 30 were replaced, but only the events of the new blocks
+        // are returned again
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(block(8)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(30, 7)]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(25, 3), log(27, 6)])
+            .unwrap();
+        mock.push(block(2)).unwrap();
+        mock.push(block(7)).unwrap();
+        mock.push(U64::from(40)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+        assert_eq!(logs, vec![log(27, 6), log(30, 7)]);
+
+        let pinned: Vec<_> = scanner
+            .pinned_ranges
+            .iter()
+            .map(|range| (range.from_block, range.to_block, range.to_block_hash))
+            .collect();
+        assert_eq!(pinned, vec![
+            (10, 20, H256::repeat_byte(2)),
+            (21, 30, H256::repeat_byte(7)),
+            (31, 40, H256::repeat_byte(8)),
+        ]);
+
+        // The event returned from block 25 is still there
+        assert_eq!(scanner.take_reorged_from(), None);
+    }
+
+    #[tokio::test]
+    async fn reorged_out_events_are_reported() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let mut scanner = scanner(provider).with_block_hash_pinning();
+
+        let log = |block_number: u64, block_hash: u8| Log {
+            block_number: Some(block_number.into()),
+            block_hash: Some(H256::repeat_byte(block_hash)),
+            ..Default::default()
+        };
+        let block = |block_hash: u8| Block::<H256> {
+            hash: Some(H256::repeat_byte(block_hash)),
+            ..Default::default()
+        };
+
+        // Responses are popped in reverse order
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(15, 1)]).unwrap();
+        mock.push(block(2)).unwrap();
+        mock.push(U64::from(20)).unwrap();
+
+        scanner.next(None, Default::default()).await.unwrap();
+
+        // Block 15 was replaced by one without the event, and the event made
+        // it into block 16
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(block(10)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(16, 5)]).unwrap();
+        mock.push(block(9)).unwrap();
+        mock.push(U64::from(30)).unwrap();
+
+        let logs = scanner.next(None, Default::default()).await.unwrap();
+        assert_eq!(logs, vec![log(16, 5)]);
+
+        assert_eq!(scanner.take_reorged_from(), Some(15));
+        assert_eq!(scanner.take_reorged_from(), None);
+    }
 }
@@ -145,6 +145,12 @@ pub enum Error {
 
     #[error("Tried to mine missing root {root:?}")]
     MissingRoot { root: Hash },
+
+    #[error("Tried to roll back root {root:?}, but later identities are processed")]
+    RootAlreadyProcessed { root: Hash },
+
+    #[error("Identity {commitment:?} is neither queued nor in the tree")]
+    IdentityNotQueued { commitment: Hash },
 }
 
 #[cfg(test)]
@@ -164,7 +170,7 @@ mod test {
     use super::Database;
     use crate::config::DatabaseConfig;
     use crate::database::query::DatabaseQuery;
-    use crate::database::types::BatchType;
+    use crate::database::types::{BatchType, QueuedIdentity, SubmissionStatus};
     use crate::identity_tree::{Hash, ProcessedStatus, UnprocessedStatus};
     use crate::prover::identity::Identity;
     use crate::prover::{ProverConfig, ProverType};
@@ -278,6 +284,207 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn acceptance_is_kept_once_inserted_into_the_tree() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+        let identities = mock_identities(3);
+        let roots = mock_roots(3);
+
+        let before = Utc::now();
+        db.insert_new_identity(identities[0], Utc::now()).await?;
+        db.insert_new_identity(identities[1], Utc::now()).await?;
+
+        for (leaf_index, identity) in identities[..2].iter().enumerate() {
+            db.insert_pending_identity(leaf_index, identity, &roots[leaf_index])
+                .await?;
+            db.remove_unprocessed_identity(identity).await?;
+        }
+        // Not accepted through the queue, e.g. inserted before acceptance was
+        // recorded
+        db.insert_pending_identity(2, &identities[2], &roots[2])
+            .await?;
+
+        let earliest = db
+            .get_earliest_acceptance(&[0, 1])
+            .await?
+            .context("Missing acceptance")?;
+        assert!(earliest >= before - chrono::Duration::seconds(1));
+        assert!(earliest <= Utc::now());
+        assert_eq!(
+            db.get_earliest_acceptance(&[1, 2]).await?,
+            db.get_earliest_acceptance(&[1]).await?
+        );
+        assert_eq!(db.get_earliest_acceptance(&[2]).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn insert_identity_unless_exists() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+
+        let commitment: Hash = U256::from(1).into();
+        let in_tree: Hash = U256::from(2).into();
+        let eligibility_timestamp = Utc::now();
+
+        let queued = db
+            .insert_new_identity_unless_exists(commitment, eligibility_timestamp, 0)
+            .await?;
+        assert_eq!(queued, QueuedIdentity::Queued);
+
+        let queued_again = db
+            .insert_new_identity_unless_exists(commitment, eligibility_timestamp, 5)
+            .await?;
+        assert!(matches!(queued_again, QueuedIdentity::AlreadyQueued {
+            status: UnprocessedStatus::New,
+            ..
+        }));
+
+        // Neither queued twice nor reprioritized
+        let unprocessed = db
+            .get_eligible_unprocessed_commitments(UnprocessedStatus::New)
+            .await?;
+        assert_eq!(unprocessed.len(), 1);
+        assert_eq!(unprocessed[0].priority, 0);
+
+        // Failed commitments are queued again
+        db.insert_failed_identity(&commitment, "failed").await?;
+        let requeued = db
+            .insert_new_identity_unless_exists(commitment, eligibility_timestamp, 0)
+            .await?;
+        assert_eq!(requeued, QueuedIdentity::Queued);
+
+        db.insert_pending_identity(0, &in_tree, &Hash::ZERO).await?;
+        let existing = db
+            .insert_new_identity_unless_exists(in_tree, eligibility_timestamp, 0)
+            .await?;
+        assert_eq!(existing, QueuedIdentity::InTree);
+        assert!(db.get_unprocessed_commit_status(&in_tree).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expire_unprocessed_identities() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+
+        let stale: Hash = U256::from(1).into();
+        let fresh: Hash = U256::from(2).into();
+
+        db.insert_new_identity(stale, Utc::now() - chrono::Duration::hours(2))
+            .await?;
+        db.insert_new_identity(fresh, Utc::now()).await?;
+
+        let expired = db
+            .expire_unprocessed_identities(Duration::from_secs(60 * 60))
+            .await?;
+        assert_eq!(expired, 1);
+
+        let (status, message) = db
+            .get_unprocessed_commit_status(&stale)
+            .await?
+            .expect("expected commitment status");
+        assert_eq!(status, UnprocessedStatus::Failed);
+        assert_eq!(message, crate::database::query::EXPIRED_MESSAGE);
+
+        let eligible = db
+            .get_eligible_unprocessed_commitments(UnprocessedStatus::New)
+            .await?;
+        assert_eq!(eligible.len(), 1);
+        assert_eq!(eligible[0].commitment, fresh);
+
+        assert_eq!(db.count_unprocessed_identities().await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unprocessed_identities_by_priority() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+
+        let eligibility_timestamp = Utc::now() - chrono::Duration::minutes(1);
+        let commitments: Vec<Hash> = (1..=4).map(|i| U256::from(i).into()).collect();
+
+        db.insert_new_identity(commitments[0], eligibility_timestamp)
+            .await?;
+        db.insert_new_identity_with_priority(commitments[1], eligibility_timestamp, 5)
+            .await?;
+        db.insert_new_identity(commitments[2], eligibility_timestamp)
+            .await?;
+        db.insert_new_identity_with_priority(commitments[3], eligibility_timestamp, 5)
+            .await?;
+
+        let eligible: Vec<Hash> = db
+            .get_eligible_unprocessed_commitments(UnprocessedStatus::New)
+            .await?
+            .into_iter()
+            .map(|identity| identity.commitment)
+            .collect();
+
+        assert_eq!(eligible, vec![
+            commitments[1],
+            commitments[3],
+            commitments[0],
+            commitments[2]
+        ]);
+
+        let mut by_priority = db.count_unprocessed_identities_by_priority().await?;
+        by_priority.sort_unstable();
+        assert_eq!(by_priority, vec![(0, 2), (5, 2)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queued_commitments_up_to() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+
+        let eligibility_timestamp = Utc::now() - chrono::Duration::minutes(1);
+        let commitments: Vec<Hash> = (1..=4).map(|i| U256::from(i).into()).collect();
+
+        db.insert_new_identity(commitments[0], eligibility_timestamp)
+            .await?;
+        db.insert_new_identity(commitments[1], eligibility_timestamp)
+            .await?;
+        db.insert_new_identity(commitments[2], eligibility_timestamp)
+            .await?;
+        db.insert_new_identity_with_priority(commitments[3], eligibility_timestamp, 5)
+            .await?;
+
+        // Ahead, but not batched until it's eligible
+        let not_eligible: Hash = U256::from(6).into();
+        db.insert_new_identity_with_priority(
+            not_eligible,
+            Utc::now() + chrono::Duration::hours(1),
+            5,
+        )
+        .await?;
+
+        // Already in the tree, but not removed from the queue yet
+        db.insert_pending_identity(0, &commitments[0], &Hash::ZERO)
+            .await?;
+
+        assert_eq!(
+            db.get_queued_commitments_up_to(&commitments[1]).await?,
+            vec![commitments[3], commitments[1]]
+        );
+        assert!(db
+            .get_queued_commitments_up_to(&U256::from(5).into())
+            .await?
+            .is_empty());
+        assert!(db
+            .get_queued_commitments_up_to(&not_eligible)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn insert_and_delete_identity() -> anyhow::Result<()> {
         let docker = Cli::default();
@@ -769,6 +976,57 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn mined_leaf_updates_after_a_root() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+
+        let identities = mock_identities(4);
+        let roots = mock_roots(5);
+
+        for i in 0..4 {
+            db.insert_pending_identity(i, &identities[i], &roots[i])
+                .await
+                .context("Inserting identity")?;
+        }
+        db.insert_pending_identity(1, &Hash::ZERO, &roots[4])
+            .await
+            .context("Deleting identity")?;
+
+        db.mark_root_as_mined_tx(&roots[4]).await?;
+
+        // Deletions are included, in the order they were made
+        let after_first = db
+            .get_mined_leaf_updates_after(&roots[0], 10)
+            .await?
+            .context("Known root")?;
+        let after_first: Vec<_> = after_first
+            .iter()
+            .map(|update| (update.leaf_index, update.commitment))
+            .collect();
+        assert_eq!(after_first, vec![
+            (1, identities[1]),
+            (2, identities[2]),
+            (3, identities[3]),
+            (1, Hash::ZERO),
+        ]);
+
+        // Known, but nothing mined after it
+        assert_eq!(
+            db.get_mined_leaf_updates_after(&roots[4], 10)
+                .await?
+                .map(|updates| updates.len()),
+            Some(0)
+        );
+
+        assert!(db
+            .get_mined_leaf_updates_after(&Hash::from(12345), 10)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn mark_root_as_mined_interaction_with_mark_root_as_processed() -> anyhow::Result<()> {
         let docker = Cli::default();
@@ -1112,6 +1370,19 @@ mod test {
 
         assert!(db.identity_exists(identities[1]).await?);
 
+        // Failed identities can be submitted again
+        db.expire_unprocessed_identities(Duration::ZERO).await?;
+        assert!(!db.identity_exists(identities[0]).await?);
+
+        db.insert_new_identity(identities[0], eligibility_timestamp)
+            .await
+            .context("Inserting failed identity again")?;
+        assert!(db.identity_exists(identities[0]).await?);
+        assert_eq!(
+            db.get_unprocessed_commit_status(&identities[0]).await?,
+            Some((UnprocessedStatus::New, String::new()))
+        );
+
         Ok(())
     }
 
@@ -1359,4 +1630,165 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_recover_interrupted_submissions() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+        let identities: Vec<_> = mock_identities(2)
+            .iter()
+            .map(|commitment| Identity::new((*commitment).into(), vec![]))
+            .collect();
+        let roots = mock_roots(3);
+        let transaction_id = String::from("173bcbfd-e1d9-40e2-ba10-fc1dfbf742c9");
+
+        db.insert_new_batch_head(&roots[0]).await?;
+        db.insert_new_batch(
+            &roots[1],
+            &roots[0],
+            BatchType::Insertion,
+            &identities[..1],
+            &[0],
+        )
+        .await?;
+        db.insert_new_batch(
+            &roots[2],
+            &roots[1],
+            BatchType::Insertion,
+            &identities[1..],
+            &[1],
+        )
+        .await?;
+
+        // The first batch was handed to the relayer, the sequencer crashed while
+        // submitting the second one
+        db.insert_submission(&roots[1]).await?;
+        db.record_submitted_transaction_tx(&roots[1], &transaction_id)
+            .await?;
+        db.insert_submission(&roots[2]).await?;
+
+        let submissions = db.get_unfinished_submissions().await?;
+        assert_eq!(submissions.len(), 2);
+        assert_eq!(submissions[0].batch_next_root, roots[1]);
+        assert_eq!(submissions[0].status, SubmissionStatus::Submitted);
+        assert_eq!(
+            submissions[0].transaction_id.as_deref(),
+            Some(transaction_id.as_str())
+        );
+        assert_eq!(submissions[1].batch_next_root, roots[2]);
+        assert_eq!(submissions[1].status, SubmissionStatus::Submitting);
+        assert_eq!(submissions[1].transaction_id, None);
+
+        let batch = db.get_batch_for_transaction(&transaction_id).await?;
+        assert_eq!(batch.map(|batch| batch.next_root), Some(roots[1]));
+
+        // Resubmissions by the monitor count against the budget too
+        let resubmitted_id = String::from("a2c3a8a4-0b1e-4a4e-9d8e-3c5f0f6b7d21");
+        db.record_resubmitted_transaction_tx(&roots[1], &resubmitted_id)
+            .await?;
+        let submission = db.get_submission(&roots[1]).await?.unwrap();
+        assert_eq!(submission.attempts, submissions[0].attempts + 1);
+        assert_eq!(
+            submission.transaction_id.as_deref(),
+            Some(resubmitted_id.as_str())
+        );
+        let transaction_id = resubmitted_id;
+
+        // The interrupted batch is picked up for submission again
+        let next_batch = db.get_next_batch_without_transaction().await?;
+        assert_eq!(next_batch.map(|batch| batch.next_root), Some(roots[2]));
+
+        db.update_submission_status(&transaction_id, SubmissionStatus::Mined)
+            .await?;
+        db.insert_submission(&roots[2]).await?;
+
+        let submissions = db.get_unfinished_submissions().await?;
+        assert_eq!(submissions.len(), 1);
+        assert_eq!(submissions[0].batch_next_root, roots[2]);
+
+        // The interrupted batch was mined, but its transaction id is unknown
+        db.update_submission_status_by_root(&roots[2], SubmissionStatus::Mined)
+            .await?;
+        assert!(db.get_unfinished_submissions().await?.is_empty());
+        assert!(db.get_next_batch_without_transaction().await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_batch() -> anyhow::Result<()> {
+        let docker = Cli::default();
+        let (db, _db_container) = setup_db(&docker).await?;
+
+        let identities = mock_identities(5);
+        let roots = mock_roots(7);
+        let batch_identities = |range: std::ops::Range<usize>| -> Vec<Identity> {
+            range
+                .map(|i| Identity::new(identities[i].into(), vec![]))
+                .collect()
+        };
+
+        for (leaf_index, identity) in identities.iter().enumerate() {
+            db.insert_pending_identity(leaf_index, identity, &roots[leaf_index + 1])
+                .await?;
+        }
+        db.insert_pending_identity(0, &Hash::ZERO, &roots[6])
+            .await?;
+
+        db.insert_new_batch_head(&roots[0]).await?;
+        db.insert_new_batch(
+            &roots[2],
+            &roots[0],
+            BatchType::Insertion,
+            &batch_identities(0..2),
+            &[0, 1],
+        )
+        .await?;
+        db.insert_new_batch(
+            &roots[4],
+            &roots[2],
+            BatchType::Insertion,
+            &batch_identities(2..4),
+            &[2, 3],
+        )
+        .await?;
+
+        db.mark_root_as_processed_tx(&roots[1]).await?;
+
+        // Identities after the root are already processed
+        assert!(db
+            .roll_back_batch_tx(&roots[0], &identities[0..2], "Timed out")
+            .await
+            .is_err());
+
+        let next_leaf = db
+            .roll_back_batch_tx(&roots[2], &identities[2..4], "Timed out")
+            .await?;
+
+        assert_eq!(next_leaf, 2);
+        assert!(db.get_next_batch(&roots[0]).await?.is_some());
+        assert!(db.get_next_batch(&roots[2]).await?.is_none());
+        assert_eq!(db.get_id_by_root(&roots[3]).await?, None);
+
+        for identity in &identities[2..4] {
+            assert_eq!(
+                db.get_unprocessed_commit_status(identity).await?,
+                Some((UnprocessedStatus::Failed, "Timed out".to_string()))
+            );
+        }
+
+        // The later insertion is queued again
+        assert_eq!(
+            db.get_unprocessed_commit_status(&identities[4]).await?,
+            Some((UnprocessedStatus::New, String::new()))
+        );
+
+        // And so is the deletion
+        let deletions = db.get_deletions().await?;
+        assert_eq!(deletions.len(), 1);
+        assert_eq!(deletions[0].leaf_index, 0);
+        assert_eq!(deletions[0].commitment, identities[0]);
+
+        Ok(())
+    }
 }
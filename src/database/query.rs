@@ -1,21 +1,30 @@
 use std::collections::HashSet;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use ruint::aliases::U256;
 use sqlx::{Executor, Postgres, Row};
 use tracing::instrument;
-use types::{DeletionEntry, LatestDeletionEntry, RecoveryEntry};
+use types::{DeletionEntry, LatestDeletionEntry, QueuedIdentity, RecoveryEntry};
 
-use crate::database::types::{BatchEntry, BatchEntryData, BatchType, TransactionEntry};
+use crate::database::types::{
+    BatchEntry, BatchEntryData, BatchType, SubmissionEntry, SubmissionStatus, TransactionEntry,
+};
 use crate::database::{types, Error};
 use crate::identity_tree::{
-    Hash, ProcessedStatus, RootItem, TreeItem, TreeUpdate, UnprocessedStatus,
+    Hash, LeafUpdate, ProcessedStatus, RootItem, TreeItem, TreeUpdate, UnprocessedStatus,
 };
 use crate::prover::identity::Identity;
 use crate::prover::{ProverConfig, ProverType};
 
 const MAX_UNPROCESSED_FETCH_COUNT: i64 = 10_000;
 
+/// The priority of queued identities that didn't request one.
+pub const DEFAULT_PRIORITY: i16 = 0;
+
+/// The error message of identities which spent too long in the queue
+pub const EXPIRED_MESSAGE: &str = "Expired";
+
 /// This trait provides the individual and composable queries to the database.
 /// Each method is a single atomic query, and can be composed within a
 /// transaction.
@@ -26,10 +35,15 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         identity: &Hash,
         root: &Hash,
     ) -> Result<(), Error> {
+        // Queued identities are still in the queue at this point, which knows
+        // when they were accepted
         let insert_pending_identity_query = sqlx::query(
             r#"
-            INSERT INTO identities (leaf_index, commitment, root, status, pending_as_of)
-            VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+            INSERT INTO identities (leaf_index, commitment, root, status, pending_as_of, accepted_at)
+            VALUES (
+                $1, $2, $3, $4, CURRENT_TIMESTAMP,
+                (SELECT created_at FROM unprocessed_identities WHERE commitment = $2)
+            )
             "#,
         )
         .bind(leaf_index as i64)
@@ -42,6 +56,28 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         Ok(())
     }
 
+    /// When the earliest accepted of the identities at `leaf_indexes` was
+    /// accepted, `None` if none of them has a known acceptance time
+    async fn get_earliest_acceptance(
+        self,
+        leaf_indexes: &[usize],
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let leaf_indexes: Vec<i64> = leaf_indexes.iter().map(|&index| index as i64).collect();
+
+        let row = sqlx::query(
+            r#"
+            SELECT MIN(accepted_at)
+            FROM identities
+            WHERE leaf_index = ANY($1)
+            "#,
+        )
+        .bind(leaf_indexes)
+        .fetch_one(self)
+        .await?;
+
+        Ok(row.get::<Option<DateTime<Utc>>, _>(0))
+    }
+
     async fn get_id_by_root(self, root: &Hash) -> Result<Option<usize>, Error> {
         let root_index_query = sqlx::query(
             r#"
@@ -98,6 +134,59 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         Ok((leaf_index + 1) as usize)
     }
 
+    /// Whether any identity inserted after the given id is no longer pending.
+    async fn identities_processed_after(self, id: usize) -> Result<bool, Error> {
+        Ok(sqlx::query(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM identities WHERE id > $1 AND status <> $2)
+            "#,
+        )
+        .bind(id as i64)
+        .bind(<&str>::from(ProcessedStatus::Pending))
+        .fetch_one(self)
+        .await?
+        .get::<bool, _>(0))
+    }
+
+    /// Deletes the identities inserted after the given id, returning them in
+    /// insertion order.
+    async fn delete_identities_after(self, id: usize) -> Result<Vec<TreeUpdate>, Error> {
+        Ok(sqlx::query_as::<_, TreeUpdate>(
+            r#"
+            WITH deleted AS (
+                DELETE FROM identities
+                WHERE id > $1
+                RETURNING id, leaf_index, commitment
+            )
+            SELECT leaf_index, commitment as element
+            FROM deleted
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_all(self)
+        .await?)
+    }
+
+    /// Returns the latest commitment at the given leaf index, which is zero if
+    /// it was deleted.
+    async fn get_leaf_commitment(self, leaf_index: usize) -> Result<Option<Hash>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT commitment
+            FROM identities
+            WHERE leaf_index = $1
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(leaf_index as i64);
+
+        let row = self.fetch_optional(query).await?;
+
+        Ok(row.map(|row| row.get::<Hash, _>(0)))
+    }
+
     async fn get_identity_leaf_index(self, identity: &Hash) -> Result<Option<TreeItem>, Error> {
         let query = sqlx::query(
             r#"
@@ -141,6 +230,69 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         .await?)
     }
 
+    /// Returns the first non-zero commitment inserted at each leaf index, in
+    /// insertion order
+    async fn get_inserted_commitments(self) -> Result<Vec<TreeUpdate>, Error> {
+        Ok(sqlx::query_as::<_, TreeUpdate>(
+            r#"
+            SELECT DISTINCT ON (leaf_index) leaf_index, commitment as element
+            FROM identities
+            WHERE commitment <> $1
+            ORDER BY leaf_index ASC, id ASC;
+            "#,
+        )
+        .bind(Hash::ZERO)
+        .fetch_all(self)
+        .await?)
+    }
+
+    /// Returns up to `limit` insertions and deletions that made it on chain
+    /// after the one resulting in `root`, in the order they were made.
+    /// Deletions have a zero commitment. `None` if `root` is unknown, e.g.
+    /// because it was rolled back.
+    async fn get_mined_leaf_updates_after(
+        self,
+        root: &Hash,
+        limit: i64,
+    ) -> Result<Option<Vec<LeafUpdate>>, Error> {
+        // Yields a single row of nulls if the root is known but nothing came
+        // after it, and no row at all if it's unknown
+        let query = sqlx::query(
+            r#"
+            WITH resumed AS (
+                SELECT id FROM identities WHERE root = $1 ORDER BY id ASC LIMIT 1
+            )
+            SELECT later.leaf_index, later.commitment, later.root
+            FROM resumed
+            LEFT JOIN identities AS later
+                ON later.id > resumed.id
+                AND later.status <> $2
+            ORDER BY later.id ASC
+            LIMIT $3
+            "#,
+        )
+        .bind(root)
+        .bind(<&str>::from(ProcessedStatus::Pending))
+        .bind(limit);
+
+        let rows = self.fetch_all(query).await?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            rows.into_iter()
+                .filter_map(|row| {
+                    Some(LeafUpdate {
+                        leaf_index: row.get::<Option<i64>, _>(0)? as usize,
+                        commitment: row.get::<Hash, _>(1),
+                        root:       row.get::<Hash, _>(2),
+                    })
+                })
+                .collect(),
+        ))
+    }
+
     async fn get_latest_root_by_status(
         self,
         status: ProcessedStatus,
@@ -195,12 +347,33 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
             r#"
             SELECT COUNT(*) as unprocessed
             FROM unprocessed_identities
+            WHERE status = $1
             "#,
-        );
+        )
+        .bind(<&str>::from(UnprocessedStatus::New));
         let result = self.fetch_one(query).await?;
         Ok(result.get::<i64, _>(0) as i32)
     }
 
+    async fn count_unprocessed_identities_by_priority(self) -> Result<Vec<(i16, i64)>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT priority, COUNT(*) as unprocessed
+            FROM unprocessed_identities
+            WHERE status = $1
+            GROUP BY priority
+            "#,
+        )
+        .bind(<&str>::from(UnprocessedStatus::New));
+
+        let result = self.fetch_all(query).await?;
+
+        Ok(result
+            .into_iter()
+            .map(|row| (row.get::<i16, _>(0), row.get::<i64, _>(1)))
+            .collect())
+    }
+
     async fn count_pending_identities(self) -> Result<i32, Error> {
         let query = sqlx::query(
             r#"
@@ -294,20 +467,112 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         identity: Hash,
         eligibility_timestamp: sqlx::types::chrono::DateTime<Utc>,
     ) -> Result<Hash, Error> {
+        self.insert_new_identity_with_priority(identity, eligibility_timestamp, DEFAULT_PRIORITY)
+            .await
+    }
+
+    async fn insert_new_identity_with_priority(
+        self,
+        identity: Hash,
+        eligibility_timestamp: sqlx::types::chrono::DateTime<Utc>,
+        priority: i16,
+    ) -> Result<Hash, Error> {
+        // A failed commitment is queued again in place of its failure
         let query = sqlx::query(
             r#"
-            INSERT INTO unprocessed_identities (commitment, status, created_at, eligibility)
-            VALUES ($1, $2, CURRENT_TIMESTAMP, $3)
+            INSERT INTO unprocessed_identities (commitment, status, created_at, eligibility, priority)
+            VALUES ($1, $2, CURRENT_TIMESTAMP, $3, $4)
+            ON CONFLICT (commitment) DO UPDATE SET
+                status = EXCLUDED.status,
+                created_at = EXCLUDED.created_at,
+                eligibility = EXCLUDED.eligibility,
+                priority = EXCLUDED.priority,
+                processed_at = NULL,
+                error_message = NULL
+            WHERE unprocessed_identities.status = $5
             "#,
         )
         .bind(identity)
         .bind(<&str>::from(UnprocessedStatus::New))
-        .bind(eligibility_timestamp);
+        .bind(eligibility_timestamp)
+        .bind(priority)
+        .bind(<&str>::from(UnprocessedStatus::Failed));
 
         self.execute(query).await?;
         Ok(identity)
     }
 
+    /// Queues a new identity unless it's queued or in the tree already, in
+    /// which case nothing is written. Checking and queuing happen in one
+    /// statement, so concurrent requests for the same commitment can't both
+    /// queue it.
+    async fn insert_new_identity_unless_exists(
+        self,
+        identity: Hash,
+        eligibility_timestamp: sqlx::types::chrono::DateTime<Utc>,
+        priority: i16,
+    ) -> Result<QueuedIdentity, Error> {
+        // Failed commitments are queued again, like in
+        // `insert_new_identity_with_priority`
+        let query = sqlx::query(
+            r#"
+            WITH in_tree AS (
+                SELECT EXISTS (SELECT 1 FROM identities WHERE commitment = $1) AS in_tree
+            ), queued AS (
+                SELECT status, created_at
+                FROM unprocessed_identities
+                WHERE commitment = $1 AND status != $5
+            ), inserted AS (
+                INSERT INTO unprocessed_identities (commitment, status, created_at, eligibility, priority)
+                SELECT $1, $2, CURRENT_TIMESTAMP, $3, $4
+                WHERE NOT (SELECT in_tree FROM in_tree)
+                ON CONFLICT (commitment) DO UPDATE SET
+                    status = EXCLUDED.status,
+                    created_at = EXCLUDED.created_at,
+                    eligibility = EXCLUDED.eligibility,
+                    priority = EXCLUDED.priority,
+                    processed_at = NULL,
+                    error_message = NULL
+                WHERE unprocessed_identities.status = $5
+                RETURNING commitment
+            )
+            SELECT
+                EXISTS (SELECT 1 FROM inserted) AS inserted,
+                (SELECT in_tree FROM in_tree) AS in_tree,
+                (SELECT status FROM queued) AS status,
+                (SELECT created_at FROM queued) AS queued_at
+            "#,
+        )
+        .bind(identity)
+        .bind(<&str>::from(UnprocessedStatus::New))
+        .bind(eligibility_timestamp)
+        .bind(priority)
+        .bind(<&str>::from(UnprocessedStatus::Failed));
+
+        let row = self.fetch_one(query).await?;
+
+        if row.get::<bool, _>("inserted") {
+            return Ok(QueuedIdentity::Queued);
+        }
+
+        if row.get::<bool, _>("in_tree") {
+            return Ok(QueuedIdentity::InTree);
+        }
+
+        let status = row.get::<Option<&str>, _>("status");
+        let queued_at = row.get::<Option<DateTime<Utc>>, _>("queued_at");
+        match status.zip(queued_at) {
+            Some((status, queued_at)) => Ok(QueuedIdentity::AlreadyQueued {
+                status: status.parse().expect("couldn't read status"),
+                queued_at,
+            }),
+            // Only possible if the row is deleted concurrently
+            None => Err(Error::IdentityNotQueued {
+                commitment: identity,
+            }),
+        }
+    }
+
     async fn insert_new_recovery(
         self,
         existing_commitment: &Hash,
@@ -458,6 +723,13 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         Ok(())
     }
 
+    /// Fetches eligible commitments, highest priority first and in insertion
+    /// order within a priority.
+    ///
+    /// Priorities are strict: as long as higher priority commitments keep
+    /// arriving faster than batches are submitted, lower priority ones are
+    /// starved. `max_queue_age` bounds how long they can wait before being
+    /// expired.
     async fn get_eligible_unprocessed_commitments(
         self,
         status: UnprocessedStatus,
@@ -466,6 +738,7 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
             r#"
                 SELECT * FROM unprocessed_identities
                 WHERE status = $1 AND CURRENT_TIMESTAMP > eligibility
+                ORDER BY priority DESC, created_at ASC, commitment ASC
                 LIMIT $2
             "#,
         )
@@ -483,10 +756,80 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
                 processed_at: row.get::<_, _>(3),
                 error_message: row.get::<_, _>(4),
                 eligibility_timestamp: row.get::<_, _>(5),
+                priority: row.get::<_, _>("priority"),
             })
             .collect::<Vec<_>>())
     }
 
+    /// Fetches the new commitments that will be inserted before `commitment`
+    /// according to [`get_eligible_unprocessed_commitments`], followed by
+    /// `commitment` itself. Commitments that are already in the tree are
+    /// skipped. Empty if `commitment` isn't queued or isn't eligible yet.
+    ///
+    /// [`get_eligible_unprocessed_commitments`]: Self::get_eligible_unprocessed_commitments
+    async fn get_queued_commitments_up_to(self, commitment: &Hash) -> Result<Vec<Hash>, Error> {
+        let query = sqlx::query(
+            r#"
+                SELECT queued.commitment
+                FROM unprocessed_identities AS queued
+                JOIN unprocessed_identities AS target ON target.commitment = $1
+                WHERE queued.status = $2 AND target.status = $2
+                AND CURRENT_TIMESTAMP > queued.eligibility
+                AND (
+                    queued.priority > target.priority
+                    OR (queued.priority = target.priority AND (
+                        queued.created_at < target.created_at
+                        OR (queued.created_at = target.created_at AND queued.commitment <= target.commitment)
+                    ))
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM identities WHERE identities.commitment = queued.commitment
+                )
+                ORDER BY queued.priority DESC, queued.created_at ASC, queued.commitment ASC
+                LIMIT $3
+            "#,
+        )
+        .bind(commitment)
+        .bind(<&str>::from(UnprocessedStatus::New))
+        .bind(MAX_UNPROCESSED_FETCH_COUNT);
+
+        let mut commitments: Vec<Hash> = self
+            .fetch_all(query)
+            .await?
+            .into_iter()
+            .map(|row| row.get::<Hash, _>(0))
+            .collect();
+
+        // `commitment` is last unless it's beyond the limit, or not eligible
+        match commitments.iter().position(|queued| queued == commitment) {
+            Some(position) => commitments.truncate(position + 1),
+            None => commitments.clear(),
+        }
+
+        Ok(commitments)
+    }
+
+    /// Marks identities that have been eligible for longer than `max_age` as
+    /// failed, returning the number of expired identities.
+    async fn expire_unprocessed_identities(self, max_age: Duration) -> Result<u64, Error> {
+        let cutoff = Utc::now()
+            - chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::max_value());
+
+        let query = sqlx::query(
+            r#"
+                UPDATE unprocessed_identities
+                SET status = $1, error_message = $2, processed_at = CURRENT_TIMESTAMP
+                WHERE status = $3 AND eligibility < $4
+            "#,
+        )
+        .bind(<&str>::from(UnprocessedStatus::Failed))
+        .bind(EXPIRED_MESSAGE)
+        .bind(<&str>::from(UnprocessedStatus::New))
+        .bind(cutoff);
+
+        Ok(self.execute(query).await?.rows_affected())
+    }
+
     async fn get_unprocessed_commit_status(
         self,
         commitment: &Hash,
@@ -509,6 +852,28 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         Ok(None)
     }
 
+    /// Marks the commitment as failed with the given message, whether it's
+    /// still queued or not.
+    async fn insert_failed_identity(self, commitment: &Hash, message: &str) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO unprocessed_identities (commitment, status, created_at, processed_at, error_message)
+            VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, $3)
+            ON CONFLICT (commitment) DO UPDATE SET
+                status = EXCLUDED.status,
+                processed_at = EXCLUDED.processed_at,
+                error_message = EXCLUDED.error_message
+            "#,
+        )
+        .bind(commitment)
+        .bind(<&str>::from(UnprocessedStatus::Failed))
+        .bind(message);
+
+        self.execute(query).await?;
+
+        Ok(())
+    }
+
     async fn remove_unprocessed_identity(self, commitment: &Hash) -> Result<(), Error> {
         let query = sqlx::query(
             r#"
@@ -522,15 +887,18 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         Ok(())
     }
 
+    /// Whether the commitment is queued or in the tree. Failed commitments
+    /// don't count, so that they can be submitted again.
     async fn identity_exists(self, commitment: Hash) -> Result<bool, Error> {
         Ok(sqlx::query(
             r#"
             select
-            EXISTS (select commitment from unprocessed_identities where commitment = $1) OR
+            EXISTS (select commitment from unprocessed_identities where commitment = $1 AND status != $2) OR
             EXISTS (select commitment from identities where commitment = $1);
             "#,
         )
         .bind(commitment)
+        .bind(<&str>::from(UnprocessedStatus::Failed))
         .fetch_one(self)
         .await?
         .get::<bool, _>(0))
@@ -678,11 +1046,15 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
                 batches.data
             FROM batches
             LEFT JOIN transactions ON batches.next_root = transactions.batch_next_root
-            WHERE transactions.batch_next_root IS NULL AND batches.prev_root IS NOT NULL
+            LEFT JOIN submissions ON batches.next_root = submissions.batch_next_root
+            WHERE transactions.batch_next_root IS NULL
+                AND batches.prev_root IS NOT NULL
+                AND submissions.status IS DISTINCT FROM $1
             ORDER BY batches.id ASC
             LIMIT 1
             "#,
         )
+        .bind(SubmissionStatus::Mined)
         .fetch_optional(self)
         .await?;
 
@@ -785,6 +1157,25 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
         Ok(())
     }
 
+    async fn update_transaction_id(
+        self,
+        batch_next_root: &Hash,
+        transaction_id: &str,
+    ) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            UPDATE transactions
+            SET transaction_id = $2
+            WHERE batch_next_root = $1
+            "#,
+        )
+        .bind(batch_next_root)
+        .bind(transaction_id);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
     async fn get_transaction_for_batch(
         self,
         next_root: &Hash,
@@ -805,4 +1196,173 @@ pub trait DatabaseQuery<'a>: Executor<'a, Database = Postgres> {
 
         Ok(res)
     }
+
+    async fn get_batch_for_transaction(
+        self,
+        transaction_id: &str,
+    ) -> Result<Option<BatchEntry>, Error> {
+        let res = sqlx::query_as::<_, BatchEntry>(
+            r#"
+            SELECT
+                batches.id,
+                batches.next_root,
+                batches.prev_root,
+                batches.created_at,
+                batches.batch_type,
+                batches.data
+            FROM batches
+            JOIN transactions ON transactions.batch_next_root = batches.next_root
+            WHERE transactions.transaction_id = $1
+            LIMIT 1
+            "#,
+        )
+        .bind(transaction_id)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Records that a batch is about to be submitted. Must be called before
+    /// handing the transaction to the relayer.
+    async fn insert_submission(self, batch_next_root: &Hash) -> Result<SubmissionEntry, Error> {
+        let res = sqlx::query_as::<_, SubmissionEntry>(
+            r#"
+            INSERT INTO submissions (batch_next_root, status, created_at, updated_at)
+            VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+            ON CONFLICT (batch_next_root) DO UPDATE
+            SET status = EXCLUDED.status,
+                transaction_id = NULL,
+                attempts = submissions.attempts + 1,
+                updated_at = CURRENT_TIMESTAMP
+            RETURNING batch_next_root, status, transaction_id, attempts, created_at
+            "#,
+        )
+        .bind(batch_next_root)
+        .bind(SubmissionStatus::Submitting)
+        .fetch_one(self)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn mark_submission_as_submitted(
+        self,
+        batch_next_root: &Hash,
+        transaction_id: &str,
+    ) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            UPDATE submissions
+            SET status = $2, transaction_id = $3, updated_at = CURRENT_TIMESTAMP
+            WHERE batch_next_root = $1
+            "#,
+        )
+        .bind(batch_next_root)
+        .bind(SubmissionStatus::Submitted)
+        .bind(transaction_id);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Counts a resubmission by the monitor as another attempt
+    async fn count_submission_attempt(self, batch_next_root: &Hash) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            UPDATE submissions
+            SET attempts = attempts + 1, updated_at = CURRENT_TIMESTAMP
+            WHERE batch_next_root = $1
+            "#,
+        )
+        .bind(batch_next_root);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    async fn get_submission(
+        self,
+        batch_next_root: &Hash,
+    ) -> Result<Option<SubmissionEntry>, Error> {
+        let res = sqlx::query_as::<_, SubmissionEntry>(
+            r#"
+            SELECT
+                batch_next_root,
+                status,
+                transaction_id,
+                attempts,
+                created_at
+            FROM submissions
+            WHERE batch_next_root = $1
+            "#,
+        )
+        .bind(batch_next_root)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn update_submission_status(
+        self,
+        transaction_id: &str,
+        status: SubmissionStatus,
+    ) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            UPDATE submissions
+            SET status = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE transaction_id = $1
+            "#,
+        )
+        .bind(transaction_id)
+        .bind(status);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    async fn update_submission_status_by_root(
+        self,
+        batch_next_root: &Hash,
+        status: SubmissionStatus,
+    ) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            UPDATE submissions
+            SET status = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE batch_next_root = $1
+            "#,
+        )
+        .bind(batch_next_root)
+        .bind(status);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Returns the submissions which were neither mined nor failed, oldest
+    /// first.
+    async fn get_unfinished_submissions(self) -> Result<Vec<SubmissionEntry>, Error> {
+        let res = sqlx::query_as::<_, SubmissionEntry>(
+            r#"
+            SELECT
+                batch_next_root,
+                status,
+                transaction_id,
+                attempts,
+                created_at
+            FROM submissions
+            WHERE status IN ($1, $2)
+            ORDER BY created_at ASC
+            "#,
+        )
+        .bind(SubmissionStatus::Submitting)
+        .bind(SubmissionStatus::Submitted)
+        .fetch_all(self)
+        .await?;
+
+        Ok(res)
+    }
 }
@@ -1,9 +1,10 @@
+use chrono::Utc;
 use sqlx::Executor;
 use tracing::instrument;
 
 use crate::database::query::DatabaseQuery;
 use crate::database::{Database, Error};
-use crate::identity_tree::{Hash, ProcessedStatus};
+use crate::identity_tree::{Hash, ProcessedStatus, TreeUpdate};
 use crate::utils::retry_tx;
 
 /// impl block for database transactions
@@ -84,4 +85,108 @@ impl Database {
         })
         .await
     }
+
+    /// Rolls back the batch following `prev_root`, along with the batches and
+    /// identities after it. The given commitments of the batch are marked as
+    /// failed with `message`, while any other insertion or deletion that was
+    /// rolled back is queued again. Returns the next leaf index as of
+    /// `prev_root`.
+    #[instrument(skip(self, failed), level = "debug")]
+    pub async fn roll_back_batch_tx(
+        &self,
+        prev_root: &Hash,
+        failed: &[Hash],
+        message: &str,
+    ) -> Result<usize, Error> {
+        retry_tx!(self.pool, tx, {
+            // The initial root has no identity of its own
+            let root_id = tx.get_id_by_root(prev_root).await?.unwrap_or(0);
+
+            if tx.identities_processed_after(root_id).await? {
+                return Err(Error::RootAlreadyProcessed { root: *prev_root });
+            }
+
+            let rolled_back = tx.delete_identities_after(root_id).await?;
+            tx.delete_batches_after_root(prev_root).await?;
+
+            let mut insertions: Vec<TreeUpdate> = Vec::new();
+            for update in rolled_back {
+                if update.element != Hash::ZERO {
+                    if !failed.contains(&update.element) {
+                        insertions.push(update);
+                    }
+                    continue;
+                }
+
+                // Deleting a commitment that's rolled back as well leaves nothing to
+                // queue
+                if let Some(position) = insertions
+                    .iter()
+                    .position(|insertion| insertion.leaf_index == update.leaf_index)
+                {
+                    insertions.remove(position);
+                    continue;
+                }
+
+                let Some(commitment) = tx.get_leaf_commitment(update.leaf_index).await? else {
+                    continue;
+                };
+
+                if commitment != Hash::ZERO
+                    && !tx.identity_is_queued_for_deletion(&commitment).await?
+                {
+                    tx.insert_new_deletion(update.leaf_index, &commitment)
+                        .await?;
+                }
+            }
+
+            for commitment in failed {
+                tx.insert_failed_identity(commitment, message).await?;
+            }
+
+            for insertion in insertions {
+                tx.insert_new_identity(insertion.element, Utc::now())
+                    .await?;
+            }
+
+            tx.get_next_leaf_index().await
+        })
+        .await
+    }
+
+    /// Records the transaction of a submitted batch
+    #[instrument(skip(self), level = "debug")]
+    pub async fn record_submitted_transaction_tx(
+        &self,
+        batch_next_root: &Hash,
+        transaction_id: &str,
+    ) -> Result<(), Error> {
+        retry_tx!(self.pool, tx, {
+            tx.mark_submission_as_submitted(batch_next_root, transaction_id)
+                .await?;
+            tx.insert_new_transaction(&transaction_id.to_string(), batch_next_root)
+                .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Replaces the transaction of a batch with its resubmission
+    pub async fn record_resubmitted_transaction_tx(
+        &self,
+        batch_next_root: &Hash,
+        transaction_id: &str,
+    ) -> Result<(), Error> {
+        retry_tx!(self.pool, tx, {
+            tx.mark_submission_as_submitted(batch_next_root, transaction_id)
+                .await?;
+            tx.count_submission_attempt(batch_next_root).await?;
+            tx.update_transaction_id(batch_next_root, transaction_id)
+                .await?;
+
+            Ok(())
+        })
+        .await
+    }
 }
@@ -16,6 +16,21 @@ pub struct UnprocessedCommitment {
     pub processed_at:          Option<DateTime<Utc>>,
     pub error_message:         Option<String>,
     pub eligibility_timestamp: DateTime<Utc>,
+    pub priority:              i16,
+}
+
+/// What queuing a new identity ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuedIdentity {
+    /// It was queued, or queued again after failing
+    Queued,
+    /// It was queued already, at `queued_at`
+    AlreadyQueued {
+        status:    UnprocessedStatus,
+        queued_at: DateTime<Utc>,
+    },
+    /// It's in the tree already
+    InTree,
 }
 
 #[derive(FromRow)]
@@ -86,6 +101,35 @@ pub struct TransactionEntry {
     pub transaction_id:  String,
 }
 
+/// The lifecycle of a batch submission. A batch's post root doubles as the
+/// idempotency key of its submission, since the contract accepts a transition
+/// away from a given pre root only once.
+#[derive(Debug, Copy, Clone, sqlx::Type, PartialEq, Eq, Hash)]
+#[sqlx(type_name = "VARCHAR", rename_all = "PascalCase")]
+pub enum SubmissionStatus {
+    /// About to be handed to the relayer. If the sequencer restarts in this
+    /// state, the transaction may or may not have been sent.
+    Submitting,
+    /// Accepted by the relayer, waiting to be mined
+    Submitted,
+    Mined,
+    Failed,
+    /// Given up on at the insertion timeout, after its transaction was
+    /// cancelled or no longer waited for
+    TimedOut,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct SubmissionEntry {
+    pub batch_next_root: Hash,
+    pub status:          SubmissionStatus,
+    pub transaction_id:  Option<String>,
+    /// The number of times the batch was handed to the relayer, including
+    /// attempts interrupted by restarts
+    pub attempts:        i32,
+    pub created_at:      DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Commitments(pub Vec<Hash>);
 
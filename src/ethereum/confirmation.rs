@@ -0,0 +1,147 @@
+//! When a transaction, and the roots it produced, count as confirmed.
+use std::time::Duration;
+
+use anyhow::Context;
+use ethers::providers::Middleware;
+use ethers::types::BlockNumber;
+use tracing::debug;
+
+use crate::config::{AppConfig, ConfirmationStrategy};
+
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Confirmations {
+    strategy: ConfirmationStrategy,
+    depth:    u64,
+}
+
+impl Confirmations {
+    #[must_use]
+    pub const fn new(config: &AppConfig) -> Self {
+        Self {
+            strategy: config.confirmation_strategy,
+            depth:    config.confirmation_depth,
+        }
+    }
+
+    /// Whether the relayer has to report a transaction as confirmed, instead
+    /// of just mined
+    #[must_use]
+    pub fn uses_relayer_status(&self) -> bool {
+        self.strategy == ConfirmationStrategy::Relayer
+    }
+
+    /// Whether mined transactions have to be waited on before they're
+    /// confirmed
+    #[must_use]
+    pub fn waits_after_mining(&self) -> bool {
+        match self.strategy {
+            ConfirmationStrategy::Depth => self.depth > 0,
+            ConfirmationStrategy::Finalized => true,
+            ConfirmationStrategy::Relayer => false,
+        }
+    }
+
+    /// The highest block whose transactions count as confirmed
+    pub async fn confirmed_head<M>(&self, provider: &M) -> anyhow::Result<u64>
+    where
+        M: Middleware,
+        <M as Middleware>::Error: 'static,
+    {
+        match self.strategy {
+            ConfirmationStrategy::Depth => {
+                let latest_block = provider
+                    .get_block_number()
+                    .await
+                    .context("Failed to fetch the latest block number")?
+                    .as_u64();
+
+                Ok(latest_block.saturating_sub(self.depth))
+            }
+            ConfirmationStrategy::Finalized => provider
+                .get_block(BlockNumber::Finalized)
+                .await
+                .context("Failed to fetch the finalized block")?
+                .and_then(|block| block.number)
+                .map(|number| number.as_u64())
+                .context("The provider doesn't report a finalized block"),
+            ConfirmationStrategy::Relayer => Ok(provider
+                .get_block_number()
+                .await
+                .context("Failed to fetch the latest block number")?
+                .as_u64()),
+        }
+    }
+
+    /// Waits until `block` is confirmed
+    pub async fn wait_for_block<M>(&self, provider: &M, block: u64) -> anyhow::Result<()>
+    where
+        M: Middleware,
+        <M as Middleware>::Error: 'static,
+    {
+        loop {
+            let confirmed_head = self.confirmed_head(provider).await?;
+
+            if block <= confirmed_head {
+                return Ok(());
+            }
+
+            debug!(block, confirmed_head, strategy = ?self.strategy, "Waiting for confirmation");
+            tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{Block, H256, U64};
+
+    use super::*;
+
+    fn confirmations(strategy: ConfirmationStrategy, depth: u64) -> Confirmations {
+        Confirmations { strategy, depth }
+    }
+
+    #[tokio::test]
+    async fn depth_is_counted_from_the_chain_head() {
+        let (provider, mock) = Provider::mocked();
+        mock.push(U64::from(100)).unwrap();
+
+        let confirmed_head = confirmations(ConfirmationStrategy::Depth, 10)
+            .confirmed_head(&provider)
+            .await
+            .unwrap();
+
+        assert_eq!(confirmed_head, 90);
+    }
+
+    #[tokio::test]
+    async fn finalized_uses_the_finalized_block() {
+        let (provider, mock) = Provider::mocked();
+        mock.push(Block::<H256> {
+            number: Some(U64::from(80)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let confirmed_head = confirmations(ConfirmationStrategy::Finalized, 10)
+            .confirmed_head(&provider)
+            .await
+            .unwrap();
+
+        assert_eq!(confirmed_head, 80);
+    }
+
+    #[tokio::test]
+    async fn relayer_status_confirms_at_the_chain_head() {
+        let (provider, mock): (Provider<MockProvider>, _) = Provider::mocked();
+        mock.push(U64::from(100)).unwrap();
+
+        let confirmations = confirmations(ConfirmationStrategy::Relayer, 10);
+
+        assert!(confirmations.uses_relayer_status());
+        assert_eq!(confirmations.confirmed_head(&provider).await.unwrap(), 100);
+    }
+}
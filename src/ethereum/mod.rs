@@ -1,16 +1,19 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use ethers::types::transaction::eip2718::TypedTransaction;
 use ethers::types::Address;
 pub use read::ReadProvider;
 use tracing::instrument;
 pub use write::TxError;
+pub use write_provider::{RelayerNonces, TransactionStatus, WindowSpend};
 
 use self::write::TransactionId;
 use self::write_provider::WriteProvider;
 use crate::config::Config;
 
+pub mod confirmation;
 pub mod read;
 pub mod write;
 
@@ -27,22 +30,33 @@ pub struct Ethereum {
 impl Ethereum {
     #[instrument(name = "Ethereum::new", level = "debug", skip_all)]
     pub async fn new(config: &Config) -> anyhow::Result<Self> {
-        let read_provider =
-            ReadProvider::new(config.providers.primary_network_provider.clone().into()).await?;
+        let read_provider = ReadProvider::with_fallbacks(
+            config.providers.primary_network_provider.clone().into(),
+            config
+                .providers
+                .fallback_network_providers
+                .0
+                .iter()
+                .cloned()
+                .map(Into::into)
+                .collect(),
+            &config.providers,
+        )
+        .await?;
 
         let mut secondary_read_providers = HashMap::new();
 
         for secondary_url in &config.providers.relayed_network_providers.0 {
-            let secondary_read_provider = ReadProvider::new(secondary_url.clone().into()).await?;
+            let secondary_read_provider =
+                ReadProvider::new(secondary_url.clone().into(), &config.providers).await?;
             secondary_read_providers.insert(
                 secondary_read_provider.chain_id.as_u64(),
                 Arc::new(secondary_read_provider),
             );
         }
 
-        let write_provider: Arc<WriteProvider> = Arc::new(
-            write_provider::WriteProvider::new(read_provider.clone(), &config.relayer).await?,
-        );
+        let write_provider: Arc<WriteProvider> =
+            Arc::new(write_provider::WriteProvider::new(read_provider.clone(), config).await?);
 
         Ok(Self {
             read_provider: Arc::new(read_provider),
@@ -75,11 +89,47 @@ impl Ethereum {
         self.write_provider.send_transaction(tx, only_once).await
     }
 
+    pub fn check_spend_limit(&self) -> Result<Option<WindowSpend>, TxError> {
+        self.write_provider.check_spend_limit()
+    }
+
     pub async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
         self.write_provider.fetch_pending_transactions().await
     }
 
+    pub async fn find_transaction(
+        &self,
+        matches: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> Result<Option<TransactionId>, TxError> {
+        self.write_provider.find_transaction(matches).await
+    }
+
+    pub async fn relayer_nonces(&self) -> Vec<RelayerNonces> {
+        self.write_provider.relayer_nonces().await
+    }
+
+    pub async fn transaction_status(
+        &self,
+        tx: TransactionId,
+    ) -> Result<Option<TransactionStatus>, TxError> {
+        self.write_provider.transaction_status(tx).await
+    }
+
     pub async fn mine_transaction(&self, tx: TransactionId) -> Result<bool, TxError> {
         self.write_provider.mine_transaction(tx).await
     }
+
+    pub fn resubmission_backoff(&self, resubmissions: u32) -> Option<Duration> {
+        self.write_provider.resubmission_backoff(resubmissions)
+    }
+
+    pub async fn cancel_transaction(&self, tx: TransactionId) -> Result<(), TxError> {
+        tracing::info!(?tx, "Cancelling transaction");
+        self.write_provider.cancel_transaction(tx).await
+    }
+
+    pub async fn resubmit_transaction(&self, tx: TransactionId) -> Result<TransactionId, TxError> {
+        tracing::info!(?tx, "Resubmitting transaction");
+        self.write_provider.resubmit_transaction(tx).await
+    }
 }
@@ -0,0 +1,280 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::prometheus::{register_int_counter_with_registry, IntCounter};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, RpcError};
+use ethers::types::U256;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::time::sleep;
+use tracing::warn;
+
+use super::rpc_logger::is_rate_limited;
+use crate::metrics;
+
+/// How long a rate limited request waits before it's sent again
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(1);
+
+static SWITCHES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        "eth_rpc_provider_switches",
+        "Number of times the active Ethereum provider was switched.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Sends requests to the active transport and switches to the next one on
+/// transport errors.
+///
+/// Switching is a single atomic swap: requests that are already in flight
+/// complete against the transport they were sent to, new requests use the new
+/// one. A request that fails with a transport error is retried once on the
+/// transport that is active afterwards. JSON-RPC error responses (e.g.
+/// reverts) are returned as is, since the node did process the request.
+/// Rate limited requests are retried once on the same transport after a
+/// backoff, since the transport is up.
+///
+/// Once the chain id is known, a transport is only switched to if it reports
+/// the same chain id. Transports on another chain, or failing to report it,
+/// are skipped.
+#[derive(Debug, Clone)]
+pub struct Failover<Inner> {
+    transports: Vec<Inner>,
+    active:     Arc<AtomicUsize>,
+    switches:   Arc<AtomicU64>,
+    chain_id:   Arc<OnceCell<U256>>,
+}
+
+impl<Inner> Failover<Inner> {
+    /// # Panics
+    ///
+    /// Panics if `transports` is empty.
+    pub fn new(transports: Vec<Inner>) -> Self {
+        assert!(!transports.is_empty(), "At least one transport is required");
+
+        Self {
+            transports,
+            active: Arc::new(AtomicUsize::new(0)),
+            switches: Arc::new(AtomicU64::new(0)),
+            chain_id: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Sets the chain id transports must report to be switched to. Shared by
+    /// all clones, and only set once.
+    pub fn set_chain_id(&self, chain_id: U256) {
+        if self.chain_id.set(chain_id).is_err() {
+            warn!(%chain_id, "Chain id of the Ethereum providers already set");
+        }
+    }
+
+    /// The number of times the active transport was switched. Shared by all
+    /// clones.
+    pub fn switches(&self) -> Arc<AtomicU64> {
+        self.switches.clone()
+    }
+}
+
+impl<Inner> Failover<Inner>
+where
+    Inner: JsonRpcClient,
+{
+    /// Switches to the next transport, e.g. when the active one keeps
+    /// answering but serves stale data.
+    pub async fn switch(&self) {
+        if self.transports.len() > 1 {
+            self.switch_from(self.active.load(Ordering::Acquire)).await;
+        }
+    }
+
+    /// Switches to the next transport on the chain, if there's any
+    async fn switch_from(&self, failed: usize) {
+        let candidates =
+            (1..self.transports.len()).map(|offset| (failed + offset) % self.transports.len());
+
+        for next in candidates {
+            if !self.is_on_chain(next).await {
+                continue;
+            }
+
+            // Concurrent failures of the same transport only switch once
+            if self
+                .active
+                .compare_exchange(failed, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                warn!(failed, next, "Switching Ethereum provider");
+
+                self.switches.fetch_add(1, Ordering::AcqRel);
+                SWITCHES.inc();
+            }
+
+            return;
+        }
+
+        warn!(
+            failed,
+            "No other Ethereum provider is on the chain, not switching"
+        );
+    }
+
+    async fn is_on_chain(&self, index: usize) -> bool {
+        let Some(expected) = self.chain_id.get() else {
+            return true;
+        };
+
+        match self.transports[index]
+            .request::<_, U256>("eth_chainId", ())
+            .await
+        {
+            Ok(chain_id) if chain_id == *expected => true,
+            Ok(chain_id) => {
+                warn!(index, %chain_id, %expected, "Ethereum provider is on another chain");
+                false
+            }
+            Err(err) => {
+                warn!(
+                    ?err,
+                    index, "Failed to fetch the chain id of an Ethereum provider"
+                );
+                false
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<Inner> JsonRpcClient for Failover<Inner>
+where
+    Inner: JsonRpcClient + 'static,
+    <Inner as JsonRpcClient>::Error: Sync + Send + 'static,
+{
+    type Error = Inner::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let active = self.active.load(Ordering::Acquire);
+
+        match self.transports[active].request(method, &params).await {
+            Err(err) if is_rate_limited(&err) => {
+                warn!(?err, method, backoff = ?RATE_LIMIT_BACKOFF, "Ethereum provider rate limited the request");
+
+                sleep(RATE_LIMIT_BACKOFF).await;
+
+                self.transports[active].request(method, params).await
+            }
+            Err(err) if self.transports.len() > 1 && err.as_error_response().is_none() => {
+                warn!(?err, method, "Ethereum provider request failed");
+
+                self.switch_from(active).await;
+
+                let active = self.active.load(Ordering::Acquire);
+                self.transports[active].request(method, params).await
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse};
+    use ethers::types::U64;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn request_fails_over_mid_flight() {
+        let primary = MockProvider::new();
+        let fallback = MockProvider::new();
+        fallback.push(U64::from(42)).unwrap();
+
+        let failover = Failover::new(vec![primary, fallback]);
+
+        // The primary has no responses queued and fails like a dead node
+        let block_number: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+
+        assert_eq!(block_number, U64::from(42));
+        assert_eq!(failover.switches().load(Ordering::Acquire), 1);
+        assert_eq!(failover.active.load(Ordering::Acquire), 1);
+    }
+
+    #[tokio::test]
+    async fn providers_on_another_chain_are_skipped() {
+        let primary = MockProvider::new();
+        let other_chain = MockProvider::new();
+        other_chain.push(U256::from(5)).unwrap();
+        // Responses are popped in reverse order
+        let fallback = MockProvider::new();
+        fallback.push(U64::from(42)).unwrap();
+        fallback.push(U256::from(1)).unwrap();
+
+        let failover = Failover::new(vec![primary, other_chain, fallback]);
+        failover.set_chain_id(U256::from(1));
+
+        let block_number: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+
+        assert_eq!(block_number, U64::from(42));
+        assert_eq!(failover.switches().load(Ordering::Acquire), 1);
+        assert_eq!(failover.active.load(Ordering::Acquire), 2);
+    }
+
+    #[tokio::test]
+    async fn providers_are_kept_without_another_on_the_chain() {
+        let fallback = MockProvider::new();
+        fallback.push(U256::from(5)).unwrap();
+
+        let failover = Failover::new(vec![MockProvider::new(), fallback]);
+        failover.set_chain_id(U256::from(1));
+
+        let result: Result<U64, _> = failover.request("eth_blockNumber", ()).await;
+
+        assert!(result.is_err());
+        assert_eq!(failover.switches().load(Ordering::Acquire), 0);
+        assert_eq!(failover.active.load(Ordering::Acquire), 0);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_back_off_instead_of_switching() {
+        // Responses are popped in reverse order
+        let primary = MockProvider::new();
+        primary.push(U64::from(42)).unwrap();
+        primary.push_response(MockResponse::Error(JsonRpcError {
+            code:    429,
+            message: "Too Many Requests".to_string(),
+            data:    None,
+        }));
+
+        let failover = Failover::new(vec![primary, MockProvider::new()]);
+
+        let block_number: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+
+        assert_eq!(block_number, U64::from(42));
+        assert_eq!(failover.switches().load(Ordering::Acquire), 0);
+    }
+
+    #[tokio::test]
+    async fn error_responses_do_not_switch() {
+        let primary = MockProvider::new();
+        primary.push_response(MockResponse::Error(JsonRpcError {
+            code:    3,
+            message: "execution reverted".to_string(),
+            data:    None,
+        }));
+
+        let failover = Failover::new(vec![primary, MockProvider::new()]);
+
+        let result: Result<U64, _> = failover.request("eth_call", ()).await;
+
+        assert!(result.is_err());
+        assert_eq!(failover.switches().load(Ordering::Acquire), 0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use ethers::providers::{Middleware, Provider, Ws};
+use futures::StreamExt;
+use tokio::sync::watch;
+use tracing::{info, warn};
+use url::Url;
+
+/// How long to wait before reconnecting a dropped subscription
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Follows the chain head through a `newHeads` WebSocket subscription, which
+/// reconnects in the background whenever it drops.
+pub struct HeadSubscription {
+    heads:     watch::Receiver<Option<u64>>,
+    last_head: Option<u64>,
+}
+
+impl HeadSubscription {
+    pub fn spawn(url: Url) -> Self {
+        let (sender, heads) = watch::channel(None);
+
+        tokio::spawn(async move {
+            // Stops once the subscription is dropped
+            while !sender.is_closed() {
+                if let Err(error) = forward_heads(&url, &sender).await {
+                    warn!(?error, "Head subscription failed");
+                }
+
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+
+        Self::new(heads)
+    }
+
+    /// Follows the heads sent through `heads`
+    pub fn new(heads: watch::Receiver<Option<u64>>) -> Self {
+        Self {
+            heads,
+            last_head: None,
+        }
+    }
+
+    /// The latest head received
+    pub const fn last_head(&self) -> Option<u64> {
+        self.last_head
+    }
+
+    /// Waits up to `timeout` for a new head. `None` if none arrived.
+    pub async fn next_head(&mut self, timeout: Duration) -> Option<u64> {
+        match tokio::time::timeout(timeout, self.heads.changed()).await {
+            Ok(Ok(())) => {
+                self.last_head = *self.heads.borrow_and_update();
+                self.last_head
+            }
+            // The subscription task never stops while we're around, but
+            // don't spin if it did
+            Ok(Err(_)) => {
+                tokio::time::sleep(timeout).await;
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+async fn forward_heads(url: &Url, sender: &watch::Sender<Option<u64>>) -> anyhow::Result<()> {
+    let provider = Provider::<Ws>::connect(url.as_str()).await?;
+    let mut blocks = provider.subscribe_blocks().await?;
+
+    info!("Subscribed to new heads");
+
+    while let Some(block) = blocks.next().await {
+        let Some(number) = block.number else {
+            continue;
+        };
+
+        if sender.send(Some(number.as_u64())).is_err() {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("Head subscription ended")
+}
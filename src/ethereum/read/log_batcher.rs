@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use ethers::providers::JsonRpcError;
+use ethers::types::{Filter, Log};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+use url::Url;
+
+#[derive(Debug, Deserialize)]
+struct BatchResponse {
+    id:     usize,
+    #[serde(default)]
+    result: Option<Vec<Log>>,
+    #[serde(default)]
+    error:  Option<JsonRpcError>,
+}
+
+/// Sends several `eth_getLogs` requests in a single JSON-RPC batch, which
+/// saves round trips when scanning many windows, e.g. from an old block.
+///
+/// Talks to the provider directly, bypassing the failover and the metrics of
+/// [`ReadProvider`](super::ReadProvider).
+pub struct LogBatcher {
+    client:      reqwest::Client,
+    url:         Url,
+    batch_size:  usize,
+    requests:    u64,
+    round_trips: u64,
+}
+
+impl LogBatcher {
+    pub fn new(url: Url, batch_size: usize, timeout: Duration) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url,
+            batch_size: batch_size.max(1),
+            requests: 0,
+            round_trips: 0,
+        })
+    }
+
+    pub const fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Fetches the logs matching any of `filters`, in the order of the
+    /// filters. Returns `None` if the provider doesn't accept the batch or
+    /// fails to answer any of its requests, in which case the requests have
+    /// to be sent individually.
+    pub async fn get_logs(&mut self, filters: &[Filter]) -> anyhow::Result<Option<Vec<Log>>> {
+        let batch: Vec<_> = filters
+            .iter()
+            .enumerate()
+            .map(|(id, filter)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": "eth_getLogs",
+                    "params": [filter],
+                })
+            })
+            .collect();
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .json(&batch)
+            .send()
+            .await
+            .context("Failed to send JSON-RPC batch")?;
+
+        if !response.status().is_success() {
+            warn!(
+                status = %response.status(),
+                "Provider rejected the JSON-RPC batch, falling back to individual requests"
+            );
+            return Ok(None);
+        }
+
+        let responses: Vec<BatchResponse> = match response.json().await {
+            Ok(responses) => responses,
+            Err(error) => {
+                warn!(
+                    ?error,
+                    "Provider doesn't support JSON-RPC batches, falling back to individual \
+                     requests"
+                );
+                return Ok(None);
+            }
+        };
+
+        // Some providers cap the size of batches and only answer a part
+        if responses.len() != filters.len() {
+            warn!(
+                requests = filters.len(),
+                responses = responses.len(),
+                "Incomplete JSON-RPC batch response, falling back to individual requests"
+            );
+            return Ok(None);
+        }
+
+        let mut results = vec![None; filters.len()];
+        for response in responses {
+            if let Some(error) = response.error {
+                warn!(
+                    window = response.id,
+                    %error,
+                    "Failed to fetch logs of batched window, falling back to individual requests"
+                );
+                return Ok(None);
+            }
+
+            // A null result isn't an empty window, the logs would be skipped
+            let Some(logs) = response.result else {
+                warn!(
+                    window = response.id,
+                    "No logs in response to batched window, falling back to individual requests"
+                );
+                return Ok(None);
+            };
+
+            let result = results
+                .get_mut(response.id)
+                .with_context(|| format!("Unexpected JSON-RPC batch id {}", response.id))?;
+            *result = Some(logs);
+        }
+
+        let results = results
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .context("Missing response in JSON-RPC batch")?;
+
+        self.requests += filters.len() as u64;
+        self.round_trips += 1;
+
+        Ok(Some(results.into_iter().flatten().collect()))
+    }
+
+    /// Logs how many round trips batching saved
+    pub fn log_summary(&self) {
+        info!(
+            requests = self.requests,
+            round_trips = self.round_trips,
+            saved_round_trips = self.requests - self.round_trips,
+            "JSON-RPC log batching summary"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Answers every request with `body`
+    fn serve(body: Value) -> LogBatcher {
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(move || async move { axum::Json(body) }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        LogBatcher::new(url.parse().unwrap(), 2, Duration::from_secs(5)).unwrap()
+    }
+
+    fn log(address: u64) -> Value {
+        json!({
+            "address": Address::from_low_u64_be(address),
+            "topics": [],
+            "data": "0x",
+        })
+    }
+
+    fn filters() -> Vec<Filter> {
+        vec![
+            Filter::new().from_block(0).to_block(9),
+            Filter::new().from_block(10).to_block(19),
+        ]
+    }
+
+    #[tokio::test]
+    async fn batched_logs_are_in_the_order_of_the_filters() {
+        let mut batcher = serve(json!([
+            { "jsonrpc": "2.0", "id": 1, "result": [log(2), log(3)] },
+            { "jsonrpc": "2.0", "id": 0, "result": [log(1)] },
+        ]));
+
+        let logs = batcher.get_logs(&filters()).await.unwrap().unwrap();
+
+        let addresses: Vec<_> = logs.iter().map(|log| log.address).collect();
+        assert_eq!(addresses, vec![
+            Address::from_low_u64_be(1),
+            Address::from_low_u64_be(2),
+            Address::from_low_u64_be(3),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn failed_entries_fall_back_to_individual_requests() {
+        let mut batcher = serve(json!([
+            { "jsonrpc": "2.0", "id": 0, "result": [log(1)] },
+            {
+                "jsonrpc": "2.0",
+                "id": 1,
+                "error": { "code": -32005, "message": "query returned more than 10000 results" },
+            },
+        ]));
+
+        assert!(batcher.get_logs(&filters()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn null_results_fall_back_to_individual_requests() {
+        let mut batcher = serve(json!([
+            { "jsonrpc": "2.0", "id": 0, "result": [log(1)] },
+            { "jsonrpc": "2.0", "id": 1, "result": null },
+        ]));
+
+        assert!(batcher.get_logs(&filters()).await.unwrap().is_none());
+    }
+}
@@ -1,3 +1,6 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use anyhow::anyhow;
 use chrono::{Duration as ChronoDuration, Utc};
 use ethers::abi::Error as AbiError;
@@ -8,35 +11,65 @@ use thiserror::Error;
 use tracing::{error, info};
 use url::Url;
 
+use self::failover::Failover;
 use self::rpc_logger::RpcLogger;
+use self::view_limiter::ViewCallLimiter;
+use crate::config::ProvidersConfig;
 
+pub mod failover;
+pub mod head_subscription;
+pub mod log_batcher;
 pub mod rpc_logger;
+pub mod view_limiter;
 
-type InnerProvider = Provider<RpcLogger<Http>>;
+type InnerProvider = Provider<RpcLogger<ViewCallLimiter<Failover<Http>>>>;
 
 #[derive(Clone, Debug)]
 pub struct ReadProvider {
     inner:        InnerProvider,
+    failover:     Failover<Http>,
+    switches:     Arc<AtomicU64>,
     pub chain_id: U256,
     pub legacy:   bool,
 }
 
 impl ReadProvider {
-    pub async fn new(url: Url) -> anyhow::Result<Self> {
+    pub async fn new(url: Url, config: &ProvidersConfig) -> anyhow::Result<Self> {
+        Self::with_fallbacks(url, vec![], config).await
+    }
+
+    /// Connects to `url`, switching to the next of `fallback_urls` whenever the
+    /// active provider fails with a transport error.
+    pub async fn with_fallbacks(
+        url: Url,
+        fallback_urls: Vec<Url>,
+        config: &ProvidersConfig,
+    ) -> anyhow::Result<Self> {
         // Connect to the Ethereum provider
-        // TODO: Allow multiple providers with failover / broadcast.
         // TODO: Requests don't seem to process in parallel. Check if this is
         // a limitation client side or server side.
         // TODO: Does the WebSocket impl handle dropped connections by
         // reconnecting? What is the timeout on stalled connections? What is
         // the retry policy?
-        let (provider, chain_id, eip1559) = {
+        let (provider, failover, switches, chain_id, eip1559) = {
             info!(
                 provider = %url,
+                fallbacks = fallback_urls.len(),
                 "Connecting to provider"
             );
-            let transport = Http::new(url);
-            let logger = RpcLogger::new(transport);
+            let client = reqwest::Client::builder()
+                .pool_max_idle_per_host(config.http_pool_max_idle_per_host)
+                .pool_idle_timeout(config.http_pool_idle_timeout)
+                .timeout(config.request_timeout)
+                .build()?;
+            let transports = std::iter::once(url)
+                .chain(fallback_urls)
+                .map(|url| Http::new_with_client(url, client.clone()))
+                .collect();
+            let failover = Failover::new(transports);
+            let switches = failover.switches();
+            let limiter = ViewCallLimiter::new(failover.clone(), config.max_concurrent_view_calls);
+            let logger = RpcLogger::new(limiter);
             let provider = Provider::new(logger);
 
             // Fetch state of the chain.
@@ -63,6 +96,7 @@ impl ReadProvider {
                 .number
                 .ok_or_else(|| anyhow!("Could not read latest block number"))?;
             let block_time = latest_block.time()?;
+            failover.set_chain_id(chain_id);
             info!(%version, %chain_id, %chain, %eip1559, %block_number, ?block_hash, %block_time, "Connected to Ethereum provider");
 
             // Sanity check the block timestamp
@@ -77,15 +111,29 @@ impl ReadProvider {
                 // Log an error, but proceed anyway since this doesn't technically block us.
                 error!(%now, %block_time, %block_age, "Block time is more than 30 minutes from now.");
             }
-            (provider, chain_id, eip1559)
+            (provider, failover, switches, chain_id, eip1559)
         };
 
         Ok(Self {
             inner: provider,
+            failover,
+            switches,
             chain_id,
             legacy: !eip1559,
         })
     }
+
+    /// The number of times this provider switched to a fallback. Changes
+    /// whenever the node serving requests changes.
+    #[must_use]
+    pub fn provider_switches(&self) -> u64 {
+        self.switches.load(Ordering::Acquire)
+    }
+
+    /// Switches to the next fallback provider, if any
+    pub async fn switch_provider(&self) {
+        self.failover.switch().await;
+    }
 }
 
 impl Middleware for ReadProvider {
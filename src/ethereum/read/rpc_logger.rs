@@ -1,29 +1,88 @@
 use std::fmt::Debug;
 
-use ::prometheus::{register_histogram, register_int_counter_vec, Histogram, IntCounterVec};
+use ::prometheus::{
+    register_histogram_with_registry, register_int_counter_vec_with_registry, Histogram,
+    IntCounterVec,
+};
 use async_trait::async_trait;
-use ethers::providers::JsonRpcClient;
+use ethers::providers::{JsonRpcClient, JsonRpcError, RpcError};
 use once_cell::sync::Lazy;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tracing::instrument;
 
+use crate::metrics;
+
 static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
-    register_int_counter_vec!(
+    register_int_counter_vec_with_registry!(
         "eth_rpc_requests",
         "Number of Ethereum provider requests made by method.",
-        &["method"]
+        &["method"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+static ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "eth_rpc_errors_total",
+        "Number of failed Ethereum provider requests by method and error class.",
+        &["method", "class"],
+        metrics::registry()
     )
     .unwrap()
 });
 static LATENCY: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!(
+    register_histogram_with_registry!(
         "eth_rpc_latency_seconds",
-        "The Ethereum provider latency in seconds."
+        "The Ethereum provider latency in seconds.",
+        metrics::registry()
     )
     .unwrap()
 });
 
+/// Sorts errors into a handful of classes to keep the label cardinality low
+fn error_class(error: &impl RpcError) -> &'static str {
+    match error.as_error_response() {
+        Some(response) => error_response_class(response),
+        None if is_rate_limited(error) => "ratelimit",
+        None if error.as_serde_error().is_some() => "other",
+        None => "transport",
+    }
+}
+
+/// Whether the provider refused the request because of its rate limit, either
+/// in a JSON-RPC error or with an HTTP 429. The HTTP transport doesn't expose
+/// the status, so the latter is recognized by the body it failed to decode.
+pub fn is_rate_limited(error: &impl RpcError) -> bool {
+    match error.as_error_response() {
+        Some(response) => is_rate_limit_response(response),
+        None => {
+            let message = error.to_string().to_lowercase();
+            message.contains("too many requests") || message.contains("rate limit")
+        }
+    }
+}
+
+fn is_rate_limit_response(response: &JsonRpcError) -> bool {
+    // 429 is used by some providers in the JSON-RPC error, -32005 is the
+    // "limit exceeded" code from EIP-1474
+    response.code == 429
+        || response.code == -32005
+        || response.message.to_lowercase().contains("rate limit")
+}
+
+fn error_response_class(response: &JsonRpcError) -> &'static str {
+    let message = response.message.to_lowercase();
+
+    if is_rate_limit_response(response) {
+        "ratelimit"
+    } else if response.code == 3 || message.contains("revert") {
+        "revert"
+    } else {
+        "other"
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RpcLogger<Inner> {
     inner: Inner,
@@ -53,6 +112,69 @@ where
         let timer = LATENCY.start_timer();
         let result = self.inner.request(method, params).await;
         timer.observe_duration();
+
+        if let Err(error) = &result {
+            ERRORS
+                .with_label_values(&[method, error_class(error)])
+                .inc();
+        }
+
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::HttpClientError;
+
+    use super::*;
+
+    fn response(code: i64, message: &str) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn error_responses_are_classified_coarsely() {
+        assert_eq!(
+            error_response_class(&response(3, "execution reverted")),
+            "revert"
+        );
+        assert_eq!(
+            error_response_class(&response(-32000, "Transaction reverted without a reason")),
+            "revert"
+        );
+        assert_eq!(
+            error_response_class(&response(-32005, "limit exceeded")),
+            "ratelimit"
+        );
+        assert_eq!(
+            error_response_class(&response(-32000, "Rate limit reached")),
+            "ratelimit"
+        );
+        assert_eq!(
+            error_response_class(&response(-32000, "nonce too low")),
+            "other"
+        );
+    }
+
+    #[test]
+    fn http_429s_are_rate_limited() {
+        let too_many_requests = HttpClientError::SerdeJson {
+            err:  serde_json::from_str::<u64>("<html>").unwrap_err(),
+            text: "<html><head><title>429 Too Many Requests</title></head></html>".to_string(),
+        };
+        assert!(is_rate_limited(&too_many_requests));
+        assert_eq!(error_class(&too_many_requests), "ratelimit");
+
+        let unexpected_body = HttpClientError::SerdeJson {
+            err:  serde_json::from_str::<u64>("<html>").unwrap_err(),
+            text: "<html><head><title>502 Bad Gateway</title></head></html>".to_string(),
+        };
+        assert!(!is_rate_limited(&unexpected_body));
+        assert_eq!(error_class(&unexpected_body), "other");
+    }
+}
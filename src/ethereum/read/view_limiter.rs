@@ -0,0 +1,133 @@
+use std::fmt::Debug;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use ::prometheus::{register_int_gauge_with_registry, IntGauge};
+use async_trait::async_trait;
+use ethers::providers::JsonRpcClient;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use crate::metrics;
+
+static IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "eth_rpc_view_calls_in_flight",
+        "Number of contract view calls and gas estimations currently in flight.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Methods that execute contract code without sending a transaction
+const VIEW_METHODS: &[&str] = &["eth_call", "eth_estimateGas"];
+
+/// Bounds the number of concurrent view calls, so that checks running for
+/// every insertion can't flood the node. Other requests aren't limited.
+#[derive(Debug, Clone)]
+pub struct ViewCallLimiter<Inner> {
+    inner:   Inner,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl<Inner> ViewCallLimiter<Inner> {
+    /// View calls aren't limited if `max_concurrent` is `None`.
+    pub fn new(inner: Inner, max_concurrent: Option<NonZeroUsize>) -> Self {
+        Self {
+            inner,
+            permits: max_concurrent
+                .map(|max_concurrent| Arc::new(Semaphore::new(max_concurrent.get()))),
+        }
+    }
+}
+
+#[async_trait]
+impl<Inner> JsonRpcClient for ViewCallLimiter<Inner>
+where
+    Inner: JsonRpcClient + 'static,
+    <Inner as JsonRpcClient>::Error: Sync + Send + 'static,
+{
+    type Error = Inner::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let Some(permits) = &self.permits else {
+            return self.inner.request(method, params).await;
+        };
+
+        if !VIEW_METHODS.contains(&method) {
+            return self.inner.request(method, params).await;
+        }
+
+        // The semaphore is never closed
+        let _permit = permits.acquire().await.expect("Semaphore closed");
+
+        let _in_flight = InFlight::new();
+
+        self.inner.request(method, params).await
+    }
+}
+
+/// Keeps the gauge accurate when a request is cancelled
+struct InFlight;
+
+impl InFlight {
+    fn new() -> Self {
+        IN_FLIGHT.inc();
+        Self
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethers::providers::{MockError, MockProvider};
+    use ethers::types::{Bytes, U64};
+
+    use super::*;
+
+    async fn eth_call(limiter: &ViewCallLimiter<MockProvider>) -> Result<Bytes, MockError> {
+        limiter.request("eth_call", ()).await
+    }
+
+    #[tokio::test]
+    async fn only_view_calls_wait_for_a_permit() {
+        let mock = MockProvider::new();
+        let limiter = ViewCallLimiter::new(mock.clone(), NonZeroUsize::new(1));
+
+        let held = limiter
+            .permits
+            .clone()
+            .unwrap()
+            .acquire_owned()
+            .await
+            .unwrap();
+
+        mock.push(U64::from(42)).unwrap();
+        let block_number: U64 = limiter.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(block_number, U64::from(42));
+
+        mock.push(Bytes::default()).unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), eth_call(&limiter))
+                .await
+                .is_err()
+        );
+
+        drop(held);
+
+        assert_eq!(eth_call(&limiter).await.unwrap(), Bytes::default());
+    }
+}
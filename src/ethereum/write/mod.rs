@@ -1,8 +1,9 @@
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
 use ethers::providers::ProviderError;
-use ethers::types::{TransactionReceipt, H256};
+use ethers::types::{TransactionReceipt, H256, U256};
 use thiserror::Error;
 
 #[derive(Clone, Debug)]
@@ -47,6 +48,33 @@ pub enum TxError {
     #[error("Transaction failed: {0:?}.")]
     Failed(Option<TransactionReceipt>),
 
+    #[error("Transaction {hash:?} reverted: {}", reason.as_deref().unwrap_or("unknown reason"))]
+    Reverted {
+        hash:   H256,
+        reason: Option<String>,
+    },
+
+    #[error("Contract root moved: expected {expected}, found {actual}")]
+    RootMoved { expected: U256, actual: U256 },
+
+    #[error("Calldata of {size} bytes exceeds the maximum of {max} bytes")]
+    CalldataTooLarge { size: usize, max: usize },
+
+    #[error("Transaction {selector} is not in the selector allowlist")]
+    SelectorNotAllowed { selector: String },
+
+    #[error("Tree is full: {capacity} leaves")]
+    TreeFull { capacity: usize },
+
+    #[error("Retry budget exhausted after {attempts} attempts over {elapsed:?}")]
+    RetryBudgetExhausted { attempts: u32, elapsed: Duration },
+
+    #[error("Insertion timed out after {elapsed:?}")]
+    InsertionTimeout { elapsed: Duration },
+
+    #[error("Gas spend limit reached: {spent} of {budget} wei spent in the current window")]
+    SpendLimitExceeded { spent: U256, budget: U256 },
+
     #[error("Error parsing transaction id: {0}")]
     Parse(Box<dyn Error + Send + Sync + 'static>),
 
@@ -5,14 +5,24 @@ use thiserror::Error;
 pub enum Error {
     #[error("Transport error")]
     Transport(#[from] ethers::providers::HttpClientError),
-    #[error("Authentication error")]
-    Authentication,
+    #[error("Authentication error: {0}")]
+    Authentication(AuthenticationError),
     #[error("Request failed")]
     RequestFailed,
     #[error("Unknown response format")]
     UnknownResponseFormat,
     #[error("Missing transaction id")]
     MissingTransactionId,
+    #[error("Transaction already known to the node")]
+    AlreadyKnown,
+}
+
+#[derive(Error, Debug)]
+pub enum AuthenticationError {
+    #[error("timed out")]
+    Timeout,
+    #[error("credentials rejected")]
+    Rejected,
 }
 
 impl RpcError for Error {
@@ -40,12 +50,85 @@ impl From<Error> for ProviderError {
 impl From<oz_api::Error> for Error {
     fn from(value: oz_api::Error) -> Self {
         match value {
-            oz_api::Error::AuthFailed(_) | oz_api::Error::Unauthorized => Self::Authentication,
+            oz_api::Error::AuthFailed(_) | oz_api::Error::Unauthorized => {
+                Self::Authentication(AuthenticationError::Rejected)
+            }
+            oz_api::Error::AuthTimeout(_) => Self::Authentication(AuthenticationError::Timeout),
+            oz_api::Error::InvalidResponse(_, body) if is_already_known(&body) => {
+                Self::AlreadyKnown
+            }
             oz_api::Error::Reqwest(_)
             | oz_api::Error::Headers(_)
             | oz_api::Error::UrlParseError(_)
-            | oz_api::Error::InvalidResponse(_) => Self::RequestFailed,
+            | oz_api::Error::InvalidResponse(..) => Self::RequestFailed,
             oz_api::Error::ParseError(_) => Self::UnknownResponseFormat,
         }
     }
 }
+
+/// Node errors meaning that the transaction being sent was sent before and is
+/// pending or already mined, as relayed by the relayer. A too low nonce can
+/// also mean that another transaction took the nonce, which is told apart by
+/// looking up the earlier submission.
+const ALREADY_KNOWN_ERRORS: &[&str] = &[
+    // geth and erigon
+    "already known",
+    "nonce too low",
+    // older geth
+    "known transaction",
+    // nethermind
+    "alreadyknown",
+    "oldnonce",
+    // openethereum
+    "already imported",
+    "nonce is too low",
+];
+
+/// Whether `message` is a node error meaning that the transaction was sent
+/// before, in which case the earlier transaction should be followed instead
+/// of sending it again
+pub fn is_already_known(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+
+    ALREADY_KNOWN_ERRORS
+        .iter()
+        .any(|error| message.contains(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn already_known_errors_are_recognized() {
+        let already_known = [
+            // geth
+            r#"{"code":-32000,"message":"already known"}"#,
+            "nonce too low: next nonce 12, tx nonce 11",
+            "known transaction: 5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060",
+            // erigon
+            "ALREADY_EXISTS: already known",
+            "NONCE_TOO_LOW: nonce too low",
+            // nethermind
+            "AlreadyKnown",
+            "OldNonce, Current nonce: 12, nonce of rejected tx: 11",
+            // openethereum
+            "Transaction with the same hash was already imported.",
+            "Transaction nonce is too low. Try incrementing the nonce.",
+        ];
+        for message in already_known {
+            assert!(is_already_known(message), "{message}");
+        }
+
+        let others = [
+            "replacement transaction underpriced",
+            "insufficient funds for gas * price + value",
+            "execution reverted",
+            "nonce too high",
+            "",
+        ];
+        for message in others {
+            assert!(!is_already_known(message), "{message}");
+        }
+    }
+}
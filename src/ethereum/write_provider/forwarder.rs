@@ -0,0 +1,372 @@
+use std::cmp;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use ethers::abi::{encode, Token};
+use ethers::providers::Middleware;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip712::EIP712Domain;
+use ethers::types::{Address, Bytes, H256, U256};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::gas_estimates::{CalldataShape, GasEstimates};
+use super::inner::{Inner, TransactionResult};
+use crate::config::ForwarderConfig;
+use crate::contracts::abi::MinimalForwarder;
+use crate::ethereum::write::TransactionId;
+use crate::ethereum::{ReadProvider, TxError};
+use crate::utils::secret::SecretUrl;
+
+const FORWARD_REQUEST_TYPE: &str =
+    "ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,bytes data)";
+
+const MINING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The `ForwardRequest` struct of the ERC-2771 `MinimalForwarder` contract.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ForwardRequest {
+    pub from:  Address,
+    pub to:    Address,
+    pub value: U256,
+    pub gas:   U256,
+    pub nonce: U256,
+    pub data:  Bytes,
+}
+
+impl ForwardRequest {
+    pub fn struct_hash(&self) -> [u8; 32] {
+        keccak256(encode(&[
+            Token::FixedBytes(keccak256(FORWARD_REQUEST_TYPE).to_vec()),
+            Token::Address(self.from),
+            Token::Address(self.to),
+            Token::Uint(self.value),
+            Token::Uint(self.gas),
+            Token::Uint(self.nonce),
+            Token::FixedBytes(keccak256(&self.data).to_vec()),
+        ]))
+    }
+
+    /// The EIP-712 digest that gets signed, i.e. `keccak256("\x19\x01" ‖
+    /// domainSeparator ‖ structHash)`.
+    pub fn typed_data_hash(&self, domain: &EIP712Domain) -> H256 {
+        let mut digest_input = [0u8; 66];
+        digest_input[..2].copy_from_slice(&[0x19, 0x01]);
+        digest_input[2..34].copy_from_slice(&domain.separator());
+        digest_input[34..].copy_from_slice(&self.struct_hash());
+
+        H256(keccak256(digest_input))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RelayRequest<'a> {
+    request:   &'a ForwardRequest,
+    signature: Bytes,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RelayResponse {
+    tx_hash: H256,
+}
+
+/// Signs transactions as EIP-712 forward requests and hands them to a relay,
+/// which submits them to the forwarder contract.
+///
+/// The relay responds with the hash of the forwarding transaction, which is
+/// used as the transaction id.
+pub struct Forwarder {
+    client:        reqwest::Client,
+    relay_url:     SecretUrl,
+    read_provider: ReadProvider,
+    forwarder:     MinimalForwarder<ReadProvider>,
+    wallet:        LocalWallet,
+    domain:        EIP712Domain,
+    gas_limit:     Option<u64>,
+    gas_estimates: GasEstimates,
+    next_nonce:    Mutex<NextNonce>,
+}
+
+/// The locally tracked nonce, along with the provider it was derived from
+#[derive(Default)]
+struct NextNonce {
+    nonce:             U256,
+    provider_switches: u64,
+}
+
+impl NextNonce {
+    /// Records that a request with `nonce` was relayed. Requests that failed
+    /// to be relayed leave the nonce, so that it's used by the next one.
+    fn advance(&mut self, nonce: U256) {
+        self.nonce = cmp::max(self.nonce, nonce + 1);
+    }
+}
+
+impl Forwarder {
+    pub fn new(config: &ForwarderConfig, read_provider: ReadProvider) -> anyhow::Result<Self> {
+        let wallet: LocalWallet = config
+            .forwarder_signing_key
+            .expose()
+            .parse()
+            .context("Invalid forwarder signing key")?;
+
+        if wallet.address() != config.forwarder_signer_address {
+            anyhow::bail!(
+                "Forwarder signing key does not match the signer address {:?}",
+                config.forwarder_signer_address
+            );
+        }
+
+        let domain = EIP712Domain {
+            name:               Some(config.forwarder_domain_name.clone()),
+            version:            Some(config.forwarder_domain_version.clone()),
+            chain_id:           Some(read_provider.chain_id),
+            verifying_contract: Some(config.forwarder_address),
+            salt:               None,
+        };
+
+        let forwarder =
+            MinimalForwarder::new(config.forwarder_address, Arc::new(read_provider.clone()));
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            relay_url: config.forwarder_relay_url.clone(),
+            read_provider,
+            forwarder,
+            wallet,
+            domain,
+            gas_limit: config.forwarder_gas_limit,
+            gas_estimates: GasEstimates::new(
+                config.forwarder_gas_estimate_ttl,
+                config.forwarder_gas_estimate_headroom,
+            )?,
+            next_nonce: Mutex::new(NextNonce::default()),
+        })
+    }
+
+    /// Returns the next forwarder nonce, accounting for requests that were
+    /// relayed but haven't been mined yet. It's only advanced once the request
+    /// using it has been relayed, see [`NextNonce::advance`], so the lock must
+    /// be held until then.
+    ///
+    /// After a provider switch the local nonce is re-seeded from the new
+    /// provider's view of the chain.
+    async fn next_nonce(&self, next_nonce: &mut NextNonce) -> Result<U256, TxError> {
+        let provider_switches = self.read_provider.provider_switches();
+        if next_nonce.provider_switches != provider_switches {
+            *next_nonce = NextNonce {
+                nonce: U256::zero(),
+                provider_switches,
+            };
+        }
+
+        let on_chain_nonce = self
+            .forwarder
+            .get_nonce(self.wallet.address())
+            .call()
+            .await
+            .map_err(|err| TxError::Fill(err.into()))?;
+
+        Ok(cmp::max(on_chain_nonce, next_nonce.nonce))
+    }
+
+    async fn estimate_gas(&self, tx: &TypedTransaction) -> Result<U256, TxError> {
+        let shape = CalldataShape::of(tx);
+
+        if let Some(gas) = shape
+            .as_ref()
+            .and_then(|shape| self.gas_estimates.get(shape))
+        {
+            return Ok(gas);
+        }
+
+        let gas = self
+            .read_provider
+            .estimate_gas(tx, None)
+            .await
+            .map_err(|err| TxError::Fill(err.into()))?;
+
+        if let Some(shape) = shape {
+            self.gas_estimates.insert(shape, gas);
+        }
+
+        Ok(gas)
+    }
+
+    async fn mine_transaction_inner(&self, hash: H256) -> Result<(), TxError> {
+        loop {
+            let receipt = self
+                .read_provider
+                .get_transaction_receipt(hash)
+                .await
+                .map_err(|err| TxError::Fetch(err.into()))?;
+
+            if let Some(receipt) = receipt {
+                if receipt.status == Some(0.into()) {
+                    // The estimate may have been too low, e.g. the transaction
+                    // ran out of gas
+                    self.gas_estimates.invalidate();
+
+                    return Err(TxError::Reverted { hash, reason: None });
+                }
+
+                return Ok(());
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Inner for Forwarder {
+    async fn send_transaction(
+        &self,
+        mut tx: TypedTransaction,
+        _only_once: bool,
+    ) -> Result<TransactionId, TxError> {
+        tx.set_from(self.wallet.address());
+
+        let to = *tx
+            .to_addr()
+            .context("Tx receiver must be an address")
+            .map_err(TxError::Send)?;
+
+        let gas = match (self.gas_limit, tx.gas()) {
+            (Some(gas_limit), _) => U256::from(gas_limit),
+            (None, Some(gas)) => *gas,
+            (None, None) => self.estimate_gas(&tx).await?,
+        };
+
+        // Held until the request is relayed, so that concurrent requests get
+        // consecutive nonces
+        let mut next_nonce = self.next_nonce.lock().await;
+
+        let request = ForwardRequest {
+            from: self.wallet.address(),
+            to,
+            value: tx.value().copied().unwrap_or_default(),
+            gas,
+            nonce: self.next_nonce(&mut next_nonce).await?,
+            data: tx.data().cloned().unwrap_or_default(),
+        };
+
+        let signature = self
+            .wallet
+            .sign_hash(request.typed_data_hash(&self.domain))
+            .map_err(|err| TxError::Send(err.into()))?;
+
+        let response: RelayResponse = self
+            .client
+            .post(self.relay_url.expose())
+            .json(&RelayRequest {
+                request:   &request,
+                signature: signature.to_vec().into(),
+            })
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| TxError::Send(err.into()))?
+            .json()
+            .await
+            .map_err(|err| TxError::Send(err.into()))?;
+
+        next_nonce.advance(request.nonce);
+
+        Ok(TransactionId(format!("{:?}", response.tx_hash)))
+    }
+
+    async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
+        // The relay doesn't expose the transactions it has in flight
+        Ok(vec![])
+    }
+
+    async fn mine_transaction(&self, tx: TransactionId) -> Result<TransactionResult, TxError> {
+        let hash: H256 = tx.0.parse().map_err(|err| TxError::Parse(Box::new(err)))?;
+
+        tokio::time::timeout(MINING_TIMEOUT, self.mine_transaction_inner(hash))
+            .await
+            .map_err(|_| TxError::ConfirmationTimeout)??;
+
+        Ok(TransactionResult {
+            transaction_id: tx.0,
+            hash:           Some(hash),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn domain() -> EIP712Domain {
+        EIP712Domain {
+            name:               Some("MinimalForwarder".to_string()),
+            version:            Some("0.0.1".to_string()),
+            chain_id:           Some(U256::from(1)),
+            verifying_contract: Some(Address::repeat_byte(0x11)),
+            salt:               None,
+        }
+    }
+
+    fn request() -> ForwardRequest {
+        ForwardRequest {
+            from:  Address::repeat_byte(0x22),
+            to:    Address::repeat_byte(0x33),
+            value: U256::zero(),
+            gas:   U256::from(1_000_000),
+            nonce: U256::from(7),
+            data:  Bytes::from(hex!("deadbeef")),
+        }
+    }
+
+    #[test]
+    fn typed_data_hash() {
+        assert_eq!(
+            domain().separator(),
+            hex!("bc312bbf6bcfe21e584766274f759e698b5066ad77353630562c3a7615f29755")
+        );
+        assert_eq!(
+            request().struct_hash(),
+            hex!("101c3dc9c8cfacaff57fd750e398dd58ceeb3d4e8bd738fa801c30963bbf91c1")
+        );
+        assert_eq!(
+            request().typed_data_hash(&domain()),
+            H256(hex!(
+                "0e6351a1d9defff4a5d557a54a675c28f91bc6c6196a1739cad5cbdd009c0019"
+            ))
+        );
+    }
+
+    #[test]
+    fn nonces_only_advance_once_relayed() {
+        let mut next_nonce = NextNonce::default();
+
+        next_nonce.advance(U256::from(3));
+        assert_eq!(next_nonce.nonce, U256::from(4));
+
+        // A request relayed behind a later one doesn't move the nonce back
+        next_nonce.advance(U256::from(1));
+        assert_eq!(next_nonce.nonce, U256::from(4));
+    }
+
+    #[test]
+    fn signature_recovers_to_signer() {
+        let wallet: LocalWallet =
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+
+        let hash = request().typed_data_hash(&domain());
+        let signature = wallet.sign_hash(hash).unwrap();
+
+        assert_eq!(signature.recover(hash).unwrap(), wallet.address());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers::abi::AbiDecode;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, U256};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec_with_registry, IntCounterVec};
+
+use crate::contracts::abi::WorldIdCalls;
+use crate::metrics;
+
+static LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "gas_estimate_cache_lookups",
+        "Lookups of cached gas estimates, by whether they were a hit or a miss.",
+        &["result"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Batches of the same kind and size sent to the same contract are assumed to
+/// cost about the same gas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CalldataShape {
+    to:         Address,
+    selector:   [u8; 4],
+    batch_size: usize,
+}
+
+impl CalldataShape {
+    /// `None` for transactions that don't submit a batch
+    pub fn of(tx: &TypedTransaction) -> Option<Self> {
+        let to = *tx.to_addr()?;
+        let data = tx.data()?;
+        let selector = data.get(..4)?.try_into().ok()?;
+
+        let batch_size = match WorldIdCalls::decode(data).ok()? {
+            WorldIdCalls::RegisterIdentities(call) => call.identity_commitments.len(),
+            // Deletion indices are packed as 4 bytes each
+            WorldIdCalls::DeleteIdentities(call) => call.packed_deletion_indices.len() / 4,
+            _ => return None,
+        };
+
+        Some(Self {
+            to,
+            selector,
+            batch_size,
+        })
+    }
+}
+
+/// Reuses gas estimates of batches with the same shape for a while, which
+/// saves an `eth_estimateGas` per transaction.
+pub struct GasEstimates {
+    ttl:              Option<Duration>,
+    // The headroom on reused estimates
+    headroom_percent: u64,
+    estimates:        Mutex<HashMap<CalldataShape, (U256, Instant)>>,
+}
+
+impl GasEstimates {
+    /// Reused estimates are multiplied by `headroom`, which can't be below 1.
+    /// Without a `ttl`, every batch is estimated anew.
+    pub fn new(ttl: Option<Duration>, headroom: f64) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            headroom >= 1.0 && headroom.is_finite(),
+            "forwarder_gas_estimate_headroom must be at least 1, got {headroom}"
+        );
+
+        Ok(Self {
+            ttl,
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            headroom_percent: (headroom * 100.0).round() as u64,
+            estimates: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The cached estimate with headroom on top
+    pub fn get(&self, shape: &CalldataShape) -> Option<U256> {
+        let ttl = self.ttl?;
+
+        let estimates = self.estimates.lock().unwrap();
+        let gas = estimates
+            .get(shape)
+            .filter(|(_, estimated_at)| estimated_at.elapsed() < ttl)
+            .map(|(gas, _)| gas.saturating_mul(self.headroom_percent.into()) / 100);
+
+        let result = if gas.is_some() { "hit" } else { "miss" };
+        LOOKUPS.with_label_values(&[result]).inc();
+
+        gas
+    }
+
+    pub fn insert(&self, shape: CalldataShape, gas: U256) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+
+        let mut estimates = self.estimates.lock().unwrap();
+        estimates.retain(|_, (_, estimated_at)| estimated_at.elapsed() < ttl);
+        estimates.insert(shape, (gas, Instant::now()));
+    }
+
+    /// Forces the next transactions to be estimated again, e.g. after one of
+    /// them failed.
+    pub fn invalidate(&self) {
+        self.estimates.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::AbiEncode;
+    use ethers::types::{Bytes, TransactionRequest};
+
+    use super::*;
+    use crate::contracts::abi::{DeleteIdentitiesCall, RegisterIdentitiesCall};
+
+    fn call(data: Vec<u8>) -> TypedTransaction {
+        TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .data(Bytes::from(data))
+            .into()
+    }
+
+    fn insertion(commitments: &[u64]) -> TypedTransaction {
+        call(
+            WorldIdCalls::RegisterIdentities(RegisterIdentitiesCall {
+                insertion_proof:      [U256::zero(); 8],
+                pre_root:             U256::from(1),
+                start_index:          0,
+                identity_commitments: commitments.iter().copied().map(U256::from).collect(),
+                post_root:            U256::from(2),
+            })
+            .encode(),
+        )
+    }
+
+    fn deletion(indices: usize) -> TypedTransaction {
+        call(
+            WorldIdCalls::DeleteIdentities(DeleteIdentitiesCall {
+                deletion_proof:          [U256::zero(); 8],
+                packed_deletion_indices: vec![0; indices * 4].into(),
+                pre_root:                U256::from(1),
+                post_root:               U256::from(2),
+            })
+            .encode(),
+        )
+    }
+
+    fn estimates(ttl: Option<Duration>) -> GasEstimates {
+        GasEstimates::new(ttl, 1.0).unwrap()
+    }
+
+    #[test]
+    fn batches_of_the_same_shape_share_estimates() {
+        let estimates = estimates(Some(Duration::from_secs(60)));
+        let shape = CalldataShape::of(&insertion(&[1, 2])).unwrap();
+
+        assert_eq!(estimates.get(&shape), None);
+
+        estimates.insert(shape, U256::from(21_000));
+
+        let same_shape = CalldataShape::of(&insertion(&[3, 4])).unwrap();
+        let larger = CalldataShape::of(&insertion(&[1, 2, 3])).unwrap();
+        let deletion = CalldataShape::of(&deletion(2)).unwrap();
+        assert_eq!(estimates.get(&same_shape), Some(U256::from(21_000)));
+        assert_eq!(estimates.get(&larger), None);
+        assert_eq!(estimates.get(&deletion), None);
+
+        estimates.invalidate();
+        assert_eq!(estimates.get(&shape), None);
+    }
+
+    #[test]
+    fn reused_estimates_get_headroom() {
+        let estimates = GasEstimates::new(Some(Duration::from_secs(60)), 1.2).unwrap();
+        let shape = CalldataShape::of(&deletion(3)).unwrap();
+
+        estimates.insert(shape, U256::from(100_000));
+
+        assert_eq!(estimates.get(&shape), Some(U256::from(120_000)));
+        assert!(GasEstimates::new(None, 0.9).is_err());
+    }
+
+    #[test]
+    fn estimates_expire() {
+        let estimates = estimates(Some(Duration::ZERO));
+        let shape = CalldataShape::of(&insertion(&[1])).unwrap();
+
+        estimates.insert(shape, U256::from(21_000));
+
+        assert_eq!(estimates.get(&shape), None);
+    }
+
+    #[test]
+    fn every_batch_is_estimated_without_a_ttl() {
+        let estimates = estimates(None);
+        let shape = CalldataShape::of(&insertion(&[1])).unwrap();
+
+        estimates.insert(shape, U256::from(21_000));
+
+        assert_eq!(estimates.get(&shape), None);
+    }
+
+    #[test]
+    fn only_batch_submissions_have_a_shape() {
+        assert!(CalldataShape::of(&call(vec![1, 2, 3, 4, 5])).is_none());
+        assert!(CalldataShape::of(&call(vec![1, 2])).is_none());
+
+        // Without a recipient, the calldata of a batch deploys a contract
+        let mut deployment = insertion(&[1]);
+        if let TypedTransaction::Legacy(request) = &mut deployment {
+            request.to = None;
+        }
+        assert!(CalldataShape::of(&deployment).is_none());
+    }
+}
@@ -0,0 +1,814 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use ethers::providers::Middleware;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{BlockNumber, TransactionRequest, U256};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use super::TransactionStatus;
+use crate::config::{GasStrategy, RelayerConfig};
+use crate::ethereum::{ReadProvider, TxError};
+
+/// The most blocks `eth_feeHistory` is generally allowed to return
+const MAX_FEE_HISTORY_BLOCKS: u64 = 1024;
+
+/// How much higher the fees of a replacement have to be than the ones of the
+/// transaction it replaces for nodes to accept it
+const REPLACEMENT_BUMP_PERCENT: u64 = 10;
+
+/// The fees returned by a [`GasStrategy::Oracle`], in wei
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OracleFees {
+    max_fee_per_gas:          U256,
+    max_priority_fee_per_gas: U256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fees {
+    Legacy {
+        gas_price: U256,
+    },
+    Eip1559 {
+        max_fee_per_gas:          U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+/// Prices transactions according to the configured [`GasStrategy`], before
+/// they're handed to the relayer. Without a strategy, fees are left to the
+/// relayer.
+///
+/// The relayer doesn't reprice transactions with fees set by the sequencer,
+/// so with `reprice_after`, transactions still pending after that long are
+/// priced again and replaced.
+///
+/// With the `oz_defender` relayer's legacy fallback, EIP-1559 transactions
+/// still pending after a while are replaced with legacy ones.
+pub struct GasPricer {
+    strategy:        Option<GasStrategy>,
+    reprice_after:   Option<Duration>,
+    reprice_max_fee: Option<U256>,
+    legacy_fallback: Option<LegacyFallback>,
+    client:          reqwest::Client,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LegacyFallback {
+    after:        Duration,
+    bump_percent: u64,
+}
+
+impl GasPricer {
+    /// Fails if the strategy's parameters are invalid, or the relayer or the
+    /// chain don't support it.
+    pub fn new(
+        strategy: Option<GasStrategy>,
+        reprice_after: Option<Duration>,
+        reprice_max_fee: Option<U256>,
+        relayer: &RelayerConfig,
+        legacy: bool,
+        request_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        if let Some(strategy) = &strategy {
+            validate(strategy, relayer, legacy)?;
+        }
+
+        if let Some(reprice_after) = reprice_after {
+            anyhow::ensure!(strategy.is_some(), "gas_reprice_after needs a gas_strategy");
+            anyhow::ensure!(
+                !reprice_after.is_zero(),
+                "gas_reprice_after must be positive"
+            );
+        }
+
+        let legacy_fallback = match relayer {
+            RelayerConfig::OzDefender(oz_config) => {
+                oz_config
+                    .oz_legacy_fallback_after
+                    .map(|after| LegacyFallback {
+                        after,
+                        bump_percent: oz_config.oz_legacy_fallback_bump_percent,
+                    })
+            }
+            _ => None,
+        };
+
+        if let Some(fallback) = &legacy_fallback {
+            anyhow::ensure!(
+                !fallback.after.is_zero(),
+                "oz_legacy_fallback_after must be positive"
+            );
+            anyhow::ensure!(
+                fallback.bump_percent >= REPLACEMENT_BUMP_PERCENT,
+                "oz_legacy_fallback_bump_percent must be at least {REPLACEMENT_BUMP_PERCENT}"
+            );
+        }
+
+        Ok(Self {
+            strategy,
+            reprice_after,
+            reprice_max_fee,
+            legacy_fallback,
+            client: reqwest::Client::builder()
+                .timeout(request_timeout)
+                .build()?,
+        })
+    }
+
+    /// How long a transaction may stay pending before it's repriced, `None`
+    /// if it's left to the relayer
+    pub fn reprice_after(&self) -> Option<Duration> {
+        self.reprice_after
+    }
+
+    /// How long an EIP-1559 transaction may stay pending before it falls back
+    /// to a legacy one, `None` if it doesn't
+    pub fn legacy_fallback_after(&self) -> Option<Duration> {
+        self.legacy_fallback.map(|fallback| fallback.after)
+    }
+
+    /// Sets the fees of `tx`. On chains without EIP-1559, the transaction is
+    /// turned into a legacy one.
+    pub async fn apply(
+        &self,
+        read_provider: &ReadProvider,
+        tx: &mut TypedTransaction,
+    ) -> Result<(), TxError> {
+        let Some(strategy) = &self.strategy else {
+            return Ok(());
+        };
+
+        let fees = self
+            .fees(strategy, read_provider, read_provider.legacy)
+            .await
+            .map_err(|err| TxError::Fill(err.into()))?;
+        debug!(?strategy, ?fees, "Priced transaction");
+
+        apply_fees(tx, fees);
+
+        Ok(())
+    }
+
+    /// Prices `tx` to replace `current`, a pending transaction with the same
+    /// calldata. The fees are at least [`REPLACEMENT_BUMP_PERCENT`] higher than
+    /// the current ones, even if the strategy prices lower. `false`, leaving
+    /// `tx` as is, if there's no strategy or the fees would exceed the reprice
+    /// cap.
+    pub async fn reprice(
+        &self,
+        read_provider: &ReadProvider,
+        tx: &mut TypedTransaction,
+        current: &TransactionStatus,
+    ) -> Result<bool, TxError> {
+        let Some(strategy) = &self.strategy else {
+            return Ok(false);
+        };
+
+        let fees = self
+            .fees(strategy, read_provider, read_provider.legacy)
+            .await
+            .map_err(|err| TxError::Fill(err.into()))?;
+        let fees = replacement_fees(fees, current);
+
+        if self.exceeds_reprice_cap(&fees) {
+            warn!(?fees, cap = ?self.reprice_max_fee, "Replacement fees exceed the reprice cap, not repricing");
+            return Ok(false);
+        }
+        debug!(?strategy, ?fees, "Repriced transaction");
+
+        apply_fees(tx, fees);
+
+        Ok(true)
+    }
+
+    fn exceeds_reprice_cap(&self, fees: &Fees) -> bool {
+        let max_fee = match fees {
+            Fees::Legacy { gas_price } => gas_price,
+            Fees::Eip1559 {
+                max_fee_per_gas, ..
+            } => max_fee_per_gas,
+        };
+
+        self.reprice_max_fee.is_some_and(|cap| *max_fee > cap)
+    }
+
+    /// Turns `tx` into a legacy transaction to replace `current`, a pending
+    /// EIP-1559 transaction with the same calldata. The gas price is the max
+    /// fee of `current` bumped by the fallback's percentage, or the price of
+    /// the strategy, or the node's without one, if that's higher. `false` if
+    /// there's no fallback or `current` is a legacy transaction already.
+    pub async fn legacy_fallback<M>(
+        &self,
+        provider: &M,
+        tx: &mut TypedTransaction,
+        current: &TransactionStatus,
+    ) -> Result<bool, TxError>
+    where
+        M: Middleware,
+        M::Error: 'static,
+    {
+        let (Some(fallback), Some(max_fee_per_gas)) =
+            (self.legacy_fallback, current.max_fee_per_gas)
+        else {
+            return Ok(false);
+        };
+
+        let priced = match &self.strategy {
+            Some(strategy) => match self.fees(strategy, provider, true).await {
+                Ok(Fees::Legacy { gas_price }) => Ok(gas_price),
+                Ok(Fees::Eip1559 {
+                    max_fee_per_gas, ..
+                }) => Ok(max_fee_per_gas),
+                Err(err) => Err(err),
+            },
+            None => provider.get_gas_price().await.map_err(Into::into),
+        }
+        .map_err(|err| TxError::Fill(err.into()))?;
+
+        let gas_price = bumped(max_fee_per_gas, fallback.bump_percent).max(priced);
+        debug!(?gas_price, "Priced legacy fallback");
+
+        apply_fees(tx, Fees::Legacy { gas_price });
+
+        Ok(true)
+    }
+
+    async fn fees<M>(
+        &self,
+        strategy: &GasStrategy,
+        provider: &M,
+        legacy: bool,
+    ) -> anyhow::Result<Fees>
+    where
+        M: Middleware,
+        M::Error: 'static,
+    {
+        let fees = match strategy {
+            GasStrategy::Node if legacy => Fees::Legacy {
+                gas_price: provider.get_gas_price().await?,
+            },
+            GasStrategy::Node => {
+                let base_fee = next_base_fee(provider).await?;
+                let max_priority_fee_per_gas: U256 = provider
+                    .provider()
+                    .request("eth_maxPriorityFeePerGas", ())
+                    .await?;
+
+                Fees::Eip1559 {
+                    max_fee_per_gas: base_fee * 2 + max_priority_fee_per_gas,
+                    max_priority_fee_per_gas,
+                }
+            }
+            GasStrategy::FeeHistory {
+                blocks,
+                reward_percentile,
+            } => {
+                let history = provider
+                    .fee_history(*blocks, BlockNumber::Latest, &[*reward_percentile])
+                    .await?;
+
+                // The last entry is the base fee of the next block
+                let base_fee = *history
+                    .base_fee_per_gas
+                    .last()
+                    .context("Empty fee history")?;
+
+                let mut rewards: Vec<U256> = history
+                    .reward
+                    .iter()
+                    .filter_map(|rewards| rewards.first().copied())
+                    .collect();
+                rewards.sort_unstable();
+                let max_priority_fee_per_gas = rewards
+                    .get(rewards.len() / 2)
+                    .copied()
+                    .context("No rewards in fee history")?;
+
+                Fees::Eip1559 {
+                    max_fee_per_gas: base_fee * 2 + max_priority_fee_per_gas,
+                    max_priority_fee_per_gas,
+                }
+            }
+            GasStrategy::Oracle { url } => {
+                let fees: OracleFees = self
+                    .client
+                    .get(url.expose())
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .context("Failed to query gas oracle")?
+                    .json()
+                    .await
+                    .context("Invalid gas oracle response")?;
+
+                anyhow::ensure!(
+                    fees.max_priority_fee_per_gas <= fees.max_fee_per_gas,
+                    "Gas oracle returned a priority fee above the max fee"
+                );
+
+                eip1559_or_legacy(legacy, fees.max_fee_per_gas, fees.max_priority_fee_per_gas)
+            }
+            GasStrategy::Static {
+                max_fee_per_gas_gwei,
+                max_priority_fee_per_gas_gwei,
+            } => eip1559_or_legacy(
+                legacy,
+                gwei(*max_fee_per_gas_gwei),
+                gwei(*max_priority_fee_per_gas_gwei),
+            ),
+        };
+
+        Ok(fees)
+    }
+}
+
+fn validate(strategy: &GasStrategy, relayer: &RelayerConfig, legacy: bool) -> anyhow::Result<()> {
+    // The other relayers price the transactions they send themselves
+    anyhow::ensure!(
+        matches!(relayer, RelayerConfig::OzDefender(_)),
+        "gas_strategy is not supported by the {} relayer",
+        relayer.kind()
+    );
+
+    match strategy {
+        GasStrategy::Node | GasStrategy::Oracle { .. } => {}
+        GasStrategy::FeeHistory {
+            blocks,
+            reward_percentile,
+        } => {
+            anyhow::ensure!(
+                !legacy,
+                "The fee_history gas strategy needs a chain with EIP-1559"
+            );
+            anyhow::ensure!(
+                (1..=MAX_FEE_HISTORY_BLOCKS).contains(blocks),
+                "gas_strategy.blocks must be between 1 and {MAX_FEE_HISTORY_BLOCKS}"
+            );
+            anyhow::ensure!(
+                (0.0..=100.0).contains(reward_percentile),
+                "gas_strategy.reward_percentile must be between 0 and 100"
+            );
+        }
+        GasStrategy::Static {
+            max_fee_per_gas_gwei,
+            max_priority_fee_per_gas_gwei,
+        } => {
+            anyhow::ensure!(
+                *max_fee_per_gas_gwei > 0,
+                "gas_strategy.max_fee_per_gas_gwei must be positive"
+            );
+            anyhow::ensure!(
+                max_priority_fee_per_gas_gwei <= max_fee_per_gas_gwei,
+                "gas_strategy.max_priority_fee_per_gas_gwei must not exceed the max fee"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn next_base_fee<M>(provider: &M) -> anyhow::Result<U256>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    let history = provider.fee_history(1, BlockNumber::Latest, &[]).await?;
+
+    history
+        .base_fee_per_gas
+        .last()
+        .copied()
+        .context("Empty fee history")
+}
+
+/// Sets the fees of `tx`, turning it into a legacy transaction for legacy
+/// fees
+fn apply_fees(tx: &mut TypedTransaction, fees: Fees) {
+    match fees {
+        Fees::Legacy { gas_price } => {
+            if let TypedTransaction::Eip1559(request) = tx {
+                *tx = TypedTransaction::Legacy(TransactionRequest {
+                    from: request.from,
+                    to: request.to.clone(),
+                    gas: request.gas,
+                    value: request.value,
+                    data: request.data.clone(),
+                    nonce: request.nonce,
+                    chain_id: request.chain_id,
+                    ..TransactionRequest::default()
+                });
+            }
+            tx.set_gas_price(gas_price);
+        }
+        Fees::Eip1559 {
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        } => match tx {
+            TypedTransaction::Eip1559(request) => {
+                request.max_fee_per_gas = Some(max_fee_per_gas);
+                request.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+            _ => {
+                tx.set_gas_price(max_fee_per_gas);
+            }
+        },
+    }
+}
+
+/// Raises `priced` to at least [`REPLACEMENT_BUMP_PERCENT`] above the fees of
+/// `current`. Legacy transactions only have a gas price, which is compared to
+/// the max fee.
+fn replacement_fees(priced: Fees, current: &TransactionStatus) -> Fees {
+    let current_max_fee = current.max_fee_per_gas.or(current.gas_price);
+    let at_least = |fee: U256, current: Option<U256>| {
+        current.map_or(fee, |current| {
+            fee.max(bumped(current, REPLACEMENT_BUMP_PERCENT))
+        })
+    };
+
+    match priced {
+        Fees::Legacy { gas_price } => Fees::Legacy {
+            gas_price: at_least(gas_price, current_max_fee),
+        },
+        Fees::Eip1559 {
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        } => {
+            let max_priority_fee_per_gas =
+                at_least(max_priority_fee_per_gas, current.max_priority_fee_per_gas);
+
+            Fees::Eip1559 {
+                max_fee_per_gas: at_least(max_fee_per_gas, current_max_fee)
+                    .max(max_priority_fee_per_gas),
+                max_priority_fee_per_gas,
+            }
+        }
+    }
+}
+
+/// `fee` raised by `percent`, rounded up
+fn bumped(fee: U256, percent: u64) -> U256 {
+    (fee.saturating_mul(U256::from(100 + percent)) + 99) / 100
+}
+
+fn eip1559_or_legacy(legacy: bool, max_fee_per_gas: U256, max_priority_fee_per_gas: U256) -> Fees {
+    if legacy {
+        Fees::Legacy {
+            gas_price: max_fee_per_gas,
+        }
+    } else {
+        Fees::Eip1559 {
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        }
+    }
+}
+
+fn gwei(gwei: u64) -> U256 {
+    U256::from(gwei) * U256::exp10(9)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{Eip1559TransactionRequest, FeeHistory};
+
+    use super::*;
+    use crate::config::TxSitterConfig;
+    use crate::utils::secret::SecretUrl;
+
+    fn pricer(strategy: &GasStrategy) -> GasPricer {
+        GasPricer::new(
+            Some(strategy.clone()),
+            None,
+            None,
+            &oz_relayer(),
+            false,
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    fn fee_history(base_fee_per_gas: &[u64], rewards: &[u64]) -> FeeHistory {
+        FeeHistory {
+            base_fee_per_gas: base_fee_per_gas.iter().copied().map(U256::from).collect(),
+            gas_used_ratio:   vec![0.5; rewards.len()],
+            oldest_block:     U256::zero(),
+            reward:           rewards.iter().map(|&reward| vec![reward.into()]).collect(),
+        }
+    }
+
+    async fn fees(strategy: &GasStrategy, mock: &MockProvider, legacy: bool) -> Fees {
+        let provider = Provider::new(mock.clone());
+
+        pricer(strategy)
+            .fees(strategy, &provider, legacy)
+            .await
+            .unwrap()
+    }
+
+    fn status(
+        gas_price: Option<u64>,
+        max_fee_per_gas: Option<u64>,
+        max_priority_fee_per_gas: Option<u64>,
+    ) -> TransactionStatus {
+        TransactionStatus {
+            transaction_id:           "0".to_string(),
+            status:                   "submitted".to_string(),
+            pending:                  true,
+            hash:                     None,
+            nonce:                    Some(0),
+            gas_price:                gas_price.map(U256::from),
+            max_fee_per_gas:          max_fee_per_gas.map(U256::from),
+            max_priority_fee_per_gas: max_priority_fee_per_gas.map(U256::from),
+        }
+    }
+
+    fn oz_relayer() -> RelayerConfig {
+        serde_json::from_value(serde_json::json!({
+            "kind": "oz_defender",
+            "oz_api_key": "",
+            "oz_api_secret": "",
+            "oz_address": "0x0000000000000000000000000000000000000000",
+        }))
+        .unwrap()
+    }
+
+    fn oz_relayer_with_fallback(bump_percent: u64) -> RelayerConfig {
+        serde_json::from_value(serde_json::json!({
+            "kind": "oz_defender",
+            "oz_api_key": "",
+            "oz_api_secret": "",
+            "oz_address": "0x0000000000000000000000000000000000000000",
+            "oz_legacy_fallback_after": "5m",
+            "oz_legacy_fallback_bump_percent": bump_percent,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn strategies_are_validated() {
+        let relayer = oz_relayer();
+
+        let fee_history = GasStrategy::FeeHistory {
+            blocks:            10,
+            reward_percentile: 50.0,
+        };
+        assert!(validate(&fee_history, &relayer, false).is_ok());
+        assert!(validate(&fee_history, &relayer, true).is_err());
+
+        let out_of_range = GasStrategy::FeeHistory {
+            blocks:            10,
+            reward_percentile: 101.0,
+        };
+        assert!(validate(&out_of_range, &relayer, false).is_err());
+
+        let inverted = GasStrategy::Static {
+            max_fee_per_gas_gwei:          10,
+            max_priority_fee_per_gas_gwei: 20,
+        };
+        assert!(validate(&inverted, &relayer, false).is_err());
+
+        let tx_sitter = RelayerConfig::TxSitter(TxSitterConfig {
+            tx_sitter_url:       "http://localhost:3000".parse().unwrap(),
+            tx_sitter_address:   Default::default(),
+            tx_sitter_gas_limit: None,
+        });
+        assert!(validate(&GasStrategy::Node, &tx_sitter, false).is_err());
+
+        let reprice_without_strategy = GasPricer::new(
+            None,
+            Some(Duration::from_secs(60)),
+            None,
+            &relayer,
+            false,
+            Duration::from_secs(5),
+        );
+        assert!(reprice_without_strategy.is_err());
+    }
+
+    #[tokio::test]
+    async fn node_fees_come_from_the_node() {
+        let mock = MockProvider::new();
+
+        mock.push(U256::from(7)).unwrap();
+        assert_eq!(fees(&GasStrategy::Node, &mock, true).await, Fees::Legacy {
+            gas_price: U256::from(7),
+        });
+
+        // Responses are popped from the back, the fee history is queried first
+        mock.push(U256::from(2)).unwrap();
+        mock.push(fee_history(&[10, 12], &[1])).unwrap();
+        assert_eq!(
+            fees(&GasStrategy::Node, &mock, false).await,
+            Fees::Eip1559 {
+                max_fee_per_gas:          U256::from(12 * 2 + 2),
+                max_priority_fee_per_gas: U256::from(2),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn fee_history_fees_use_the_median_reward() {
+        let strategy = GasStrategy::FeeHistory {
+            blocks:            3,
+            reward_percentile: 50.0,
+        };
+        let mock = MockProvider::new();
+
+        mock.push(fee_history(&[10, 11, 12, 13], &[5, 1, 3]))
+            .unwrap();
+        assert_eq!(fees(&strategy, &mock, false).await, Fees::Eip1559 {
+            max_fee_per_gas:          U256::from(13 * 2 + 3),
+            max_priority_fee_per_gas: U256::from(3),
+        });
+
+        mock.push(fee_history(&[], &[])).unwrap();
+        let provider = Provider::new(mock);
+        assert!(pricer(&strategy)
+            .fees(&strategy, &provider, false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn oracle_fees_come_from_the_oracle() {
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::get(|| async {
+                axum::Json(serde_json::json!({
+                    "maxFeePerGas": "0x64",
+                    "maxPriorityFeePerGas": "0xa",
+                }))
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        let strategy = GasStrategy::Oracle {
+            url: SecretUrl::new(url.parse().unwrap()),
+        };
+        let mock = MockProvider::new();
+
+        assert_eq!(fees(&strategy, &mock, false).await, Fees::Eip1559 {
+            max_fee_per_gas:          U256::from(100),
+            max_priority_fee_per_gas: U256::from(10),
+        });
+        assert_eq!(fees(&strategy, &mock, true).await, Fees::Legacy {
+            gas_price: U256::from(100),
+        });
+    }
+
+    #[test]
+    fn reprices_stop_at_the_cap() {
+        let capped = GasPricer::new(
+            Some(GasStrategy::Node),
+            Some(Duration::from_secs(60)),
+            Some(gwei(100)),
+            &oz_relayer(),
+            false,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let eip1559 = |max_fee_per_gas| Fees::Eip1559 {
+            max_fee_per_gas,
+            max_priority_fee_per_gas: gwei(2),
+        };
+        assert!(!capped.exceeds_reprice_cap(&eip1559(gwei(100))));
+        assert!(capped.exceeds_reprice_cap(&eip1559(gwei(101))));
+        assert!(capped.exceeds_reprice_cap(&Fees::Legacy {
+            gas_price: gwei(101),
+        }));
+
+        // Bumping a pending transaction at the cap goes past it
+        let current = status(None, Some(gwei(100).as_u64()), Some(gwei(2).as_u64()));
+        assert!(capped.exceeds_reprice_cap(&replacement_fees(eip1559(gwei(50)), &current)));
+
+        assert!(!pricer(&GasStrategy::Node).exceeds_reprice_cap(&eip1559(U256::MAX)));
+    }
+
+    #[tokio::test]
+    async fn static_fees_are_in_gwei() {
+        let strategy = GasStrategy::Static {
+            max_fee_per_gas_gwei:          30,
+            max_priority_fee_per_gas_gwei: 2,
+        };
+        let mock = MockProvider::new();
+
+        assert_eq!(fees(&strategy, &mock, false).await, Fees::Eip1559 {
+            max_fee_per_gas:          gwei(30),
+            max_priority_fee_per_gas: gwei(2),
+        });
+        assert_eq!(fees(&strategy, &mock, true).await, Fees::Legacy {
+            gas_price: gwei(30),
+        });
+    }
+
+    #[tokio::test]
+    async fn legacy_fallbacks_outbid_the_pending_max_fee() {
+        let pricer = GasPricer::new(
+            None,
+            None,
+            None,
+            &oz_relayer_with_fallback(20),
+            false,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(
+            pricer.legacy_fallback_after(),
+            Some(Duration::from_secs(300))
+        );
+
+        let mock = MockProvider::new();
+        let provider = Provider::new(mock.clone());
+        let calldata = vec![1, 2, 3];
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .data(calldata.clone())
+            .into();
+
+        // The node's gas price is below the bumped max fee
+        mock.push(U256::from(50)).unwrap();
+        assert!(pricer
+            .legacy_fallback(&provider, &mut tx, &status(None, Some(100), Some(10)))
+            .await
+            .unwrap());
+        let TypedTransaction::Legacy(request) = &tx else {
+            panic!("Expected a legacy transaction");
+        };
+        assert_eq!(request.gas_price, Some(U256::from(120)));
+        assert_eq!(tx.data().map(|data| data.to_vec()), Some(calldata));
+
+        // And above it
+        mock.push(U256::from(500)).unwrap();
+        assert!(pricer
+            .legacy_fallback(&provider, &mut tx, &status(None, Some(100), Some(10)))
+            .await
+            .unwrap());
+        assert_eq!(tx.gas_price(), Some(U256::from(500)));
+
+        // Legacy transactions don't fall back again
+        assert!(!pricer
+            .legacy_fallback(&provider, &mut tx, &status(Some(120), None, None))
+            .await
+            .unwrap());
+
+        let too_small_bump = GasPricer::new(
+            None,
+            None,
+            None,
+            &oz_relayer_with_fallback(5),
+            false,
+            Duration::from_secs(5),
+        );
+        assert!(too_small_bump.is_err());
+    }
+
+    #[test]
+    fn replacements_outbid_the_pending_transaction() {
+        let current = status(None, Some(100), Some(10));
+
+        // Priced below the pending transaction, so bumped above it
+        assert_eq!(
+            replacement_fees(
+                Fees::Eip1559 {
+                    max_fee_per_gas:          U256::from(50),
+                    max_priority_fee_per_gas: U256::from(5),
+                },
+                &current,
+            ),
+            Fees::Eip1559 {
+                max_fee_per_gas:          U256::from(110),
+                max_priority_fee_per_gas: U256::from(11),
+            }
+        );
+
+        // Priced above it, so kept
+        let priced = Fees::Eip1559 {
+            max_fee_per_gas:          U256::from(200),
+            max_priority_fee_per_gas: U256::from(20),
+        };
+        assert_eq!(replacement_fees(priced, &current), priced);
+
+        // Legacy prices are compared to the max fee, and rounded up
+        assert_eq!(
+            replacement_fees(
+                Fees::Legacy {
+                    gas_price: U256::from(1),
+                },
+                &status(Some(101), None, None),
+            ),
+            Fees::Legacy {
+                gas_price: U256::from(112),
+            }
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use ethers::types::transaction::eip2718::TypedTransaction;
 use ethers::types::H256;
 
+use super::{RelayerNonces, TransactionStatus};
 use crate::ethereum::write::TransactionId;
 use crate::ethereum::TxError;
 
@@ -15,6 +16,50 @@ pub trait Inner: Send + Sync + 'static {
     async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError>;
 
     async fn mine_transaction(&self, tx: TransactionId) -> Result<TransactionResult, TxError>;
+
+    /// A transaction with the calldata of a sent one but no fees, to be sent
+    /// again once it failed in the relayer or to replace it while pending.
+    /// `None` if the relayer can't tell what the transaction was.
+    async fn resubmission(&self, _tx: TransactionId) -> Result<Option<TypedTransaction>, TxError> {
+        Ok(None)
+    }
+
+    /// Replaces a pending transaction with `replacement`, under the same id and
+    /// nonce. Only relayers that accept fees from the sequencer support it.
+    async fn replace_transaction(
+        &self,
+        tx: TransactionId,
+        _replacement: TypedTransaction,
+    ) -> Result<(), TxError> {
+        Err(TxError::Send(From::from(format!(
+            "The relayer can't replace transaction {tx}"
+        ))))
+    }
+
+    /// A sent transaction that hasn't failed and whose calldata satisfies
+    /// `matches`, among the recent transactions of the relayer, pending ones
+    /// included. `None` if there's none or the relayer doesn't list them.
+    async fn find_transaction(
+        &self,
+        _matches: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> Result<Option<TransactionId>, TxError> {
+        Ok(None)
+    }
+
+    /// The status of a sent transaction, as far as the relayer knows it.
+    /// `None` if the relayer doesn't report it.
+    async fn transaction_status(
+        &self,
+        _tx: TransactionId,
+    ) -> Result<Option<TransactionStatus>, TxError> {
+        Ok(None)
+    }
+
+    /// The nonces of the relayers, as far as the relayer knows them. Empty if
+    /// the relayer doesn't report them.
+    async fn relayer_nonces(&self) -> Vec<RelayerNonces> {
+        Vec::new()
+    }
 }
 
 pub struct TransactionResult {
@@ -1,27 +1,139 @@
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use ethers::providers::Middleware;
+use ethers::abi::ParamType;
+use ethers::providers::{Middleware, ProviderError, RpcError};
 use ethers::types::transaction::eip2718::TypedTransaction;
-use ethers::types::{Address, U64};
+use ethers::types::{
+    Address, BlockId, BlockNumber, Eip1559TransactionRequest, TransactionReceipt,
+    TransactionRequest, H256, U256, U64,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_counter_vec_with_registry, register_histogram_vec_with_registry,
+    CounterVec, HistogramVec,
+};
+use serde::Serialize;
 use tracing::{info, warn};
 
-use self::inner::Inner;
+use self::forwarder::Forwarder;
+use self::gas_strategy::GasPricer;
+use self::inner::{Inner, TransactionResult};
 use self::openzeppelin::OzRelay;
+use self::spend_governor::SpendGovernor;
+pub use self::spend_governor::WindowSpend;
 use self::tx_sitter::TxSitter;
+use super::confirmation::Confirmations;
 use super::write::TransactionId;
 use super::{ReadProvider, TxError};
-use crate::config::RelayerConfig;
+use crate::config::{Config, OzFailedPolicy, RelayerConfig};
+use crate::metrics;
 
 mod error;
+mod forwarder;
+mod gas_estimates;
+mod gas_strategy;
 mod inner;
 mod openzeppelin;
+mod spend_governor;
+mod status_listing;
 mod tx_sitter;
 
+/// The gas of a plain transfer, which is what cancellations are
+const TRANSFER_GAS: u64 = 21_000;
+
+/// How often a missing receipt is checked again during the dropped
+/// transaction grace period
+const RECEIPT_RECHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+static CONFIRMATION_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "sequencer_confirmation_latency_seconds",
+        "Time from submitting a transaction to the relayer until it is mined.",
+        &["backend"],
+        exponential_buckets(1.0, 1.5, 20).unwrap(),
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+// Every submitted transaction is a batch, so the gas used isn't labelled by
+// batching
+static GAS_USED: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "sequencer_gas_used",
+        "Gas used by successful transactions.",
+        &["backend"],
+        exponential_buckets(50_000.0, 1.5, 20).unwrap(),
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static WEI_SPENT: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec_with_registry!(
+        "sequencer_wei_spent",
+        "Total wei spent on gas by successful transactions.",
+        &["backend"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// The nonces of a relayer. A `chain_nonce` stuck below `relayer_nonce` while
+/// transactions are pending means the transaction at `chain_nonce` is blocking
+/// the ones after it.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayerNonces {
+    pub relayer:              usize,
+    pub address:              Option<Address>,
+    /// The nonce the relayer assigns to its next transaction
+    pub relayer_nonce:        Option<u64>,
+    pub pending_transactions: Option<u64>,
+    /// The nonce of the next transaction to be mined
+    pub chain_nonce:          Option<u64>,
+    /// The same, counting the transactions in the node's mempool
+    pub chain_pending_nonce:  Option<u64>,
+    /// Why some of the nonces are missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:                Option<String>,
+}
+
+/// A transaction as reported by the relayer, to tell why it's stuck, e.g.
+/// underpriced or behind a lower nonce
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionStatus {
+    pub transaction_id:           String,
+    pub status:                   String,
+    /// Whether the relayer may still replace it
+    #[serde(skip)]
+    pub pending:                  bool,
+    pub hash:                     Option<H256>,
+    pub nonce:                    Option<u64>,
+    pub gas_price:                Option<U256>,
+    pub max_fee_per_gas:          Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+}
+
 pub struct WriteProvider {
-    read_provider: ReadProvider,
-    inner:         Arc<dyn Inner>,
-    address:       Address,
+    read_provider:     ReadProvider,
+    inner:             Arc<dyn Inner>,
+    backend:           &'static str,
+    address:           Address,
+    max_calldata_size: usize,
+    confirmations:     Confirmations,
+    spend_governor:    SpendGovernor,
+    gas_pricer:        GasPricer,
+    decode_reverts:    bool,
+    dropped_tx_grace:  Option<Duration>,
+    failed_policy:     OzFailedPolicy,
+    // Submission times of transactions sent by this instance that haven't been
+    // mined yet
+    submitted_at:      Mutex<HashMap<String, Instant>>,
 }
 
 impl fmt::Debug for WriteProvider {
@@ -29,47 +141,217 @@ impl fmt::Debug for WriteProvider {
         f.debug_struct("WriteProvider")
             .field("read_provider", &self.read_provider)
             .field("inner", &"<REDACTED>")
+            .field("backend", &self.backend)
             .field("address", &self.address)
+            .field("max_calldata_size", &self.max_calldata_size)
+            .field("confirmations", &self.confirmations)
+            .field("decode_reverts", &self.decode_reverts)
+            .field("dropped_tx_grace", &self.dropped_tx_grace)
+            .field("failed_policy", &self.failed_policy)
             .finish()
     }
 }
 
 impl WriteProvider {
-    pub async fn new(read_provider: ReadProvider, config: &RelayerConfig) -> anyhow::Result<Self> {
-        let address = config.address();
+    pub async fn new(read_provider: ReadProvider, config: &Config) -> anyhow::Result<Self> {
+        let address = config.relayer.address();
+        let confirmations = Confirmations::new(&config.app);
 
-        let inner: Arc<dyn Inner> = match config {
+        let backend = config.relayer.kind();
+        let inner: Arc<dyn Inner> = match &config.relayer {
             RelayerConfig::OzDefender(oz_config) => {
                 tracing::info!("Initializing OZ Relayer");
-                Arc::new(OzRelay::new(oz_config).await?)
+                Arc::new(OzRelay::new(oz_config, confirmations.uses_relayer_status()).await?)
             }
             RelayerConfig::TxSitter(tx_sitter_config) => {
                 tracing::info!("Initializing TxSitter");
-                Arc::new(TxSitter::new(tx_sitter_config))
+                Arc::new(TxSitter::new(
+                    tx_sitter_config,
+                    confirmations.uses_relayer_status(),
+                ))
+            }
+            RelayerConfig::Forwarder(forwarder_config) => {
+                tracing::info!("Initializing Forwarder");
+                Arc::new(Forwarder::new(forwarder_config, read_provider.clone())?)
             }
         };
 
+        let failed_policy = match &config.relayer {
+            RelayerConfig::OzDefender(oz_config) => oz_config.oz_failed_policy,
+            _ => OzFailedPolicy::Error,
+        };
+
+        let gas_pricer = GasPricer::new(
+            config.app.gas_strategy.clone(),
+            config.app.gas_reprice_after,
+            config
+                .app
+                .gas_reprice_max_fee_gwei
+                .map(|gwei| U256::from(gwei) * U256::exp10(9)),
+            &config.relayer,
+            read_provider.legacy,
+            config.providers.request_timeout,
+        )?;
+
         Ok(Self {
             read_provider,
             inner,
+            backend,
             address,
+            max_calldata_size: config.app.max_calldata_size,
+            confirmations,
+            spend_governor: SpendGovernor::new(
+                config
+                    .app
+                    .spend_limit_gwei
+                    .map(|gwei| U256::from(gwei) * U256::exp10(9)),
+                config.app.spend_limit_window,
+            ),
+            gas_pricer,
+            decode_reverts: config.app.decode_revert_reasons,
+            dropped_tx_grace: config.app.dropped_tx_grace,
+            failed_policy,
+            submitted_at: Mutex::new(HashMap::new()),
         })
     }
 
     pub async fn send_transaction(
         &self,
-        tx: TypedTransaction,
+        mut tx: TypedTransaction,
         only_once: bool,
     ) -> Result<TransactionId, TxError> {
-        self.inner.send_transaction(tx, only_once).await
+        check_calldata_size(&tx, self.max_calldata_size)?;
+        self.spend_governor.check()?;
+        self.gas_pricer.apply(&self.read_provider, &mut tx).await?;
+        let reservation = self.spend_governor.reserve(max_cost(&tx))?;
+
+        let submitted_at = Instant::now();
+        let tx_id = match self.inner.send_transaction(tx, only_once).await {
+            Ok(tx_id) => tx_id,
+            Err(error) => {
+                self.spend_governor.cancel(reservation);
+                return Err(error);
+            }
+        };
+        self.spend_governor.assign(reservation, &tx_id.0);
+
+        self.submitted_at
+            .lock()
+            .unwrap()
+            .entry(tx_id.0.clone())
+            .or_insert(submitted_at);
+
+        Ok(tx_id)
+    }
+
+    /// How long to wait before resubmitting a failed transaction for the
+    /// `resubmissions + 1`th time, `None` if it isn't resubmitted.
+    pub fn resubmission_backoff(&self, resubmissions: u32) -> Option<Duration> {
+        let OzFailedPolicy::Resubmit {
+            max_resubmissions,
+            backoff,
+        } = self.failed_policy
+        else {
+            return None;
+        };
+
+        (resubmissions < max_resubmissions)
+            .then(|| backoff.saturating_mul(1 << resubmissions.min(16)))
+    }
+
+    /// Sends a failed transaction again. It goes through the same checks and
+    /// pricing as a new one.
+    pub async fn resubmit_transaction(&self, tx: TransactionId) -> Result<TransactionId, TxError> {
+        let Some(resubmission) = self.inner.resubmission(tx.clone()).await? else {
+            return Err(TxError::Failed(None));
+        };
+
+        self.submitted_at.lock().unwrap().remove(tx.as_ref());
+        self.spend_governor.release(tx.as_ref());
+
+        self.send_transaction(resubmission, false).await
+    }
+
+    /// The gas spend within the current spend limit window, `None` if there's
+    /// no limit.
+    pub fn check_spend_limit(&self) -> Result<Option<WindowSpend>, TxError> {
+        self.spend_governor.check()
     }
 
     pub async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
         self.inner.fetch_pending_transactions().await
     }
 
+    /// A sent transaction that hasn't failed and whose calldata satisfies
+    /// `matches`, as far as the relayer lists its recent transactions
+    pub async fn find_transaction(
+        &self,
+        matches: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> Result<Option<TransactionId>, TxError> {
+        self.inner.find_transaction(matches).await
+    }
+
+    /// The status of a sent transaction, as reported by the relayer. `None` if
+    /// the relayer doesn't report it.
+    pub async fn transaction_status(
+        &self,
+        tx: TransactionId,
+    ) -> Result<Option<TransactionStatus>, TxError> {
+        self.inner.transaction_status(tx).await
+    }
+
+    /// The nonces of every relayer, as reported by the relayer and the chain.
+    /// Empty if the relayer doesn't report them. Failing to query a nonce
+    /// leaves it out and records the error, rather than failing.
+    pub async fn relayer_nonces(&self) -> Vec<RelayerNonces> {
+        let mut relayers = self.inner.relayer_nonces().await;
+
+        for relayer in &mut relayers {
+            let Some(address) = relayer.address else {
+                continue;
+            };
+
+            let chain_nonces = futures::try_join!(
+                self.read_provider
+                    .get_transaction_count(address, Some(BlockNumber::Latest.into())),
+                self.read_provider
+                    .get_transaction_count(address, Some(BlockNumber::Pending.into())),
+            );
+
+            match chain_nonces {
+                Ok((nonce, pending_nonce)) => {
+                    relayer.chain_nonce = Some(nonce.as_u64());
+                    relayer.chain_pending_nonce = Some(pending_nonce.as_u64());
+                }
+                Err(error) => {
+                    warn!(
+                        relayer = relayer.relayer,
+                        ?error,
+                        "Failed to query the chain nonce"
+                    );
+                    relayer
+                        .error
+                        .get_or_insert_with(|| format!("Failed to query the chain nonce: {error}"));
+                }
+            }
+        }
+
+        relayers
+    }
+
     pub async fn mine_transaction(&self, tx: TransactionId) -> Result<bool, TxError> {
-        let oz_transaction_result = self.inner.mine_transaction(tx.clone()).await;
+        let _submitted = Submitted {
+            submitted_at: &self.submitted_at,
+            id:           tx.as_ref(),
+        };
+        // Released however waiting ends, unless the spend is recorded first
+        let _reserved = self.spend_governor.hold(tx.as_ref());
+
+        let oz_transaction_result = self.mine_repricing(&tx).await;
+
+        if oz_transaction_result.is_ok() {
+            self.observe_confirmation(&tx);
+        }
 
         if let Err(TxError::Failed(_)) = oz_transaction_result {
             warn!(?tx, "Transaction failed in OZ Relayer");
@@ -88,29 +370,538 @@ impl WriteProvider {
 
         info!(?tx_hash, "Waiting for transaction to be mined");
 
-        let tx = self
-            .read_provider
-            .get_transaction_receipt(tx_hash)
-            .await
-            .map_err(|err| TxError::Fetch(err.into()))?;
+        let receipt =
+            receipt_within_grace(&self.read_provider, tx_hash, self.dropped_tx_grace).await?;
 
-        let tx = tx.ok_or_else(|| {
-            TxError::Fetch(From::from(format!(
-                "Failed to get transaction receipt for transaction id {}",
-                oz_transaction.transaction_id
-            )))
-        })?;
+        // Reverted transactions are paid for too
+        if let Some(wei_spent) = wei_spent(&receipt) {
+            self.spend_governor.record(tx.as_ref(), wei_spent);
+        }
+
+        check_reverted(&self.read_provider, &receipt, self.decode_reverts).await?;
+
+        self.observe_gas(&receipt);
+
+        self.wait_for_confirmation(&receipt).await?;
+
+        Ok(true)
+    }
+
+    /// Waits for the relayer to mine the transaction. With
+    /// `gas_reprice_after`, it's repriced every time it's been pending that
+    /// long, since the relayer doesn't reprice the fees it's given. With the
+    /// legacy fallback, it's replaced once with a legacy transaction.
+    async fn mine_repricing(&self, tx: &TransactionId) -> Result<TransactionResult, TxError> {
+        let mut mine = self.inner.mine_transaction(tx.clone());
+
+        let reprice_after = self.gas_pricer.reprice_after();
+        let fallback_after = self.gas_pricer.legacy_fallback_after();
+        if reprice_after.is_none() && fallback_after.is_none() {
+            return mine.await;
+        }
+
+        let fallback = tokio::time::sleep(fallback_after.unwrap_or(Duration::MAX));
+        let reprice = tokio::time::sleep(reprice_after.unwrap_or(Duration::MAX));
+        tokio::pin!(fallback, reprice);
+        let mut fell_back = fallback_after.is_none();
+        let mut repricing = reprice_after.is_some();
+
+        loop {
+            tokio::select! {
+                result = &mut mine => return result,
+                () = &mut fallback, if !fell_back => {
+                    fell_back = true;
+                    if let Err(error) = self.fall_back_to_legacy(tx).await {
+                        warn!(?tx, ?error, "Failed to fall back to a legacy transaction");
+                    }
+                }
+                () = &mut reprice, if repricing => {
+                    match self.reprice(tx).await {
+                        Ok(keep_repricing) => repricing = keep_repricing,
+                        Err(error) => warn!(?tx, ?error, "Failed to reprice pending transaction"),
+                    }
+                    if let Some(reprice_after) = reprice_after {
+                        reprice.as_mut().reset(tokio::time::Instant::now() + reprice_after);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces a pending EIP-1559 transaction with a legacy one
+    async fn fall_back_to_legacy(&self, tx: &TransactionId) -> Result<(), TxError> {
+        let Some(current) = self.inner.transaction_status(tx.clone()).await? else {
+            return Ok(());
+        };
+
+        if !current.pending {
+            return Ok(());
+        }
+
+        let Some(mut replacement) = self.inner.resubmission(tx.clone()).await? else {
+            return Ok(());
+        };
+
+        if !self
+            .gas_pricer
+            .legacy_fallback(&self.read_provider, &mut replacement, &current)
+            .await?
+        {
+            return Ok(());
+        }
+
+        warn!(
+            ?tx,
+            status = %current.status,
+            max_fee_per_gas = ?current.max_fee_per_gas,
+            gas_price = ?replacement.gas_price(),
+            "EIP-1559 transaction still pending, falling back to a legacy transaction"
+        );
+
+        self.inner
+            .replace_transaction(tx.clone(), replacement.clone())
+            .await?;
+
+        if let Some(max_cost) = max_cost(&replacement) {
+            self.spend_governor.raise(tx.as_ref(), max_cost);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces a pending transaction with one priced anew, if its cost fits
+    /// within the spend limit and its fees within the reprice cap. Returns
+    /// whether to keep repricing: `false` once the cap or the limit is
+    /// reached.
+    async fn reprice(&self, tx: &TransactionId) -> Result<bool, TxError> {
+        let Some(current) = self.inner.transaction_status(tx.clone()).await? else {
+            return Ok(true);
+        };
+
+        if !current.pending {
+            return Ok(true);
+        }
+
+        let Some(mut replacement) = self.inner.resubmission(tx.clone()).await? else {
+            return Ok(true);
+        };
+
+        if !self
+            .gas_pricer
+            .reprice(&self.read_provider, &mut replacement, &current)
+            .await?
+        {
+            return Ok(false);
+        }
+
+        if let Some(max_cost) = max_cost(&replacement) {
+            if let Err(error) = self.spend_governor.try_raise(tx.as_ref(), max_cost) {
+                warn!(?tx, %error, "Repricing would exceed the spend limit, not repricing");
+                return Ok(false);
+            }
+        }
+
+        self.inner
+            .replace_transaction(tx.clone(), replacement.clone())
+            .await?;
+
+        info!(
+            ?tx,
+            status = %current.status,
+            max_fee_per_gas = ?current.max_fee_per_gas.or(current.gas_price),
+            new_max_fee_per_gas = ?replacement.gas_price(),
+            "Repriced pending transaction"
+        );
+
+        Ok(true)
+    }
+
+    /// Replaces a pending transaction with an empty transfer to the relayer
+    /// under the same nonce, so that it can't be mined anymore. Fails if it
+    /// isn't pending or the relayer can't replace transactions.
+    pub async fn cancel_transaction(&self, tx: TransactionId) -> Result<(), TxError> {
+        let current = self.inner.transaction_status(tx.clone()).await?;
+        if current.as_ref().is_some_and(|current| !current.pending) {
+            return Err(TxError::Send(From::from(format!(
+                "Transaction {tx} isn't pending anymore"
+            ))));
+        }
 
-        if tx.status == Some(U64::from(1u64)) {
-            Ok(true)
+        let mut cancellation: TypedTransaction = if self.read_provider.legacy {
+            TransactionRequest::new().into()
         } else {
-            warn!(?tx, "Transaction failed");
+            Eip1559TransactionRequest::new().into()
+        };
+        cancellation.set_to(self.address);
+        cancellation.set_value(U256::zero());
+        cancellation.set_gas(TRANSFER_GAS);
 
-            Ok(false)
+        // Without the relayer's fees to outbid, the relayer prices it
+        match &current {
+            Some(current) => {
+                self.gas_pricer
+                    .reprice(&self.read_provider, &mut cancellation, current)
+                    .await?;
+            }
+            None => {
+                self.gas_pricer
+                    .apply(&self.read_provider, &mut cancellation)
+                    .await?;
+            }
         }
+
+        self.inner
+            .replace_transaction(tx.clone(), cancellation)
+            .await?;
+
+        warn!(?tx, "Cancelled pending transaction");
+
+        Ok(())
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn observe_gas(&self, receipt: &TransactionReceipt) {
+        let Some(gas_used) = receipt.gas_used else {
+            return;
+        };
+
+        GAS_USED
+            .with_label_values(&[self.backend])
+            .observe(u128::try_from(gas_used).unwrap_or(u128::MAX) as f64);
+
+        if let Some(wei_spent) = wei_spent(receipt) {
+            WEI_SPENT
+                .with_label_values(&[self.backend])
+                .inc_by(u128::try_from(wei_spent).unwrap_or(u128::MAX) as f64);
+        }
+    }
+
+    /// Waits for the confirmation strategy to consider the mined transaction
+    /// confirmed. Fails if the transaction has been reorged out meanwhile.
+    async fn wait_for_confirmation(&self, receipt: &TransactionReceipt) -> Result<(), TxError> {
+        if !self.confirmations.waits_after_mining() {
+            return Ok(());
+        }
+
+        let Some(block_number) = receipt.block_number else {
+            return Err(TxError::Fetch(From::from(format!(
+                "Missing block number on the receipt of {:?}",
+                receipt.transaction_hash
+            ))));
+        };
+
+        self.confirmations
+            .wait_for_block(&self.read_provider, block_number.as_u64())
+            .await
+            .map_err(|err| TxError::Fetch(err.into()))?;
+
+        let confirmed_receipt = receipt_within_grace(
+            &self.read_provider,
+            receipt.transaction_hash,
+            self.dropped_tx_grace,
+        )
+        .await?;
+
+        if confirmed_receipt.block_hash != receipt.block_hash {
+            return Err(TxError::Dropped(receipt.transaction_hash));
+        }
+
+        Ok(())
     }
 
     pub fn address(&self) -> Address {
         self.address
     }
+
+    /// Transactions submitted before a restart don't have a submission time
+    /// and aren't observed.
+    fn observe_confirmation(&self, tx: &TransactionId) {
+        let Some(submitted_at) = self.submitted_at.lock().unwrap().remove(tx.as_ref()) else {
+            return;
+        };
+
+        CONFIRMATION_LATENCY
+            .with_label_values(&[self.backend])
+            .observe(submitted_at.elapsed().as_secs_f64());
+    }
+}
+
+/// Forgets the submission time of a transaction once waiting for it ends,
+/// however it ends, so that transactions that fail, revert or time out don't
+/// pile up. A transaction waited for again after that isn't observed.
+struct Submitted<'a> {
+    submitted_at: &'a Mutex<HashMap<String, Instant>>,
+    id:           &'a str,
+}
+
+impl Drop for Submitted<'_> {
+    fn drop(&mut self) {
+        self.submitted_at.lock().unwrap().remove(self.id);
+    }
+}
+
+/// Fetches the receipt of a transaction. If there's none, it's checked again
+/// until `grace` is over, since lagging providers may not know of a
+/// transaction that was just mined. Fails with `TxError::Dropped` if there's
+/// still none then, so that the transaction can be resubmitted or failed.
+async fn receipt_within_grace<M>(
+    provider: &M,
+    hash: H256,
+    grace: Option<Duration>,
+) -> Result<TransactionReceipt, TxError>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    let fetch_receipt = || async {
+        provider
+            .get_transaction_receipt(hash)
+            .await
+            .map_err(|err| TxError::Fetch(err.into()))
+    };
+
+    if let Some(receipt) = fetch_receipt().await? {
+        return Ok(receipt);
+    }
+
+    let Some(grace) = grace else {
+        warn!(?hash, "No receipt for transaction, considering it dropped");
+        return Err(TxError::Dropped(hash));
+    };
+
+    info!(?hash, ?grace, "No receipt for transaction, checking again");
+    let deadline = Instant::now() + grace;
+    let mut rechecks = 0;
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        tokio::time::sleep(RECEIPT_RECHECK_INTERVAL.min(remaining)).await;
+        rechecks += 1;
+
+        if let Some(receipt) = fetch_receipt().await? {
+            info!(?hash, rechecks, "Found the receipt on a re-check");
+            return Ok(receipt);
+        }
+
+        info!(
+            ?hash,
+            rechecks,
+            ?remaining,
+            "Still no receipt on a re-check"
+        );
+    }
+
+    warn!(
+        ?hash,
+        ?grace,
+        rechecks,
+        "No receipt for transaction within the grace period, considering it dropped"
+    );
+
+    Err(TxError::Dropped(hash))
+}
+
+/// Fails with `TxError::Reverted` if the mined transaction reverted, with the
+/// reason if `decode_reverts` is set
+async fn check_reverted<M>(
+    provider: &M,
+    receipt: &TransactionReceipt,
+    decode_reverts: bool,
+) -> Result<(), TxError>
+where
+    M: Middleware<Error = ProviderError>,
+{
+    if receipt.status == Some(U64::from(1u64)) {
+        return Ok(());
+    }
+
+    let reason = if decode_reverts {
+        revert_reason(provider, receipt).await
+    } else {
+        None
+    };
+    warn!(?receipt, ?reason, "Transaction reverted");
+
+    Err(TxError::Reverted {
+        hash: receipt.transaction_hash,
+        reason,
+    })
+}
+
+/// Replays the reverted transaction at its block to get the reason, which
+/// nodes only report for calls
+async fn revert_reason<M>(provider: &M, receipt: &TransactionReceipt) -> Option<String>
+where
+    M: Middleware<Error = ProviderError>,
+{
+    let tx = provider
+        .get_transaction(receipt.transaction_hash)
+        .await
+        .map_err(|error| warn!(?error, "Failed to fetch the reverted transaction"))
+        .ok()??;
+
+    let call: TypedTransaction = (&tx).into();
+    // Replayed on the state the transaction ran against, as of the end of the
+    // previous block, since the state of its own block already has its effects
+    let block = receipt
+        .block_number
+        .map(|block_number| BlockId::from(block_number.saturating_sub(1.into())));
+
+    let error = provider.call(&call, block).await.err()?;
+    let data = error.as_error_response()?.as_revert_data()?;
+
+    Some(decode_revert_reason(&data))
+}
+
+/// The message of `Error(string)` reverts, the hex encoded data of others, e.g.
+/// custom errors
+fn decode_revert_reason(data: &[u8]) -> String {
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+    data.strip_prefix(&ERROR_SELECTOR)
+        .and_then(|encoded| ethers::abi::decode(&[ParamType::String], encoded).ok())
+        .and_then(|mut tokens| tokens.pop()?.into_string())
+        .unwrap_or_else(|| format!("0x{}", hex::encode(data)))
+}
+
+/// The most a transaction may spend, `None` if its gas limit or fee isn't set
+/// yet
+fn max_cost(tx: &TypedTransaction) -> Option<U256> {
+    Some(tx.gas()?.saturating_mul(tx.gas_price()?))
+}
+
+fn wei_spent(receipt: &TransactionReceipt) -> Option<U256> {
+    Some(
+        receipt
+            .gas_used?
+            .saturating_mul(receipt.effective_gas_price?),
+    )
+}
+
+fn check_calldata_size(tx: &TypedTransaction, max: usize) -> Result<(), TxError> {
+    let size = tx.data().map_or(0, |data| data.len());
+
+    if size > max {
+        return Err(TxError::CalldataTooLarge { size, max });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{JsonRpcError, MockResponse, Provider};
+    use ethers::types::{Bytes, Transaction, TransactionRequest};
+
+    use super::*;
+
+    fn receipt(status: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: H256::repeat_byte(1),
+            block_number: Some(U64::from(10)),
+            status: Some(U64::from(status)),
+            ..TransactionReceipt::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn receipts_are_rechecked_within_the_grace_period() {
+        let (provider, mock) = Provider::mocked();
+        let hash = H256::repeat_byte(1);
+
+        // Found on the first re-check. Responses are popped from the back.
+        mock.push(receipt(1)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        let found = receipt_within_grace(&provider, hash, Some(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        assert_eq!(found.transaction_hash, hash);
+
+        // Still missing once the grace period is over
+        for _ in 0..10 {
+            mock.push(Option::<TransactionReceipt>::None).unwrap();
+        }
+        let result = receipt_within_grace(&provider, hash, Some(Duration::from_millis(50))).await;
+        assert!(matches!(result, Err(TxError::Dropped(dropped)) if dropped == hash));
+    }
+
+    #[tokio::test]
+    async fn missing_receipts_are_dropped_without_a_grace_period() {
+        let (provider, mock) = Provider::mocked();
+        let hash = H256::repeat_byte(1);
+
+        mock.push(receipt(1)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+
+        let result = receipt_within_grace(&provider, hash, None).await;
+        assert!(matches!(result, Err(TxError::Dropped(dropped)) if dropped == hash));
+    }
+
+    #[tokio::test]
+    async fn reverted_receipts_fail() {
+        let (provider, mock) = Provider::mocked();
+
+        assert!(check_reverted(&provider, &receipt(1), true).await.is_ok());
+
+        // Without decoding, the node isn't asked for the reason
+        let result = check_reverted(&provider, &receipt(0), false).await;
+        assert!(matches!(
+            result,
+            Err(TxError::Reverted { hash, reason: None }) if hash == H256::repeat_byte(1)
+        ));
+
+        let mut revert_data = vec![0x08, 0xc3, 0x79, 0xa0];
+        revert_data.extend(ethers::abi::encode(&[ethers::abi::Token::String(
+            "ProofValidationFailure".to_string(),
+        )]));
+
+        // Responses are popped from the back, the transaction is fetched
+        // before it's replayed
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code:    3,
+            message: "execution reverted: ProofValidationFailure".to_string(),
+            data:    Some(serde_json::Value::String(format!(
+                "0x{}",
+                hex::encode(&revert_data)
+            ))),
+        }));
+        mock.push(Transaction {
+            hash: H256::repeat_byte(1),
+            ..Transaction::default()
+        })
+        .unwrap();
+
+        let result = check_reverted(&provider, &receipt(0), true).await;
+        assert!(matches!(
+            result,
+            Err(TxError::Reverted { reason: Some(reason), .. }) if reason == "ProofValidationFailure"
+        ));
+    }
+
+    #[test]
+    fn oversized_calldata_is_rejected() {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .data(Bytes::from(vec![0; 33]))
+            .into();
+
+        assert!(check_calldata_size(&tx, 33).is_ok());
+        assert!(matches!(
+            check_calldata_size(&tx, 32),
+            Err(TxError::CalldataTooLarge { size: 33, max: 32 })
+        ));
+    }
+
+    #[test]
+    fn revert_reasons_are_decoded() {
+        let mut error_string = vec![0x08, 0xc3, 0x79, 0xa0];
+        error_string.extend(ethers::abi::encode(&[ethers::abi::Token::String(
+            "ProofValidationFailure".to_string(),
+        )]));
+        let custom_error = [0xde, 0xad, 0xbe, 0xef];
+
+        assert_eq!(
+            decode_revert_reason(&error_string),
+            "ProofValidationFailure"
+        );
+        assert_eq!(decode_revert_reason(&custom_error), "0xdeadbeef");
+    }
 }
@@ -1,128 +1,592 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
 
 use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Eip1559TransactionRequest, TransactionRequest, U256};
 use once_cell::sync::Lazy;
 use oz_api::data::transactions::{RelayerTransactionBase, SendBaseTransactionRequest, Status};
 use oz_api::OzApi;
-use prometheus::{register_int_counter_vec, IntCounterVec};
-use tokio::time::timeout;
-use tracing::{error, info, info_span, Instrument};
+use prometheus::{
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, IntCounterVec,
+    IntGaugeVec,
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, info_span, trace, warn, Instrument};
 
 use super::error::Error;
 use super::inner::{Inner, TransactionResult};
-use crate::config::OzDefenderConfig;
+use super::status_listing::StatusListing;
+use super::{RelayerNonces, TransactionStatus};
+use crate::config::{OzDefenderConfig, OzRelayerSelection};
 use crate::ethereum::write::TransactionId;
 use crate::ethereum::TxError;
+use crate::metrics;
+use crate::utils::secret::SecretString;
+
+/// How often the status of an in-flight transaction is polled
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many of a relayer's most recent transactions are listed when polling
+/// statuses in batch
+const LISTED_TRANSACTIONS: usize = 50;
+
+/// The delay before the first retry of listing recent transactions, doubled
+/// on every further retry
+const LIST_TRANSACTIONS_BACKOFF: Duration = Duration::from_secs(1);
 
 static TX_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
-    register_int_counter_vec!("eth_tx_count", "The transaction count by bytes4.", &[
-        "bytes4"
-    ])
+    register_int_counter_vec_with_registry!(
+        "eth_tx_count",
+        "The transaction count by bytes4, `transfer` for plain value transfers.",
+        &["bytes4"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static RELAYER_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "oz_relayer_in_flight",
+        "Transactions submitted to a relayer that haven't been mined yet.",
+        &["relayer"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static RELAYER_SUBMISSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "oz_relayer_submissions",
+        "Transactions submitted to a relayer.",
+        &["relayer"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static STATUS_POLLS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "oz_status_polls",
+        "Transaction status requests sent to a relayer.",
+        &["relayer"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static RELAYER_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "oz_relayer_failures",
+        "Failed submissions and failed transactions of a relayer.",
+        &["relayer"],
+        metrics::registry()
+    )
     .unwrap()
 });
 
+/// A single Defender relayer, with its own API client and auth headers.
 #[derive(Debug)]
-pub struct OzRelay {
-    oz_api:               OzApi,
-    transaction_validity: chrono::Duration,
-    send_timeout:         Duration,
-    mine_timeout:         Duration,
-    gas_limit:            Option<u64>,
+struct Relayer {
+    oz_api:         OzApi,
+    label:          String,
+    in_flight:      AtomicUsize,
+    status_listing: StatusListing,
 }
 
-impl OzRelay {
-    pub async fn new(options: &OzDefenderConfig) -> anyhow::Result<Self> {
-        let oz_api = if options.oz_api_key.is_empty() && options.oz_api_secret.is_empty() {
+impl Relayer {
+    async fn new(
+        api_url: &str,
+        api_key: &str,
+        api_secret: &str,
+        auth_timeout: Duration,
+        clock_skew: Duration,
+        index: usize,
+    ) -> anyhow::Result<Self> {
+        let oz_api = if api_key.is_empty() && api_secret.is_empty() {
             tracing::warn!(
+                relayer = index,
                 "OpenZeppelin Defender API Key and Secret are empty. Connection will operate \
                  without authentication headers. Use only in development."
             );
 
-            OzApi::without_auth(&options.oz_api_url)?
+            OzApi::without_auth(api_url)?
         } else {
-            OzApi::new(
-                &options.oz_api_url,
-                &options.oz_api_key,
-                &options.oz_api_secret,
-            )
-            .await?
+            OzApi::new(api_url, api_key, api_secret, auth_timeout)
+                .await?
+                .with_clock_skew(clock_skew)
         };
 
         Ok(Self {
             oz_api,
+            label: index.to_string(),
+            in_flight: AtomicUsize::new(0),
+            status_listing: StatusListing::new(POLL_INTERVAL),
+        })
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn track_submission(&self) {
+        RELAYER_SUBMISSIONS.with_label_values(&[&self.label]).inc();
+    }
+
+    fn track_in_flight(&self) {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+
+        RELAYER_IN_FLIGHT
+            .with_label_values(&[&self.label])
+            .set(in_flight as i64);
+    }
+
+    fn track_settled(&self) {
+        let in_flight = self
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(1))
+            })
+            .map_or(0, |n| n.saturating_sub(1));
+
+        RELAYER_IN_FLIGHT
+            .with_label_values(&[&self.label])
+            .set(in_flight as i64);
+    }
+
+    fn track_failure(&self) {
+        RELAYER_FAILURES.with_label_values(&[&self.label]).inc();
+    }
+}
+
+#[derive(Debug)]
+pub struct OzRelay {
+    relayers:                  Vec<Relayer>,
+    selection:                 OzRelayerSelection,
+    next_relayer:              AtomicUsize,
+    // Held from selecting a relayer until the transaction is in flight, so
+    // that concurrent submissions pick the same relayer
+    selection_lock:            tokio::sync::Mutex<()>,
+    // The relayer each in-flight transaction id was submitted through
+    in_flight:                 Mutex<HashMap<String, usize>>,
+    transaction_validity:      chrono::Duration,
+    send_timeout:              Duration,
+    mine_timeout:              Duration,
+    gas_limit:                 Option<u64>,
+    list_retries:              u32,
+    // Shared by all relayers, since they count towards the same quota
+    poll_permits:              Option<Semaphore>,
+    batch_status_polling:      bool,
+    // `None` if every selector is allowed
+    allowed_selectors:         Option<HashSet<String>>,
+    follow_known_transactions: bool,
+    log_payloads:              bool,
+    // Wait for the confirmed status instead of just mined
+    wait_confirmed:            bool,
+    // Only kept to make sure they never end up in logged payloads
+    credentials:               Vec<SecretString>,
+}
+
+impl OzRelay {
+    pub async fn new(options: &OzDefenderConfig, wait_confirmed: bool) -> anyhow::Result<Self> {
+        let mut relayers = vec![
+            Relayer::new(
+                &options.oz_api_url,
+                options.oz_api_key.expose(),
+                options.oz_api_secret.expose(),
+                options.oz_send_timeout,
+                options.oz_clock_skew,
+                0,
+            )
+            .await?,
+        ];
+
+        let mut credentials = vec![options.oz_api_key.clone(), options.oz_api_secret.clone()];
+
+        for (index, relayer) in options.oz_additional_relayers.0.iter().enumerate() {
+            relayers.push(
+                Relayer::new(
+                    &options.oz_api_url,
+                    relayer.api_key.expose(),
+                    relayer.api_secret.expose(),
+                    options.oz_send_timeout,
+                    options.oz_clock_skew,
+                    index + 1,
+                )
+                .await?,
+            );
+
+            credentials.push(relayer.api_key.clone());
+            credentials.push(relayer.api_secret.clone());
+        }
+
+        info!(
+            relayers = relayers.len(),
+            selection = ?options.oz_relayer_selection,
+            "Initialized OZ Relayers"
+        );
+
+        Ok(Self {
+            relayers,
+            selection: options.oz_relayer_selection,
+            next_relayer: AtomicUsize::new(0),
+            selection_lock: tokio::sync::Mutex::new(()),
+            in_flight: Mutex::new(HashMap::new()),
             transaction_validity: chrono::Duration::from_std(options.oz_transaction_validity)?,
             send_timeout: options.oz_send_timeout,
             mine_timeout: options.oz_mine_timeout,
             gas_limit: options.oz_gas_limit,
+            list_retries: options.oz_list_transactions_retries,
+            poll_permits: options
+                .oz_max_concurrent_polls
+                .map(|max_concurrent| Semaphore::new(max_concurrent.get())),
+            batch_status_polling: options.oz_batch_status_polling,
+            allowed_selectors: parse_selectors(&options.oz_allowed_selectors.0)?,
+            follow_known_transactions: options.oz_follow_known_transactions,
+            log_payloads: options.oz_log_payloads,
+            wait_confirmed,
+            credentials,
         })
     }
 
-    async fn query(&self, tx_id: &str) -> Result<RelayerTransactionBase, Error> {
-        let tx = self.oz_api.query_transaction(tx_id).await?;
+    /// Picks the relayer of the next transaction. Batches are chained by
+    /// their roots and every relayer has its own nonces, so batches sent
+    /// through different relayers could be mined out of order and revert.
+    /// While any transaction is in flight, the next one goes through the same
+    /// relayer, and the relayer only changes once they're all settled.
+    async fn select_relayer(&self) -> usize {
+        if let Some(index) = self.in_flight.lock().unwrap().values().next() {
+            return *index;
+        }
+
+        let next = self.next_relayer.fetch_add(1, Ordering::Relaxed) % self.relayers.len();
+
+        match self.selection {
+            OzRelayerSelection::RoundRobin => next,
+            OzRelayerSelection::LeastBusy => self.least_busy_relayer().await.unwrap_or(next),
+        }
+    }
+
+    /// The unpaused relayer with the fewest pending transactions according to
+    /// Defender, `None` if no relayer status could be queried
+    async fn least_busy_relayer(&self) -> Option<usize> {
+        let statuses = self.relayers.iter().map(|relayer| async move {
+            timeout(self.send_timeout, relayer.oz_api.relayer_status())
+                .await
+                .ok()
+                .and_then(Result::ok)
+        });
+
+        futures::future::join_all(statuses)
+            .await
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, status)| {
+                let status = status?;
+                (!status.paused).then_some((index, status.number_of_pending_transactions))
+            })
+            .min_by_key(|(_, pending)| *pending)
+            .map(|(index, _)| index)
+    }
+
+    fn track_in_flight(&self, index: usize, tx_id: &str) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if !in_flight.contains_key(tx_id) {
+            in_flight.insert(tx_id.to_owned(), index);
+            self.relayers[index].track_in_flight();
+        }
+    }
+
+    fn settle(&self, index: usize, tx_id: &str) {
+        if self.in_flight.lock().unwrap().remove(tx_id).is_some() {
+            self.relayers[index].track_settled();
+        }
+    }
+
+    /// Finds the relayer a transaction was submitted through. Transactions
+    /// that weren't submitted by this instance (e.g. before a restart) are
+    /// looked up on every relayer.
+    async fn relayer_for(&self, tx_id: &str) -> Result<usize, TxError> {
+        if let Some(index) = self.in_flight.lock().unwrap().get(tx_id) {
+            return Ok(*index);
+        }
+
+        if self.relayers.len() == 1 {
+            return Ok(0);
+        }
+
+        for (index, relayer) in self.relayers.iter().enumerate() {
+            if relayer.oz_api.query_transaction(tx_id).await.is_ok() {
+                return Ok(index);
+            }
+        }
+
+        Err(TxError::Fetch(From::from(format!(
+            "Transaction {tx_id} not found on any relayer"
+        ))))
+    }
+
+    async fn poll_permit(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.poll_permits {
+            Some(permits) => Some(permits.acquire().await.expect("Semaphore closed")),
+            None => None,
+        }
+    }
+
+    async fn query(&self, relayer: &Relayer, tx_id: &str) -> Result<RelayerTransactionBase, Error> {
+        let _permit = self.poll_permit().await;
+
+        STATUS_POLLS.with_label_values(&[&relayer.label]).inc();
+        let tx = relayer.oz_api.query_transaction(tx_id).await?;
 
         Ok(tx)
     }
 
-    async fn list_recent_transactions(&self) -> Result<Vec<RelayerTransactionBase>, Error> {
-        let transactions = self.oz_api.list_transactions(None, Some(10)).await?;
+    /// Polls the status of a transaction, from the shared listing of the
+    /// relayer's transactions if batch polling is enabled and there's more
+    /// than one in flight
+    async fn poll_status(
+        &self,
+        relayer: &Relayer,
+        tx_id: &str,
+    ) -> Result<RelayerTransactionBase, Error> {
+        if self.batch_status_polling && relayer.in_flight() > 1 {
+            let listed = relayer
+                .status_listing
+                .get(tx_id, || async {
+                    let _permit = self.poll_permit().await;
+
+                    STATUS_POLLS.with_label_values(&[&relayer.label]).inc();
+                    relayer
+                        .oz_api
+                        .list_transactions(None, Some(LISTED_TRANSACTIONS))
+                        .await
+                })
+                .await?;
+
+            if let Some(tx) = listed {
+                return Ok(tx);
+            }
+        }
+
+        self.query(relayer, tx_id).await
+    }
+
+    async fn list_recent_transactions(
+        &self,
+        relayer: &Relayer,
+    ) -> Result<Vec<RelayerTransactionBase>, Error> {
+        let transactions = relayer.oz_api.list_transactions(None, Some(10)).await?;
 
         Ok(transactions)
     }
 
+    /// Lists the recent transactions of a relayer, retrying with exponential
+    /// backoff. `None` if all attempts failed.
+    async fn list_recent_transactions_with_retries(
+        &self,
+        relayer: &Relayer,
+    ) -> Option<Vec<RelayerTransactionBase>> {
+        let mut backoff = LIST_TRANSACTIONS_BACKOFF;
+
+        for attempt in 0..=self.list_retries {
+            match self.list_recent_transactions(relayer).await {
+                Ok(transactions) => return Some(transactions),
+                Err(error) if attempt < self.list_retries => {
+                    warn!(
+                        ?error,
+                        attempt,
+                        ?backoff,
+                        "Failed to list recent transactions, retrying"
+                    );
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(error) => {
+                    error!(?error, attempt, "Failed to list recent transactions");
+                }
+            }
+        }
+
+        None
+    }
+
     async fn mine_transaction_id_unchecked(
         &self,
+        index: usize,
         id: &str,
     ) -> Result<RelayerTransactionBase, TxError> {
+        let relayer = &self.relayers[index];
+
         loop {
-            let transaction = self.query(id).await.map_err(|error| {
+            let transaction = self.poll_status(relayer, id).await.map_err(|error| {
                 error!(?error, "Failed to get transaction status");
                 TxError::Send(error.into())
             })?;
 
             let status = transaction.status;
 
+            // Only a terminal status frees the nonce. A transaction that's
+            // still pending when waiting for it times out keeps pinning the
+            // relayer, so that the next batch can't overtake it through
+            // another one.
+            if matches!(status, Status::Mined | Status::Confirmed | Status::Failed) {
+                self.settle(index, id);
+            }
+
             // Terminal failure. The transaction won't be retried by OpenZeppelin. No reason
             // provided
-            match status {
-                Status::Failed => return Err(TxError::Failed(None)),
-                Status::Mined | Status::Confirmed => return Ok(transaction),
+            let settled = match status {
+                Status::Failed => {
+                    warn!(
+                        tx_id = id,
+                        nonce = ?transaction.nonce,
+                        gas_price = ?transaction.gas_price,
+                        max_fee_per_gas = ?transaction.max_fee_per_gas,
+                        "Transaction failed in OZ Relay"
+                    );
+                    return Err(TxError::Failed(None));
+                }
+                Status::Confirmed => true,
+                Status::Mined => !self.wait_confirmed,
+                _ => false,
+            };
+
+            match transaction.hash {
+                Some(hash) if settled => {
+                    info!(
+                        tx_id = id,
+                        ?hash,
+                        ?status,
+                        nonce = ?transaction.nonce,
+                        gas_price = ?transaction.gas_price,
+                        max_fee_per_gas = ?transaction.max_fee_per_gas,
+                        "Transaction mined by OZ Relay"
+                    );
+                    return Ok(transaction);
+                }
+                // The hash is only reported once the relayer has broadcast the
+                // transaction, so keep polling until it shows up
+                None if settled => {
+                    warn!(
+                        tx_id = id,
+                        ?status,
+                        "Mined transaction has no hash yet, waiting 5 s"
+                    );
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
                 _ => {
-                    info!("waiting 5 s to mine");
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    info!(
+                        tx_id = id,
+                        ?status,
+                        nonce = ?transaction.nonce,
+                        gas_price = ?transaction.gas_price,
+                        max_fee_per_gas = ?transaction.max_fee_per_gas,
+                        max_priority_fee_per_gas = ?transaction.max_priority_fee_per_gas,
+                        "waiting 5 s to mine"
+                    );
+                    tokio::time::sleep(POLL_INTERVAL).await;
                 }
             }
         }
     }
 
-    async fn mine_transaction_id(&self, id: &str) -> Result<RelayerTransactionBase, TxError> {
-        timeout(self.mine_timeout, self.mine_transaction_id_unchecked(id))
-            .await
-            .map_err(|_| TxError::ConfirmationTimeout)?
+    async fn mine_transaction_id(
+        &self,
+        index: usize,
+        id: &str,
+    ) -> Result<RelayerTransactionBase, TxError> {
+        let relayer = &self.relayers[index];
+
+        // Also counts transactions submitted before a restart, so that the
+        // next batches go through the same relayer
+        self.track_in_flight(index, id);
+
+        let result = timeout(
+            self.mine_timeout,
+            self.mine_transaction_id_unchecked(index, id),
+        )
+        .await
+        .map_err(|_| TxError::ConfirmationTimeout)?;
+
+        if matches!(result, Err(TxError::Failed(_))) {
+            relayer.track_failure();
+        }
+
+        result
     }
 
     async fn send_oz_transaction<T: Into<TypedTransaction> + Send + Sync>(
         &self,
+        relayer: &Relayer,
         tx: T,
     ) -> Result<String, Error> {
         let tx: TypedTransaction = tx.into();
-        let api_tx = SendBaseTransactionRequest {
-            to:          tx.to(),
-            value:       tx.value(),
-            gas_limit:   tx.gas(),
-            data:        tx.data(),
+        let api_tx = self.api_transaction(&tx);
+
+        if self.log_payloads {
+            self.trace_payload(&api_tx);
+        }
+
+        let tx = relayer.oz_api.send_transaction(api_tx).await?;
+
+        Ok(tx.transaction_id)
+    }
+
+    fn api_transaction<'a>(&self, tx: &'a TypedTransaction) -> SendBaseTransactionRequest<'a> {
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match &tx {
+            TypedTransaction::Eip1559(request) => (
+                None,
+                request.max_fee_per_gas.as_ref(),
+                request.max_priority_fee_per_gas.as_ref(),
+            ),
+            TypedTransaction::Legacy(request) => (request.gas_price.as_ref(), None, None),
+            TypedTransaction::Eip2930(request) => (request.tx.gas_price.as_ref(), None, None),
+        };
+        SendBaseTransactionRequest {
+            to: tx.to(),
+            value: tx.value(),
+            gas_limit: tx.gas(),
+            data: tx.data(),
             valid_until: Some(chrono::Utc::now() + self.transaction_validity),
+            gas_price,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        }
+    }
+
+    fn trace_payload(&self, api_tx: &SendBaseTransactionRequest<'_>) {
+        let payload = match serde_json::to_string(api_tx) {
+            Ok(payload) => payload,
+            Err(error) => {
+                error!(?error, "Failed to serialize transaction payload");
+                return;
+            }
         };
 
-        let tx = self.oz_api.send_transaction(api_tx).await?;
+        // Credentials are only ever sent in headers, but never risk logging them
+        let contains_credentials = self
+            .credentials
+            .iter()
+            .any(|secret| !secret.expose().is_empty() && payload.contains(secret.expose()));
 
-        Ok(tx.transaction_id)
+        debug_assert!(!contains_credentials, "Payload contains credentials");
+        if contains_credentials {
+            error!("Transaction payload contains credentials, not logging it");
+            return;
+        }
+
+        trace!(%payload, "Sending transaction payload to OZ Relay");
     }
 
     /// When `only_once` is set to true, this method tries to be idempotent.
     ///
     /// Before submiting a transaction, it'll query `OpenZepellin` for the list
-    /// of 10 most recent transactions to see if it's not processing already
+    /// of 10 most recent transactions of every relayer to see if it's not
+    /// processing already
     ///
     /// `OpenZeppelin` doesn't provide guarantees on how fast transactions will
     /// show up on the list of recent transactions ("order of seconds to be
@@ -141,79 +605,290 @@ impl OzRelay {
         if only_once {
             info!("checking if can resubmit");
 
-            let existing_transactions = self.list_recent_transactions().await.map_err(|e| {
-                error!(?e, "error occurred");
-                TxError::Send(e.into())
-            })?;
-
-            let existing_transaction =
-                existing_transactions
-                    .iter()
-                    .find(|el| match (&el.data, tx.data()) {
-                        (Some(a), Some(b)) => a == b,
-                        _ => false,
-                    });
-
-            if let Some(existing_transaction) = existing_transaction {
-                info!(only_once, "mining previously submitted transaction");
-
-                let transaction_id = existing_transaction.transaction_id.clone();
+            if let Some((index, transaction_id)) = self.find_previous_submission(&tx).await {
+                info!(
+                    only_once,
+                    relayer = index,
+                    "mining previously submitted transaction"
+                );
 
-                self.mine_transaction_id(&transaction_id).await?;
+                self.mine_transaction_id(index, &transaction_id).await?;
 
                 return Ok(TransactionId(transaction_id));
             }
         }
 
-        info!(?tx, gas_limit=?tx.gas(), "Sending transaction.");
-        let bytes4: u32 = tx.data().map_or(0, |data| {
-            let mut buffer = [0; 4];
-            buffer.copy_from_slice(&data.as_ref()[..4]); // TODO: Don't panic.
-            u32::from_be_bytes(buffer)
-        });
-        let bytes4 = format!("{bytes4:8x}");
-        TX_COUNT.with_label_values(&[&bytes4]).inc();
+        let selector = selector_label(&tx);
+        if let Some(allowed) = &self.allowed_selectors {
+            if !allowed.contains(&selector) {
+                warn!(selector, "Transaction is not in the selector allowlist");
+                return Err(TxError::SelectorNotAllowed { selector });
+            }
+        }
+
+        let _selection = self.selection_lock.lock().await;
+        let index = self.select_relayer().await;
+        let relayer = &self.relayers[index];
+
+        info!(?tx, gas_limit=?tx.gas(), relayer = index, "Sending transaction.");
+        TX_COUNT.with_label_values(&[&tx_count_label(&tx)]).inc();
 
         // Send TX to OZ Relay
-        let tx_id = timeout(self.send_timeout, self.send_oz_transaction(tx.clone()))
-            .instrument(info_span!("Send TX to mempool"))
-            .await
-            .map_err(|elapsed| {
-                error!(?elapsed, "Send transaction timed out");
-                TxError::SendTimeout
-            })?
-            .map_err(|error| {
+        let sent = timeout(
+            self.send_timeout,
+            self.send_oz_transaction(relayer, tx.clone()),
+        )
+        .instrument(info_span!("Send TX to mempool"))
+        .await
+        .map_err(|elapsed| {
+            error!(?elapsed, "Send transaction timed out");
+            relayer.track_failure();
+            TxError::SendTimeout
+        })?;
+
+        let tx_id = match sent {
+            Ok(tx_id) => tx_id,
+            // An earlier attempt went through after all, e.g. one that timed
+            // out. Following it avoids sending the transaction twice.
+            Err(Error::AlreadyKnown) if self.follow_known_transactions => {
+                warn!(
+                    relayer = index,
+                    "Transaction already known to the node, looking for the earlier submission"
+                );
+
+                let Some((index, transaction_id)) = self.find_previous_submission(&tx).await else {
+                    error!("Could not find the earlier submission of a known transaction");
+                    return Err(TxError::Send(Error::AlreadyKnown.into()));
+                };
+
+                info!(
+                    relayer = index,
+                    ?transaction_id,
+                    "Following the earlier submission instead of resubmitting"
+                );
+
+                return Ok(TransactionId(transaction_id));
+            }
+            Err(error) => {
                 error!(?error, "Failed to send transaction");
-                TxError::Send(error.into())
-            })?;
+                relayer.track_failure();
+                return Err(TxError::Send(error.into()));
+            }
+        };
+
+        self.track_in_flight(index, &tx_id);
+        relayer.track_submission();
 
-        info!(?tx_id, "Transaction submitted to OZ Relay");
+        info!(?tx_id, relayer = index, "Transaction submitted to OZ Relay");
 
         Ok(TransactionId(tx_id))
     }
 
+    /// Finds an earlier submission of `tx` that hasn't failed among the recent
+    /// transactions of every relayer, returning the relayer and the
+    /// transaction id
+    async fn find_previous_submission(&self, tx: &TypedTransaction) -> Option<(usize, String)> {
+        self.find_recent_transaction(|existing| is_previous_submission(existing, tx))
+            .await
+            .map(|(index, existing)| (index, existing.transaction_id))
+    }
+
+    /// Finds the first of the recent transactions of every relayer that
+    /// `matches`, returning the relayer and the transaction
+    async fn find_recent_transaction(
+        &self,
+        matches: impl Fn(&RelayerTransactionBase) -> bool + Send,
+    ) -> Option<(usize, RelayerTransactionBase)> {
+        for (index, relayer) in self.relayers.iter().enumerate() {
+            // The transaction might be in flight, but not knowing is no reason
+            // to give up on the other relayers
+            let Some(existing_transactions) =
+                self.list_recent_transactions_with_retries(relayer).await
+            else {
+                warn!(
+                    relayer = index,
+                    "Could not check the relayer for a previous submission, skipping it"
+                );
+                continue;
+            };
+
+            if let Some(existing_transaction) = existing_transactions.into_iter().find(&matches) {
+                return Some((index, existing_transaction));
+            }
+        }
+
+        None
+    }
+
     pub async fn mine_transaction(
         &self,
         tx_id: TransactionId,
     ) -> Result<RelayerTransactionBase, TxError> {
-        self.mine_transaction_id(tx_id.0.as_str()).await
+        let index = self.relayer_for(tx_id.as_ref()).await?;
+
+        self.mine_transaction_id(index, &tx_id.0).await
     }
 
-    pub async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
-        let recent_pending_txs = self
-            .list_recent_transactions()
+    /// A resubmission of a transaction that failed in OZ Relay
+    pub async fn resubmission(&self, tx_id: TransactionId) -> Result<TypedTransaction, TxError> {
+        let index = self.relayer_for(tx_id.as_ref()).await?;
+
+        let failed = self
+            .query(&self.relayers[index], tx_id.as_ref())
             .await
             .map_err(|err| TxError::Fetch(Box::new(err)))?;
 
-        let pending_txs = recent_pending_txs
-            .into_iter()
-            .map(|tx| TransactionId(tx.transaction_id))
-            .collect();
+        Ok(resubmission(&failed))
+    }
+
+    /// Replaces a pending transaction through the relayer it was submitted
+    /// through, which keeps its id
+    pub async fn replace_transaction(
+        &self,
+        tx_id: TransactionId,
+        mut replacement: TypedTransaction,
+    ) -> Result<(), TxError> {
+        if let Some(gas_limit) = self.gas_limit {
+            replacement.set_gas(gas_limit);
+        }
+
+        let index = self.relayer_for(tx_id.as_ref()).await?;
+        let relayer = &self.relayers[index];
+        let api_tx = self.api_transaction(&replacement);
+
+        if self.log_payloads {
+            self.trace_payload(&api_tx);
+        }
+
+        timeout(
+            self.send_timeout,
+            relayer.oz_api.replace_transaction(tx_id.as_ref(), api_tx),
+        )
+        .await
+        .map_err(|_| TxError::SendTimeout)?
+        .map_err(|err| TxError::Send(Box::new(Error::from(err))))?;
+
+        Ok(())
+    }
+
+    pub async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
+        let mut pending_txs = vec![];
+
+        for relayer in &self.relayers {
+            let recent_pending_txs = self
+                .list_recent_transactions(relayer)
+                .await
+                .map_err(|err| TxError::Fetch(Box::new(err)))?;
+
+            pending_txs.extend(
+                recent_pending_txs
+                    .into_iter()
+                    .map(|tx| TransactionId(tx.transaction_id)),
+            );
+        }
 
         Ok(pending_txs)
     }
 }
 
+/// The hex function selector of a transaction, `transfer` for plain value
+/// transfers and `fallback` for calldata too short to hold a selector
+fn selector_label(tx: &TypedTransaction) -> String {
+    let data: Option<&[u8]> = tx.data().map(|data| data.as_ref());
+
+    match data {
+        None | Some([]) => TRANSFER_SELECTOR.to_string(),
+        Some(data) => data
+            .get(..4)
+            .map_or_else(|| "fallback".to_string(), hex::encode),
+    }
+}
+
+const TRANSFER_SELECTOR: &str = "transfer";
+
+/// The `TX_COUNT` label of a transaction. Selectors keep the label's original
+/// space padded format, which existing dashboards match on.
+fn tx_count_label(tx: &TypedTransaction) -> String {
+    let selector = tx
+        .data()
+        .and_then(|data| data.get(..4))
+        .and_then(|selector| <[u8; 4]>::try_from(selector).ok());
+
+    match selector {
+        Some(selector) => format!("{:8x}", u32::from_be_bytes(selector)),
+        None => selector_label(tx),
+    }
+}
+
+/// Normalizes the selector allowlist to the labels of [`selector_label`]
+fn parse_selectors(selectors: &[String]) -> anyhow::Result<Option<HashSet<String>>> {
+    if selectors.is_empty() {
+        return Ok(None);
+    }
+
+    selectors
+        .iter()
+        .map(|selector| {
+            if selector == TRANSFER_SELECTOR {
+                return Ok(selector.clone());
+            }
+
+            let hex = selector.strip_prefix("0x").unwrap_or(selector);
+            anyhow::ensure!(
+                hex.len() == 8 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+                "Invalid selector {selector} in oz_allowed_selectors"
+            );
+
+            Ok(hex.to_ascii_lowercase())
+        })
+        .collect::<anyhow::Result<_>>()
+        .map(Some)
+}
+
+/// Whether `existing` is an earlier submission of `tx` that may still be mined.
+/// Calls are recognized by their calldata. Transfers have none to tell them
+/// apart, so they're only recognized by their recipient and nonce, if `tx` has
+/// one.
+fn is_previous_submission(existing: &RelayerTransactionBase, tx: &TypedTransaction) -> bool {
+    if existing.status == Status::Failed {
+        return false;
+    }
+
+    match tx.data().filter(|data| !data.is_empty()) {
+        Some(data) => existing.data.as_ref() == Some(data),
+        None => {
+            tx.to() == Some(&existing.to)
+                && tx
+                    .nonce()
+                    .is_some_and(|nonce| existing.nonce.map(U256::from).as_ref() == Some(nonce))
+        }
+    }
+}
+
+/// A transaction with the calldata of a failed one. It's left without fees, so
+/// that it's priced anew when it's sent.
+fn resubmission(failed: &RelayerTransactionBase) -> TypedTransaction {
+    if failed.max_fee_per_gas.is_some() {
+        Eip1559TransactionRequest {
+            to: Some(failed.to.clone()),
+            value: failed.value,
+            data: failed.data.clone(),
+            gas: Some(failed.gas_limit.into()),
+            ..Eip1559TransactionRequest::default()
+        }
+        .into()
+    } else {
+        TransactionRequest {
+            to: Some(failed.to.clone()),
+            value: failed.value,
+            data: failed.data.clone(),
+            gas: Some(failed.gas_limit.into()),
+            ..TransactionRequest::default()
+        }
+        .into()
+    }
+}
+
 #[async_trait::async_trait]
 impl Inner for OzRelay {
     async fn send_transaction(
@@ -236,4 +911,375 @@ impl Inner for OzRelay {
             hash:           transaction.hash,
         })
     }
+
+    async fn resubmission(&self, tx: TransactionId) -> Result<Option<TypedTransaction>, TxError> {
+        self.resubmission(tx).await.map(Some)
+    }
+
+    async fn replace_transaction(
+        &self,
+        tx: TransactionId,
+        replacement: TypedTransaction,
+    ) -> Result<(), TxError> {
+        self.replace_transaction(tx, replacement).await
+    }
+
+    async fn find_transaction(
+        &self,
+        matches: &(dyn Fn(&[u8]) -> bool + Send + Sync),
+    ) -> Result<Option<TransactionId>, TxError> {
+        let found = self
+            .find_recent_transaction(|existing| {
+                existing.status != Status::Failed
+                    && existing
+                        .data
+                        .as_ref()
+                        .is_some_and(|data| matches(data.as_ref()))
+            })
+            .await;
+
+        Ok(found.map(|(index, transaction)| {
+            // The next batches have to follow it through the same relayer
+            if transaction.status != Status::Mined && transaction.status != Status::Confirmed {
+                self.track_in_flight(index, &transaction.transaction_id);
+            }
+
+            TransactionId(transaction.transaction_id)
+        }))
+    }
+
+    async fn transaction_status(
+        &self,
+        tx: TransactionId,
+    ) -> Result<Option<TransactionStatus>, TxError> {
+        let index = self.relayer_for(tx.as_ref()).await?;
+
+        let transaction = self
+            .query(&self.relayers[index], tx.as_ref())
+            .await
+            .map_err(|err| TxError::Fetch(Box::new(err)))?;
+
+        Ok(Some(TransactionStatus {
+            transaction_id:           transaction.transaction_id,
+            status:                   transaction.status.to_string(),
+            pending:                  matches!(
+                transaction.status,
+                Status::Pending | Status::Sent | Status::Submitted | Status::Inmempool
+            ),
+            hash:                     transaction.hash,
+            nonce:                    transaction.nonce,
+            gas_price:                transaction.gas_price,
+            max_fee_per_gas:          transaction.max_fee_per_gas,
+            max_priority_fee_per_gas: transaction.max_priority_fee_per_gas,
+        }))
+    }
+
+    async fn relayer_nonces(&self) -> Vec<RelayerNonces> {
+        let statuses = self.relayers.iter().map(|relayer| async move {
+            timeout(self.send_timeout, relayer.oz_api.relayer_status())
+                .await
+                .map_err(|_| "Timed out querying the relayer status".to_string())
+                .and_then(|status| {
+                    status.map_err(|error| format!("Failed to query the relayer status: {error}"))
+                })
+        });
+
+        futures::future::join_all(statuses)
+            .await
+            .into_iter()
+            .enumerate()
+            .map(|(relayer, status)| match status {
+                Ok(status) => RelayerNonces {
+                    relayer,
+                    address: Some(status.address),
+                    relayer_nonce: Some(status.nonce),
+                    pending_transactions: Some(status.number_of_pending_transactions),
+                    ..RelayerNonces::default()
+                },
+                Err(error) => {
+                    warn!(relayer, %error, "Failed to query the relayer nonce");
+
+                    RelayerNonces {
+                        relayer,
+                        error: Some(error),
+                        ..RelayerNonces::default()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use ethers::types::{Address, Bytes, NameOrAddress};
+    use ethers::utils::{Anvil, AnvilInstance};
+
+    use super::*;
+
+    fn failed(max_fee_per_gas: Option<U256>) -> RelayerTransactionBase {
+        RelayerTransactionBase {
+            hash: None,
+            transaction_id: "0".to_string(),
+            to: NameOrAddress::Address(Address::repeat_byte(1)),
+            value: None,
+            gas_limit: 1_000_000,
+            data: Some(vec![1, 2, 3].into()),
+            valid_until: Utc::now(),
+            status: Status::Failed,
+            gas_price: Some(U256::from(10)),
+            max_fee_per_gas,
+            max_priority_fee_per_gas: max_fee_per_gas.map(|_| U256::from(1)),
+            nonce: Some(7),
+        }
+    }
+
+    async fn relay(relayers: usize) -> OzRelay {
+        relay_at("http://localhost:0", Address::zero(), relayers).await
+    }
+
+    async fn relay_at(url: &str, address: Address, relayers: usize) -> OzRelay {
+        let additional: Vec<_> = (1..relayers)
+            .map(|_| serde_json::json!({ "api_key": "", "api_secret": "" }))
+            .collect();
+        let options: OzDefenderConfig = serde_json::from_value(serde_json::json!({
+            "oz_api_url": url,
+            "oz_api_key": "",
+            "oz_api_secret": "",
+            "oz_address": address,
+            "oz_additional_relayers": serde_json::to_string(&additional).unwrap(),
+        }))
+        .unwrap();
+
+        OzRelay::new(&options, false).await.unwrap()
+    }
+
+    /// Defender in front of a chain that doesn't mine, so that the sent
+    /// transactions stay pending
+    async fn micro_oz() -> (AnvilInstance, micro_oz::ServerHandle) {
+        let anvil = Anvil::new().arg("--no-mining").spawn();
+        let micro_oz = micro_oz::spawn(anvil.endpoint(), anvil.keys()[0].clone().into())
+            .await
+            .unwrap();
+
+        (anvil, micro_oz)
+    }
+
+    #[tokio::test]
+    async fn pending_transactions_are_found_by_calldata() {
+        let (_anvil, micro_oz) = micro_oz().await;
+        let relay = relay_at(&micro_oz.endpoint(), micro_oz.address(), 1).await;
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .data(vec![1, 2, 3])
+            .into();
+        let sent = relay.send_transaction(tx, false).await.unwrap();
+
+        let found = Inner::find_transaction(&relay, &|calldata: &[u8]| calldata == [1, 2, 3])
+            .await
+            .unwrap();
+        assert_eq!(found.map(|tx_id| tx_id.0), Some(sent.0));
+
+        let found = Inner::find_transaction(&relay, &|calldata: &[u8]| calldata == [4])
+            .await
+            .unwrap();
+        assert!(found.is_none());
+
+        micro_oz.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn timed_out_transactions_stay_in_flight() {
+        let (_anvil, micro_oz) = micro_oz().await;
+        let mut relay = relay_at(&micro_oz.endpoint(), micro_oz.address(), 1).await;
+        relay.mine_timeout = Duration::from_secs(1);
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .data(vec![1, 2, 3])
+            .into();
+        let sent = relay.send_transaction(tx, false).await.unwrap();
+
+        let result = relay.mine_transaction_id(0, &sent.0).await;
+        assert!(matches!(result, Err(TxError::ConfirmationTimeout)));
+
+        // Still pending, so it keeps pinning the relayer
+        assert_eq!(relay.in_flight.lock().unwrap().get(&sent.0), Some(&0));
+        assert_eq!(relay.relayers[0].in_flight(), 1);
+
+        micro_oz.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn known_transactions_follow_the_earlier_submission() {
+        let (_anvil, micro_oz) = micro_oz().await;
+        let mut relay = relay_at(&micro_oz.endpoint(), micro_oz.address(), 1).await;
+        relay.follow_known_transactions = true;
+
+        let tx = |data: Vec<u8>| -> TypedTransaction {
+            TransactionRequest::new()
+                .to(Address::repeat_byte(1))
+                .data(data)
+                .into()
+        };
+        let sent = relay
+            .send_transaction(tx(vec![1, 2, 3]), false)
+            .await
+            .unwrap();
+
+        // e.g. an earlier attempt that timed out went through after all
+        micro_oz.reject_sends(Some("nonce too low: next nonce 1, tx nonce 0"));
+        let followed = relay
+            .send_transaction(tx(vec![1, 2, 3]), false)
+            .await
+            .unwrap();
+        assert_eq!(followed.0, sent.0);
+
+        // Another transaction took the nonce
+        let result = relay.send_transaction(tx(vec![4]), false).await;
+        assert!(matches!(result, Err(TxError::Send(_))));
+
+        micro_oz.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn listings_are_retried() {
+        let (_anvil, micro_oz) = micro_oz().await;
+        let mut relay = relay_at(&micro_oz.endpoint(), micro_oz.address(), 1).await;
+        relay.list_retries = 2;
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .data(vec![1, 2, 3])
+            .into();
+        let sent = relay.send_transaction(tx.clone(), false).await.unwrap();
+
+        // Every attempt but the last fails
+        micro_oz.fail_lists(2);
+        let found = relay.find_previous_submission(&tx).await;
+        assert_eq!(found, Some((0, sent.0)));
+
+        // The relayer is skipped once all attempts failed
+        micro_oz.fail_lists(3);
+        assert_eq!(relay.find_previous_submission(&tx).await, None);
+
+        micro_oz.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn polls_are_capped_across_transactions() {
+        let mut relay = relay(2).await;
+        assert!(relay.poll_permit().await.is_none());
+
+        relay.poll_permits = Some(Semaphore::new(2));
+        let first = relay.poll_permit().await.unwrap();
+        let _second = relay.poll_permit().await.unwrap();
+
+        // Polls beyond the cap wait for one in flight to finish
+        let third = timeout(Duration::from_millis(100), relay.poll_permit()).await;
+        assert!(third.is_err());
+
+        drop(first);
+        let third = timeout(Duration::from_millis(100), relay.poll_permit()).await;
+        assert!(third.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn batches_stay_on_one_relayer_while_in_flight() {
+        let relay = relay(3).await;
+
+        let first = relay.select_relayer().await;
+        relay.track_in_flight(first, "0");
+        assert_eq!(relay.select_relayer().await, first);
+        assert_eq!(relay.select_relayer().await, first);
+
+        // Once it reaches a terminal status, the transaction stops counting
+        relay.settle(first, "0");
+        assert!(relay.in_flight.lock().unwrap().is_empty());
+        assert_eq!(relay.relayers[first].in_flight(), 0);
+        assert_ne!(relay.select_relayer().await, first);
+    }
+
+    #[test]
+    fn resubmissions_keep_calldata_but_not_fees() {
+        let eip1559 = resubmission(&failed(Some(U256::from(20))));
+        let TypedTransaction::Eip1559(request) = &eip1559 else {
+            panic!("Expected an EIP-1559 transaction");
+        };
+        // Fees are set anew when the resubmission is sent
+        assert_eq!(request.max_fee_per_gas, None);
+        assert_eq!(request.max_priority_fee_per_gas, None);
+        assert_eq!(eip1559.data(), failed(None).data.as_ref());
+        assert_eq!(eip1559.gas(), Some(&U256::from(1_000_000)));
+        // The relayer assigns a new nonce
+        assert_eq!(eip1559.nonce(), None);
+
+        let legacy = resubmission(&failed(None));
+        assert!(matches!(legacy, TypedTransaction::Legacy(_)));
+        assert_eq!(legacy.gas_price(), None);
+    }
+
+    #[test]
+    fn value_transfers_are_first_class() {
+        let recipient = NameOrAddress::Address(Address::repeat_byte(1));
+        let transfer: TypedTransaction = TransactionRequest::new()
+            .to(recipient.clone())
+            .value(1)
+            .into();
+        let call: TypedTransaction = TransactionRequest::new()
+            .to(recipient)
+            .data(vec![0x22, 0x17, 0xb2, 0x11, 0])
+            .into();
+
+        assert_eq!(selector_label(&transfer), "transfer");
+        assert_eq!(selector_label(&call), "2217b211");
+        assert_eq!(
+            selector_label(&TransactionRequest::new().data(vec![1]).into()),
+            "fallback"
+        );
+
+        assert_eq!(tx_count_label(&transfer), "transfer");
+        assert_eq!(tx_count_label(&call), "2217b211");
+        assert_eq!(
+            tx_count_label(
+                &TransactionRequest::new()
+                    .data(vec![0, 0, 0xab, 0xcd])
+                    .into()
+            ),
+            "    abcd"
+        );
+
+        let allowed = parse_selectors(&["0x2217B211".to_string()])
+            .unwrap()
+            .unwrap();
+        assert!(allowed.contains(&selector_label(&call)));
+        assert!(!allowed.contains(&selector_label(&transfer)));
+        assert!(parse_selectors(&["transfer".to_string()])
+            .unwrap()
+            .unwrap()
+            .contains(&selector_label(&transfer)));
+        assert!(parse_selectors(&["0x2217".to_string()]).is_err());
+        assert!(parse_selectors(&[]).unwrap().is_none());
+
+        // Previous transfers without calldata can't be told apart by it
+        let mut existing = failed(None);
+        existing.status = Status::Submitted;
+        existing.data = Some(Bytes::new());
+        assert!(!is_previous_submission(&existing, &transfer));
+        let mut same_nonce = transfer.clone();
+        same_nonce.set_nonce(7);
+        assert!(is_previous_submission(&existing, &same_nonce));
+        let mut other_nonce = transfer;
+        other_nonce.set_nonce(8);
+        assert!(!is_previous_submission(&existing, &other_nonce));
+
+        existing.data = call.data().cloned();
+        assert!(is_previous_submission(&existing, &call));
+
+        // Failed submissions won't be mined, so they're not followed
+        existing.status = Status::Failed;
+        assert!(!is_previous_submission(&existing, &call));
+    }
 }
@@ -0,0 +1,400 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers::types::U256;
+use once_cell::sync::Lazy;
+use prometheus::{register_gauge_with_registry, Gauge};
+use tracing::warn;
+
+use crate::ethereum::TxError;
+use crate::metrics;
+
+static WINDOW_SPEND: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge_with_registry!(
+        "sequencer_window_wei_spent",
+        "Wei spent on gas within the current spend limit window, including the most in-flight \
+         transactions may spend.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Share of the budget, in percent, above which the spend counts as near the
+/// limit
+const NEAR_LIMIT_PERCENT: u64 = 80;
+
+/// The spend within the current window, relative to the budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowSpend {
+    pub spent:  U256,
+    pub budget: U256,
+}
+
+impl WindowSpend {
+    pub fn is_near_limit(&self) -> bool {
+        self.spent.saturating_mul(U256::from(100))
+            >= self.budget.saturating_mul(U256::from(NEAR_LIMIT_PERCENT))
+    }
+}
+
+/// The most a transaction about to be sent may spend, held against the budget
+/// until it's mined or waiting for it ends
+#[derive(Debug)]
+pub struct Reservation(u64);
+
+/// Gives up the reservation of a transaction once waiting for it ends, however
+/// it ends. It's a no-op if the transaction's spend was recorded by then.
+pub struct Held<'a> {
+    governor: &'a SpendGovernor,
+    tx_id:    &'a str,
+}
+
+impl Drop for Held<'_> {
+    fn drop(&mut self) {
+        self.governor.release(self.tx_id);
+    }
+}
+
+#[derive(Default)]
+struct Spends {
+    mined:            VecDeque<(Instant, U256)>,
+    in_flight:        HashMap<u64, U256>,
+    reservations:     HashMap<String, u64>,
+    next_reservation: u64,
+}
+
+/// Caps the wei spent on gas over a rolling window. Both successful and
+/// reverted transactions count, since both are paid for. In-flight
+/// transactions count with the most they may spend, their gas limit times
+/// their max fee, so that the cap holds before they're mined.
+///
+/// Spends are only tracked in memory, a restart starts with an empty window.
+pub struct SpendGovernor {
+    budget: Option<U256>,
+    window: Duration,
+    spends: Mutex<Spends>,
+}
+
+impl SpendGovernor {
+    /// `budget` is the most wei that may be spent within `window`. Without one,
+    /// reservations only tie transactions to their ids and mined spends
+    /// aren't kept.
+    pub fn new(budget: Option<U256>, window: Duration) -> Self {
+        Self {
+            budget,
+            window,
+            spends: Mutex::new(Spends::default()),
+        }
+    }
+
+    /// The spend within the current window, `None` if there's no budget.
+    /// Fails with `TxError::SpendLimitExceeded` once the budget is used up.
+    pub fn check(&self) -> Result<Option<WindowSpend>, TxError> {
+        let Some(budget) = self.budget else {
+            return Ok(None);
+        };
+
+        let spent = self.window_spend(&mut self.spends.lock().unwrap());
+        if spent >= budget {
+            return Err(TxError::SpendLimitExceeded { spent, budget });
+        }
+
+        Ok(Some(WindowSpend { spent, budget }))
+    }
+
+    /// Holds `max_cost` against the budget for a transaction about to be
+    /// sent, failing with `TxError::SpendLimitExceeded` if it doesn't fit. A
+    /// transaction of unknown cost counts as the largest spend within the
+    /// window.
+    pub fn reserve(&self, max_cost: Option<U256>) -> Result<Reservation, TxError> {
+        let mut spends = self.spends.lock().unwrap();
+        let reservation = spends.next_reservation;
+        spends.next_reservation += 1;
+
+        let Some(budget) = self.budget else {
+            return Ok(Reservation(reservation));
+        };
+
+        let spent = self.window_spend(&mut spends);
+        let max_cost = max_cost.unwrap_or_else(|| {
+            spends
+                .mined
+                .iter()
+                .map(|(_, wei)| *wei)
+                .max()
+                .unwrap_or_default()
+        });
+        if spent >= budget || spent.saturating_add(max_cost) > budget {
+            return Err(TxError::SpendLimitExceeded { spent, budget });
+        }
+
+        spends.in_flight.insert(reservation, max_cost);
+        self.window_spend(&mut spends);
+
+        Ok(Reservation(reservation))
+    }
+
+    /// Ties a reservation to the transaction it was made for, once sent
+    pub fn assign(&self, reservation: Reservation, tx_id: &str) {
+        self.spends
+            .lock()
+            .unwrap()
+            .reservations
+            .insert(tx_id.to_owned(), reservation.0);
+    }
+
+    /// Raises the reservation of a transaction replaced at a higher fee to get
+    /// it unstuck, e.g. by the legacy fallback. The replacement isn't refused,
+    /// since holding back a stuck transaction stalls the batches behind it.
+    pub fn raise(&self, tx_id: &str, max_cost: U256) {
+        let mut spends = self.spends.lock().unwrap();
+        let Some(reservation) = spends.reservations.get(tx_id).copied() else {
+            return;
+        };
+        if let Some(reserved) = spends.in_flight.get_mut(&reservation) {
+            *reserved = (*reserved).max(max_cost);
+        }
+        self.window_spend(&mut spends);
+    }
+
+    /// Like `raise`, but for a replacement that may be refused: fails with
+    /// `TxError::SpendLimitExceeded`, leaving the reservation as is, if the
+    /// raised one doesn't fit within the budget.
+    pub fn try_raise(&self, tx_id: &str, max_cost: U256) -> Result<(), TxError> {
+        let mut spends = self.spends.lock().unwrap();
+        let Some(reservation) = spends.reservations.get(tx_id).copied() else {
+            return Ok(());
+        };
+
+        if let Some(budget) = self.budget {
+            let spent = self.window_spend(&mut spends);
+            let reserved = spends
+                .in_flight
+                .get(&reservation)
+                .copied()
+                .unwrap_or_default();
+            let raised = spent
+                .saturating_sub(reserved)
+                .saturating_add(max_cost.max(reserved));
+            if raised > budget {
+                return Err(TxError::SpendLimitExceeded { spent, budget });
+            }
+        }
+
+        if let Some(reserved) = spends.in_flight.get_mut(&reservation) {
+            *reserved = (*reserved).max(max_cost);
+        }
+        self.window_spend(&mut spends);
+
+        Ok(())
+    }
+
+    /// Gives up a reservation whose transaction wasn't sent
+    pub fn cancel(&self, reservation: Reservation) {
+        let mut spends = self.spends.lock().unwrap();
+        spends.in_flight.remove(&reservation.0);
+        self.window_spend(&mut spends);
+    }
+
+    /// Holds the reservation of a sent transaction while it's waited for
+    pub fn hold<'a>(&'a self, tx_id: &'a str) -> Held<'a> {
+        Held {
+            governor: self,
+            tx_id,
+        }
+    }
+
+    /// Gives up the reservation of a transaction that failed without being
+    /// mined
+    pub fn release(&self, tx_id: &str) {
+        let mut spends = self.spends.lock().unwrap();
+        if let Some(reservation) = spends.reservations.remove(tx_id) {
+            spends.in_flight.remove(&reservation);
+        }
+        self.window_spend(&mut spends);
+    }
+
+    /// Replaces the reservation of a mined transaction with what it spent
+    pub fn record(&self, tx_id: &str, wei: U256) {
+        {
+            let mut spends = self.spends.lock().unwrap();
+            if let Some(reservation) = spends.reservations.remove(tx_id) {
+                spends.in_flight.remove(&reservation);
+            }
+            if self.budget.is_none() {
+                return;
+            }
+            spends.mined.push_back((Instant::now(), wei));
+        }
+
+        if let Ok(Some(spend)) = self.check() {
+            if spend.is_near_limit() {
+                warn!(
+                    spent = %spend.spent,
+                    budget = %spend.budget,
+                    "Gas spend is close to the limit of the window"
+                );
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn window_spend(&self, spends: &mut Spends) -> U256 {
+        while spends
+            .mined
+            .front()
+            .is_some_and(|(spent_at, _)| spent_at.elapsed() >= self.window)
+        {
+            spends.mined.pop_front();
+        }
+
+        let spent = spends
+            .mined
+            .iter()
+            .map(|(_, wei)| wei)
+            .chain(spends.in_flight.values())
+            .fold(U256::zero(), |total, wei| total.saturating_add(*wei));
+
+        WINDOW_SPEND.set(u128::try_from(spent).unwrap_or(u128::MAX) as f64);
+
+        spent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spending_stops_at_the_budget() {
+        let governor = SpendGovernor::new(Some(U256::from(100)), Duration::from_secs(3600));
+
+        governor.record("a", U256::from(50));
+        let spend = governor.check().unwrap().unwrap();
+        assert_eq!(spend.spent, U256::from(50));
+        assert!(!spend.is_near_limit());
+
+        governor.record("b", U256::from(30));
+        assert!(governor.check().unwrap().unwrap().is_near_limit());
+
+        governor.record("c", U256::from(20));
+        assert!(matches!(
+            governor.check(),
+            Err(TxError::SpendLimitExceeded { spent, budget })
+                if spent == U256::from(100) && budget == U256::from(100)
+        ));
+    }
+
+    #[test]
+    fn in_flight_transactions_count_towards_the_budget() {
+        let governor = SpendGovernor::new(Some(U256::from(100)), Duration::from_secs(3600));
+
+        let reservation = governor.reserve(Some(U256::from(60))).unwrap();
+        governor.assign(reservation, "a");
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(60));
+
+        // Wouldn't fit next to the in-flight one, even though nothing's mined
+        assert!(matches!(
+            governor.reserve(Some(U256::from(50))),
+            Err(TxError::SpendLimitExceeded { .. })
+        ));
+
+        // Mining replaces the reservation with the actual spend
+        governor.record("a", U256::from(20));
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(20));
+
+        let reservation = governor.reserve(Some(U256::from(50))).unwrap();
+        governor.cancel(reservation);
+        let reservation = governor.reserve(Some(U256::from(80))).unwrap();
+        governor.assign(reservation, "b");
+        governor.raise("b", U256::from(90));
+        assert!(governor.check().is_err());
+
+        governor.release("b");
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(20));
+    }
+
+    #[test]
+    fn reprices_are_refused_past_the_budget() {
+        let governor = SpendGovernor::new(Some(U256::from(100)), Duration::from_secs(3600));
+
+        governor.record("a", U256::from(20));
+        let reservation = governor.reserve(Some(U256::from(50))).unwrap();
+        governor.assign(reservation, "b");
+
+        governor.try_raise("b", U256::from(70)).unwrap();
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(90));
+
+        // Refused, the reservation stays as it was
+        assert!(matches!(
+            governor.try_raise("b", U256::from(90)),
+            Err(TxError::SpendLimitExceeded { .. })
+        ));
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(90));
+    }
+
+    #[tokio::test]
+    async fn timed_out_transactions_release_their_reservation() {
+        let governor = SpendGovernor::new(Some(U256::from(100)), Duration::from_secs(3600));
+
+        let reservation = governor.reserve(Some(U256::from(60))).unwrap();
+        governor.assign(reservation, "a");
+
+        let wait = async {
+            let _held = governor.hold("a");
+            std::future::pending::<()>().await;
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(1), wait)
+            .await
+            .is_err());
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::zero());
+        assert!(governor.spends.lock().unwrap().reservations.is_empty());
+
+        // The spend of a mined transaction stays once waiting ends
+        let reservation = governor.reserve(Some(U256::from(60))).unwrap();
+        governor.assign(reservation, "b");
+        {
+            let _held = governor.hold("b");
+            governor.record("b", U256::from(30));
+        }
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(30));
+    }
+
+    #[test]
+    fn transactions_of_unknown_cost_count_as_the_largest_spend() {
+        let governor = SpendGovernor::new(Some(U256::from(100)), Duration::from_secs(3600));
+
+        governor.record("a", U256::from(40));
+        governor.reserve(None).unwrap();
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::from(80));
+
+        assert!(governor.reserve(None).is_err());
+    }
+
+    #[test]
+    fn spending_resumes_when_the_window_rolls() {
+        let governor = SpendGovernor::new(Some(U256::from(100)), Duration::ZERO);
+
+        governor.record("a", U256::from(500));
+
+        assert_eq!(governor.check().unwrap().unwrap().spent, U256::zero());
+    }
+
+    #[test]
+    fn reservations_always_succeed_without_a_budget() {
+        let governor = SpendGovernor::new(None, Duration::from_secs(3600));
+
+        let reservation = governor.reserve(Some(U256::MAX)).unwrap();
+        governor.assign(reservation, "a");
+        governor.try_raise("a", U256::MAX).unwrap();
+        governor.record("a", U256::MAX);
+        governor.reserve(None).unwrap();
+
+        // Nothing was kept to report on
+        assert_eq!(governor.check().unwrap(), None);
+        let spends = governor.spends.lock().unwrap();
+        assert!(spends.mined.is_empty());
+        assert!(spends.in_flight.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use oz_api::data::transactions::RelayerTransactionBase;
+use tokio::sync::Mutex;
+
+/// Shares one listing of a relayer's recent transactions between everyone
+/// polling for the status of a transaction, so that polling N transactions
+/// costs a single request per interval instead of N.
+///
+/// Callers that arrive while the listing is being fetched wait for it, rather
+/// than fetching it again.
+#[derive(Debug)]
+pub struct StatusListing {
+    max_age: Duration,
+    listing: Mutex<Option<Listing>>,
+}
+
+#[derive(Debug)]
+struct Listing {
+    fetched_at:   Instant,
+    transactions: Arc<HashMap<String, RelayerTransactionBase>>,
+}
+
+impl StatusListing {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            listing: Mutex::new(None),
+        }
+    }
+
+    /// The status of `tx_id` according to a listing fetched with `list` at
+    /// most `max_age` ago. `None` if the transaction isn't listed, e.g.
+    /// because it's too old, in which case it has to be queried by id.
+    pub async fn get<F, Fut, E>(
+        &self,
+        tx_id: &str,
+        list: F,
+    ) -> Result<Option<RelayerTransactionBase>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<RelayerTransactionBase>, E>>,
+    {
+        let transactions = {
+            let mut listing = self.listing.lock().await;
+
+            match &*listing {
+                Some(listing) if listing.fetched_at.elapsed() < self.max_age => {
+                    listing.transactions.clone()
+                }
+                _ => {
+                    let transactions: Arc<HashMap<_, _>> = Arc::new(
+                        list()
+                            .await?
+                            .into_iter()
+                            .map(|tx| (tx.transaction_id.clone(), tx))
+                            .collect(),
+                    );
+
+                    *listing = Some(Listing {
+                        fetched_at:   Instant::now(),
+                        transactions: transactions.clone(),
+                    });
+
+                    transactions
+                }
+            }
+        };
+
+        Ok(transactions.get(tx_id).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::Utc;
+    use ethers::types::{Address, NameOrAddress};
+    use oz_api::data::transactions::Status;
+
+    use super::*;
+
+    fn transaction(id: usize) -> RelayerTransactionBase {
+        RelayerTransactionBase {
+            hash: None,
+            transaction_id: id.to_string(),
+            to: NameOrAddress::Address(Address::zero()),
+            value: None,
+            gas_limit: 0,
+            data: None,
+            valid_until: Utc::now(),
+            status: Status::Mined,
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_waiters_share_one_listing() {
+        let listing = Arc::new(StatusListing::new(Duration::from_secs(60)));
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let waiters: Vec<_> = (0..10)
+            .map(|id| {
+                let listing = listing.clone();
+                let fetches = fetches.clone();
+
+                tokio::spawn(async move {
+                    listing
+                        .get(&id.to_string(), || async {
+                            fetches.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            Ok::<_, ()>((0..5).map(transaction).collect())
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+
+        for (id, waiter) in waiters.into_iter().enumerate() {
+            let status = waiter.await.unwrap();
+            assert_eq!(status.is_some(), id < 5);
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_listings_are_fetched_again() {
+        let listing = StatusListing::new(Duration::ZERO);
+
+        for _ in 0..2 {
+            listing
+                .get("0", || async { Ok::<_, ()>(vec![transaction(0)]) })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        let status = listing
+            .get("0", || async { Ok::<_, ()>(vec![]) })
+            .await
+            .unwrap();
+        assert!(status.is_none());
+    }
+}
@@ -15,15 +15,18 @@ use crate::ethereum::TxError;
 const MINING_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub struct TxSitter {
-    client:    TxSitterClient,
-    gas_limit: Option<u64>,
+    client:         TxSitterClient,
+    gas_limit:      Option<u64>,
+    // Wait for the finalized status instead of just mined
+    wait_finalized: bool,
 }
 
 impl TxSitter {
-    pub fn new(config: &TxSitterConfig) -> Self {
+    pub fn new(config: &TxSitterConfig, wait_finalized: bool) -> Self {
         Self {
-            client:    TxSitterClient::new(&config.tx_sitter_url),
+            client: TxSitterClient::new(config.tx_sitter_url.expose()),
             gas_limit: config.tx_sitter_gas_limit,
+            wait_finalized,
         }
     }
 
@@ -34,7 +37,13 @@ impl TxSitter {
         loop {
             let tx = self.client.get_tx(&tx_id.0).await.map_err(TxError::Send)?;
 
-            if tx.status == TxStatus::Mined || tx.status == TxStatus::Finalized {
+            let confirmed = match tx.status {
+                TxStatus::Finalized => true,
+                TxStatus::Mined => !self.wait_finalized,
+                _ => false,
+            };
+
+            if confirmed {
                 return Ok(TransactionResult {
                     transaction_id: tx.tx_id,
                     hash:           Some(
@@ -0,0 +1,539 @@
+//! Runtime signals about the state of the sequencer, reported by the health
+//! endpoint and as metrics.
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use ethers::types::U256;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_gauge_with_registry, IntGauge};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+use crate::identity_tree::Hash;
+use crate::metrics;
+
+static BLOCK_LAG: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "block_lag",
+        "Number of blocks the event processing is behind the chain head",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static INSERT_BACKPRESSURE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "insert_backpressure",
+        "Whether insertions are rejected because event processing is lagging behind",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static RELAYER_BALANCE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "relayer_balance_gwei",
+        "Balance of the relayer account in gwei",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static INSUFFICIENT_BALANCE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "batches_paused_insufficient_balance",
+        "Whether batch submission is paused because the relayer balance is too low",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static READ_ONLY: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "sequencer_read_only",
+        "Whether insertions are rejected because batch submissions keep failing",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static PAUSED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "sequencer_paused",
+        "Whether insertions are paused by an operator",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static CONTRACT_PAUSED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "identity_manager_paused",
+        "Whether insertions are rejected because the identity manager contract is paused",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static CHAIN_HEAD_AGE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "seconds_since_chain_head_advance",
+        "Seconds since the provider last reported a new chain head",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static STALE_CHAIN_HEAD: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "stale_chain_head",
+        "Whether the chain head stopped advancing for longer than the configured maximum",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static ROOT_DIVERGED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "contract_root_diverged",
+        "Whether the last reconciliation found a contract root the local tree has never seen",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+const GWEI: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub block_lag:            u64,
+    pub backpressure:         bool,
+    pub insufficient_balance: bool,
+    pub read_only:            bool,
+    pub paused:               bool,
+    pub contract_paused:      bool,
+    pub stale_chain_head:     bool,
+    pub last_reconciliation:  Option<Reconciliation>,
+}
+
+/// How the contract root compares to the local tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReconciliationStatus {
+    /// The contract root is the root of the mined tree
+    InSync,
+    /// The contract root is a local root other than the mined one, e.g.
+    /// because its events weren't processed yet
+    Known,
+    /// The local tree has never seen the contract root
+    Diverged,
+}
+
+/// The outcome of comparing the contract root against the local tree
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reconciliation {
+    /// The block the contract root was read at
+    pub block:         u64,
+    pub contract_root: Hash,
+    pub mined_root:    Hash,
+    pub status:        ReconciliationStatus,
+    pub reconciled_at: DateTime<Utc>,
+}
+
+pub struct Health {
+    max_block_lag:            Option<u64>,
+    block_lag:                AtomicU64,
+    min_relayer_balance_gwei: Option<u64>,
+    insufficient_balance:     AtomicBool,
+    read_only_after_failures: Option<u32>,
+    submission_failures:      AtomicU32,
+    read_only:                AtomicBool,
+    paused:                   AtomicBool,
+    contract_paused:          AtomicBool,
+    max_chain_head_age:       Option<Duration>,
+    /// The last chain head and when it was first seen
+    chain_head:               Mutex<Option<(u64, Instant)>>,
+    stale_chain_head:         AtomicBool,
+    /// The last block whose tree changes are all applied to the local tree
+    applied_block:            Mutex<Option<u64>>,
+    last_reconciliation:      Mutex<Option<Reconciliation>>,
+}
+
+impl Health {
+    #[must_use]
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            max_block_lag:            config.max_block_lag,
+            block_lag:                AtomicU64::new(0),
+            min_relayer_balance_gwei: config.min_relayer_balance_gwei,
+            insufficient_balance:     AtomicBool::new(false),
+            read_only_after_failures: config.read_only_after_failures,
+            submission_failures:      AtomicU32::new(0),
+            read_only:                AtomicBool::new(false),
+            paused:                   AtomicBool::new(false),
+            contract_paused:          AtomicBool::new(false),
+            max_chain_head_age:       config.max_chain_head_age,
+            chain_head:               Mutex::new(None),
+            stale_chain_head:         AtomicBool::new(false),
+            applied_block:            Mutex::new(None),
+            last_reconciliation:      Mutex::new(None),
+        }
+    }
+
+    /// Whether batch submission depends on the relayer balance
+    pub fn checks_relayer_balance(&self) -> bool {
+        self.min_relayer_balance_gwei.is_some()
+    }
+
+    pub fn set_relayer_balance(&self, balance: U256) {
+        let balance_gwei = balance / U256::from(GWEI);
+        let insufficient = self
+            .min_relayer_balance_gwei
+            .is_some_and(|min_balance_gwei| balance_gwei < U256::from(min_balance_gwei));
+
+        let was_insufficient = self
+            .insufficient_balance
+            .swap(insufficient, Ordering::Relaxed);
+
+        if insufficient && !was_insufficient {
+            warn!(%balance, "Batch submission paused: insufficient balance");
+        } else if !insufficient && was_insufficient {
+            info!(%balance, "Batch submission resumed: relayer balance topped up");
+        }
+
+        RELAYER_BALANCE.set(balance_gwei.try_into().unwrap_or(i64::MAX));
+        INSUFFICIENT_BALANCE.set(i64::from(insufficient));
+    }
+
+    /// Whether batches should be held back until the relayer account is
+    /// topped up
+    pub fn is_balance_insufficient(&self) -> bool {
+        self.insufficient_balance.load(Ordering::Relaxed)
+    }
+
+    pub fn set_block_lag(&self, block_lag: u64) {
+        self.block_lag.store(block_lag, Ordering::Relaxed);
+
+        BLOCK_LAG.set(block_lag.try_into().unwrap_or(i64::MAX));
+        INSERT_BACKPRESSURE.set(i64::from(self.is_backpressured()));
+    }
+
+    pub fn block_lag(&self) -> u64 {
+        self.block_lag.load(Ordering::Relaxed)
+    }
+
+    /// Records the last block whose tree changes are all applied to the local
+    /// tree
+    pub fn set_applied_block(&self, block: u64) {
+        *self.applied_block.lock().unwrap() = Some(block);
+    }
+
+    /// The last block whose tree changes are all applied to the local tree,
+    /// `None` until the first scan
+    pub fn applied_block(&self) -> Option<u64> {
+        *self.applied_block.lock().unwrap()
+    }
+
+    /// Whether new insertions should be rejected until event processing
+    /// catches up with the chain head
+    pub fn is_backpressured(&self) -> bool {
+        self.max_block_lag
+            .is_some_and(|max_block_lag| self.block_lag() > max_block_lag)
+    }
+
+    /// Counts a failed batch submission. Returns whether it made the sequencer
+    /// read-only.
+    pub fn record_submission_failure(&self) -> bool {
+        let failures = self.submission_failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let Some(max_failures) = self.read_only_after_failures else {
+            return false;
+        };
+        if failures < max_failures || self.read_only.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+
+        warn!(failures, "Read-only: batch submissions keep failing");
+        READ_ONLY.set(1);
+
+        true
+    }
+
+    /// Resets the failure streak, which lifts the read-only mode
+    pub fn record_submission_success(&self) {
+        self.submission_failures.store(0, Ordering::Relaxed);
+
+        if self.read_only.swap(false, Ordering::Relaxed) {
+            info!("Read-only lifted: a batch submission succeeded");
+            READ_ONLY.set(0);
+        }
+    }
+
+    /// Whether new insertions should be rejected until batch submissions
+    /// recover
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Pauses or resumes insertions. Returns whether the state changed.
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let was_paused = self.paused.swap(paused, Ordering::Relaxed);
+        PAUSED.set(i64::from(paused));
+
+        if paused && !was_paused {
+            warn!("Insertions paused");
+        } else if !paused && was_paused {
+            info!("Insertions resumed");
+        }
+
+        paused != was_paused
+    }
+
+    /// Whether new insertions are rejected and the insert queue is held, for
+    /// maintenance
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Records whether the identity manager contract is paused. Returns
+    /// whether the state changed.
+    pub fn set_contract_paused(&self, paused: bool) -> bool {
+        let was_paused = self.contract_paused.swap(paused, Ordering::Relaxed);
+        CONTRACT_PAUSED.set(i64::from(paused));
+
+        if paused && !was_paused {
+            warn!("Identity manager contract paused");
+        } else if !paused && was_paused {
+            info!("Identity manager contract unpaused");
+        }
+
+        paused != was_paused
+    }
+
+    /// Whether new insertions are rejected because they would revert on chain
+    pub fn is_contract_paused(&self) -> bool {
+        self.contract_paused.load(Ordering::Relaxed)
+    }
+
+    /// Tracks the chain head reported by the provider. Returns whether the
+    /// chain head just became stale.
+    pub fn set_chain_head(&self, head: u64) -> bool {
+        let age = {
+            let mut chain_head = self.chain_head.lock().unwrap();
+            match *chain_head {
+                Some((last_head, advanced_at)) if head <= last_head => advanced_at.elapsed(),
+                _ => {
+                    *chain_head = Some((head, Instant::now()));
+                    Duration::ZERO
+                }
+            }
+        };
+        CHAIN_HEAD_AGE.set(age.as_secs().try_into().unwrap_or(i64::MAX));
+
+        let stale = self.max_chain_head_age.is_some_and(|max_age| age > max_age);
+
+        let was_stale = self.stale_chain_head.swap(stale, Ordering::Relaxed);
+        STALE_CHAIN_HEAD.set(i64::from(stale));
+
+        if stale && !was_stale {
+            warn!(head, ?age, "Chain head stopped advancing");
+        } else if !stale && was_stale {
+            info!(head, "Chain head advances again");
+        }
+
+        stale && !was_stale
+    }
+
+    /// Whether the provider seems stuck, i.e. hasn't reported a new block for
+    /// longer than the configured maximum
+    pub fn is_chain_head_stale(&self) -> bool {
+        self.stale_chain_head.load(Ordering::Relaxed)
+    }
+
+    /// Records the outcome of a reconciliation. Returns whether the contract
+    /// root just diverged from the local tree.
+    pub fn record_reconciliation(&self, reconciliation: Reconciliation) -> bool {
+        let diverged = reconciliation.status == ReconciliationStatus::Diverged;
+        ROOT_DIVERGED.set(i64::from(diverged));
+
+        let previous = self
+            .last_reconciliation
+            .lock()
+            .unwrap()
+            .replace(reconciliation);
+        let was_diverged =
+            previous.is_some_and(|previous| previous.status == ReconciliationStatus::Diverged);
+
+        diverged && !was_diverged
+    }
+
+    pub fn last_reconciliation(&self) -> Option<Reconciliation> {
+        self.last_reconciliation.lock().unwrap().clone()
+    }
+
+    pub fn report(&self) -> HealthReport {
+        HealthReport {
+            block_lag:            self.block_lag(),
+            backpressure:         self.is_backpressured(),
+            insufficient_balance: self.is_balance_insufficient(),
+            read_only:            self.is_read_only(),
+            paused:               self.is_paused(),
+            contract_paused:      self.is_contract_paused(),
+            stale_chain_head:     self.is_chain_head_stale(),
+            last_reconciliation:  self.last_reconciliation(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(max_block_lag: Option<u64>) -> Health {
+        Health {
+            max_block_lag,
+            block_lag: AtomicU64::new(0),
+            min_relayer_balance_gwei: None,
+            insufficient_balance: AtomicBool::new(false),
+            read_only_after_failures: None,
+            submission_failures: AtomicU32::new(0),
+            read_only: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            contract_paused: AtomicBool::new(false),
+            max_chain_head_age: None,
+            chain_head: Mutex::new(None),
+            stale_chain_head: AtomicBool::new(false),
+            applied_block: Mutex::new(None),
+            last_reconciliation: Mutex::new(None),
+        }
+    }
+
+    #[test]
+    fn backpressure_above_max_block_lag() {
+        let health = health(Some(10));
+
+        health.set_block_lag(10);
+        assert!(!health.is_backpressured());
+
+        health.set_block_lag(11);
+        assert!(health.is_backpressured());
+
+        health.set_block_lag(0);
+        assert!(!health.is_backpressured());
+    }
+
+    #[test]
+    fn no_backpressure_without_max_block_lag() {
+        let health = health(None);
+
+        health.set_block_lag(u64::MAX);
+        assert!(!health.is_backpressured());
+    }
+
+    #[test]
+    fn paused_below_min_relayer_balance() {
+        let health = Health {
+            min_relayer_balance_gwei: Some(10),
+            ..health(None)
+        };
+
+        health.set_relayer_balance(U256::from(10 * GWEI));
+        assert!(!health.is_balance_insufficient());
+
+        health.set_relayer_balance(U256::from(10 * GWEI - 1));
+        assert!(health.is_balance_insufficient());
+
+        health.set_relayer_balance(U256::from(11 * GWEI));
+        assert!(!health.is_balance_insufficient());
+    }
+
+    #[test]
+    fn read_only_after_failure_streak() {
+        let health = Health {
+            read_only_after_failures: Some(2),
+            ..health(None)
+        };
+
+        assert!(!health.record_submission_failure());
+        health.record_submission_success();
+        assert!(!health.record_submission_failure());
+        assert!(!health.is_read_only());
+
+        assert!(health.record_submission_failure());
+        assert!(!health.record_submission_failure());
+        assert!(health.is_read_only());
+
+        health.record_submission_success();
+        assert!(!health.is_read_only());
+    }
+
+    #[test]
+    fn pausing_reports_state_changes() {
+        let health = health(None);
+
+        assert!(health.set_paused(true));
+        assert!(!health.set_paused(true));
+        assert!(health.report().paused);
+
+        assert!(health.set_paused(false));
+        assert!(!health.is_paused());
+    }
+
+    #[test]
+    fn contract_pause_is_reported_separately() {
+        let health = health(None);
+
+        assert!(health.set_contract_paused(true));
+        assert!(!health.set_contract_paused(true));
+        assert!(health.report().contract_paused);
+        assert!(!health.report().paused);
+
+        assert!(health.set_contract_paused(false));
+        assert!(!health.is_contract_paused());
+    }
+
+    #[test]
+    fn chain_head_stale_until_it_advances() {
+        let health = Health {
+            max_chain_head_age: Some(Duration::ZERO),
+            ..health(None)
+        };
+
+        assert!(!health.set_chain_head(10));
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert!(health.set_chain_head(10));
+        assert!(!health.set_chain_head(10));
+        assert!(health.is_chain_head_stale());
+
+        assert!(!health.set_chain_head(11));
+        assert!(!health.is_chain_head_stale());
+    }
+
+    #[test]
+    fn divergence_is_reported_once() {
+        let health = health(None);
+        let reconciliation = |status| Reconciliation {
+            block: 1,
+            contract_root: Hash::from(1),
+            mined_root: Hash::ZERO,
+            status,
+            reconciled_at: Utc::now(),
+        };
+
+        assert!(!health.record_reconciliation(reconciliation(ReconciliationStatus::Known)));
+        assert!(health.record_reconciliation(reconciliation(ReconciliationStatus::Diverged)));
+        assert!(!health.record_reconciliation(reconciliation(ReconciliationStatus::Diverged)));
+
+        assert!(!health.record_reconciliation(reconciliation(ReconciliationStatus::InSync)));
+        assert_eq!(
+            health.report().last_reconciliation.map(|last| last.status),
+            Some(ReconciliationStatus::InSync)
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_with_registry, IntCounter};
+
+use crate::metrics;
+
+static DEDUP_HITS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        "insert_dedup_hits",
+        "Number of insertions answered with the status of the commitment queued recently.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Tells client retries apart from conflicting insertions, so that a client
+/// retrying an insertion gets the status of the original request instead of a
+/// conflict. The commitment is only ever queued once, by the database.
+pub struct InsertDedup {
+    window: Option<Duration>,
+}
+
+impl InsertDedup {
+    /// `window` is how long after a commitment was queued inserting it again
+    /// is taken for a client retry. Without one, every repeated insertion is a
+    /// conflict.
+    pub fn new(window: Option<Duration>) -> Self {
+        Self { window }
+    }
+
+    /// Whether inserting a commitment queued at `queued_at` again counts as
+    /// a retry of that insertion
+    pub fn is_retry(&self, queued_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let Some(window) = self.window else {
+            return false;
+        };
+
+        // Clocks of the database and the sequencer may disagree slightly
+        let retry = (now - queued_at)
+            .to_std()
+            .map_or(true, |queued_for| queued_for < window);
+
+        if retry {
+            DEDUP_HITS.inc();
+        }
+
+        retry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_are_told_apart_by_when_the_commitment_was_queued() {
+        let dedup = InsertDedup::new(Some(Duration::from_secs(60)));
+        let now = Utc::now();
+
+        assert!(dedup.is_retry(now - chrono::Duration::seconds(30), now));
+        // Queued a whole window ago, so it's a conflicting insertion
+        assert!(!dedup.is_retry(now - chrono::Duration::seconds(60), now));
+        assert!(!dedup.is_retry(now - chrono::Duration::days(1), now));
+    }
+
+    #[test]
+    fn database_clocks_ahead_of_the_sequencer_still_make_retries() {
+        let dedup = InsertDedup::new(Some(Duration::from_secs(60)));
+        let now = Utc::now();
+
+        assert!(dedup.is_retry(now + chrono::Duration::seconds(1), now));
+    }
+
+    #[test]
+    fn repeated_insertions_conflict_without_a_window() {
+        let dedup = InsertDedup::new(None);
+        let now = Utc::now();
+
+        // Even one queued just now, or by a database clock ahead
+        assert!(!dedup.is_retry(now, now));
+        assert!(!dedup.is_retry(now + chrono::Duration::seconds(1), now));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::identity_tree::Hash;
+
+#[derive(Debug, Default)]
+struct Lists {
+    allowed: Option<HashSet<Hash>>,
+    denied:  HashSet<Hash>,
+}
+
+/// Restricts which commitments can be inserted, based on an optional allowlist
+/// and denylist file. Both files contain one commitment per line, blank lines
+/// and lines starting with `#` are ignored.
+#[derive(Debug)]
+pub struct CommitmentFilter {
+    allowlist: Option<PathBuf>,
+    denylist:  Option<PathBuf>,
+    lists:     RwLock<Lists>,
+}
+
+impl CommitmentFilter {
+    pub fn new(allowlist: Option<PathBuf>, denylist: Option<PathBuf>) -> anyhow::Result<Self> {
+        let filter = Self {
+            allowlist,
+            denylist,
+            lists: RwLock::new(Lists::default()),
+        };
+        filter.reload()?;
+
+        Ok(filter)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.allowlist.is_some() || self.denylist.is_some()
+    }
+
+    /// Reads the files again. The current lists are kept if either file can't
+    /// be read.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let allowed = self.allowlist.as_deref().map(read_list).transpose()?;
+        let denied = self
+            .denylist
+            .as_deref()
+            .map(read_list)
+            .transpose()?
+            .unwrap_or_default();
+
+        if self.is_enabled() {
+            info!(
+                allowed = allowed.as_ref().map(HashSet::len),
+                denied = denied.len(),
+                "Loaded commitment filter"
+            );
+        }
+
+        *self.lists.write().unwrap() = Lists { allowed, denied };
+
+        Ok(())
+    }
+
+    /// Whether the commitment may be inserted
+    pub fn is_allowed(&self, commitment: &Hash) -> bool {
+        let lists = self.lists.read().unwrap();
+
+        if lists.denied.contains(commitment) {
+            return false;
+        }
+
+        lists
+            .allowed
+            .as_ref()
+            .map_or(true, |allowed| allowed.contains(commitment))
+    }
+}
+
+fn read_list(path: &Path) -> anyhow::Result<HashSet<Hash>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read commitment list {}", path.display()))?;
+
+    parse_list(&contents).with_context(|| format!("Invalid commitment list {}", path.display()))
+}
+
+fn parse_list(contents: &str) -> anyhow::Result<HashSet<Hash>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Hash::from_str(line).with_context(|| format!("Invalid commitment {line}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn list_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn lists_skip_comments_and_blank_lines() {
+        let list = parse_list("# blocked\n0x01\n\n  2  \n").unwrap();
+
+        assert_eq!(list, HashSet::from([Hash::from(1), Hash::from(2)]));
+    }
+
+    #[test]
+    fn denied_commitments_are_blocked() {
+        let denylist = list_file("0x01\n");
+        let filter = CommitmentFilter::new(None, Some(denylist.path().to_owned())).unwrap();
+
+        assert!(!filter.is_allowed(&Hash::from(1)));
+        assert!(filter.is_allowed(&Hash::from(2)));
+    }
+
+    #[test]
+    fn only_allowed_commitments_pass() {
+        let allowlist = list_file("0x01\n0x02\n");
+        let denylist = list_file("0x02\n");
+        let filter = CommitmentFilter::new(
+            Some(allowlist.path().to_owned()),
+            Some(denylist.path().to_owned()),
+        )
+        .unwrap();
+
+        assert!(filter.is_allowed(&Hash::from(1)));
+        assert!(!filter.is_allowed(&Hash::from(2)));
+        assert!(!filter.is_allowed(&Hash::from(3)));
+    }
+
+    #[test]
+    fn reloading_picks_up_changes() {
+        let mut denylist = list_file("0x01\n");
+        let filter = CommitmentFilter::new(None, Some(denylist.path().to_owned())).unwrap();
+
+        denylist.write_all(b"0x02\n").unwrap();
+        filter.reload().unwrap();
+
+        assert!(!filter.is_allowed(&Hash::from(2)));
+    }
+
+    #[test]
+    fn everything_is_allowed_without_lists() {
+        let filter = CommitmentFilter::new(None, None).unwrap();
+
+        assert!(!filter.is_enabled());
+        assert!(filter.is_allowed(&Hash::from(1)));
+    }
+}
@@ -1 +1,3 @@
+pub mod dedup;
+pub mod filter;
 pub mod validator;
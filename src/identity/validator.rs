@@ -30,3 +30,21 @@ impl Default for IdentityValidator {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_canonical_field_elements_are_reduced() {
+        let validator = IdentityValidator::new();
+
+        assert!(validator.identity_is_reduced(Hash::ZERO));
+        assert!(validator.identity_is_reduced(Hash::from(MODULUS - Field::from(1))));
+
+        // Would be the same elements as 0 and 1 if reduced
+        assert!(!validator.identity_is_reduced(Hash::from(MODULUS)));
+        assert!(!validator.identity_is_reduced(Hash::from(MODULUS + Field::from(1))));
+        assert!(!validator.identity_is_reduced(Hash::MAX));
+    }
+}
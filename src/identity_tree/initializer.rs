@@ -1,19 +1,96 @@
 use std::sync::Arc;
 use std::time::Instant;
 
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec_with_registry, IntCounterVec};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use semaphore::poseidon_tree::LazyPoseidonTree;
-use tracing::{info, instrument, warn};
+use tracing::{error, info, instrument, warn};
 
-use crate::config::TreeConfig;
+use crate::config::{LeafConflictPolicy, TreeConfig};
 use crate::contracts::IdentityManager;
 use crate::database::query::DatabaseQuery;
 use crate::database::Database;
 use crate::identity_tree::{
-    CanonicalTreeBuilder, Hash, ProcessedStatus, TreeState, TreeUpdate, TreeVersionReadOps,
-    TreeWithNextVersion,
+    CanonicalTreeBuilder, DerivedTreeBuilder, Hash, LeafConflict, ProcessedStatus, TreeState,
+    TreeUpdate, TreeVersionReadOps, TreeWithNextVersion, Version,
 };
+use crate::metrics;
 use crate::utils::tree_updates::dedup_tree_updates;
 
+static LEAF_CONFLICTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "tree_leaf_conflicts",
+        "Replayed commitments that conflicted with the tree, by the policy applied.",
+        &["policy"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Decides what to do with a conflicting update. `Ok` means the update should
+/// overwrite the leaf, otherwise the conflict is returned.
+fn resolve_conflict(
+    policy: LeafConflictPolicy,
+    conflict: LeafConflict,
+) -> Result<(), LeafConflict> {
+    let label = match policy {
+        LeafConflictPolicy::Halt => "halt",
+        LeafConflictPolicy::Overwrite => "overwrite",
+        LeafConflictPolicy::RollbackAndResync => "rollback_and_resync",
+    };
+    LEAF_CONFLICTS.with_label_values(&[label]).inc();
+
+    let LeafConflict {
+        leaf_index,
+        existing,
+        element,
+    } = &conflict;
+
+    match policy {
+        LeafConflictPolicy::Halt => {
+            error!(leaf_index, ?existing, ?element, "Leaf conflict, halting");
+            Err(conflict)
+        }
+        LeafConflictPolicy::Overwrite => {
+            warn!(
+                leaf_index,
+                ?existing,
+                ?element,
+                "Leaf conflict, overwriting the leaf"
+            );
+            Ok(())
+        }
+        LeafConflictPolicy::RollbackAndResync => {
+            warn!(
+                leaf_index,
+                ?existing,
+                ?element,
+                "Leaf conflict, rolling back and resyncing"
+            );
+            Err(conflict)
+        }
+    }
+}
+
+/// Replays `updates` on top of `builder`, overwriting conflicting leaves if
+/// `policy` allows it. Returns the first conflict that isn't resolved, after
+/// which the builder is only partially replayed.
+fn replay<P: Version>(
+    builder: &mut DerivedTreeBuilder<P>,
+    updates: Vec<TreeUpdate>,
+    policy: LeafConflictPolicy,
+) -> Result<(), LeafConflict> {
+    for update in updates {
+        if let Err(conflict) = builder.apply(&update) {
+            resolve_conflict(policy, conflict)?;
+            builder.update(&update);
+        }
+    }
+
+    Ok(())
+}
+
 pub struct TreeInitializer {
     pub database:         Arc<Database>,
     pub identity_manager: Arc<IdentityManager>,
@@ -107,11 +184,48 @@ impl TreeInitializer {
         info!("Initializing tree from the database");
         let tree_state = self.initialize_tree(mined_items).await?;
 
+        self.validate_leaf_count(&tree_state).await?;
+
         info!("tree initialization successful");
 
         Ok(tree_state)
     }
 
+    /// The threads the dense part of the tree is hashed on. The resulting tree
+    /// doesn't depend on the number of threads.
+    fn loading_pool(&self) -> anyhow::Result<ThreadPool> {
+        let mut builder =
+            ThreadPoolBuilder::new().thread_name(|index| format!("tree-loading-{index}"));
+
+        if let Some(threads) = self.config.loading_threads {
+            builder = builder.num_threads(threads);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Fails if the tree doesn't have as many leaves as the contract, which
+    /// means insertions are missing from the database. Skipped if the contract
+    /// doesn't expose its leaf count.
+    async fn validate_leaf_count(&self, tree_state: &TreeState) -> anyhow::Result<()> {
+        let Some(contract_leaf_count) = self.identity_manager.leaf_count().await? else {
+            info!("Contract doesn't expose its leaf count, skipping validation");
+            return Ok(());
+        };
+
+        let tree_size = tree_state.processed_tree().next_leaf();
+        if tree_size != contract_leaf_count {
+            anyhow::bail!(
+                "Tree has {tree_size} leaves but the contract has {contract_leaf_count}, \
+                 insertions are missing from the database"
+            );
+        }
+
+        info!(tree_size, "Tree size matches the contract");
+
+        Ok(())
+    }
+
     pub fn get_leftover_leaves_and_update_index(
         index: &mut Option<usize>,
         dense_prefix_depth: usize,
@@ -158,15 +272,29 @@ impl TreeInitializer {
             &mined_items,
         );
 
-        let Some(mined_builder) = CanonicalTreeBuilder::restore(
-            self.identity_manager.tree_depth(),
-            self.config.dense_tree_prefix_depth,
-            &self.identity_manager.initial_leaf_value(),
-            last_mined_index_in_dense,
-            &leftover_items,
-            self.config.tree_gc_threshold,
-            &self.config.cache_file,
-        ) else {
+        let pool = self.loading_pool()?;
+        let tree_depth = self.identity_manager.tree_depth();
+        let dense_tree_prefix_depth = self.config.dense_tree_prefix_depth;
+        let initial_leaf_value = self.identity_manager.initial_leaf_value();
+        let tree_gc_threshold = self.config.tree_gc_threshold;
+        let cache_file = self.config.cache_file.clone();
+
+        let mined_builder = tokio::task::spawn_blocking(move || {
+            pool.install(|| {
+                CanonicalTreeBuilder::restore(
+                    tree_depth,
+                    dense_tree_prefix_depth,
+                    &initial_leaf_value,
+                    last_mined_index_in_dense,
+                    &leftover_items,
+                    tree_gc_threshold,
+                    &cache_file,
+                )
+            })
+        })
+        .await?;
+
+        let Some(mined_builder) = mined_builder else {
             return Ok(None);
         };
 
@@ -194,8 +322,15 @@ impl TreeInitializer {
             .get_commitments_by_status(ProcessedStatus::Processed)
             .await?;
 
-        for processed_item in processed_items {
-            processed_builder.update(&processed_item);
+        // Rolling back means falling back to rebuilding the tree from the
+        // database, which overwrites the cache
+        let policy = self.config.leaf_conflict_policy;
+        let rolls_back = policy == LeafConflictPolicy::RollbackAndResync;
+
+        match replay(&mut processed_builder, processed_items, policy) {
+            Ok(()) => {}
+            Err(_) if rolls_back => return Ok(None),
+            Err(conflict) => return Err(conflict.into()),
         }
 
         let (processed, batching_builder) = processed_builder.seal_and_continue();
@@ -205,8 +340,10 @@ impl TreeInitializer {
             .database
             .get_commitments_by_status(ProcessedStatus::Pending)
             .await?;
-        for update in pending_items {
-            latest_builder.update(&update);
+        match replay(&mut latest_builder, pending_items, policy) {
+            Ok(()) => {}
+            Err(_) if rolls_back => return Ok(None),
+            Err(conflict) => return Err(conflict.into()),
         }
         let latest = latest_builder.seal();
 
@@ -243,20 +380,23 @@ impl TreeInitializer {
         };
 
         info!("Creating mined tree");
+        let pool = self.loading_pool()?;
         let tree_depth = self.identity_manager.tree_depth();
         let dense_tree_prefix_depth = self.config.dense_tree_prefix_depth;
         let tree_gc_threshold = self.config.tree_gc_threshold;
         let cache_file = self.config.cache_file.clone();
 
         let mined_builder = tokio::task::spawn_blocking(move || {
-            CanonicalTreeBuilder::new(
-                tree_depth,
-                dense_tree_prefix_depth,
-                tree_gc_threshold,
-                initial_leaf_value,
-                &initial_leaves,
-                &cache_file,
-            )
+            pool.install(|| {
+                CanonicalTreeBuilder::new(
+                    tree_depth,
+                    dense_tree_prefix_depth,
+                    tree_gc_threshold,
+                    initial_leaf_value,
+                    &initial_leaves,
+                    &cache_file,
+                )
+            })
         })
         .await?;
 
@@ -267,15 +407,20 @@ impl TreeInitializer {
             .get_commitments_by_status(ProcessedStatus::Processed)
             .await?;
 
+        // The tree is already built from the database here, so there's
+        // nothing left to roll back to
+        let policy = match self.config.leaf_conflict_policy {
+            LeafConflictPolicy::RollbackAndResync => LeafConflictPolicy::Halt,
+            policy => policy,
+        };
+
         info!("Updating processed tree");
         let processed_builder = tokio::task::spawn_blocking(move || {
-            for processed_item in processed_items {
-                processed_builder.update(&processed_item);
-            }
+            replay(&mut processed_builder, processed_items, policy)?;
 
-            processed_builder
+            anyhow::Ok(processed_builder)
         })
-        .await?;
+        .await??;
 
         let (processed, batching_builder) = processed_builder.seal_and_continue();
         let (batching, mut latest_builder) = batching_builder.seal_and_continue();
@@ -287,13 +432,11 @@ impl TreeInitializer {
 
         info!("Updating latest tree");
         let latest_builder = tokio::task::spawn_blocking(move || {
-            for update in pending_items {
-                latest_builder.update(&update);
-            }
+            replay(&mut latest_builder, pending_items, policy)?;
 
-            latest_builder
+            anyhow::Ok(latest_builder)
         })
-        .await?;
+        .await??;
 
         let latest = latest_builder.seal();
 
@@ -315,8 +458,27 @@ mod test {
     use ethers::types::U256;
     use ruint::Uint;
 
-    use crate::identity_tree::initializer::TreeInitializer;
-    use crate::identity_tree::TreeUpdate;
+    use crate::config::LeafConflictPolicy;
+    use crate::identity_tree::initializer::{replay, resolve_conflict, TreeInitializer};
+    use crate::identity_tree::{
+        CanonicalTreeBuilder, Hash, LeafConflict, TreeUpdate, TreeVersionReadOps,
+        TreeWithNextVersion,
+    };
+
+    fn mined_builder(leaves: &[u64], cache_file: &std::path::Path) -> CanonicalTreeBuilder {
+        let leaves: Vec<Hash> = leaves.iter().copied().map(Hash::from).collect();
+
+        CanonicalTreeBuilder::new(10, 10, 0, Hash::ZERO, &leaves, cache_file.to_str().unwrap())
+    }
+
+    /// A processed commitment at leaf 1 replacing 2 with 5, e.g. after a
+    /// reorg, and a new one at leaf 3
+    fn replayed() -> Vec<TreeUpdate> {
+        vec![
+            TreeUpdate::new(1, Hash::from(5)),
+            TreeUpdate::new(3, Hash::from(4)),
+        ]
+    }
 
     pub fn generate_test_identities_with_index(identity_count: usize) -> Vec<TreeUpdate> {
         let mut identities = vec![];
@@ -403,4 +565,65 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn only_overwrite_resolves_conflicts() {
+        let conflict = || LeafConflict {
+            leaf_index: 3,
+            existing:   Uint::from(1),
+            element:    Uint::from(2),
+        };
+
+        assert!(resolve_conflict(LeafConflictPolicy::Halt, conflict()).is_err());
+        assert!(resolve_conflict(LeafConflictPolicy::Overwrite, conflict()).is_ok());
+        assert!(resolve_conflict(LeafConflictPolicy::RollbackAndResync, conflict()).is_err());
+    }
+
+    #[test]
+    fn overwriting_trusts_the_replayed_commitments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (mined, mut processed_builder) =
+            mined_builder(&[1, 2, 3], &temp_dir.path().join("mined")).seal();
+        replay(
+            &mut processed_builder,
+            replayed(),
+            LeafConflictPolicy::Overwrite,
+        )
+        .unwrap();
+        let processed = processed_builder.seal();
+
+        let (expected, _) = mined_builder(&[1, 5, 3, 4], &temp_dir.path().join("expected")).seal();
+        assert_eq!(processed.get_root(), expected.get_root());
+        assert_eq!(processed.get_leaf(1), Hash::from(5));
+
+        // The mined tree only changes once the processed one is mined
+        assert_eq!(mined.get_leaf(1), Hash::from(2));
+        assert_eq!(mined.peek_next_updates(10).len(), 2);
+    }
+
+    #[test]
+    fn rolling_back_leaves_the_rebuild_to_the_caller() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        for policy in [
+            LeafConflictPolicy::Halt,
+            LeafConflictPolicy::RollbackAndResync,
+        ] {
+            let (mined, mut processed_builder) =
+                mined_builder(&[1, 2, 3], &temp_dir.path().join("mined")).seal();
+            let mined_root = mined.get_root();
+
+            let conflict = replay(&mut processed_builder, replayed(), policy).unwrap_err();
+            assert_eq!(conflict.leaf_index, 1);
+            assert_eq!(conflict.existing, Hash::from(2));
+            assert_eq!(conflict.element, Hash::from(5));
+
+            // Nothing past the conflict is replayed, and the mined tree is
+            // left as it was
+            assert_eq!(processed_builder.seal().get_leaf(3), Hash::ZERO);
+            assert_eq!(mined.get_root(), mined_root);
+            assert!(mined.peek_next_updates(10).is_empty());
+        }
+    }
 }
@@ -8,14 +8,18 @@ use semaphore::poseidon_tree::{PoseidonHash, Proof};
 use semaphore::{lazy_merkle_tree, Field};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use thiserror::Error;
 use tracing::{info, warn};
 
 pub mod initializer;
+pub mod proof_cache;
+pub mod recent_roots;
 mod status;
 
 pub type PoseidonTree<Version> = LazyMerkleTree<PoseidonHash, Version>;
 pub type Hash = <PoseidonHash as Hasher>::Hash;
 
+use self::proof_cache::ProofCache;
 pub use self::status::{ProcessedStatus, Status, UnknownStatus, UnprocessedStatus};
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, FromRow)]
@@ -35,6 +39,26 @@ impl TreeUpdate {
     }
 }
 
+/// A replayed update would overwrite a different commitment that's already in
+/// the tree.
+#[derive(Debug, Error)]
+#[error("Conflicting commitment at leaf {leaf_index}: tree has {existing}, update has {element}")]
+pub struct LeafConflict {
+    pub leaf_index: usize,
+    pub existing:   Hash,
+    pub element:    Hash,
+}
+
+/// A leaf as it was applied to a tree version, along with the root right after
+/// it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafUpdate {
+    pub leaf_index: usize,
+    pub commitment: Hash,
+    pub root:       Hash,
+}
+
 #[derive(Debug)]
 pub struct TreeItem {
     pub status:     ProcessedStatus,
@@ -139,6 +163,31 @@ where
         (self.tree.root(), proof)
     }
 
+    /// Applies an update replayed from storage, e.g. on startup.
+    ///
+    /// Updates that are already reflected in the tree are skipped without
+    /// recording a diff. Insertions at an index that's already taken by a
+    /// different commitment are rejected.
+    fn apply_replayed(&mut self, update: &TreeUpdate) -> Result<(), LeafConflict> {
+        let existing = self.get_leaf(update.leaf_index);
+
+        if existing == update.element {
+            return Ok(());
+        }
+
+        if update.leaf_index < self.next_leaf && update.element != Hash::ZERO {
+            return Err(LeafConflict {
+                leaf_index: update.leaf_index,
+                existing,
+                element: update.element,
+            });
+        }
+
+        self.update(update.leaf_index, update.element);
+
+        Ok(())
+    }
+
     /// Returns _up to_ `maximum_update_count` contiguous deletion or insertion
     /// updates that are to be applied to the tree.
     fn peek_next_updates(&self, maximum_update_count: usize) -> Vec<AppliedTreeUpdate> {
@@ -173,12 +222,12 @@ where
             .collect()
     }
 
-    fn apply_updates_up_to(&mut self, root: Hash) -> usize {
+    fn apply_updates_up_to(&mut self, root: Hash) -> Vec<LeafUpdate> {
         let Some(next) = self.next.clone() else {
-            return 0;
+            return vec![];
         };
 
-        let num_updates;
+        let leaf_updates;
         {
             // Acquire the exclusive write lock on the next version.
             let mut next = next.get_data();
@@ -191,19 +240,26 @@ where
 
             let Some(index_of_root) = index_of_root else {
                 warn!(?root, "Root not found in the diff");
-                return 0;
+                return vec![];
             };
 
             let applied_updates: Vec<_> = next.metadata.diff.drain(..=index_of_root).collect();
 
-            num_updates = applied_updates.len();
+            leaf_updates = applied_updates
+                .iter()
+                .map(|applied| LeafUpdate {
+                    leaf_index: applied.update.leaf_index,
+                    commitment: applied.update.element,
+                    root:       applied.result.root(),
+                })
+                .collect();
 
             self.apply_diffs(applied_updates);
         }
 
         self.garbage_collect();
 
-        num_updates
+        leaf_updates
     }
 }
 
@@ -376,6 +432,8 @@ pub trait TreeVersionReadOps {
     fn next_leaf(&self) -> usize;
     /// Returns the given leaf value, the root of the tree and the proof
     fn get_leaf_and_proof(&self, leaf: usize) -> (Hash, Hash, Proof);
+    /// Returns the given leaf value and the root of the tree it's in
+    fn get_leaf_and_root(&self, leaf: usize) -> (Hash, Hash);
     /// Returns the merkle proof and element at the given leaf.
     fn get_proof(&self, leaf: usize) -> (Hash, Proof);
     /// Gets the leaf value at a given index.
@@ -403,6 +461,12 @@ where
         (leaf, root, proof)
     }
 
+    fn get_leaf_and_root(&self, leaf: usize) -> (Hash, Hash) {
+        let tree = self.get_data();
+
+        (tree.get_leaf(leaf), tree.get_root())
+    }
+
     fn get_proof(&self, leaf: usize) -> (Hash, Proof) {
         let tree = self.get_data();
         tree.get_proof(leaf)
@@ -442,6 +506,32 @@ impl TreeVersion<Latest> {
         output
     }
 
+    /// Like [`append_many`](Self::append_many), but on a copy of the tree, so
+    /// that the tree itself is left untouched. Returns the root, proof of
+    /// inclusion and leaf index of the last identity, or `None` if there are no
+    /// identities or the next leaf is no longer `next_leaf`.
+    #[must_use]
+    pub fn project_append(
+        &self,
+        next_leaf: usize,
+        identities: &[Hash],
+    ) -> Option<(Hash, Proof, usize)> {
+        let data = self.get_data();
+        if data.next_leaf != next_leaf || identities.is_empty() {
+            return None;
+        }
+
+        let mut tree = data.tree.clone();
+        drop(data);
+
+        for (idx, identity) in identities.iter().enumerate() {
+            tree = tree.update(next_leaf + idx, identity);
+        }
+
+        let leaf_index = next_leaf + identities.len() - 1;
+        Some((tree.root(), tree.proof(leaf_index), leaf_index))
+    }
+
     /// Deletes many identities from the tree, returns a list with the root
     /// and proof of inclusion
     #[must_use]
@@ -483,6 +573,10 @@ where
 pub trait TreeWithNextVersion {
     fn peek_next_updates(&self, maximum_update_count: usize) -> Vec<AppliedTreeUpdate>;
     fn apply_updates_up_to(&self, root: Hash) -> usize;
+
+    /// Like [`apply_updates_up_to`](Self::apply_updates_up_to), but returns
+    /// the applied leaves in order.
+    fn apply_leaf_updates_up_to(&self, root: Hash) -> Vec<LeafUpdate>;
 }
 
 impl<V> TreeWithNextVersion for TreeVersion<V>
@@ -495,6 +589,10 @@ where
     }
 
     fn apply_updates_up_to(&self, root: Hash) -> usize {
+        self.get_data().apply_updates_up_to(root).len()
+    }
+
+    fn apply_leaf_updates_up_to(&self, root: Hash) -> Vec<LeafUpdate> {
         self.get_data().apply_updates_up_to(root)
     }
 }
@@ -559,12 +657,66 @@ impl TreeState {
         &self.batching
     }
 
+    /// Whether the trees can be rolled back to `root`, i.e. it's the processed
+    /// root or in the batching tree
+    #[must_use]
+    pub fn can_roll_back_to(&self, root: Hash) -> bool {
+        self.processed.get_root() == root
+            || self
+                .batching
+                .get_data()
+                .metadata
+                .diff
+                .iter()
+                .any(|update| update.result.root() == root)
+    }
+
+    /// Discards the batching and latest updates past `root`, e.g. once the
+    /// batch following it is given up on. The latest tree continues from
+    /// `root` at `next_leaf`. Returns `false`, leaving the trees untouched, if
+    /// `root` isn't the processed root or in the batching tree.
+    pub fn roll_back_to(&self, root: Hash, next_leaf: usize) -> bool {
+        let processed = self.processed.get_data();
+        let mut batching = self.batching.get_data();
+        let mut latest = self.latest.get_data();
+
+        if processed.get_root() == root {
+            batching.metadata.diff.clear();
+            batching.tree = processed.tree.clone();
+        } else {
+            let Some(index_of_root) = batching
+                .metadata
+                .diff
+                .iter()
+                .position(|update| update.result.root() == root)
+            else {
+                warn!(?root, "Root not found in the batching tree");
+                return false;
+            };
+
+            batching.metadata.diff.truncate(index_of_root + 1);
+            batching.tree = batching.metadata.diff[index_of_root].result.clone();
+        }
+        batching.next_leaf = next_leaf;
+
+        latest.metadata.diff.clear();
+        latest.tree = batching.tree.clone();
+        latest.next_leaf = next_leaf;
+
+        true
+    }
+
+    /// Proofs of mined leaves are looked up in `proof_cache` first
     #[must_use]
-    pub fn get_proof_for(&self, item: &TreeItem) -> (Field, InclusionProof) {
+    pub fn get_proof_for(
+        &self,
+        item: &TreeItem,
+        proof_cache: &ProofCache,
+    ) -> (Field, InclusionProof) {
         let (leaf, root, proof) = match item.status {
             ProcessedStatus::Pending => self.latest.get_leaf_and_proof(item.leaf_index),
             ProcessedStatus::Processed => self.processed.get_leaf_and_proof(item.leaf_index),
-            ProcessedStatus::Mined => self.mined.get_leaf_and_proof(item.leaf_index),
+            ProcessedStatus::Mined => proof_cache.get_leaf_and_proof(&self.mined, item.leaf_index),
         };
 
         let proof = InclusionProof {
@@ -684,6 +836,12 @@ impl CanonicalTreeBuilder {
         self.0.update(update.leaf_index, update.element);
     }
 
+    /// Replays an update, skipping it if it's already applied. See
+    /// [`TreeVersionData::apply_replayed`].
+    pub fn apply(&mut self, update: &TreeUpdate) -> Result<(), LeafConflict> {
+        self.0.apply_replayed(update)
+    }
+
     /// Seals this version and returns a builder for the next version.
     #[must_use]
     pub fn seal(self) -> (TreeVersion<Canonical>, DerivedTreeBuilder<Canonical>) {
@@ -726,6 +884,12 @@ impl<P: Version> DerivedTreeBuilder<P> {
         self.current.update(update.leaf_index, update.element);
     }
 
+    /// Replays an update, skipping it if it's already applied. See
+    /// [`TreeVersionData::apply_replayed`].
+    pub fn apply(&mut self, update: &TreeUpdate) -> Result<(), LeafConflict> {
+        self.current.apply_replayed(update)
+    }
+
     /// Seals this version and returns a builder for the next version.
     #[must_use]
     pub fn seal_and_continue(
@@ -751,7 +915,9 @@ impl<P: Version> DerivedTreeBuilder<P> {
 #[cfg(test)]
 mod tests {
 
-    use super::{CanonicalTreeBuilder, Hash, TreeWithNextVersion};
+    use super::{
+        CanonicalTreeBuilder, Hash, TreeState, TreeUpdate, TreeVersionReadOps, TreeWithNextVersion,
+    };
 
     #[test]
     fn test_peek_next_updates() {
@@ -800,4 +966,199 @@ mod tests {
 
         assert_eq!(next_updates.len(), 3);
     }
+
+    #[test]
+    fn replaying_applied_updates_is_a_noop() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let mut mined_builder = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        );
+        mined_builder.update(&TreeUpdate::new(0, Hash::from(1)));
+
+        let (mined_tree, mut processed_builder) = mined_builder.seal();
+
+        processed_builder
+            .apply(&TreeUpdate::new(0, Hash::from(1)))
+            .unwrap();
+        processed_builder
+            .apply(&TreeUpdate::new(1, Hash::from(2)))
+            .unwrap();
+        processed_builder
+            .apply(&TreeUpdate::new(1, Hash::from(2)))
+            .unwrap();
+        let processed_tree = processed_builder.seal();
+
+        assert_eq!(mined_tree.peek_next_updates(10).len(), 1);
+        assert_eq!(processed_tree.get_leaf(1), Hash::from(2));
+    }
+
+    #[test]
+    fn replaying_conflicting_updates_fails() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let mut mined_builder = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        );
+        mined_builder.update(&TreeUpdate::new(0, Hash::from(1)));
+
+        let (_, mut processed_builder) = mined_builder.seal();
+
+        let conflict = processed_builder
+            .apply(&TreeUpdate::new(0, Hash::from(2)))
+            .unwrap_err();
+        assert_eq!(conflict.leaf_index, 0);
+        assert_eq!(conflict.existing, Hash::from(1));
+
+        // Deletions of existing leaves are not conflicts
+        processed_builder
+            .apply(&TreeUpdate::new(0, Hash::ZERO))
+            .unwrap();
+    }
+
+    #[test]
+    fn tree_does_not_depend_on_the_number_of_threads() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let leaves: Vec<Hash> = (1..=1000u64).map(Hash::from).collect();
+
+        let roots: Vec<Hash> = [1, 4]
+            .into_iter()
+            .map(|threads| {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                let cache_file = temp_dir.path().join(format!("testfile-{threads}"));
+
+                let (tree, _) = pool
+                    .install(|| {
+                        CanonicalTreeBuilder::new(
+                            12,
+                            8,
+                            0,
+                            Hash::ZERO,
+                            &leaves,
+                            cache_file.to_str().unwrap(),
+                        )
+                    })
+                    .seal();
+
+                tree.get_root()
+            })
+            .collect();
+
+        assert_eq!(roots[0], roots[1]);
+    }
+
+    #[test]
+    fn applied_leaf_updates_are_in_insertion_order() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (canonical_tree, processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let processed_tree = processed_builder.seal();
+        let appended = processed_tree.append_many(&[Hash::from(1), Hash::from(2), Hash::from(3)]);
+        let _ = processed_tree.append_many(&[Hash::from(4)]);
+
+        let leaf_updates = canonical_tree.apply_leaf_updates_up_to(appended[2].0);
+
+        assert_eq!(leaf_updates.len(), 3);
+        for (update, (root, _, leaf_index)) in leaf_updates.iter().zip(&appended) {
+            assert_eq!(update.leaf_index, *leaf_index);
+            assert_eq!(update.commitment, Hash::from(*leaf_index + 1));
+            assert_eq!(update.root, *root);
+        }
+        assert_eq!(canonical_tree.get_root(), appended[2].0);
+    }
+
+    #[test]
+    fn projected_appends_leave_the_tree_untouched() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let processed_tree = processed_builder.seal();
+        let _ = processed_tree.append_many(&[Hash::from(1)]);
+        let root = processed_tree.get_root();
+
+        let identities = [Hash::from(2), Hash::from(3)];
+        let (projected_root, proof, leaf_index) =
+            processed_tree.project_append(1, &identities).unwrap();
+
+        assert_eq!(processed_tree.get_root(), root);
+        assert_eq!(processed_tree.next_leaf(), 1);
+        assert!(processed_tree.project_append(0, &identities).is_none());
+
+        let appended = processed_tree.append_many(&identities);
+        assert_eq!(appended[1], (projected_root, proof, leaf_index));
+    }
+
+    #[test]
+    fn rolling_back_discards_later_updates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (mined, processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let (processed, batching_builder) = processed_builder.seal_and_continue();
+        let (batching, latest_builder) = batching_builder.seal_and_continue();
+        let latest = latest_builder.seal();
+        let tree_state = TreeState::new(mined, processed.clone(), batching.clone(), latest.clone());
+
+        let initial_root = latest.get_root();
+        let appended = latest.append_many(&[Hash::from(1), Hash::from(2), Hash::from(3)]);
+        batching.apply_updates_up_to(appended[1].0);
+        processed.apply_updates_up_to(appended[0].0);
+
+        // Rolled back within the batching tree
+        assert!(tree_state.can_roll_back_to(appended[1].0));
+        assert!(tree_state.roll_back_to(appended[1].0, 2));
+        assert_eq!(batching.get_root(), appended[1].0);
+        assert_eq!(latest.get_root(), appended[1].0);
+        assert_eq!(latest.next_leaf(), 2);
+        assert!(batching.peek_next_updates(10).is_empty());
+
+        // Rolled back to the processed tree
+        let appended = latest.append_many(&[Hash::from(4)]);
+        assert_eq!(appended[0].2, 2);
+        assert!(tree_state.roll_back_to(processed.get_root(), 1));
+        assert_eq!(batching.get_root(), processed.get_root());
+        assert_eq!(latest.get_root(), processed.get_root());
+        assert_eq!(latest.next_leaf(), 1);
+
+        // Roots that were never batched are left alone
+        assert!(!tree_state.can_roll_back_to(initial_root));
+        assert!(!tree_state.roll_back_to(initial_root, 0));
+        assert_eq!(latest.get_root(), processed.get_root());
+    }
 }
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter_vec_with_registry, register_int_gauge_with_registry, IntCounterVec,
+    IntGauge,
+};
+use semaphore::merkle_tree::{Branch, Hasher};
+use semaphore::poseidon_tree::{PoseidonHash, Proof};
+use tracing::{info, warn};
+
+use crate::identity_tree::{Hash, LeafUpdate, TreeVersionReadOps};
+use crate::metrics;
+
+static LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "proof_cache_lookups",
+        "Lookups of cached inclusion proofs, by whether they were a hit or a miss.",
+        &["result"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+static CACHED_PROOFS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "proof_cache_size",
+        "Number of inclusion proofs in the cache.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Keeps inclusion proofs of a tree version, so that they don't have to be
+/// recomputed for every request.
+///
+/// Adding a leaf changes exactly one sibling in the proof of every other leaf,
+/// the one at the level where their paths meet. Instead of recomputing the
+/// cached proofs, that sibling is replaced with the matching node on the path
+/// of the new leaf.
+///
+/// A cached proof takes around `40 * depth` bytes, plus the `HashMap` entry,
+/// i.e. about 1.3 KiB at depth 30.
+pub struct ProofCache {
+    capacity: usize,
+    state:    Mutex<CachedProofs>,
+}
+
+/// The proofs are always of the tree with `root`
+#[derive(Default)]
+struct CachedProofs {
+    root:   Hash,
+    proofs: HashMap<usize, Proof>,
+}
+
+impl ProofCache {
+    /// Keeps proofs of up to `capacity` leaves, nothing is cached if it's
+    /// `None`.
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity: capacity.unwrap_or_default(),
+            state:    Mutex::new(CachedProofs::default()),
+        }
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Computes the proofs of the first leaves of `tree`, up to the capacity.
+    /// Retried if the tree changes in the meantime.
+    pub fn fill(&self, tree: &impl TreeVersionReadOps) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        loop {
+            let root = tree.get_root();
+            let leaves = tree.next_leaf().min(self.capacity);
+
+            let mut proofs = HashMap::with_capacity(leaves);
+            for leaf_index in 0..leaves {
+                let (_, proof_root, proof) = tree.get_leaf_and_proof(leaf_index);
+                if proof_root != root {
+                    break;
+                }
+                proofs.insert(leaf_index, proof);
+            }
+
+            let mut state = self.state.lock().unwrap();
+            if proofs.len() == leaves && tree.get_root() == root {
+                CACHED_PROOFS.set(proofs.len() as i64);
+                info!(leaves, ?root, "Proof cache filled");
+                *state = CachedProofs { root, proofs };
+                return;
+            }
+        }
+    }
+
+    /// Returns the leaf, root and proof at `leaf_index`, from the cache if
+    /// possible. Proofs computed on a miss are cached if there's room.
+    pub fn get_leaf_and_proof(
+        &self,
+        tree: &impl TreeVersionReadOps,
+        leaf_index: usize,
+    ) -> (Hash, Hash, Proof) {
+        if !self.is_enabled() {
+            return tree.get_leaf_and_proof(leaf_index);
+        }
+
+        // Read together, so that the leaf is of the root the proof is checked
+        // against
+        let (leaf, root) = tree.get_leaf_and_root(leaf_index);
+        let cached = {
+            let state = self.state.lock().unwrap();
+            (state.root == root)
+                .then(|| state.proofs.get(&leaf_index).cloned())
+                .flatten()
+        };
+
+        if let Some(proof) = cached {
+            LOOKUPS.with_label_values(&["hit"]).inc();
+            return (leaf, root, proof);
+        }
+        LOOKUPS.with_label_values(&["miss"]).inc();
+
+        let (leaf, root, proof) = tree.get_leaf_and_proof(leaf_index);
+
+        let mut state = self.state.lock().unwrap();
+        if state.root == root && state.proofs.len() < self.capacity {
+            state.proofs.insert(leaf_index, proof.clone());
+            CACHED_PROOFS.set(state.proofs.len() as i64);
+        }
+
+        (leaf, root, proof)
+    }
+
+    /// Brings the cached proofs up to date after `updates` moved `tree` away
+    /// from `prev_root`. The cache is cleared if it wasn't of `prev_root`.
+    ///
+    /// The proofs are updated on a copy on the blocking pool, taking
+    /// `updates × cached proofs` steps, and swapped in once done. Lookups
+    /// meanwhile miss, since the tree is already past the cached root.
+    pub async fn update<T>(&self, tree: T, prev_root: Hash, updates: Vec<LeafUpdate>)
+    where
+        T: TreeVersionReadOps + Send + 'static,
+    {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let proofs = {
+            let mut state = self.state.lock().unwrap();
+
+            if state.root != prev_root {
+                state.proofs.clear();
+                state.root = tree.get_root();
+                CACHED_PROOFS.set(0);
+                return;
+            }
+
+            state.proofs.clone()
+        };
+
+        let updated = tokio::task::spawn_blocking(move || {
+            let mut proofs = proofs;
+            apply_updates(&tree, &mut proofs, &updates);
+            (tree.get_root(), proofs)
+        })
+        .await;
+
+        let mut state = self.state.lock().unwrap();
+        match updated {
+            // Left as is if it moved on in the meantime
+            Ok((root, proofs)) if state.root == prev_root => {
+                *state = CachedProofs { root, proofs };
+            }
+            Ok(_) => {}
+            Err(error) => {
+                warn!(?error, "Failed to update the proof cache, clearing it");
+                state.proofs.clear();
+                state.root = Hash::ZERO;
+            }
+        }
+        CACHED_PROOFS.set(state.proofs.len() as i64);
+    }
+}
+
+/// Replaces, in each of `proofs`, the sibling that each of `updates` changed
+fn apply_updates(
+    tree: &impl TreeVersionReadOps,
+    proofs: &mut HashMap<usize, Proof>,
+    updates: &[LeafUpdate],
+) {
+    for update in updates {
+        let updated = update.leaf_index;
+        let (leaf, _, proof) = tree.get_leaf_and_proof(updated);
+        let path = path_nodes(leaf, &proof);
+
+        for (&leaf_index, cached) in proofs.iter_mut() {
+            if leaf_index == updated {
+                *cached = proof.clone();
+                continue;
+            }
+
+            // The paths meet at the most significant differing bit
+            let level = (leaf_index ^ updated).ilog2() as usize;
+            cached.0[level] = match cached.0[level] {
+                Branch::Left(_) => Branch::Left(path[level]),
+                Branch::Right(_) => Branch::Right(path[level]),
+            };
+        }
+    }
+}
+
+/// The nodes on the path from `leaf` to the root, starting with the leaf and
+/// excluding the root
+fn path_nodes(leaf: Hash, proof: &Proof) -> Vec<Hash> {
+    let mut nodes = Vec::with_capacity(proof.0.len());
+    let mut node = leaf;
+
+    for branch in &proof.0 {
+        nodes.push(node);
+        node = match branch {
+            Branch::Left(sibling) => PoseidonHash::hash_node(&node, sibling),
+            Branch::Right(sibling) => PoseidonHash::hash_node(sibling, &node),
+        };
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity_tree::{CanonicalTreeBuilder, TreeWithNextVersion};
+
+    #[tokio::test]
+    async fn updated_proofs_match_the_tree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let leaves: Vec<Hash> = (1..=5u64).map(Hash::from).collect();
+
+        let (mined_tree, processed_builder) = CanonicalTreeBuilder::new(
+            4,
+            2,
+            0,
+            Hash::ZERO,
+            &leaves,
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let processed_tree = processed_builder.seal();
+
+        let cache = ProofCache::new(Some(16));
+        cache.fill(&mined_tree);
+
+        let appended = processed_tree.append_many(&[Hash::from(6), Hash::from(7)]);
+        let _ = processed_tree.delete_many(&[1]);
+
+        let prev_root = mined_tree.get_root();
+        let updates = mined_tree.apply_leaf_updates_up_to(processed_tree.get_root());
+        assert_eq!(updates.len(), 3);
+        cache.update(mined_tree.clone(), prev_root, updates).await;
+
+        assert_eq!(mined_tree.get_root(), processed_tree.get_root());
+        assert_eq!(appended.len(), 2);
+        for leaf_index in 0..7 {
+            let cached = cache.get_leaf_and_proof(&mined_tree, leaf_index);
+            assert_eq!(cached, mined_tree.get_leaf_and_proof(leaf_index));
+        }
+    }
+
+    #[test]
+    fn lookups_ahead_of_the_cache_miss() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (mined_tree, processed_builder) = CanonicalTreeBuilder::new(
+            4,
+            2,
+            0,
+            Hash::ZERO,
+            &[Hash::from(1), Hash::from(2)],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let processed_tree = processed_builder.seal();
+
+        let cache = ProofCache::new(Some(16));
+        cache.fill(&mined_tree);
+
+        // The tree moves on before the cache is updated, as while it's rebuilt
+        let _ = processed_tree.append_many(&[Hash::from(3)]);
+        mined_tree.apply_leaf_updates_up_to(processed_tree.get_root());
+
+        for leaf_index in 0..3 {
+            let looked_up = cache.get_leaf_and_proof(&mined_tree, leaf_index);
+            assert_eq!(looked_up, mined_tree.get_leaf_and_proof(leaf_index));
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_caches_are_cleared() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (mined_tree, _) = CanonicalTreeBuilder::new(
+            4,
+            2,
+            0,
+            Hash::ZERO,
+            &[Hash::from(1)],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+
+        let cache = ProofCache::new(Some(16));
+        cache.fill(&mined_tree);
+
+        cache.update(mined_tree, Hash::from(42), vec![]).await;
+
+        assert!(cache.state.lock().unwrap().proofs.is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::identity_tree::Hash;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentRoot {
+    pub root:         Hash,
+    /// `None` for the root the sequencer started with
+    pub block_number: Option<u64>,
+    /// The number of leaves in the tree with this root, deleted ones included
+    pub tree_size:    usize,
+}
+
+/// The last roots mined on mainnet, so that clients can check proofs against
+/// any of them, not only the latest one, which may change while they verify.
+pub struct RecentRoots {
+    capacity: usize,
+    roots:    Mutex<VecDeque<RecentRoot>>,
+}
+
+impl RecentRoots {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            roots: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn push(&self, root: RecentRoot) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut roots = self.roots.lock().unwrap();
+        if roots.len() == self.capacity {
+            roots.pop_back();
+        }
+        roots.push_front(root);
+    }
+
+    /// Drops the roots newer than `root`, if it's one of them, e.g. after a
+    /// rollback to it
+    pub fn roll_back_to(&self, root: Hash) {
+        let mut roots = self.roots.lock().unwrap();
+
+        if let Some(position) = roots.iter().position(|recent| recent.root == root) {
+            roots.drain(..position);
+        }
+    }
+
+    /// Drops the roots mined from `block_number` on, e.g. because their blocks
+    /// were reorged out
+    pub fn remove_from_block(&self, block_number: u64) {
+        self.roots.lock().unwrap().retain(|recent| {
+            recent
+                .block_number
+                .map_or(true, |mined_at| mined_at < block_number)
+        });
+    }
+
+    /// Newest first
+    pub fn get(&self) -> Vec<RecentRoot> {
+        self.roots.lock().unwrap().iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(tree_size: usize) -> RecentRoot {
+        RecentRoot {
+            root: Hash::from(tree_size),
+            block_number: Some(tree_size as u64),
+            tree_size,
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_roots() {
+        let recent_roots = RecentRoots::new(2);
+
+        for tree_size in 1..=3 {
+            recent_roots.push(root(tree_size));
+        }
+
+        assert_eq!(recent_roots.get(), vec![root(3), root(2)]);
+    }
+
+    #[test]
+    fn rolled_back_roots_are_dropped() {
+        let recent_roots = RecentRoots::new(8);
+        for tree_size in 1..=4 {
+            recent_roots.push(root(tree_size));
+        }
+
+        recent_roots.roll_back_to(root(3).root);
+        assert_eq!(recent_roots.get(), vec![root(3), root(2), root(1)]);
+
+        // Unknown roots aren't ahead of any root kept
+        recent_roots.roll_back_to(root(7).root);
+        assert_eq!(recent_roots.get(), vec![root(3), root(2), root(1)]);
+    }
+
+    #[test]
+    fn reorged_roots_are_dropped() {
+        let recent_roots = RecentRoots::new(8);
+        recent_roots.push(RecentRoot {
+            block_number: None,
+            ..root(0)
+        });
+        for tree_size in 1..=4 {
+            recent_roots.push(root(tree_size));
+        }
+
+        recent_roots.remove_from_block(2);
+        assert_eq!(recent_roots.get(), vec![root(1), RecentRoot {
+            block_number: None,
+            ..root(0)
+        }]);
+    }
+}
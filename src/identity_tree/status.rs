@@ -34,6 +34,10 @@ pub enum UnprocessedStatus {
     /// Root is unprocessed - i.e. not included in sequencer's
     /// in-memory tree.
     New,
+
+    /// The identity will never be included in the tree, e.g. because it
+    /// spent too long in the queue. The reason is stored as the error message.
+    Failed,
 }
 
 /// A status type visible on the API level - contains both the processed and
@@ -101,6 +105,7 @@ impl FromStr for UnprocessedStatus {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "new" => Ok(Self::New),
+            "failed" => Ok(Self::Failed),
             _ => Err(UnknownStatus),
         }
     }
@@ -110,6 +115,7 @@ impl From<UnprocessedStatus> for &str {
     fn from(scope: UnprocessedStatus) -> Self {
         match scope {
             UnprocessedStatus::New => "new",
+            UnprocessedStatus::Failed => "failed",
         }
     }
 }
@@ -135,6 +141,7 @@ mod tests {
     #[test_case(Status::Processed(ProcessedStatus::Pending) => "pending")]
     #[test_case(Status::Processed(ProcessedStatus::Mined) => "mined")]
     #[test_case(Status::Unprocessed(UnprocessedStatus::New) => "new")]
+    #[test_case(Status::Unprocessed(UnprocessedStatus::Failed) => "failed")]
     fn serialize_status(api_status: Status) -> &'static str {
         let s = serde_json::to_string(&api_status).unwrap();
 
@@ -147,6 +154,7 @@ mod tests {
     #[test_case("pending" => Status::Processed(ProcessedStatus::Pending))]
     #[test_case("mined" => Status::Processed(ProcessedStatus::Mined))]
     #[test_case("new" => Status::Unprocessed(UnprocessedStatus::New))]
+    #[test_case("failed" => Status::Unprocessed(UnprocessedStatus::Failed))]
     fn deserialize_status(s: &str) -> Status {
         // Wrapped because JSON expected `"something"` and not `something`
         let wrapped = format!("\"{s}\"");
@@ -2,11 +2,15 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions, clippy::too_many_arguments)]
 
+pub mod alerts;
 pub mod app;
+pub mod commands;
 pub mod config;
 mod contracts;
 mod database;
 mod ethereum;
+pub mod health;
+pub mod metrics;
 
 mod identity;
 pub mod identity_tree;
@@ -7,13 +7,17 @@
 )]
 
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use signup_sequencer::app::App;
+use signup_sequencer::commands::catch_up::catch_up;
+use signup_sequencer::commands::verify_tree::{spot_check_tree, verify_tree};
 use signup_sequencer::config::{Config, ServiceConfig};
-use signup_sequencer::server;
+use signup_sequencer::metrics::Pusher;
 use signup_sequencer::shutdown::watch_shutdown_signals;
 use signup_sequencer::task_monitor::TaskMonitor;
+use signup_sequencer::{metrics, server};
 use telemetry_batteries::tracing::datadog::DatadogBattery;
 use telemetry_batteries::tracing::stdout::StdoutBattery;
 use telemetry_batteries::tracing::TracingShutdownHandle;
@@ -22,6 +26,36 @@ use telemetry_batteries::tracing::TracingShutdownHandle;
 struct Args {
     /// Path to the optional config file
     config: Option<PathBuf>,
+
+    /// Brings the tree up to date with the chain, writes the tree cache file
+    /// and exits, without starting the server
+    #[arg(long)]
+    catch_up_only: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Compares the local tree against the insertions on chain and reports
+    /// the first divergence. Does not modify any state.
+    VerifyTree {
+        /// The block to start scanning from, e.g. the contract deployment
+        /// block
+        #[arg(long, default_value_t = 0)]
+        from_block: u64,
+
+        /// The number of blocks to fetch logs for at once, defaults to
+        /// `app.scanning_window_size`
+        #[arg(long)]
+        window_size: Option<u64>,
+
+        /// Instead of replaying events, only compare the contract root and a
+        /// sample of this many leaves read through the contract getters
+        #[arg(long)]
+        sample: Option<usize>,
+    },
 }
 
 #[tokio::main]
@@ -36,6 +70,48 @@ async fn sequencer_app(args: Args) -> anyhow::Result<()> {
     let config = load_config(&args)?;
 
     let _tracing_shutdown_handle = init_telemetry(&config.service)?;
+    metrics::init(&config.service)?;
+
+    let pusher = config
+        .service
+        .pushgateway
+        .as_ref()
+        .map(|pushgateway| Pusher::new(pushgateway, &config.service.service_name))
+        .transpose()?
+        .map(Arc::new);
+
+    if let Some(pusher) = &pusher {
+        pusher.clone().spawn();
+    }
+
+    let result = run(args, config).await;
+
+    // Whatever ran last wouldn't be pushed otherwise
+    if let Some(pusher) = pusher {
+        if let Err(error) = pusher.push().await {
+            tracing::warn!(?error, "Failed to push metrics on exit");
+        }
+    }
+
+    result
+}
+
+async fn run(args: Args, config: Config) -> anyhow::Result<()> {
+    if let Some(command) = args.command {
+        return run_command(&config, command).await;
+    }
+
+    if args.catch_up_only {
+        let report = catch_up(config).await?;
+
+        println!("{report}");
+
+        if !report.is_synced() {
+            anyhow::bail!("Local tree is not at the contract root");
+        }
+
+        return Ok(());
+    }
 
     watch_shutdown_signals();
 
@@ -47,6 +123,7 @@ async fn sequencer_app(args: Args) -> anyhow::Result<()> {
 
     // Create App struct
     let app = App::new(config).await?;
+    app.watch_commitment_filter_reloads()?;
 
     let task_monitor = TaskMonitor::new(app.clone());
 
@@ -62,6 +139,39 @@ async fn sequencer_app(args: Args) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn run_command(config: &Config, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::VerifyTree {
+            sample: Some(sample),
+            ..
+        } => {
+            let report = spot_check_tree(config, sample).await?;
+
+            println!("{report}");
+
+            if !report.is_consistent() {
+                anyhow::bail!("Local tree diverges from the chain");
+            }
+        }
+        Command::VerifyTree {
+            from_block,
+            window_size,
+            sample: None,
+        } => {
+            let window_size = window_size.unwrap_or(config.app.scanning_window_size);
+            let report = verify_tree(config, from_block, window_size).await?;
+
+            println!("{report}");
+
+            if report.first_divergence.is_some() {
+                anyhow::bail!("Local tree diverges from the chain");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn load_config(args: &Args) -> anyhow::Result<Config> {
     let mut settings = config::Config::builder();
 
@@ -100,7 +210,11 @@ mod tests {
     #[test]
     fn test_example_env() {
         dotenv::from_path("example.env").ok();
-        let args = Args { config: None };
+        let args = Args {
+            config:        None,
+            catch_up_only: false,
+            command:       None,
+        };
         let config = load_config(&args).unwrap();
         println!("{:#?}", config);
     }
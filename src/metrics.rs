@@ -0,0 +1,177 @@
+//! The registry all metrics are registered with, and pushing them to a
+//! Pushgateway.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use anyhow::Context;
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, Registry, TextEncoder};
+use reqwest::header::CONTENT_TYPE;
+use tokio::time;
+use tracing::warn;
+use url::Url;
+
+use crate::config::{PushgatewayConfig, ServiceConfig};
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Sets the prefix and constant labels of all metrics. Must be called before
+/// any metric is used, since metrics are named when they're registered.
+pub fn init(config: &ServiceConfig) -> anyhow::Result<()> {
+    let labels: HashMap<String, String> = config.metrics_labels.0.clone();
+    let labels = (!labels.is_empty()).then_some(labels);
+
+    let registry = Registry::new_custom(config.metrics_prefix.clone(), labels)?;
+
+    REGISTRY
+        .set(registry)
+        .map_err(|_| anyhow::anyhow!("Metrics registry was initialized twice or after use"))
+}
+
+/// Metrics registered before [`init`] end up unprefixed, e.g. in tests.
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::new)
+}
+
+/// The metrics of [`registry`], along with those of libraries registering with
+/// the default registry. A family in both is only taken from ours, since the
+/// text format doesn't allow a family to appear twice.
+pub fn gather() -> Vec<MetricFamily> {
+    merge_families(registry().gather(), prometheus::gather())
+}
+
+fn merge_families(ours: Vec<MetricFamily>, default: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    let names: HashSet<String> = ours
+        .iter()
+        .map(|family| family.get_name().to_owned())
+        .collect();
+
+    let mut metric_families = ours;
+    metric_families.extend(
+        default
+            .into_iter()
+            .filter(|family| !names.contains(family.get_name())),
+    );
+    metric_families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+    metric_families
+}
+
+/// How long a push may take
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pushes all registered metrics to a Prometheus Pushgateway, replacing the
+/// ones previously pushed under the same labels.
+pub struct Pusher {
+    client:   reqwest::Client,
+    url:      Url,
+    interval: Duration,
+}
+
+impl Pusher {
+    pub fn new(config: &PushgatewayConfig, service_name: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(config.url.expose()).context("Invalid pushgateway url")?;
+        let job = config.job.as_deref().unwrap_or(service_name);
+
+        Ok(Self {
+            client:   reqwest::Client::builder().timeout(PUSH_TIMEOUT).build()?,
+            url:      grouping_url(url, job, config.instance.as_deref())?,
+            interval: config.push_interval,
+        })
+    }
+
+    pub async fn push(&self) -> anyhow::Result<()> {
+        let encoder = TextEncoder::new();
+        // As served at `/metrics`
+        let metric_families = gather();
+
+        let mut body = vec![];
+        encoder.encode(&metric_families, &mut body)?;
+
+        self.client
+            .put(self.url.clone())
+            .header(CONTENT_TYPE, encoder.format_type())
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Pushes on every interval until the process exits. The final push is
+    /// up to the caller, since the process may exit before the next tick.
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = time::interval(self.interval);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(error) = self.push().await {
+                    warn!(?error, "Failed to push metrics");
+                }
+            }
+        });
+    }
+}
+
+/// The url of the metrics group,
+/// `{url}/metrics/job/{job}[/instance/{instance}]`
+fn grouping_url(mut url: Url, job: &str, instance: Option<&str>) -> anyhow::Result<Url> {
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("Pushgateway url can't be a base"))?;
+
+        segments.pop_if_empty().extend(["metrics", "job", job]);
+        if let Some(instance) = instance {
+            segments.extend(["instance", instance]);
+        }
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_are_merged_without_duplicates() {
+        let register = |registry: &Registry, name: &str, value: u64| {
+            let counter = prometheus::IntCounter::new(name, "help").unwrap();
+            counter.inc_by(value);
+            registry.register(Box::new(counter)).unwrap();
+        };
+
+        let ours = Registry::new();
+        let default = Registry::new();
+        register(&ours, "shared_total", 1);
+        register(&default, "shared_total", 2);
+        register(&default, "library_total", 3);
+
+        let merged = merge_families(ours.gather(), default.gather());
+
+        let names: Vec<_> = merged.iter().map(MetricFamily::get_name).collect();
+        assert_eq!(names, ["library_total", "shared_total"]);
+        assert_eq!(merged[1].get_metric()[0].get_counter().get_value(), 1.0);
+    }
+
+    #[test]
+    fn grouping_labels_are_escaped() {
+        let url = Url::parse("http://pushgateway:9091/").unwrap();
+
+        assert_eq!(
+            grouping_url(url.clone(), "catch_up", None)
+                .unwrap()
+                .as_str(),
+            "http://pushgateway:9091/metrics/job/catch_up"
+        );
+        assert_eq!(
+            grouping_url(url, "catch up", Some("a/b")).unwrap().as_str(),
+            "http://pushgateway:9091/metrics/job/catch%20up/instance/a%2Fb"
+        );
+    }
+}
@@ -20,12 +20,13 @@ use ethers::types::U256;
 use ethers::utils::keccak256;
 pub use map::ProverMap;
 use once_cell::sync::Lazy;
-use prometheus::{exponential_buckets, register_histogram, Histogram};
+use prometheus::{exponential_buckets, register_histogram_with_registry, Histogram};
 pub use proof::Proof;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use url::Url;
 
+use crate::metrics;
 use crate::prover::identity::Identity;
 use crate::utils::index_packing::pack_indices;
 
@@ -33,19 +34,21 @@ use crate::utils::index_packing::pack_indices;
 const MTB_PROVE_ENDPOINT: &str = "prove";
 
 static TOTAL_PROVING_TIME: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!(
+    register_histogram_with_registry!(
         "total_proving_time",
         "The time to generate a proof in seconds. Includes preparing the data for the prover",
-        exponential_buckets(0.1, 1.5, 25).unwrap()
+        exponential_buckets(0.1, 1.5, 25).unwrap(),
+        metrics::registry()
     )
     .unwrap()
 });
 
 static PROVER_PROVING_TIME: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!(
+    register_histogram_with_registry!(
         "prover_proving_time",
         "Only the time between sending a request and receiving the proof",
-        exponential_buckets(0.1, 1.5, 25).unwrap()
+        exponential_buckets(0.1, 1.5, 25).unwrap(),
+        metrics::registry()
     )
     .unwrap()
 });
@@ -0,0 +1,97 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::Address;
+use ethers::utils::hash_message;
+
+use crate::identity_tree::Hash;
+use crate::server::data::InsertAcknowledgment;
+
+/// Signs acknowledgments of accepted insertions, which clients can keep as
+/// receipts. They are EIP-191 signatures of [`message`], so that they can be
+/// checked with any Ethereum library against the signer address served at
+/// `/acknowledgmentSigner`.
+pub struct AcknowledgmentSigner {
+    wallet: LocalWallet,
+}
+
+impl AcknowledgmentSigner {
+    pub fn new(signing_key: &str) -> anyhow::Result<Self> {
+        let wallet = signing_key
+            .parse()
+            .context("Invalid acknowledgment signing key")?;
+
+        Ok(Self { wallet })
+    }
+
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    pub fn sign(
+        &self,
+        ticket: Hash,
+        commitment: Hash,
+        timestamp: DateTime<Utc>,
+    ) -> anyhow::Result<InsertAcknowledgment> {
+        let signature = self
+            .wallet
+            .sign_hash(hash_message(message(ticket, commitment, timestamp)))?;
+
+        Ok(InsertAcknowledgment {
+            ticket,
+            commitment,
+            timestamp,
+            signature: format!("0x{signature}"),
+            signer: self.address(),
+        })
+    }
+}
+
+/// The signed message, one field per line so that it's readable in wallets.
+/// Hashes are 0x-prefixed 64 digit hex, the timestamp is in unix seconds.
+fn message(ticket: Hash, commitment: Hash, timestamp: DateTime<Utc>) -> String {
+    format!(
+        "signup-sequencer insert acknowledgment\nticket: 0x{}\ncommitment: 0x{}\ntimestamp: {}",
+        hex::encode(ticket.to_be_bytes::<32>()),
+        hex::encode(commitment.to_be_bytes::<32>()),
+        timestamp.timestamp()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Signature;
+
+    use super::*;
+
+    fn verify(acknowledgment: &InsertAcknowledgment, signer: Address) -> bool {
+        let signature: Signature = acknowledgment.signature.parse().unwrap();
+        let message = message(
+            acknowledgment.ticket,
+            acknowledgment.commitment,
+            acknowledgment.timestamp,
+        );
+
+        signature.verify(message, signer).is_ok()
+    }
+
+    // Anvil's first development key
+    const SIGNING_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    #[test]
+    fn acknowledgments_verify_against_the_signer() {
+        let signer = AcknowledgmentSigner::new(SIGNING_KEY).unwrap();
+        let commitment = Hash::from(42);
+
+        let acknowledgment = signer.sign(commitment, commitment, Utc::now()).unwrap();
+        assert!(verify(&acknowledgment, signer.address()));
+
+        let tampered = InsertAcknowledgment {
+            commitment: Hash::from(43),
+            ..acknowledgment.clone()
+        };
+        assert!(!verify(&tampered, signer.address()));
+        assert!(!verify(&acknowledgment, Address::zero()));
+    }
+}
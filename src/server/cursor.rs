@@ -0,0 +1,102 @@
+use ethers::utils::keccak256;
+
+use crate::identity_tree::Hash;
+use crate::server::error::Error;
+
+/// Bytes of the MAC kept in a cursor
+const MAC_LENGTH: usize = 16;
+
+/// Issues and checks resumable positions in the `/leafUpdates` stream.
+///
+/// A cursor is the hex encoded root after the last delivered leaf, followed by
+/// a MAC of that root. Without the MAC, clients could resume from arbitrary
+/// roots, e.g. ones that were never streamed. Keccak isn't subject to length
+/// extension, so hashing the secret followed by the root is a sound MAC.
+pub struct Cursors {
+    secret: Vec<u8>,
+}
+
+impl Cursors {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn encode(&self, root: Hash) -> String {
+        let root = root.to_be_bytes::<32>();
+
+        let mut cursor = root.to_vec();
+        cursor.extend_from_slice(&self.mac(&root));
+
+        hex::encode(cursor)
+    }
+
+    /// Returns the root of a cursor issued by [`Self::encode`]. Fails with
+    /// `Error::InvalidCursor` for anything else.
+    pub fn decode(&self, cursor: &str) -> Result<Hash, Error> {
+        let cursor = hex::decode(cursor).map_err(|_| Error::InvalidCursor)?;
+        if cursor.len() != 32 + MAC_LENGTH {
+            return Err(Error::InvalidCursor);
+        }
+
+        let (root, mac) = cursor.split_at(32);
+        let root: [u8; 32] = root.try_into().map_err(|_| Error::InvalidCursor)?;
+
+        // Compared in constant time, so that the MAC can't be guessed byte by
+        // byte from response times
+        let difference = self
+            .mac(&root)
+            .iter()
+            .zip(mac)
+            .fold(0, |difference, (expected, actual)| {
+                difference | (expected ^ actual)
+            });
+        if difference != 0 {
+            return Err(Error::InvalidCursor);
+        }
+
+        Ok(Hash::from_be_bytes(root))
+    }
+
+    fn mac(&self, root: &[u8; 32]) -> [u8; MAC_LENGTH] {
+        let mut message = self.secret.clone();
+        message.extend_from_slice(root);
+
+        let mut mac = [0; MAC_LENGTH];
+        mac.copy_from_slice(&keccak256(message)[..MAC_LENGTH]);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursors = Cursors::new("secret");
+        let root = Hash::from(42);
+
+        assert_eq!(cursors.decode(&cursors.encode(root)).unwrap(), root);
+    }
+
+    #[test]
+    fn tampered_cursors_are_rejected() {
+        let cursors = Cursors::new("secret");
+        let cursor = cursors.encode(Hash::from(42));
+
+        let mut tampered = hex::decode(&cursor).unwrap();
+        tampered[31] ^= 1;
+        let other_secret = Cursors::new("other").encode(Hash::from(42));
+
+        for cursor in [
+            hex::encode(tampered),
+            other_secret,
+            cursor[..cursor.len() - 2].to_string(),
+            "not hex".to_string(),
+        ] {
+            assert!(matches!(cursors.decode(&cursor), Err(Error::InvalidCursor)));
+        }
+    }
+}
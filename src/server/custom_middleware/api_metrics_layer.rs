@@ -3,24 +3,37 @@ use axum::middleware::Next;
 use axum::response::Response;
 use once_cell::sync::Lazy;
 use prometheus::{
-    opts, register_counter, register_histogram, register_int_counter_vec, Counter, Histogram,
-    IntCounterVec,
+    opts, register_counter_with_registry, register_histogram_with_registry,
+    register_int_counter_vec_with_registry, Counter, Histogram, IntCounterVec,
 };
 
-static REQUESTS: Lazy<Counter> =
-    Lazy::new(|| register_counter!(opts!("api_requests", "Number of requests received.")).unwrap());
+use crate::metrics;
+
+static REQUESTS: Lazy<Counter> = Lazy::new(|| {
+    register_counter_with_registry!(
+        opts!("api_requests", "Number of requests received."),
+        metrics::registry()
+    )
+    .unwrap()
+});
 
 static STATUS: Lazy<IntCounterVec> = Lazy::new(|| {
-    register_int_counter_vec!(
+    register_int_counter_vec_with_registry!(
         "api_response_status",
         "The API responses by status code.",
-        &["status_code"]
+        &["status_code"],
+        metrics::registry()
     )
     .unwrap()
 });
 
 static LATENCY: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!("api_latency_seconds", "The API latency in seconds.").unwrap()
+    register_histogram_with_registry!(
+        "api_latency_seconds",
+        "The API latency in seconds.",
+        metrics::registry()
+    )
+    .unwrap()
 });
 
 pub async fn middleware<B>(request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
@@ -1,8 +1,15 @@
+use chrono::{DateTime, Utc};
+use ethers::types::Address;
 use hyper::StatusCode;
+use semaphore::poseidon_tree::{Branch, Proof as MerkleProof};
 use semaphore::protocol::Proof;
 use semaphore::Field;
-use serde::{Deserialize, Serialize};
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer, Serialize};
 
+use super::error::Error as ServerError;
+use crate::config::CommitmentByteOrder;
+use crate::identity_tree::recent_roots::RecentRoot;
 use crate::identity_tree::{
     Hash, InclusionProof, ProcessedStatus, RootItem, Status, UnprocessedStatus,
 };
@@ -12,6 +19,51 @@ use crate::prover::{ProverConfig, ProverType};
 #[serde(transparent)]
 pub struct InclusionProofResponse(pub InclusionProof);
 
+/// The current version of the [`ProofBundle`] schema.
+pub const PROOF_BUNDLE_VERSION: u32 = 1;
+
+/// An inclusion proof in the layout used by Semaphore circuits and tooling.
+///
+/// Field elements are decimal strings. `pathElements[i]` is the sibling at
+/// depth `i` counted from the leaf, and `pathIndices[i]` is `0` if the path
+/// goes through the left child at that depth and `1` if it goes through the
+/// right child.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofBundle {
+    pub version:       u32,
+    pub leaf:          String,
+    pub path_elements: Vec<String>,
+    pub path_indices:  Vec<u8>,
+    pub root:          String,
+}
+
+impl ProofBundle {
+    #[must_use]
+    pub fn new(leaf: Field, root: Field, proof: &MerkleProof) -> Self {
+        let (path_elements, path_indices) = proof
+            .0
+            .iter()
+            .map(|branch| match branch {
+                Branch::Left(sibling) => (sibling.to_string(), 0),
+                Branch::Right(sibling) => (sibling.to_string(), 1),
+            })
+            .unzip();
+
+        Self {
+            version: PROOF_BUNDLE_VERSION,
+            leaf: leaf.to_string(),
+            path_elements,
+            path_indices,
+            root: root.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProofBundleResponse(pub ProofBundle);
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct ListBatchSizesResponse(pub Vec<ProverConfig>);
@@ -24,7 +76,111 @@ pub struct VerifySemaphoreProofResponse(pub RootItem);
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct InsertCommitmentRequest {
-    pub identity_commitment: Hash,
+    pub identity_commitment: RawCommitment,
+    /// Commitments with a higher priority are inserted first. Defaults to
+    /// [`DEFAULT_PRIORITY`](crate::database::query::DEFAULT_PRIORITY).
+    #[serde(default)]
+    pub priority:            Option<i16>,
+}
+
+/// A commitment as sent by a client, in the configured
+/// [`CommitmentByteOrder`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct RawCommitment {
+    value:      Hash,
+    // Whether all 32 bytes were sent, leading zeros included
+    #[serde(skip)]
+    full_width: bool,
+}
+
+impl RawCommitment {
+    /// The commitment in big endian, given the byte order it was sent in.
+    /// Little endian commitments must be all 32 bytes, since their leading
+    /// zeros are significant. Not validated otherwise, a little endian
+    /// commitment might not be reduced once converted.
+    pub fn in_byte_order(self, byte_order: CommitmentByteOrder) -> Result<Hash, ServerError> {
+        match byte_order {
+            CommitmentByteOrder::BigEndian => Ok(self.value),
+            CommitmentByteOrder::LittleEndian if !self.full_width => {
+                Err(ServerError::ShortCommitment)
+            }
+            CommitmentByteOrder::LittleEndian => {
+                Ok(Hash::from_le_bytes(self.value.to_be_bytes::<32>()))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RawCommitment {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hex = String::deserialize(deserializer)?;
+        let digits = hex.strip_prefix("0x").unwrap_or(&hex);
+        let full_width = digits.len() == 64;
+        let value = Hash::deserialize(hex.as_str().into_deserializer())?;
+
+        Ok(Self { value, full_width })
+    }
+}
+
+/// Returned when an insertion didn't finish within the insert timeout. The
+/// ticket is the commitment, whose status can be followed through
+/// `/inclusionProof`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InsertCommitmentTicket {
+    pub ticket:           Hash,
+    /// The status of the commitment, if an earlier insertion queued it within
+    /// `app.insert_dedup_window`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status:           Option<UnprocessedStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledgment:   Option<InsertAcknowledgment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optimistic_proof: Option<OptimisticProof>,
+}
+
+/// A proof against the root the tree is projected to have once the insertions
+/// queued ahead of the commitment are applied, see
+/// `server.optimistic_proofs`. Never confirmed: the commitment may end up at
+/// another leaf, under another root, or not be inserted at all.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimisticProof {
+    pub leaf_index:     usize,
+    pub projected_root: Hash,
+    pub proof:          MerkleProof,
+    /// Always `false`, the confirmed proof is served by `/inclusionProof`
+    pub confirmed:      bool,
+}
+
+/// A receipt for an accepted insertion, see
+/// [`AcknowledgmentSigner`](super::acknowledgment::AcknowledgmentSigner)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InsertAcknowledgment {
+    pub ticket:     Hash,
+    pub commitment: Hash,
+    pub timestamp:  DateTime<Utc>,
+    /// 0x-prefixed hex of the 65 byte signature
+    pub signature:  String,
+    pub signer:     Address,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcknowledgmentSignerResponse {
+    pub address: Address,
+}
+
+/// The last roots mined on mainnet, newest first
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentRootsResponse {
+    pub roots: Vec<RecentRoot>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -55,7 +211,11 @@ pub struct RemoveBatchSizeRequest {
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct InclusionProofRequest {
-    pub identity_commitment: Hash,
+    pub identity_commitment: RawCommitment,
+    /// Only serve the proof if the contract accepts its root, see
+    /// [`AppConfig::verify_proofs_on_chain`](crate::config::AppConfig::verify_proofs_on_chain)
+    #[serde(default)]
+    pub verify_on_chain:     bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -77,12 +237,29 @@ pub struct VerifySemaphoreProofQuery {
     pub max_root_age_seconds: Option<i64>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TransactionStatusQuery {
+    /// The id the relayer assigned to the transaction
+    pub transaction_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct LeafUpdatesQuery {
+    /// Resumes the stream after the event with this id
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct DeletionRequest {
     /// The identity commitment to delete.
-    pub identity_commitment: Hash,
+    pub identity_commitment: RawCommitment,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -90,9 +267,9 @@ pub struct DeletionRequest {
 #[serde(deny_unknown_fields)]
 pub struct RecoveryRequest {
     /// The leaf index of the identity commitment to delete.
-    pub previous_identity_commitment: Hash,
+    pub previous_identity_commitment: RawCommitment,
     /// The new identity commitment to insert.
-    pub new_identity_commitment:      Hash,
+    pub new_identity_commitment:      RawCommitment,
 }
 
 impl InclusionProofResponse {
@@ -122,6 +299,7 @@ impl ToResponseCode for InclusionProofResponse {
             Status::Processed(ProcessedStatus::Mined | ProcessedStatus::Processed) => {
                 StatusCode::OK
             }
+            Status::Unprocessed(UnprocessedStatus::Failed) => StatusCode::BAD_REQUEST,
         }
     }
 }
@@ -166,3 +344,92 @@ impl ToResponseCode for () {
         StatusCode::OK
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proof_bundle_schema() {
+        let proof = MerkleProof(vec![
+            Branch::Left(Field::from(7)),
+            Branch::Right(Field::from(11)),
+            Branch::Left(Field::from(0x1234)),
+        ]);
+
+        let bundle = ProofBundle::new(Field::from(3), Field::from(42), &proof);
+
+        let expected = serde_json::json!({
+            "version": 1,
+            "leaf": "3",
+            "pathElements": ["7", "11", "4660"],
+            "pathIndices": [0, 1, 0],
+            "root": "42"
+        });
+
+        assert_eq!(serde_json::to_value(&bundle).unwrap(), expected);
+        assert_eq!(
+            serde_json::from_value::<ProofBundle>(expected).unwrap(),
+            bundle
+        );
+    }
+
+    #[test]
+    fn little_endian_commitments_are_converted() {
+        let request: InsertCommitmentRequest = serde_json::from_value(serde_json::json!({
+            "identityCommitment": "0x0100000000000000000000000000000000000000000000000000000000000000"
+        }))
+        .unwrap();
+
+        let commitment = request.identity_commitment;
+        assert_eq!(
+            commitment
+                .in_byte_order(CommitmentByteOrder::LittleEndian)
+                .unwrap(),
+            Hash::from(1)
+        );
+        assert_eq!(
+            commitment
+                .in_byte_order(CommitmentByteOrder::BigEndian)
+                .unwrap(),
+            Hash::from(1) << 248
+        );
+
+        // Without its leading zeros, a little endian commitment is ambiguous
+        let request: DeletionRequest = serde_json::from_value(serde_json::json!({
+            "identityCommitment": "0x01"
+        }))
+        .unwrap();
+        assert!(matches!(
+            request
+                .identity_commitment
+                .in_byte_order(CommitmentByteOrder::LittleEndian),
+            Err(ServerError::ShortCommitment)
+        ));
+        assert_eq!(
+            request
+                .identity_commitment
+                .in_byte_order(CommitmentByteOrder::BigEndian)
+                .unwrap(),
+            Hash::from(1)
+        );
+    }
+
+    #[test]
+    fn on_chain_verification_is_opt_in() {
+        let commitment = "0x0100000000000000000000000000000000000000000000000000000000000000";
+
+        let request: InclusionProofRequest = serde_json::from_value(serde_json::json!({
+            "identityCommitment": commitment
+        }))
+        .unwrap();
+        assert!(!request.verify_on_chain);
+
+        let request: InclusionProofRequest = serde_json::from_value(serde_json::json!({
+            "identityCommitment": commitment,
+            "verifyOnChain": true
+        }))
+        .unwrap();
+        assert!(request.verify_on_chain);
+    }
+}
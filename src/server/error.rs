@@ -23,12 +23,18 @@ pub enum Error {
     IndexOutOfBounds,
     #[error("provided identity commitment not found")]
     IdentityCommitmentNotFound,
+    #[error("no inclusion proof is available for the identity commitment yet")]
+    InclusionProofUnavailable,
     #[error("provided identity commitment is invalid")]
     InvalidCommitment,
     #[error("provided identity commitment is not in reduced form")]
     UnreducedCommitment,
+    #[error("little endian identity commitments must be 32 bytes of hex")]
+    ShortCommitment,
     #[error("provided identity commitment is already included")]
     DuplicateCommitment,
+    #[error("provided identity commitment is blocked")]
+    Blocked,
     #[error("Root mismatch between tree and contract.")]
     RootMismatch,
     #[error("Root provided in semaphore proof is too old.")]
@@ -37,6 +43,10 @@ pub enum Error {
     IdentityQueuedForDeletion,
     #[error("Identity has already been deleted.")]
     IdentityAlreadyDeleted,
+    #[error("invalid or tampered cursor")]
+    InvalidCursor,
+    #[error("the cursor's insertion is no longer known, resync from inclusion proofs")]
+    CursorRootUnknown,
     #[error("invalid JSON request: {0}")]
     InvalidSerialization(#[from] serde_json::Error),
     #[error(transparent)]
@@ -53,6 +63,8 @@ pub enum Error {
     ProverError,
     #[error("Failed to insert identity")]
     FailedToInsert,
+    #[error("The insertion failed: {0}")]
+    InsertionFailed(String),
     #[error("The provided batch size already exists")]
     BatchSizeAlreadyExists,
     #[error("The requested batch size does not exist")]
@@ -67,6 +79,22 @@ pub enum Error {
     Sqlx(#[from] sqlx::Error),
     #[error("The tree is uninitialized. Try again in a few moments.")]
     TreeStateUninitialized,
+    #[error("The sequencer is lagging behind the chain. Try again in a few moments.")]
+    Backpressure,
+    #[error("The local tree lags too far behind to reconcile. Try again in a few moments.")]
+    ReconciliationSkipped,
+    #[error("Insertions are failing, the sequencer is read-only until they recover.")]
+    ReadOnly,
+    #[error("Insertions are paused for maintenance. Try again later.")]
+    Paused,
+    #[error("The identity manager contract is paused. Try again later.")]
+    ContractPaused,
+    #[error("The gas spend limit is reached. Try again once the window rolls.")]
+    SpendLimitExceeded,
+    #[error("missing or invalid admin token")]
+    Unauthorized,
+    #[error("Too many insertions are waiting for this client. Try again later.")]
+    TooManyRequests,
     #[error(transparent)]
     Other(#[from] EyreError),
 }
@@ -75,14 +103,29 @@ impl Error {
     fn to_status_code(&self) -> StatusCode {
         match self {
             Self::InvalidMethod => StatusCode::METHOD_NOT_ALLOWED,
-            Self::InvalidPath | Self::IdentityCommitmentNotFound => StatusCode::NOT_FOUND,
+            Self::InvalidPath
+            | Self::IdentityCommitmentNotFound
+            | Self::InclusionProofUnavailable => StatusCode::NOT_FOUND,
             Self::InvalidContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
-            Self::IndexOutOfBounds | Self::InvalidCommitment | Self::InvalidSerialization(_) => {
-                StatusCode::BAD_REQUEST
-            }
+            Self::IndexOutOfBounds
+            | Self::InvalidCommitment
+            | Self::ShortCommitment
+            | Self::InvalidCursor
+            | Self::InvalidSerialization(_) => StatusCode::BAD_REQUEST,
             Self::IdentityAlreadyDeleted
             | Self::IdentityQueuedForDeletion
             | Self::DuplicateCommitment => StatusCode::CONFLICT,
+            Self::Backpressure
+            | Self::ReconciliationSkipped
+            | Self::ReadOnly
+            | Self::Paused
+            | Self::ContractPaused
+            | Self::SpendLimitExceeded
+            | Self::RootMismatch => StatusCode::SERVICE_UNAVAILABLE,
+            Self::CursorRootUnknown => StatusCode::GONE,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::Blocked => StatusCode::FORBIDDEN,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,321 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, IntGauge, IntGaugeVec,
+};
+use tokio::sync::oneshot;
+
+use crate::metrics;
+
+/// How many clients get their own queue depth label. The others are summed up
+/// as `other`, to bound the cardinality.
+const TOP_CLIENTS: usize = 10;
+
+static QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "sequencer_insert_queue_depth",
+        "Insertions waiting for a slot, by client. Only the clients with the most waiting \
+         insertions have their own label.",
+        &["client"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "sequencer_inserts_in_flight",
+        "Insertions holding a slot.",
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Bounds how many insertions are handled at once. Once every slot is taken,
+/// insertions wait in one queue per client, and freed slots go to the clients
+/// in turn, so that a client submitting faster than the others can't starve
+/// them. A client's own insertions are admitted in the order they arrived.
+#[derive(Debug)]
+pub struct FairQueue {
+    max_in_flight:         usize,
+    max_queued_per_client: usize,
+    state:                 Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    /// The clients with waiting insertions, in the order they're served next
+    clients:   VecDeque<(String, VecDeque<oneshot::Sender<Permit>>)>,
+}
+
+/// A slot, freed when dropped
+#[derive(Debug)]
+pub struct Permit {
+    queue: Option<Arc<FairQueue>>,
+}
+
+/// The client already has the maximum number of insertions waiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+/// Removes a waiter's queue entry once it stops waiting. Declared before the
+/// waiter's receiver, so that the receiver is dropped, and the entry closed,
+/// first.
+struct Waiting<'a> {
+    queue:  &'a FairQueue,
+    client: &'a str,
+}
+
+impl FairQueue {
+    pub fn new(max_in_flight: usize, max_queued_per_client: usize) -> Self {
+        Self {
+            max_in_flight,
+            max_queued_per_client,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Waits for a slot for an insertion of `client`. Fails right away if the
+    /// client has too many insertions waiting already.
+    pub async fn acquire(self: &Arc<Self>, client: &str) -> Result<Permit, QueueFull> {
+        let _waiting = Waiting {
+            queue: self,
+            client,
+        };
+
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+
+            // Slots are handed over to waiting insertions directly, so there
+            // are none while there's a free slot
+            if state.in_flight < self.max_in_flight {
+                state.in_flight += 1;
+                IN_FLIGHT.set(state.in_flight as i64);
+
+                return Ok(Permit {
+                    queue: Some(self.clone()),
+                });
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            match state.clients.iter_mut().find(|(id, _)| id == client) {
+                Some((_, waiters)) => {
+                    waiters.retain(|waiter| !waiter.is_closed());
+                    if waiters.len() >= self.max_queued_per_client {
+                        return Err(QueueFull);
+                    }
+
+                    waiters.push_back(sender);
+                }
+                None if self.max_queued_per_client == 0 => return Err(QueueFull),
+                None => state
+                    .clients
+                    .push_back((client.to_owned(), VecDeque::from([sender]))),
+            }
+            state.report_depths();
+
+            receiver
+        };
+
+        // Waiters are only dropped after being sent a permit, and the queue
+        // outlives them. A waiter that gives up after being sent one drops it
+        // along with the receiver, which frees the slot.
+        Ok(receiver.await.expect("Fair queue dropped a waiter"))
+    }
+
+    /// Drops the entries of the client's waiters that gave up
+    fn remove_closed(&self, client: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(position) = state.clients.iter().position(|(id, _)| id == client) else {
+            return;
+        };
+
+        let waiters = &mut state.clients[position].1;
+        waiters.retain(|waiter| !waiter.is_closed());
+        if waiters.is_empty() {
+            state.clients.remove(position);
+        }
+
+        state.report_depths();
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+
+        while let Some((client, mut waiters)) = state.clients.pop_front() {
+            let waiter = waiters.pop_front();
+            if !waiters.is_empty() {
+                state.clients.push_back((client, waiters));
+            }
+
+            let Some(waiter) = waiter else {
+                continue;
+            };
+
+            let permit = Permit {
+                queue: Some(self.clone()),
+            };
+            match waiter.send(permit) {
+                Ok(()) => {
+                    state.report_depths();
+                    return;
+                }
+                // The insertion was cancelled while waiting, e.g. because the
+                // request timed out. The slot stays taken for the next one.
+                Err(mut permit) => permit.queue = None,
+            }
+        }
+
+        state.in_flight -= 1;
+        IN_FLIGHT.set(state.in_flight as i64);
+        state.report_depths();
+    }
+}
+
+impl State {
+    fn report_depths(&self) {
+        let mut depths: Vec<_> = self
+            .clients
+            .iter()
+            .map(|(client, waiters)| {
+                let waiting = waiters.iter().filter(|waiter| !waiter.is_closed()).count();
+                (client.as_str(), waiting)
+            })
+            .collect();
+        depths.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+
+        QUEUE_DEPTH.reset();
+        for (client, waiting) in depths.iter().take(TOP_CLIENTS) {
+            QUEUE_DEPTH
+                .with_label_values(&[client])
+                .set(*waiting as i64);
+        }
+
+        let others: usize = depths.iter().skip(TOP_CLIENTS).map(|(_, n)| n).sum();
+        QUEUE_DEPTH.with_label_values(&["other"]).set(others as i64);
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.queue.remove_closed(self.client);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn admitted_order(queue: &Arc<FairQueue>, clients: &[&'static str]) -> Vec<&'static str> {
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let held = queue.acquire("holder").await.unwrap();
+        let waiters: Vec<_> = clients
+            .iter()
+            .map(|&client| {
+                let queue = queue.clone();
+                let order = order.clone();
+
+                tokio::spawn(async move {
+                    let _permit = queue.acquire(client).await.unwrap();
+                    order.lock().unwrap().push(client);
+                })
+            })
+            .collect();
+
+        // Queues the waiters in order
+        for _ in 0..clients.len() {
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn clients_are_admitted_in_turn() {
+        let queue = Arc::new(FairQueue::new(1, 8));
+
+        let order = admitted_order(&queue, &["spammer", "spammer", "spammer", "other"]).await;
+
+        assert_eq!(order, vec!["spammer", "other", "spammer", "spammer"]);
+        assert_eq!(queue.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_are_skipped() {
+        let queue = Arc::new(FairQueue::new(1, 8));
+
+        let held = queue.acquire("a").await.unwrap();
+        let cancelled = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire("b").await.unwrap() }
+        });
+        tokio::task::yield_now().await;
+        cancelled.abort();
+        let _ = cancelled.await;
+
+        drop(held);
+
+        let _permit = queue.acquire("c").await.unwrap();
+        assert_eq!(queue.state.lock().unwrap().in_flight, 1);
+        assert!(queue.state.lock().unwrap().clients.is_empty());
+    }
+
+    #[tokio::test]
+    async fn waiters_giving_up_after_admission_free_the_slot() {
+        let queue = Arc::new(FairQueue::new(1, 8));
+
+        let held = queue.acquire("a").await.unwrap();
+        let admitted = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire("b").await.unwrap() }
+        });
+        tokio::task::yield_now().await;
+
+        // Hands the slot over to the waiter, which gives up before taking it
+        drop(held);
+        admitted.abort();
+        let _ = admitted.await;
+
+        assert_eq!(queue.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn full_client_queues_are_rejected() {
+        let queue = Arc::new(FairQueue::new(1, 1));
+
+        let _held = queue.acquire("a").await.unwrap();
+        let _waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire("b").await }
+        });
+        tokio::task::yield_now().await;
+
+        assert_eq!(queue.acquire("b").await.unwrap_err(), QueueFull);
+        // Other clients still get in line
+        let other = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire("c").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!other.is_finished());
+        assert_eq!(queue.state.lock().unwrap().clients.len(), 2);
+    }
+}
@@ -1,15 +1,21 @@
+use std::cmp::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
 use once_cell::sync::Lazy;
-use prometheus::{linear_buckets, register_gauge, register_histogram, Gauge, Histogram};
+use prometheus::{
+    exponential_buckets, linear_buckets, register_gauge_with_registry,
+    register_histogram_with_registry, register_int_gauge_vec_with_registry, Gauge, Histogram,
+    IntGaugeVec,
+};
 use tokio::sync::{broadcast, mpsc, Mutex, Notify, RwLock};
 use tokio::task::JoinHandle;
 use tracing::{info, instrument, warn};
 
 use crate::app::App;
-use crate::database::query::DatabaseQuery as _;
+use crate::database::query::{DatabaseQuery as _, DEFAULT_PRIORITY};
 use crate::database::Database;
+use crate::metrics;
 
 pub mod tasks;
 
@@ -19,6 +25,7 @@ const FINALIZE_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
 const QUEUE_MONITOR_BACKOFF: Duration = Duration::from_secs(5);
 const INSERT_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
 const DELETE_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
+const RECONCILE_ROOTS_BACKOFF: Duration = Duration::from_secs(5);
 
 struct RunningInstance {
     handles:         Vec<JoinHandle<()>>,
@@ -26,22 +33,59 @@ struct RunningInstance {
 }
 
 static PENDING_IDENTITIES: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!("pending_identities", "Identities not submitted on-chain").unwrap()
+    register_gauge_with_registry!(
+        "pending_identities",
+        "Identities not submitted on-chain",
+        metrics::registry()
+    )
+    .unwrap()
 });
 
 static UNPROCESSED_IDENTITIES: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!(
+    register_gauge_with_registry!(
         "unprocessed_identities",
-        "Identities not processed by identity committer"
+        "Identities not processed by identity committer",
+        metrics::registry()
     )
     .unwrap()
 });
 
+static UNPROCESSED_IDENTITIES_BY_PRIORITY: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "unprocessed_identities_by_priority",
+        "Identities not processed by identity committer, by priority relative to the default",
+        &["priority"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// Priorities are bucketed relative to the default one, to bound the label
+/// cardinality
+pub(crate) fn priority_label(priority: i16) -> &'static str {
+    match priority.cmp(&DEFAULT_PRIORITY) {
+        Ordering::Less => "low",
+        Ordering::Equal => "default",
+        Ordering::Greater => "high",
+    }
+}
+
 static BATCH_SIZES: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!(
+    register_histogram_with_registry!(
         "submitted_batch_sizes",
         "Submitted batch size",
-        linear_buckets(f64::from(1), f64::from(1), 100).unwrap()
+        linear_buckets(f64::from(1), f64::from(1), 100).unwrap(),
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+static BATCH_FILL_TIME: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram_with_registry!(
+        "insertion_batch_fill_time_seconds",
+        "Time between picking up the first identity of an insertion batch and submitting it",
+        exponential_buckets(0.1, 2.0, 16).unwrap(),
+        metrics::registry()
     )
     .unwrap()
 });
@@ -142,19 +186,39 @@ impl TaskMonitor {
         );
         handles.push(queue_monitor_handle);
 
+        // Compare the contract root against the local tree
+        let app = self.app.clone();
+        let reconcile_roots = move || tasks::reconcile_roots::reconcile_roots(app.clone());
+        let reconcile_roots_handle = crate::utils::spawn_monitored_with_backoff(
+            reconcile_roots,
+            shutdown_sender.clone(),
+            RECONCILE_ROOTS_BACKOFF,
+        );
+        handles.push(reconcile_roots_handle);
+
         // Process identities
         let base_next_batch_notify = Arc::new(Notify::new());
 
+        // Keeps batches from being created, or identities from being added to
+        // the tree, while batches are rolled back
+        let pending_insertion_mutex = Arc::new(Mutex::new(()));
+
+        // Keeps later batches from being sent while a failed transaction is
+        // resubmitted
+        let submission_mutex = Arc::new(Mutex::new(()));
+
         // Create batches
         let app = self.app.clone();
         let next_batch_notify = base_next_batch_notify.clone();
         let wake_up_notify = base_wake_up_notify.clone();
+        let insertion_mutex = pending_insertion_mutex.clone();
 
         let create_batches = move || {
             tasks::create_batches::create_batches(
                 app.clone(),
                 next_batch_notify.clone(),
                 wake_up_notify.clone(),
+                insertion_mutex.clone(),
             )
         };
         let create_batches_handle = crate::utils::spawn_monitored_with_backoff(
@@ -168,6 +232,8 @@ impl TaskMonitor {
         let app = self.app.clone();
         let next_batch_notify = base_next_batch_notify.clone();
         let wake_up_notify = base_wake_up_notify.clone();
+        let insertion_mutex = pending_insertion_mutex.clone();
+        let batch_submission_mutex = submission_mutex.clone();
 
         let process_identities = move || {
             tasks::process_batches::process_batches(
@@ -175,6 +241,8 @@ impl TaskMonitor {
                 monitored_txs_sender.clone(),
                 next_batch_notify.clone(),
                 wake_up_notify.clone(),
+                insertion_mutex.clone(),
+                batch_submission_mutex.clone(),
             )
         };
         let process_identities_handle = crate::utils::spawn_monitored_with_backoff(
@@ -186,8 +254,15 @@ impl TaskMonitor {
 
         // Monitor transactions
         let app = self.app.clone();
-        let monitor_txs =
-            move || tasks::monitor_txs::monitor_txs(app.clone(), monitored_txs_receiver.clone());
+        let insertion_mutex = pending_insertion_mutex.clone();
+        let monitor_txs = move || {
+            tasks::monitor_txs::monitor_txs(
+                app.clone(),
+                monitored_txs_receiver.clone(),
+                submission_mutex.clone(),
+                insertion_mutex.clone(),
+            )
+        };
         let monitor_txs_handle = crate::utils::spawn_monitored_with_backoff(
             monitor_txs,
             shutdown_sender.clone(),
@@ -195,8 +270,6 @@ impl TaskMonitor {
         );
         handles.push(monitor_txs_handle);
 
-        let pending_insertion_mutex = Arc::new(Mutex::new(()));
-
         // Insert identities
         let app = self.app.clone();
         let wake_up_notify = base_wake_up_notify.clone();
@@ -250,6 +323,15 @@ impl TaskMonitor {
     async fn log_unprocessed_identities_count(database: &Database) -> anyhow::Result<()> {
         let identities = database.count_unprocessed_identities().await?;
         UNPROCESSED_IDENTITIES.set(f64::from(identities));
+
+        let by_priority = database.count_unprocessed_identities_by_priority().await?;
+        UNPROCESSED_IDENTITIES_BY_PRIORITY.reset();
+        for (priority, count) in by_priority {
+            UNPROCESSED_IDENTITIES_BY_PRIORITY
+                .with_label_values(&[priority_label(priority)])
+                .add(count);
+        }
+
         Ok(())
     }
 
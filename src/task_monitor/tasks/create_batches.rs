@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
 use chrono::{DateTime, Utc};
@@ -6,10 +7,13 @@ use ethers::prelude::U256;
 use ruint::Uint;
 use semaphore::merkle_tree::Proof;
 use semaphore::poseidon_tree::{Branch, PoseidonHash};
-use tokio::sync::Notify;
+use serde_json::json;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::Instant;
 use tokio::{select, time};
 use tracing::instrument;
 
+use crate::alerts::Alert;
 use crate::app::App;
 use crate::contracts::IdentityManager;
 use crate::database;
@@ -20,7 +24,7 @@ use crate::identity_tree::{
 };
 use crate::prover::identity::Identity;
 use crate::prover::Prover;
-use crate::task_monitor::TaskMonitor;
+use crate::task_monitor::{TaskMonitor, BATCH_FILL_TIME};
 use crate::utils::batch_type::BatchType;
 
 /// The number of seconds either side of the timer tick to treat as enough to
@@ -31,6 +35,7 @@ pub async fn create_batches(
     app: Arc<App>,
     next_batch_notify: Arc<Notify>,
     wake_up_notify: Arc<Notify>,
+    pending_insertions_mutex: Arc<Mutex<()>>,
 ) -> anyhow::Result<()> {
     tracing::info!("Awaiting for a clean slate");
     app.identity_manager.await_clean_slate().await?;
@@ -57,8 +62,17 @@ pub async fn create_batches(
         .await?
         .unwrap_or(Utc::now());
 
+    // When the first identity of the current insertion batch was picked up
+    let mut batch_started_at: Option<Instant> = None;
+    let batch_window = app.config.app.batch_window;
+
     loop {
-        // We wait either for a timer tick or a full batch
+        let batch_window_deadline = batch_started_at
+            .zip(batch_window)
+            .map(|(started_at, window)| started_at + window);
+
+        // We wait either for a timer tick, a full batch or the end of the batch
+        // window
         select! {
             _ = timer.tick() => {
                 tracing::info!("Identity batch insertion woken due to timeout");
@@ -67,6 +81,10 @@ pub async fn create_batches(
             () = wake_up_notify.notified() => {
                 tracing::trace!("Identity batch insertion woken due to request");
             },
+
+            () = sleep_until(batch_window_deadline) => {
+                tracing::info!("Identity batch insertion woken due to batch window");
+            },
         }
 
         let Some(batch_type) = determine_batch_type(app.tree_state()?.batching_tree()) else {
@@ -76,9 +94,20 @@ pub async fn create_batches(
         let batch_size = if batch_type.is_deletion() {
             app.identity_manager.max_deletion_batch_size().await
         } else {
-            app.identity_manager.max_insertion_batch_size().await
+            let max_batch_size = app.identity_manager.max_insertion_batch_size().await;
+
+            app.config
+                .app
+                .batch_size
+                .map_or(max_batch_size, |batch_size| {
+                    batch_size.get().min(max_batch_size)
+                })
         };
 
+        // Held until the updates are committed, so that they aren't rolled back in
+        // the meantime
+        let _guard = pending_insertions_mutex.lock().await;
+
         let updates = app
             .tree_state()?
             .batching_tree()
@@ -89,6 +118,34 @@ pub async fn create_batches(
             continue;
         }
 
+        if app.health.checks_relayer_balance() {
+            // Checked again on the next tick, rather than restarting the task
+            let balance = match app.identity_manager.relayer_balance().await {
+                Ok(balance) => balance,
+                Err(error) => {
+                    tracing::warn!(?error, "Failed to fetch the relayer balance. Waiting.");
+                    continue;
+                }
+            };
+            app.health.set_relayer_balance(balance);
+
+            if app.health.is_balance_insufficient() {
+                tracing::trace!(%balance, "Relayer balance is insufficient. Waiting.");
+                app.alerts
+                    .critical(Alert::new(
+                        "insufficient_balance",
+                        "Batch submission paused, the relayer balance is too low",
+                        json!({ "balance": balance.to_string() }),
+                    ))
+                    .await;
+                continue;
+            }
+        }
+
+        if !spend_within_limit(&app).await {
+            continue;
+        }
+
         // If the batch is a deletion, process immediately without resetting the timer
         if batch_type.is_deletion() {
             commit_identities(
@@ -110,9 +167,15 @@ pub async fn create_batches(
 
             let batch_time_elapsed = current_time >= timeout_batch_time;
 
-            // If the batch size is full or if the insertion time has elapsed
-            // process the batch
-            if updates.len() >= batch_size || batch_time_elapsed {
+            let started_at = *batch_started_at.get_or_insert_with(Instant::now);
+            let batch_window_elapsed =
+                batch_window.is_some_and(|window| started_at.elapsed() >= window);
+
+            // If the batch size is full or if the insertion time or the batch
+            // window has elapsed process the batch
+            if updates.len() >= batch_size || batch_time_elapsed || batch_window_elapsed {
+                record_fill_time(batch_started_at.take());
+
                 commit_identities(
                     &app.database,
                     &app.identity_manager,
@@ -149,6 +212,8 @@ pub async fn create_batches(
 
                 // If the next batch is deletion, process the current insertion batch
                 if next_batch_is_deletion {
+                    record_fill_time(batch_started_at.take());
+
                     commit_identities(
                         &app.database,
                         &app.identity_manager,
@@ -175,6 +240,51 @@ pub async fn create_batches(
     }
 }
 
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Records how long an insertion batch took to fill. Its size is recorded by
+/// `TaskMonitor::log_batch_size`.
+fn record_fill_time(started_at: Option<Instant>) {
+    let fill_time = started_at.map_or(Duration::ZERO, |started_at| started_at.elapsed());
+    BATCH_FILL_TIME.observe(fill_time.as_secs_f64());
+}
+
+/// Whether the gas spend allows another batch, alerting once it's close to or
+/// at the limit
+async fn spend_within_limit(app: &App) -> bool {
+    let (alert, within_limit) = match app.identity_manager.check_spend_limit() {
+        Ok(Some(spend)) if spend.is_near_limit() => (
+            Alert::new(
+                "spend_limit_near",
+                "The gas spend is close to the limit of the window",
+                json!({ "spent": spend.spent.to_string(), "budget": spend.budget.to_string() }),
+            ),
+            true,
+        ),
+        Ok(_) => return true,
+        Err(error) => {
+            tracing::trace!(%error, "Gas spend limit reached. Waiting.");
+            (
+                Alert::new(
+                    "spend_limit_exceeded",
+                    "Batch submission paused, the gas spend limit of the window is reached",
+                    json!({ "error": error.to_string() }),
+                ),
+                false,
+            )
+        }
+    };
+
+    app.alerts.critical(alert).await;
+
+    within_limit
+}
+
 async fn ensure_batch_chain_initialized(app: &Arc<App>) -> anyhow::Result<()> {
     let batch_head = app.database.get_batch_head().await?;
     if batch_head.is_none() {
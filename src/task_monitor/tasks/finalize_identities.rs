@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -8,7 +10,10 @@ use ethers::abi::RawLog;
 use ethers::contract::EthEvent;
 use ethers::providers::Middleware;
 use ethers::types::{Address, Log, Topic, ValueOrArray, U256};
-use tracing::{info, instrument};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec_with_registry, IntCounterVec};
+use tokio::sync::broadcast;
+use tracing::{info, instrument, warn};
 
 use crate::app::App;
 use crate::contracts::abi::{BridgedWorldId, RootAddedFilter, TreeChangeKind, TreeChangedFilter};
@@ -16,9 +21,133 @@ use crate::contracts::scanner::BlockScanner;
 use crate::contracts::IdentityManager;
 use crate::database::query::DatabaseQuery as _;
 use crate::database::Database;
-use crate::identity_tree::{Canonical, Intermediate, TreeVersion, TreeWithNextVersion};
+use crate::ethereum::confirmation::Confirmations;
+use crate::ethereum::read::head_subscription::HeadSubscription;
+use crate::identity_tree::proof_cache::ProofCache;
+use crate::identity_tree::recent_roots::{RecentRoot, RecentRoots};
+use crate::identity_tree::{
+    Canonical, Intermediate, LeafUpdate, TreeVersion, TreeVersionReadOps, TreeWithNextVersion,
+};
+use crate::metrics;
 use crate::utils::retry_tx;
 
+static EVENT_SOURCE_SWITCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "event_source_switches",
+        "Switches between the new block subscription and polling for triggering event scans, by \
+         the source switched to.",
+        &["source"],
+        metrics::registry()
+    )
+    .unwrap()
+});
+
+/// What triggers the next scan for chain events
+enum ScanTrigger {
+    /// Every `time_between_scans`
+    Polling,
+    /// Every new block announced by the subscription, falling back to polling
+    /// while it's stalled
+    Subscription {
+        subscription: HeadSubscription,
+        stalled:      bool,
+    },
+}
+
+impl ScanTrigger {
+    fn new(app: &App) -> Self {
+        match &app.config.providers.ws_network_provider {
+            Some(url) => Self::Subscription {
+                subscription: HeadSubscription::spawn(url.clone().into()),
+                stalled:      false,
+            },
+            None => Self::Polling,
+        }
+    }
+
+    /// Waits until the next scan is due
+    async fn wait(&mut self, app: &App) {
+        let config = &app.config.app;
+        let client = app.identity_manager.abi().client();
+
+        self.wait_for(
+            config.time_between_scans,
+            config.subscription_stall_timeout,
+            || async {
+                client
+                    .get_block_number()
+                    .await
+                    .map(|head| head.as_u64())
+                    .map_err(anyhow::Error::from)
+            },
+        )
+        .await;
+    }
+
+    /// Waits until the next scan is due. `chain_head` is only fetched when the
+    /// subscription goes quiet, to tell whether it stalled.
+    async fn wait_for<F, Fut>(
+        &mut self,
+        time_between_scans: Duration,
+        stall_timeout: Duration,
+        chain_head: F,
+    ) where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<u64>>,
+    {
+        let Self::Subscription {
+            subscription,
+            stalled,
+        } = self
+        else {
+            tokio::time::sleep(time_between_scans).await;
+            return;
+        };
+
+        if *stalled {
+            if subscription.next_head(time_between_scans).await.is_some() {
+                info!("New block subscription recovered, switching back from polling");
+                EVENT_SOURCE_SWITCHES
+                    .with_label_values(&["subscription"])
+                    .inc();
+                *stalled = false;
+            }
+
+            return;
+        }
+
+        if subscription.next_head(stall_timeout).await.is_some() {
+            return;
+        }
+
+        // Only a stall if blocks were produced in the meantime. If that can't
+        // be told, this round scans anyway and the next one checks again.
+        let chain_head = match chain_head().await {
+            Ok(chain_head) => chain_head,
+            Err(error) => {
+                warn!(
+                    ?error,
+                    "Failed to fetch the chain head, can't tell whether the new block \
+                     subscription stalled"
+                );
+                return;
+            }
+        };
+        if subscription
+            .last_head()
+            .map_or(true, |last_head| chain_head > last_head)
+        {
+            warn!(
+                chain_head,
+                last_head = subscription.last_head(),
+                "New block subscription stalled, switching to polling"
+            );
+            EVENT_SOURCE_SWITCHES.with_label_values(&["polling"]).inc();
+            *stalled = true;
+        }
+    }
+}
+
 pub async fn finalize_roots(app: Arc<App>) -> anyhow::Result<()> {
     let mainnet_abi = app.identity_manager.abi();
     let secondary_abis = app.identity_manager.secondary_abis();
@@ -28,20 +157,50 @@ pub async fn finalize_roots(app: Arc<App>) -> anyhow::Result<()> {
         app.config.app.scanning_window_size,
     )
     .await?
-    .with_offset(app.config.app.scanning_chain_head_offset);
+    .with_offset(app.config.app.scanning_chain_head_offset)
+    .with_overlap(app.config.app.scanning_window_overlap)
+    .with_confirmations(Confirmations::new(&app.config.app))
+    .with_block_hash_pinning();
 
-    let mut secondary_scanners =
-        init_secondary_scanners(secondary_abis, app.config.app.scanning_window_size).await?;
+    let mut secondary_scanners = init_secondary_scanners(
+        secondary_abis,
+        app.config.app.scanning_window_size,
+        app.config.app.scanning_window_overlap,
+    )
+    .await?;
 
     let mainnet_address = mainnet_abi.address();
 
+    let mut scan_trigger = ScanTrigger::new(&app);
+
+    // Fetched logs that weren't applied yet, because of `max_events_per_scan`
+    let mut pending_logs = VecDeque::new();
+
     loop {
-        let mainnet_logs = fetch_mainnet_logs(&mut mainnet_scanner, mainnet_address).await?;
+        // The scanner only moves on once the backlog is applied, so that the
+        // logs stay in order
+        if pending_logs.is_empty() {
+            pending_logs.extend(fetch_mainnet_logs(&mut mainnet_scanner, mainnet_address).await?);
+
+            // The roots of the replacing blocks are pushed again as they're
+            // applied
+            if let Some(reorged_from) = mainnet_scanner.take_reorged_from() {
+                app.recent_roots.remove_from_block(reorged_from);
+            }
+
+            app.health.set_block_lag(mainnet_scanner.block_lag());
+            app.record_chain_head(mainnet_scanner.chain_head()).await;
+        }
+
+        let applied = events_to_apply(app.config.app.max_events_per_scan, pending_logs.len());
+        let mainnet_logs: Vec<Log> = pending_logs.drain(..applied).collect();
 
         finalize_mainnet_roots(
             &app.database,
             &app.identity_manager,
             app.tree_state()?.processed_tree(),
+            &app.leaf_updates,
+            &app.recent_roots,
             &mainnet_logs,
             app.config.app.max_epoch_duration,
         )
@@ -54,14 +213,38 @@ pub async fn finalize_roots(app: Arc<App>) -> anyhow::Result<()> {
             &app.database,
             &app.identity_manager,
             app.tree_state()?.mined_tree(),
+            &app.proof_cache,
             roots,
         )
         .await?;
 
-        tokio::time::sleep(app.config.app.time_between_scans).await;
+        // Everything before the next pending log is applied
+        let applied_block = pending_logs
+            .front()
+            .and_then(|log| log.block_number)
+            .map_or(mainnet_scanner.current_block(), |block_number| {
+                block_number.as_u64()
+            });
+        app.health
+            .set_applied_block(applied_block.saturating_sub(1));
+
+        if pending_logs.is_empty() {
+            scan_trigger.wait(&app).await;
+        } else {
+            info!(
+                remaining = pending_logs.len(),
+                "Applying the remaining tree changes in the next scan"
+            );
+            tokio::task::yield_now().await;
+        }
     }
 }
 
+/// How many of the `pending` fetched logs are applied in this scan
+fn events_to_apply(max_events_per_scan: Option<NonZeroUsize>, pending: usize) -> usize {
+    max_events_per_scan.map_or(pending, |max_events| max_events.get().min(pending))
+}
+
 async fn fetch_mainnet_logs<M>(
     mainnet_scanner: &mut BlockScanner<M>,
     mainnet_address: Address,
@@ -120,6 +303,8 @@ async fn finalize_mainnet_roots(
     database: &Database,
     identity_manager: &IdentityManager,
     processed_tree: &TreeVersion<Intermediate>,
+    leaf_updates: &broadcast::Sender<LeafUpdate>,
+    recent_roots: &RecentRoots,
     logs: &[Log],
     max_epoch_duration: Duration,
 ) -> Result<(), anyhow::Error> {
@@ -159,7 +344,19 @@ async fn finalize_mainnet_roots(
             .await?;
         }
 
-        let updates_count = processed_tree.apply_updates_up_to(post_root.into());
+        let updates = processed_tree.apply_leaf_updates_up_to(post_root.into());
+        let updates_count = updates.len();
+
+        // Sending only fails if there are no subscribers
+        for update in updates {
+            let _ = leaf_updates.send(update);
+        }
+
+        recent_roots.push(RecentRoot {
+            root:         post_root.into(),
+            block_number: log.block_number.map(|block_number| block_number.as_u64()),
+            tree_size:    processed_tree.next_leaf(),
+        });
 
         info!(updates_count, ?pre_root, ?post_root, "Mined tree updated");
     }
@@ -172,6 +369,7 @@ async fn finalize_secondary_roots(
     database: &Database,
     identity_manager: &IdentityManager,
     finalized_tree: &TreeVersion<Canonical>,
+    proof_cache: &ProofCache,
     roots: Vec<U256>,
 ) -> Result<(), anyhow::Error> {
     for root in roots {
@@ -183,7 +381,12 @@ async fn finalize_secondary_roots(
         }
 
         database.mark_root_as_mined_tx(&root.into()).await?;
-        finalized_tree.apply_updates_up_to(root.into());
+
+        let prev_root = finalized_tree.get_root();
+        let updates = finalized_tree.apply_leaf_updates_up_to(root.into());
+        proof_cache
+            .update(finalized_tree.clone(), prev_root, updates)
+            .await;
 
         info!(?root, "Root finalized");
     }
@@ -194,6 +397,7 @@ async fn finalize_secondary_roots(
 async fn init_secondary_scanners<T>(
     providers: &[BridgedWorldId<T>],
     scanning_window_size: u64,
+    scanning_window_overlap: u64,
 ) -> anyhow::Result<HashMap<Address, BlockScanner<Arc<T>>>>
 where
     T: Middleware,
@@ -202,8 +406,9 @@ where
     let mut secondary_scanners = HashMap::new();
 
     for bridged_abi in providers {
-        let scanner =
-            BlockScanner::new_latest(bridged_abi.client().clone(), scanning_window_size).await?;
+        let scanner = BlockScanner::new_latest(bridged_abi.client().clone(), scanning_window_size)
+            .await?
+            .with_overlap(scanning_window_overlap);
 
         let address = bridged_abi.address();
 
@@ -230,7 +435,9 @@ fn extract_roots_from_mainnet_logs(mainnet_logs: Vec<Log>) -> Vec<U256> {
 fn raw_log_to_tree_changed(log: &Log) -> Option<TreeChangedFilter> {
     let raw_log = RawLog::from((log.topics.clone(), log.data.to_vec()));
 
-    TreeChangedFilter::decode_log(&raw_log).ok()
+    TreeChangedFilter::decode_any_layout(&raw_log)
+        .map_err(|error| warn!(?error, "Skipping undecodable TreeChanged log"))
+        .ok()
 }
 
 fn extract_roots_from_secondary_logs(logs: &[Log]) -> Vec<U256> {
@@ -295,3 +502,80 @@ async fn update_eligible_recoveries(
     })
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use super::*;
+
+    const TIME_BETWEEN_SCANS: Duration = Duration::from_secs(30);
+    const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+    impl ScanTrigger {
+        fn is_stalled(&self) -> bool {
+            matches!(self, Self::Subscription { stalled: true, .. })
+        }
+    }
+
+    #[test]
+    fn events_are_applied_up_to_the_limit() {
+        assert_eq!(events_to_apply(None, 500), 500);
+        assert_eq!(events_to_apply(NonZeroUsize::new(100), 500), 100);
+        assert_eq!(events_to_apply(NonZeroUsize::new(100), 40), 40);
+        assert_eq!(events_to_apply(NonZeroUsize::new(100), 0), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_subscriptions_fall_back_to_polling() {
+        let (heads, receiver) = watch::channel(None);
+        let mut trigger = ScanTrigger::Subscription {
+            subscription: HeadSubscription::new(receiver),
+            stalled:      false,
+        };
+
+        heads.send(Some(1)).unwrap();
+        trigger
+            .wait_for(TIME_BETWEEN_SCANS, STALL_TIMEOUT, || async { Ok(1) })
+            .await;
+        assert!(!trigger.is_stalled());
+
+        // Quiet, but so is the chain
+        trigger
+            .wait_for(TIME_BETWEEN_SCANS, STALL_TIMEOUT, || async { Ok(1) })
+            .await;
+        assert!(!trigger.is_stalled());
+
+        // A failure to tell doesn't count as a stall, nor fails the scans
+        trigger
+            .wait_for(TIME_BETWEEN_SCANS, STALL_TIMEOUT, || async {
+                Err(anyhow::anyhow!("connection reset"))
+            })
+            .await;
+        assert!(!trigger.is_stalled());
+
+        // The chain moved on without the subscription
+        trigger
+            .wait_for(TIME_BETWEEN_SCANS, STALL_TIMEOUT, || async { Ok(2) })
+            .await;
+        assert!(trigger.is_stalled());
+
+        // Polls while it's stalled
+        let started_at = tokio::time::Instant::now();
+        trigger
+            .wait_for(TIME_BETWEEN_SCANS, STALL_TIMEOUT, || async {
+                Err(anyhow::anyhow!(
+                    "The chain head isn't checked while polling"
+                ))
+            })
+            .await;
+        assert_eq!(started_at.elapsed(), TIME_BETWEEN_SCANS);
+        assert!(trigger.is_stalled());
+
+        heads.send(Some(3)).unwrap();
+        trigger
+            .wait_for(TIME_BETWEEN_SCANS, STALL_TIMEOUT, || async { Ok(3) })
+            .await;
+        assert!(!trigger.is_stalled());
+    }
+}
@@ -1,6 +1,9 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use prometheus::{exponential_buckets, register_histogram_vec_with_registry, HistogramVec};
 use tokio::sync::{Mutex, Notify};
 use tokio::time::sleep;
 use tracing::instrument;
@@ -10,6 +13,23 @@ use crate::database::query::DatabaseQuery as _;
 use crate::database::types::UnprocessedCommitment;
 use crate::database::Database;
 use crate::identity_tree::{Latest, TreeVersion, TreeVersionReadOps, UnprocessedStatus};
+use crate::metrics;
+use crate::task_monitor::priority_label;
+
+// Together with `insertion_batch_fill_time_seconds` and
+// `sequencer_confirmation_latency_seconds` this covers the time from an insert
+// request to its batch being mined
+static QUEUE_WAIT: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "sequencer_queue_wait_seconds",
+        "Time identities spend in the insert queue, from being queued until they are added to the \
+         tree for batching, by priority relative to the default.",
+        &["priority"],
+        exponential_buckets(0.1, 2.0, 20).unwrap(),
+        metrics::registry()
+    )
+    .unwrap()
+});
 
 pub async fn insert_identities(
     app: Arc<App>,
@@ -17,6 +37,23 @@ pub async fn insert_identities(
     wake_up_notify: Arc<Notify>,
 ) -> anyhow::Result<()> {
     loop {
+        // The queue is held, the identities stay eligible for when it resumes
+        if app.health.is_paused() {
+            sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+
+        if let Some(max_queue_age) = app.config.app.max_queue_age {
+            let expired = app
+                .database
+                .expire_unprocessed_identities(max_queue_age)
+                .await?;
+
+            if expired > 0 {
+                tracing::warn!(expired, ?max_queue_age, "Expired queued identities");
+            }
+        }
+
         // get commits from database
         let unprocessed = app
             .database
@@ -59,7 +96,7 @@ async fn insert_identities_batch(
                 .remove_unprocessed_identity(&identity.commitment)
                 .await?;
         } else {
-            filtered_identities.push(identity.commitment);
+            filtered_identities.push(identity);
         }
     }
 
@@ -74,11 +111,15 @@ async fn insert_identities_batch(
          {next_db_index}"
     );
 
-    let data = latest_tree.append_many(&filtered_identities);
+    let commitments: Vec<_> = filtered_identities
+        .iter()
+        .map(|identity| identity.commitment)
+        .collect();
+    let data = latest_tree.append_many(&commitments);
 
     assert_eq!(
         data.len(),
-        filtered_identities.len(),
+        commitments.len(),
         "Length mismatch when appending identities to tree"
     );
 
@@ -86,11 +127,27 @@ async fn insert_identities_batch(
 
     for ((root, _proof, leaf_index), identity) in items {
         database
-            .insert_pending_identity(leaf_index, &identity, &root)
+            .insert_pending_identity(leaf_index, &identity.commitment, &root)
+            .await?;
+
+        database
+            .remove_unprocessed_identity(&identity.commitment)
             .await?;
 
-        database.remove_unprocessed_identity(&identity).await?;
+        observe_queue_wait(&identity);
     }
 
     Ok(())
 }
+
+fn observe_queue_wait(identity: &UnprocessedCommitment) {
+    // Measured from when it was queued, so that a delayed eligibility counts
+    // as waiting too
+    let wait = (Utc::now() - identity.created_at)
+        .to_std()
+        .unwrap_or_default();
+
+    QUEUE_WAIT
+        .with_label_values(&[priority_label(identity.priority)])
+        .observe(wait.as_secs_f64());
+}
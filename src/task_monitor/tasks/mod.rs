@@ -5,3 +5,4 @@ pub mod insert_identities;
 pub mod monitor_queue;
 pub mod monitor_txs;
 pub mod process_batches;
+pub mod reconcile_roots;
@@ -1,23 +1,293 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use tokio::sync::{mpsc, Mutex};
 
+use super::process_batches::{
+    accepted_at, alert_on_submission_error, budget_exhausted, give_up_batch, insertion_timed_out,
+    is_past_deadline, within_insertion_timeout, within_submission_budget,
+};
 use crate::app::App;
-use crate::ethereum::write::TransactionId;
+use crate::database::query::DatabaseQuery as _;
+use crate::database::types::{BatchEntry, SubmissionEntry, SubmissionStatus};
+use crate::ethereum::write::{TransactionId, TxError};
 
 pub async fn monitor_txs(
     app: Arc<App>,
     monitored_txs_receiver: Arc<Mutex<mpsc::Receiver<TransactionId>>>,
+    submission_mutex: Arc<Mutex<()>>,
+    pending_insertions_mutex: Arc<Mutex<()>>,
 ) -> anyhow::Result<()> {
     let mut monitored_txs_receiver = monitored_txs_receiver.lock().await;
 
-    while let Some(tx) = monitored_txs_receiver.recv().await {
-        assert!(
-            (app.identity_manager.mine_transaction(tx.clone()).await?),
-            "Failed to mine transaction: {}",
-            tx
-        );
+    while let Some(mut tx) = monitored_txs_receiver.recv().await {
+        let batch = app.database.get_batch_for_transaction(&tx.0).await?;
+        let accepted_at = match &batch {
+            Some(batch) => Some(accepted_at(&app, batch).await?),
+            None => None,
+        };
+        let mut resubmissions = 0;
+
+        let status = loop {
+            let submission = match &batch {
+                Some(batch) => app.database.get_submission(&batch.next_root).await?,
+                None => None,
+            };
+
+            let mined = match mine_transaction(&app, &tx, accepted_at, submission.as_ref()).await {
+                // Its receipt is gone after the grace period, so it's sent
+                // again like a failed one
+                Err(error)
+                    if matches!(error.downcast_ref::<TxError>(), Some(TxError::Dropped(_))) =>
+                {
+                    tracing::warn!(?tx, %error, "Transaction dropped");
+                    Ok(false)
+                }
+                mined => mined,
+            };
+
+            match mined {
+                Ok(true) => break SubmissionStatus::Mined,
+                // Failed in the relayer or dropped, so it may be sent again
+                Ok(false) => {
+                    let (Some(batch), Some(accepted_at)) = (&batch, accepted_at) else {
+                        break SubmissionStatus::Failed;
+                    };
+
+                    if let Some(error) = submission
+                        .as_ref()
+                        .and_then(|submission| budget_exhausted(&app, submission))
+                    {
+                        break give_up_sent_batch(
+                            &app,
+                            &submission_mutex,
+                            &pending_insertions_mutex,
+                            &tx,
+                            batch,
+                            &error.into(),
+                        )
+                        .await?;
+                    }
+
+                    match resubmit(
+                        &app,
+                        &submission_mutex,
+                        &tx,
+                        batch,
+                        accepted_at,
+                        resubmissions,
+                    )
+                    .await?
+                    {
+                        Some(resubmitted) => {
+                            tx = resubmitted;
+                            resubmissions += 1;
+                        }
+                        None => break SubmissionStatus::Failed,
+                    }
+                }
+                // Mined but reverted, so the batch didn't make it on chain
+                Err(error)
+                    if matches!(
+                        error.downcast_ref::<TxError>(),
+                        Some(TxError::Reverted { .. })
+                    ) =>
+                {
+                    tracing::error!(?tx, %error, "Transaction reverted");
+                    break SubmissionStatus::Failed;
+                }
+                Err(error) if is_past_deadline(&error) => {
+                    let Some(batch) = &batch else {
+                        return Err(error);
+                    };
+
+                    break give_up_sent_batch(
+                        &app,
+                        &submission_mutex,
+                        &pending_insertions_mutex,
+                        &tx,
+                        batch,
+                        &error,
+                    )
+                    .await?;
+                }
+                Err(error) => return Err(error),
+            }
+        };
+
+        if resubmissions > 0 {
+            tracing::info!(
+                ?tx,
+                resubmissions,
+                ?status,
+                "Resubmitted transaction settled"
+            );
+        }
+
+        match status {
+            SubmissionStatus::Mined => app.health.record_submission_success(),
+            // Recorded when the batch was given up on
+            SubmissionStatus::TimedOut => {}
+            _ => app.record_submission_failure().await,
+        }
+        app.database.update_submission_status(&tx.0, status).await?;
+
+        // Recorded above, the next transactions are monitored all the same
+        if status == SubmissionStatus::Failed {
+            tracing::error!(?tx, "Failed to mine transaction");
+        }
     }
 
     Ok(())
 }
+
+/// Waits for the transaction to be mined, until the insertion timeout or the
+/// submission budget of its batch, which bounds its reprices too
+async fn mine_transaction(
+    app: &App,
+    tx: &TransactionId,
+    accepted_at: Option<DateTime<Utc>>,
+    submission: Option<&SubmissionEntry>,
+) -> anyhow::Result<bool> {
+    let mine = app.identity_manager.mine_transaction(tx.clone());
+
+    match (accepted_at, submission) {
+        (Some(accepted_at), Some(submission)) => {
+            within_insertion_timeout(
+                app.config.app.insertion_timeout,
+                accepted_at,
+                within_submission_budget(app.config.app.submission_budget, submission, mine),
+            )
+            .await
+        }
+        (Some(accepted_at), None) => {
+            within_insertion_timeout(app.config.app.insertion_timeout, accepted_at, mine).await
+        }
+        _ => mine.await,
+    }
+}
+
+/// Gives up on a batch whose transaction wasn't mined within the insertion
+/// timeout or the submission budget. If it's the latest batch sent, its
+/// transaction is cancelled if it's still pending and the batch rolled back,
+/// unless it was mined before the cancellation. An earlier batch can't be
+/// cancelled without reverting the ones sent after it, so it's only no longer
+/// waited for, with the chain deciding its fate.
+async fn give_up_sent_batch(
+    app: &App,
+    submission_mutex: &Mutex<()>,
+    pending_insertions_mutex: &Mutex<()>,
+    tx: &TransactionId,
+    batch: &BatchEntry,
+    error: &anyhow::Error,
+) -> anyhow::Result<SubmissionStatus> {
+    tracing::error!(?tx, %error, "Giving up on sent batch");
+
+    // No batch may be sent behind it while it's cancelled
+    let _guard = submission_mutex.lock().await;
+
+    let latest_batch = app.database.get_latest_batch_with_transaction().await?;
+    if latest_batch.map(|latest| latest.next_root) != Some(batch.next_root) {
+        tracing::warn!(
+            ?tx,
+            next_root = ?batch.next_root,
+            "Timed out behind a later batch, no longer waiting for it"
+        );
+        return abandon_batch(app, error).await;
+    }
+
+    if let Err(cancel_error) = app.identity_manager.cancel_transaction(tx.clone()).await {
+        tracing::warn!(?tx, error = %cancel_error, "Failed to cancel timed out transaction");
+    }
+
+    // Either the transaction or its cancellation settles the nonce
+    let settled = app.identity_manager.mine_transaction(tx.clone()).await;
+
+    if app
+        .identity_manager
+        .is_root_mined(batch.next_root.into())
+        .await?
+    {
+        tracing::warn!(
+            ?tx,
+            "Timed out transaction was mined before it was cancelled"
+        );
+        return Ok(SubmissionStatus::Mined);
+    }
+
+    if let Err(settle_error) = settled {
+        tracing::error!(?tx, error = %settle_error, "Timed out transaction didn't settle");
+        return abandon_batch(app, error).await;
+    }
+
+    give_up_batch(app, pending_insertions_mutex, batch, error).await?;
+
+    Ok(SubmissionStatus::TimedOut)
+}
+
+/// Stops waiting for a timed out batch that's left in the tree
+async fn abandon_batch(app: &App, error: &anyhow::Error) -> anyhow::Result<SubmissionStatus> {
+    alert_on_submission_error(app, error).await;
+    app.record_submission_failure().await;
+
+    Ok(SubmissionStatus::TimedOut)
+}
+
+/// Resubmits a transaction that failed in the relayer, if the failed policy
+/// allows it, the insertion hasn't timed out and no later batch has been sent
+/// meanwhile. A later batch would get mined ahead of the resubmission and
+/// revert. Returns the id of the resubmission.
+async fn resubmit(
+    app: &App,
+    submission_mutex: &Mutex<()>,
+    tx: &TransactionId,
+    batch: &BatchEntry,
+    accepted_at: DateTime<Utc>,
+    resubmissions: u32,
+) -> anyhow::Result<Option<TransactionId>> {
+    let Some(backoff) = app.identity_manager.resubmission_backoff(resubmissions) else {
+        tracing::warn!(
+            ?tx,
+            resubmissions,
+            "Transaction failed, not resubmitting it again"
+        );
+        return Ok(None);
+    };
+
+    tokio::time::sleep(backoff).await;
+
+    if let Some(error) = insertion_timed_out(app.config.app.insertion_timeout, accepted_at) {
+        tracing::warn!(?tx, %error, "Insertion timed out, not resubmitting it");
+        return Ok(None);
+    }
+
+    let _guard = submission_mutex.lock().await;
+
+    let latest_batch = app.database.get_latest_batch_with_transaction().await?;
+    if latest_batch.map(|latest| latest.next_root) != Some(batch.next_root) {
+        tracing::warn!(
+            ?tx,
+            next_root = ?batch.next_root,
+            "Transaction failed behind a later batch, not resubmitting it"
+        );
+        return Ok(None);
+    }
+
+    let resubmitted = app
+        .identity_manager
+        .resubmit_transaction(tx.clone())
+        .await?;
+
+    app.database
+        .record_resubmitted_transaction_tx(&batch.next_root, &resubmitted.0)
+        .await?;
+
+    tracing::warn!(
+        failed_tx_id = tx.0,
+        tx_id = resubmitted.0,
+        resubmissions = resubmissions + 1,
+        "Transaction failed in the relayer, resubmitted it"
+    );
+
+    Ok(Some(resubmitted))
+}
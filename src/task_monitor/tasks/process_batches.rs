@@ -1,16 +1,22 @@
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use ethers::types::U256;
-use tokio::sync::{mpsc, Notify};
+use serde_json::json;
+use tokio::sync::{mpsc, Mutex, Notify};
 use tokio::{select, time};
 use tracing::instrument;
 
+use crate::alerts::Alert;
 use crate::app::App;
 use crate::contracts::IdentityManager;
 use crate::database::query::DatabaseQuery as _;
-use crate::database::types::{BatchEntry, BatchType};
-use crate::ethereum::write::TransactionId;
+use crate::database::types::{BatchEntry, BatchType, SubmissionEntry, SubmissionStatus};
+use crate::ethereum::write::{TransactionId, TxError};
+use crate::identity_tree::Hash;
 use crate::prover::Prover;
 use crate::utils::index_packing::pack_indices;
 
@@ -19,6 +25,8 @@ pub async fn process_batches(
     monitored_txs_sender: Arc<mpsc::Sender<TransactionId>>,
     next_batch_notify: Arc<Notify>,
     wake_up_notify: Arc<Notify>,
+    pending_insertions_mutex: Arc<Mutex<()>>,
+    submission_mutex: Arc<Mutex<()>>,
 ) -> anyhow::Result<()> {
     tracing::info!("Awaiting for a clean slate");
     app.identity_manager.await_clean_slate().await?;
@@ -26,6 +34,9 @@ pub async fn process_batches(
     // This is a tricky way to know that we are not changing data during tree
     // initialization process.
     _ = app.tree_state()?;
+
+    recover_submissions(&app, &monitored_txs_sender).await?;
+
     tracing::info!("Starting identity processor.");
 
     // We start a timer and force it to perform one initial tick to avoid an
@@ -48,18 +59,92 @@ pub async fn process_batches(
             },
         }
 
+        // Checked on every wake up, so that insertions resume soon after the
+        // contract is unpaused
+        match app.refresh_contract_paused().await {
+            Ok(false) => {}
+            Ok(true) => {
+                tracing::trace!("Identity manager contract is paused. Waiting.");
+                continue;
+            }
+            Err(error) => tracing::warn!(?error, "Failed to read the contract's paused state"),
+        }
+
+        // Held until the transaction is recorded, so that a failed one isn't
+        // resubmitted behind it
+        let _submission_guard = submission_mutex.lock().await;
+
         let next_batch = app.database.get_next_batch_without_transaction().await?;
         let Some(next_batch) = next_batch else {
             continue;
         };
 
-        let tx_id =
-            commit_identities(&app.identity_manager, &monitored_txs_sender, &next_batch).await?;
+        // Batches created before the limit was reached wait for the window to
+        // roll
+        if let Err(error) = app.identity_manager.check_spend_limit() {
+            tracing::trace!(%error, "Gas spend limit reached. Waiting.");
+            continue;
+        }
+
+        let submission = app
+            .database
+            .insert_submission(&next_batch.next_root)
+            .await?;
+
+        let accepted_at = accepted_at(&app, &next_batch).await?;
 
+        // Checked ahead of each attempt only, as an attempt that's cut short may
+        // have sent the transaction already
+        let given_up = budget_exhausted(&app, &submission)
+            .or_else(|| insertion_timed_out(app.config.app.insertion_timeout, accepted_at));
+        if let Some(error) = given_up {
+            give_up_batch(&app, &pending_insertions_mutex, &next_batch, &error.into()).await?;
+            continue;
+        }
+
+        let committed = within_insertion_timeout(
+            app.config.app.insertion_timeout,
+            accepted_at,
+            within_submission_budget(
+                app.config.app.submission_budget,
+                &submission,
+                commit_identities(&app.identity_manager, &next_batch),
+            ),
+        )
+        .await;
+
+        let tx_id = match committed {
+            Ok(tx_id) => tx_id,
+            // The relayer may have got the transaction before the attempt was
+            // cut short, in which case it's cancelled by the monitor
+            Err(error) if is_past_deadline(&error) => {
+                let sent = app
+                    .identity_manager
+                    .find_batch_submission(next_batch.next_root.into())
+                    .await?;
+
+                if sent.is_none() {
+                    give_up_batch(&app, &pending_insertions_mutex, &next_batch, &error).await?;
+                    continue;
+                }
+
+                sent
+            }
+            Err(error) => {
+                alert_on_submission_error(&app, &error).await;
+                app.record_submission_failure().await;
+                return Err(error);
+            }
+        };
+
+        // Transactions are only monitored once recorded, so that the monitor
+        // can find their batch
         if let Some(tx_id) = tx_id {
             app.database
-                .insert_new_transaction(&tx_id.0, &next_batch.next_root)
+                .record_submitted_transaction_tx(&next_batch.next_root, &tx_id.0)
                 .await?;
+
+            monitored_txs_sender.send(tx_id).await?;
         }
 
         // We want to check if there's a full batch available immediately
@@ -67,9 +152,282 @@ pub async fn process_batches(
     }
 }
 
+/// Resumes the submissions interrupted by a restart. Submitted transactions
+/// are monitored again. Batches whose submission was cut short are
+/// resubmitted, unless the relayer got the transaction anyway or their root
+/// made it on chain.
+async fn recover_submissions(
+    app: &App,
+    monitored_txs_sender: &mpsc::Sender<TransactionId>,
+) -> anyhow::Result<()> {
+    let submissions = app.database.get_unfinished_submissions().await?;
+
+    for submission in submissions {
+        let next_root = submission.batch_next_root;
+
+        match (submission.status, submission.transaction_id) {
+            (SubmissionStatus::Submitted, Some(transaction_id)) => {
+                tracing::info!(?next_root, transaction_id, "Resuming monitoring of batch");
+
+                if app
+                    .database
+                    .get_transaction_for_batch(&next_root)
+                    .await?
+                    .is_none()
+                {
+                    app.database
+                        .insert_new_transaction(&transaction_id, &next_root)
+                        .await?;
+                }
+
+                monitored_txs_sender
+                    .send(TransactionId(transaction_id))
+                    .await?;
+            }
+            _ => {
+                // Sending it again would revert, as the batch is already on its
+                // way
+                if let Some(transaction_id) = app
+                    .identity_manager
+                    .find_batch_submission(next_root.into())
+                    .await?
+                {
+                    tracing::info!(
+                        ?next_root,
+                        %transaction_id,
+                        "Interrupted submission reached the relayer, resuming monitoring"
+                    );
+
+                    app.database
+                        .record_submitted_transaction_tx(&next_root, &transaction_id.0)
+                        .await?;
+
+                    monitored_txs_sender.send(transaction_id).await?;
+                } else if app.identity_manager.is_root_mined(next_root.into()).await? {
+                    tracing::warn!(
+                        ?next_root,
+                        "Interrupted submission was mined, but the relayer no longer lists it"
+                    );
+
+                    // Without a transaction id, the mined status is what keeps
+                    // the batch from being submitted again
+                    app.database
+                        .update_submission_status_by_root(&next_root, SubmissionStatus::Mined)
+                        .await?;
+                } else {
+                    tracing::warn!(?next_root, "Interrupted submission will be retried");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn alert_on_submission_error(app: &App, error: &anyhow::Error) {
+    let alert = match error.downcast_ref::<TxError>() {
+        Some(TxError::TreeFull { capacity }) => Alert::new(
+            "tree_full",
+            "The tree on chain is full, insertions can't be submitted",
+            json!({ "capacity": capacity }),
+        ),
+        Some(TxError::RootMoved { expected, actual }) => Alert::new(
+            "root_mismatch",
+            "The contract root doesn't match the local tree",
+            json!({ "expected": expected.to_string(), "actual": actual.to_string() }),
+        ),
+        Some(TxError::RetryBudgetExhausted { attempts, elapsed }) => Alert::new(
+            "retry_budget_exhausted",
+            "A batch couldn't be submitted within its retry budget",
+            json!({ "attempts": attempts, "elapsed": format!("{elapsed:?}") }),
+        ),
+        Some(TxError::InsertionTimeout { elapsed }) => Alert::new(
+            "insertion_timeout",
+            "A batch wasn't mined within the insertion timeout",
+            json!({ "elapsed": format!("{elapsed:?}") }),
+        ),
+        Some(TxError::SpendLimitExceeded { spent, budget }) => Alert::new(
+            "spend_limit_exceeded",
+            "Batch submission paused, the gas spend limit of the window is reached",
+            json!({ "spent": spent.to_string(), "budget": budget.to_string() }),
+        ),
+        _ => return,
+    };
+
+    app.alerts.critical(alert).await;
+}
+
+/// Whether the submission has used up the configured number of attempts or
+/// amount of time, counted from its first attempt.
+pub fn budget_exhausted(app: &App, submission: &SubmissionEntry) -> Option<TxError> {
+    let attempts = u32::try_from(submission.attempts).unwrap_or(u32::MAX);
+    let elapsed = (Utc::now() - submission.created_at)
+        .to_std()
+        .unwrap_or_default();
+
+    let attempts_exhausted = app
+        .config
+        .app
+        .submission_budget_attempts
+        .is_some_and(|max_attempts| attempts > max_attempts);
+    let time_exhausted = app
+        .config
+        .app
+        .submission_budget
+        .is_some_and(|budget| elapsed >= budget);
+
+    (attempts_exhausted || time_exhausted)
+        .then_some(TxError::RetryBudgetExhausted { attempts, elapsed })
+}
+
+/// When the earliest identity of an insertion batch was accepted, which the
+/// insertion timeout is measured from. Deletions, and identities accepted
+/// before acceptance times were recorded, count from the batch's creation.
+pub async fn accepted_at(app: &App, batch: &BatchEntry) -> anyhow::Result<DateTime<Utc>> {
+    if batch.batch_type != BatchType::Insertion {
+        return Ok(batch.created_at);
+    }
+
+    let accepted_at = app
+        .database
+        .get_earliest_acceptance(&batch.data.0.indexes)
+        .await?;
+
+    Ok(accepted_at.unwrap_or(batch.created_at))
+}
+
+/// Gives up on the batch, rolling it back from the database and the tree
+/// along with everything after it. Its insertions are marked as failed, while
+/// the later identities are queued again.
+pub async fn give_up_batch(
+    app: &App,
+    pending_insertions_mutex: &Mutex<()>,
+    batch: &BatchEntry,
+    error: &anyhow::Error,
+) -> anyhow::Result<()> {
+    let prev_root = batch
+        .prev_root
+        .context("The batch head can't be rolled back")?;
+
+    tracing::error!(?prev_root, next_root = ?batch.next_root, %error, "Giving up on batch");
+
+    alert_on_submission_error(app, error).await;
+    app.record_submission_failure().await;
+
+    let failed: Vec<Hash> = if batch.batch_type == BatchType::Insertion {
+        batch
+            .data
+            .0
+            .identities
+            .iter()
+            .map(|identity| identity.commitment.into())
+            .collect()
+    } else {
+        vec![]
+    };
+
+    // No batch may be created nor identity added to the tree in the meantime
+    let _guard = pending_insertions_mutex.lock().await;
+
+    // Checked ahead of the database, so that neither is rolled back if the
+    // tree can't be
+    let tree_state = app.tree_state()?;
+    anyhow::ensure!(
+        tree_state.can_roll_back_to(prev_root),
+        "Rolled back root {prev_root} isn't in the batching tree"
+    );
+
+    let next_leaf = app
+        .database
+        .roll_back_batch_tx(&prev_root, &failed, &error.to_string())
+        .await?;
+
+    if !tree_state.roll_back_to(prev_root, next_leaf) {
+        anyhow::bail!("Root {prev_root} left the batching tree while rolling back to it");
+    }
+    app.recent_roots.roll_back_to(prev_root);
+
+    Ok(())
+}
+
+/// Runs `future` until `timeout` has passed since `accepted_at`, after which
+/// it's dropped, cancelling whatever it was waiting for, and
+/// `TxError::InsertionTimeout` is returned. Without a timeout, it runs to
+/// completion.
+pub async fn within_insertion_timeout<T>(
+    timeout: Option<Duration>,
+    accepted_at: DateTime<Utc>,
+    future: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    within_deadline(timeout, accepted_at, future, |elapsed| {
+        TxError::InsertionTimeout { elapsed }
+    })
+    .await
+}
+
+/// Runs `future` until the time budget of the submission has passed since its
+/// first attempt, after which it's dropped like with
+/// `within_insertion_timeout` and `TxError::RetryBudgetExhausted` is returned.
+/// This bounds the relayer retries, reprices and re-authentication within an
+/// attempt as well.
+pub async fn within_submission_budget<T>(
+    budget: Option<Duration>,
+    submission: &SubmissionEntry,
+    future: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let attempts = u32::try_from(submission.attempts).unwrap_or(u32::MAX);
+
+    within_deadline(budget, submission.created_at, future, |elapsed| {
+        TxError::RetryBudgetExhausted { attempts, elapsed }
+    })
+    .await
+}
+
+async fn within_deadline<T>(
+    timeout: Option<Duration>,
+    since: DateTime<Utc>,
+    future: impl Future<Output = anyhow::Result<T>>,
+    timed_out: impl FnOnce(Duration) -> TxError,
+) -> anyhow::Result<T> {
+    let Some(timeout) = timeout else {
+        return future.await;
+    };
+
+    let elapsed = (Utc::now() - since).to_std().unwrap_or_default();
+
+    // Not even polled past the deadline
+    if elapsed >= timeout {
+        return Err(timed_out(elapsed).into());
+    }
+
+    time::timeout(timeout.saturating_sub(elapsed), future)
+        .await
+        .map_err(|_| timed_out(timeout))?
+}
+
+/// Whether `timeout` has passed since `accepted_at`.
+pub fn insertion_timed_out(
+    timeout: Option<Duration>,
+    accepted_at: DateTime<Utc>,
+) -> Option<TxError> {
+    let elapsed = (Utc::now() - accepted_at).to_std().unwrap_or_default();
+
+    timeout
+        .is_some_and(|timeout| elapsed >= timeout)
+        .then_some(TxError::InsertionTimeout { elapsed })
+}
+
+/// Whether the batch ran out of time, either its insertion timeout or its
+/// submission budget
+pub fn is_past_deadline(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<TxError>(),
+        Some(TxError::InsertionTimeout { .. } | TxError::RetryBudgetExhausted { .. })
+    )
+}
+
 async fn commit_identities(
     identity_manager: &IdentityManager,
-    monitored_txs_sender: &mpsc::Sender<TransactionId>,
     batch: &BatchEntry,
 ) -> anyhow::Result<Option<TransactionId>> {
     // If the update is an insertion
@@ -99,10 +457,6 @@ async fn commit_identities(
         delete_identities(identity_manager, &prover, batch).await?
     };
 
-    if let Some(tx_id) = tx_id.clone() {
-        monitored_txs_sender.send(tx_id).await?;
-    }
-
     Ok(tx_id)
 }
 
@@ -204,3 +558,85 @@ pub async fn delete_identities(
 
     Ok(Some(transaction_id))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    use super::*;
+
+    struct DropGuard(Arc<AtomicBool>);
+
+    impl Drop for DropGuard {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn insertions_are_cancelled_at_the_timeout() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = DropGuard(dropped.clone());
+        let start = Instant::now();
+
+        let result =
+            within_insertion_timeout(Some(Duration::from_millis(100)), Utc::now(), async move {
+                let _guard = guard;
+                time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<TxError>(),
+            Some(TxError::InsertionTimeout { .. })
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn attempts_are_cut_short_by_the_submission_budget() {
+        let submission = SubmissionEntry {
+            batch_next_root: Hash::ZERO,
+            status:          SubmissionStatus::Submitting,
+            transaction_id:  None,
+            attempts:        2,
+            created_at:      Utc::now(),
+        };
+
+        let result =
+            within_submission_budget::<()>(Some(Duration::from_millis(100)), &submission, async {
+                time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+
+        let error = result.unwrap_err();
+        assert!(is_past_deadline(&error));
+        assert!(matches!(
+            error.downcast_ref::<TxError>(),
+            Some(TxError::RetryBudgetExhausted { attempts: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn timed_out_insertions_are_not_started() {
+        let created_at = Utc::now() - chrono::Duration::seconds(10);
+
+        let result =
+            within_insertion_timeout::<()>(Some(Duration::from_secs(1)), created_at, async {
+                panic!("Polled past the timeout")
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            within_insertion_timeout(None, created_at, async { Ok(1) })
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
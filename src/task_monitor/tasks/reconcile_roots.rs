@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use tokio::time;
+
+use crate::app::App;
+
+pub async fn reconcile_roots(app: Arc<App>) -> anyhow::Result<()> {
+    let interval = app.config.app.reconcile_interval;
+    if interval.is_zero() {
+        tracing::info!("Periodic root reconciliation is disabled");
+        return Ok(());
+    }
+
+    let mut timer = time::interval(interval);
+
+    loop {
+        timer.tick().await;
+
+        // A tree that's still being initialized isn't diverged
+        if app.tree_state().is_err() {
+            continue;
+        }
+
+        app.reconcile_roots().await?;
+    }
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::prelude::*;
+use hyper::StatusCode;
+use signup_sequencer::utils::secret::SecretString;
+
+const ADMIN_TOKEN: &str = "admin-secret";
+
+async fn get_relayer_nonces(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    admin_token: Option<&str>,
+) -> (StatusCode, String) {
+    let mut req = Request::builder()
+        .method("GET")
+        .uri(uri.to_owned() + "/relayerNonces");
+    if let Some(admin_token) = admin_token {
+        req = req.header("X-Admin-Token", admin_token);
+    }
+    let req = req
+        .body(Body::empty())
+        .expect("Failed to create relayer nonces hyper::Body");
+
+    let response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status();
+
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .expect("Failed to read body bytes");
+
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Tests that admin endpoints only answer requests carrying the configured
+/// admin token, using `GET /relayerNonces`.
+#[tokio::test]
+async fn admin_endpoints() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting admin endpoints test");
+
+    let ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+    let batch_size: usize = 3;
+
+    let docker = Cli::default();
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) = spawn_deps(
+        initial_root,
+        &[batch_size],
+        &[],
+        DEFAULT_TREE_DEPTH as u8,
+        &docker,
+    )
+    .await?;
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let mut config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    config.server.admin_token = Some(SecretString::new(ADMIN_TOKEN.to_string()));
+
+    let (_, app_handle, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    let (status, _) = get_relayer_nonces(&uri, &client, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = get_relayer_nonces(&uri, &client, Some("admin-secreT")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = get_relayer_nonces(&uri, &client, Some(ADMIN_TOKEN)).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+
+    let relayers: serde_json::Value = serde_json::from_str(&body)?;
+    let relayers = relayers.as_array().expect("Expected a list of relayers");
+    assert_eq!(relayers.len(), 1);
+
+    let relayer = &relayers[0];
+    assert_eq!(relayer["relayer"], 0);
+    assert_eq!(
+        relayer["address"],
+        serde_json::to_value(micro_oz.address())?
+    );
+    assert_eq!(relayer["relayerNonce"], relayer["chainPendingNonce"]);
+    assert!(relayer["chainNonce"].is_u64());
+    assert!(relayer.get("error").is_none(), "{relayer}");
+
+    shutdown();
+    app_handle.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
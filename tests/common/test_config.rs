@@ -8,7 +8,7 @@ use signup_sequencer::config::{
     RelayerConfig, ServerConfig, ServiceConfig, TreeConfig,
 };
 use signup_sequencer::prover::ProverConfig;
-use signup_sequencer::utils::secret::SecretUrl;
+use signup_sequencer::utils::secret::{SecretString, SecretUrl};
 use url::Url;
 
 use crate::ProverService;
@@ -134,8 +134,39 @@ impl TestConfigBuilder {
                 max_epoch_duration:         default::max_epoch_duration(),
                 scanning_window_size:       default::scanning_window_size(),
                 scanning_chain_head_offset: default::scanning_chain_head_offset(),
+                scanning_window_overlap:    default::scanning_window_overlap(),
                 time_between_scans:         Duration::from_secs(DEFAULT_TIME_BETWEEN_SCANS_SECONDS),
+                max_events_per_scan:        None,
+                subscription_stall_timeout: default::subscription_stall_timeout(),
                 monitored_txs_capacity:     default::monitored_txs_capacity(),
+                check_root_before_submit:   default::check_root_before_submit(),
+                max_queue_age:              None,
+                max_block_lag:              None,
+                read_only_after_failures:   None,
+                max_chain_head_age:         None,
+                reconcile_interval:         default::reconcile_interval(),
+                reconcile_confirmations:    default::reconcile_confirmations(),
+                verify_proofs_on_chain:     default::verify_proofs_on_chain(),
+                insert_dedup_window:        None,
+                commitment_allowlist:       None,
+                commitment_denylist:        None,
+                batch_window:               None,
+                batch_size:                 None,
+                max_calldata_size:          default::max_calldata_size(),
+                min_relayer_balance_gwei:   None,
+                confirmation_strategy:      Default::default(),
+                confirmation_depth:         default::confirmation_depth(),
+                check_tree_capacity:        default::check_tree_capacity(),
+                decode_revert_reasons:      default::decode_revert_reasons(),
+                dropped_tx_grace:           None,
+                submission_budget:          None,
+                submission_budget_attempts: None,
+                insertion_timeout:          None,
+                spend_limit_gwei:           None,
+                gas_strategy:               None,
+                gas_reprice_after:          None,
+                gas_reprice_max_fee_gwei:   None,
+                spend_limit_window:         default::spend_limit_window(),
             },
             tree:      TreeConfig {
                 tree_depth:              self.tree_depth,
@@ -144,28 +175,53 @@ impl TestConfigBuilder {
                 cache_file:              self.cache_file.context("Missing cache file")?,
                 force_cache_purge:       default::force_cache_purge(),
                 initial_leaf_value:      default::initial_leaf_value(),
+                loading_threads:         None,
+                leaf_conflict_policy:    Default::default(),
+                proof_cache_size:        None,
+                recent_roots:            default::recent_roots(),
             },
             network:   NetworkConfig {
-                identity_manager_address:           self
-                    .identity_manager_address
-                    .context("Missing identity manager address")?,
+                identity_manager_address:           Some(
+                    self.identity_manager_address
+                        .context("Missing identity manager address")?,
+                ),
+                identity_manager_addresses:         Default::default(),
                 relayed_identity_manager_addresses: Default::default(),
             },
             providers: ProvidersConfig {
-                primary_network_provider:  self
+                primary_network_provider:    self
                     .primary_network_provider
                     .context("Missing primary network provider")?,
-                relayed_network_providers: Default::default(),
+                relayed_network_providers:   Default::default(),
+                fallback_network_providers:  Default::default(),
+                http_pool_max_idle_per_host: default::http_pool_max_idle_per_host(),
+                http_pool_idle_timeout:      default::http_pool_idle_timeout(),
+                request_timeout:             default::provider_request_timeout(),
+                ws_network_provider:         None,
+                max_concurrent_view_calls:   None,
+                log_batch_size:              None,
             },
             relayer:   RelayerConfig::OzDefender(OzDefenderConfig {
-                oz_api_url:              self.oz_api_url.context("Missing oz api url")?,
-                oz_address:              self.oz_address.context("Missing oz address")?,
-                oz_api_key:              "".to_string(),
-                oz_api_secret:           "".to_string(),
+                oz_api_url: self.oz_api_url.context("Missing oz api url")?,
+                oz_address: self.oz_address.context("Missing oz address")?,
+                oz_api_key: SecretString::new(String::new()),
+                oz_api_secret: SecretString::new(String::new()),
                 oz_transaction_validity: default::oz_transaction_validity(),
-                oz_send_timeout:         default::oz_send_timeout(),
-                oz_mine_timeout:         default::oz_mine_timeout(),
-                oz_gas_limit:            Default::default(),
+                oz_send_timeout: default::oz_send_timeout(),
+                oz_mine_timeout: default::oz_mine_timeout(),
+                oz_clock_skew: default::oz_clock_skew(),
+                oz_gas_limit: Default::default(),
+                oz_list_transactions_retries: default::oz_list_transactions_retries(),
+                oz_max_concurrent_polls: None,
+                oz_batch_status_polling: default::oz_batch_status_polling(),
+                oz_log_payloads: default::oz_log_payloads(),
+                oz_additional_relayers: Default::default(),
+                oz_relayer_selection: Default::default(),
+                oz_failed_policy: Default::default(),
+                oz_allowed_selectors: Default::default(),
+                oz_follow_known_transactions: default::oz_follow_known_transactions(),
+                oz_legacy_fallback_after: None,
+                oz_legacy_fallback_bump_percent: default::oz_legacy_fallback_bump_percent(),
             }),
             database:  DatabaseConfig {
                 database,
@@ -173,8 +229,18 @@ impl TestConfigBuilder {
                 max_connections: default::max_connections(),
             },
             server:    ServerConfig {
-                address:       SocketAddr::from(([127, 0, 0, 1], 0)),
+                address: SocketAddr::from(([127, 0, 0, 1], 0)),
                 serve_timeout: default::serve_timeout(),
+                insert_timeout: None,
+                admin_token: None,
+                cursor_secret: None,
+                acknowledgment_signing_key: None,
+                commitment_byte_order: Default::default(),
+                await_inclusion: default::await_inclusion(),
+                max_concurrent_inserts: None,
+                max_queued_inserts_per_client: default::max_queued_inserts_per_client(),
+                client_id_header: None,
+                optimistic_proofs: default::optimistic_proofs(),
             },
             service:   ServiceConfig::default(),
         };
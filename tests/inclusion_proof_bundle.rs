@@ -0,0 +1,150 @@
+mod common;
+
+use common::prelude::*;
+use hyper::StatusCode;
+use semaphore::merkle_tree::Hasher;
+use signup_sequencer::server::data::ProofBundle;
+
+async fn get_proof_bundle(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    commitment: &Hash,
+) -> (StatusCode, String) {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri.to_owned() + "/inclusionProofBundle")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "identityCommitment": commitment,
+            })
+            .to_string(),
+        ))
+        .expect("Failed to create proof bundle hyper::Body");
+
+    let response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status();
+
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .expect("Failed to read body bytes");
+
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Tests that `POST /inclusionProofBundle` answers with a proof of a mined
+/// identity that hashes up to the root of the tree.
+#[tokio::test]
+async fn inclusion_proof_bundle() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting inclusion proof bundle test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+    let batch_size: usize = 3;
+
+    let docker = Cli::default();
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) = spawn_deps(
+        initial_root,
+        &[batch_size],
+        &[],
+        DEFAULT_TREE_DEPTH as u8,
+        &docker,
+    )
+    .await?;
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    let (_, app_handle, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    let (status, _) = get_proof_bundle(&uri, &client, &identities_ref[0]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    for leaf_index in 0..batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, leaf_index).await;
+    }
+
+    // Waits for the batch to be mined
+    for (leaf_index, identity) in identities_ref.iter().enumerate() {
+        test_inclusion_proof(&uri, &client, leaf_index, &ref_tree, identity, false).await;
+    }
+
+    let leaf_index = 1;
+    let (status, body) = get_proof_bundle(&uri, &client, &identities_ref[leaf_index]).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+
+    let bundle: ProofBundle = serde_json::from_str(&body)?;
+    assert_eq!(bundle.version, 1);
+    assert_eq!(bundle.leaf, identities_ref[leaf_index].to_string());
+    assert_eq!(bundle.root, ref_tree.root().to_string());
+
+    let ref_proof = ref_tree.proof(leaf_index).unwrap();
+    let ref_siblings: Vec<String> = ref_proof
+        .0
+        .iter()
+        .map(|branch| match branch {
+            Branch::Left(sibling) | Branch::Right(sibling) => sibling.to_string(),
+        })
+        .collect();
+    assert_eq!(bundle.path_elements, ref_siblings);
+
+    // The path indices spell out the leaf index, and the path hashes up to the
+    // root
+    let path_index = bundle
+        .path_indices
+        .iter()
+        .rev()
+        .fold(0, |index, bit| index * 2 + usize::from(*bit));
+    assert_eq!(path_index, leaf_index);
+
+    let root = bundle.path_elements.iter().zip(&bundle.path_indices).fold(
+        Field::from_str_radix(&bundle.leaf, 10)?,
+        |node, (sibling, index)| {
+            let sibling = Field::from_str_radix(sibling, 10).unwrap();
+            match index {
+                0 => PoseidonHash::hash_node(&node, &sibling),
+                _ => PoseidonHash::hash_node(&sibling, &node),
+            }
+        },
+    );
+    assert_eq!(root.to_string(), bundle.root);
+
+    shutdown();
+    app_handle.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use common::prelude::*;
+use signup_sequencer::identity_tree::UnprocessedStatus;
+
+use crate::common::test_inclusion_status;
+
+/// Tests that a batch which can't be submitted within the insertion timeout
+/// is given up on, failing its identities, and that they can be inserted
+/// again afterwards.
+#[tokio::test]
+async fn insertion_timeout() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting insertion timeout test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let batch_size: usize = 3;
+
+    let docker = Cli::default();
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) = spawn_deps(
+        initial_root,
+        &[batch_size],
+        &[],
+        DEFAULT_TREE_DEPTH as u8,
+        &docker,
+    )
+    .await?;
+
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    prover_mock.set_availability(false).await;
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let mut config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    // Shorter than the backoff between failed attempts, so that the batch is
+    // given up on at the second attempt
+    config.app.insertion_timeout = Some(Duration::from_secs(3));
+
+    let (_, app_handle, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    for leaf_index in 0..batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, leaf_index).await;
+    }
+
+    // Wait for the batch to be given up on
+    tokio::time::sleep(Duration::from_secs(15)).await;
+
+    for identity in &identities_ref {
+        test_inclusion_status(&uri, &client, identity, UnprocessedStatus::Failed).await;
+    }
+
+    prover_mock.set_availability(true).await;
+
+    info!("Prover has been reenabled");
+
+    // The failed identities are inserted again, from the same leaf
+    for leaf_index in 0..batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, leaf_index).await;
+    }
+
+    for (leaf_index, identity) in identities_ref.iter().enumerate() {
+        test_inclusion_proof(&uri, &client, leaf_index, &ref_tree, identity, false).await;
+    }
+
+    shutdown();
+    app_handle.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
+
+/// Tests that a batch whose transaction is still pending at the insertion
+/// timeout is cancelled, so that it can't be mined after its identities have
+/// been failed.
+#[tokio::test]
+async fn sent_insertion_is_cancelled_at_the_timeout() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting sent insertion timeout test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let batch_size: usize = 3;
+
+    let docker = Cli::default();
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) = spawn_deps(
+        initial_root,
+        &[batch_size],
+        &[],
+        DEFAULT_TREE_DEPTH as u8,
+        &docker,
+    )
+    .await?;
+
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let mut config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    config.app.insertion_timeout = Some(Duration::from_secs(30));
+
+    let (_, app_handle, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    // Keeps the batch pending in the mempool
+    let chain = ethers::providers::Provider::<ethers::providers::Http>::try_from(
+        mock_chain.anvil.endpoint(),
+    )?;
+    chain
+        .request::<_, ()>("evm_setIntervalMining", [0u64])
+        .await?;
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    for leaf_index in 0..batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, leaf_index).await;
+    }
+
+    // Past the timeout, the pending batch gets replaced by its cancellation
+    tokio::time::sleep(Duration::from_secs(40)).await;
+
+    chain
+        .request::<_, ()>("evm_setIntervalMining", [2u64])
+        .await?;
+
+    info!("Mining has been reenabled");
+
+    // Wait for the cancellation to be mined and the batch given up on
+    tokio::time::sleep(Duration::from_secs(20)).await;
+
+    for identity in &identities_ref {
+        test_inclusion_status(&uri, &client, identity, UnprocessedStatus::Failed).await;
+    }
+
+    let latest_root: U256 = mock_chain
+        .identity_manager
+        .method::<_, U256>("latestRoot", ())?
+        .call()
+        .await?;
+    assert_eq!(latest_root, initial_root);
+
+    shutdown();
+    app_handle.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
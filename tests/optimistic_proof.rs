@@ -0,0 +1,125 @@
+mod common;
+
+use common::prelude::*;
+use hyper::StatusCode;
+use signup_sequencer::server::data::InsertCommitmentTicket;
+
+use crate::common::construct_insert_identity_body;
+
+async fn insert_identity(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    commitment: &Hash,
+) -> InsertCommitmentTicket {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri.to_owned() + "/insertIdentity")
+        .header("Content-Type", "application/json")
+        .body(construct_insert_identity_body(commitment))
+        .expect("Failed to create insert identity hyper::Body");
+
+    let response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .expect("Failed to read body bytes");
+
+    serde_json::from_slice(&bytes).expect("Failed to parse the insertion ticket")
+}
+
+/// Tests that an insertion is answered with an unconfirmed proof against the
+/// projected root when `server.optimistic_proofs` is set, and that none is
+/// returned while the tree and the queue disagree.
+#[tokio::test]
+async fn optimistic_proof() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting optimistic proof test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+    let batch_size: usize = 3;
+
+    let docker = Cli::default();
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) = spawn_deps(
+        initial_root,
+        &[batch_size],
+        &[],
+        DEFAULT_TREE_DEPTH as u8,
+        &docker,
+    )
+    .await?;
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let mut config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    config.server.optimistic_proofs = true;
+
+    let (app, app_handle, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    // Each insertion is projected behind the ones queued or inserted ahead of
+    // it
+    for (leaf_index, identity) in identities_ref.iter().enumerate().take(2) {
+        let ticket = insert_identity(&uri, &client, identity).await;
+        ref_tree.set(leaf_index, *identity);
+
+        let proof = ticket
+            .optimistic_proof
+            .expect("Missing the optimistic proof");
+        assert!(!proof.confirmed);
+        assert_eq!(proof.leaf_index, leaf_index);
+        assert_eq!(proof.projected_root, ref_tree.root());
+        assert_eq!(proof.proof, ref_tree.proof(leaf_index).unwrap());
+    }
+
+    // Keeps the last identity queued
+    let ticket = insert_identity(&uri, &client, &identities_ref[2]).await;
+    app.health.set_paused(true);
+    assert!(ticket.optimistic_proof.is_some());
+    assert!(app.optimistic_proof(&identities_ref[2]).await?.is_some());
+
+    // Moves the tree ahead of the database, as while a batch is inserted
+    app.tree_state()?
+        .latest_tree()
+        .append_many(&[Hash::from(1)]);
+
+    assert!(app.optimistic_proof(&identities_ref[2]).await?.is_none());
+
+    shutdown();
+    app_handle.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::prelude::*;
+use signup_sequencer::config::OzFailedPolicy;
+
+/// Tests that a batch whose transaction is dropped from the mempool is
+/// resubmitted under the resubmit policy, and that the resubmission is
+/// followed until the batch is mined.
+#[tokio::test]
+async fn dropped_transactions_are_resubmitted() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting resubmission test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let batch_size: usize = 3;
+
+    let docker = Cli::default();
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) = spawn_deps(
+        initial_root,
+        &[batch_size],
+        &[],
+        DEFAULT_TREE_DEPTH as u8,
+        &docker,
+    )
+    .await?;
+
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let mut config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    if let RelayerConfig::OzDefender(oz) = &mut config.relayer {
+        oz.oz_failed_policy = OzFailedPolicy::Resubmit {
+            max_resubmissions: 2,
+            backoff:           Duration::from_secs(1),
+        };
+    }
+
+    let (_, app_handle, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    // Keeps the batch pending in the mempool, so that it can be dropped
+    let chain = ethers::providers::Provider::<ethers::providers::Http>::try_from(
+        mock_chain.anvil.endpoint(),
+    )?;
+    chain
+        .request::<_, ()>("evm_setIntervalMining", [0u64])
+        .await?;
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    for leaf_index in 0..batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, leaf_index).await;
+    }
+
+    let dropped = loop {
+        let pool = chain.txpool_content().await?;
+        if let Some(tx) = pool.pending.values().flat_map(|txs| txs.values()).next() {
+            break tx.hash;
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+
+    chain
+        .request::<_, Option<H256>>("anvil_dropTransaction", [dropped])
+        .await?;
+
+    info!(?dropped, "Pending batch has been dropped");
+
+    // The relayer reports the dropped transaction as failed, and the batch is
+    // sent again
+    let resubmitted = loop {
+        let pool = chain.txpool_content().await?;
+        if let Some(tx) = pool.pending.values().flat_map(|txs| txs.values()).next() {
+            break tx.hash;
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+    assert_ne!(resubmitted, dropped);
+
+    chain
+        .request::<_, ()>("evm_setIntervalMining", [2u64])
+        .await?;
+
+    info!("Mining has been reenabled");
+
+    for (leaf_index, identity) in identities_ref.iter().enumerate() {
+        test_inclusion_proof(&uri, &client, leaf_index, &ref_tree, identity, false).await;
+    }
+
+    let latest_root: U256 = mock_chain
+        .identity_manager
+        .method::<_, U256>("latestRoot", ())?
+        .call()
+        .await?;
+    assert_eq!(latest_root, ref_tree.root().into());
+
+    shutdown();
+    app_handle.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}